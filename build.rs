@@ -60,6 +60,9 @@ fn main() {
                         match extension {
                             "vert" => shaderc::ShaderKind::Vertex,
                             "frag" => shaderc::ShaderKind::Fragment,
+                            "tesc" => shaderc::ShaderKind::TessControl,
+                            "tese" => shaderc::ShaderKind::TessEvaluation,
+                            "geom" => shaderc::ShaderKind::Geometry,
                             "comp" => shaderc::ShaderKind::Compute,
                             _ => panic!("Unknown Vulkan shader file extension {extension}"),
                         },
@@ -0,0 +1,143 @@
+use crate::engine::rendersystem::Bounds;
+use log::warn;
+use std::collections::HashMap;
+
+// Overlap queries between axis aligned boxes in world space, for game logic that needs to know
+// what's touching what. It's only a broadphase, there's no contacts or response, and boxes that
+// just touch count as overlapping. Model bounds are in model space, so they have to go through
+// Bounds::transformed with wherever the model is first.
+//
+// Finding every pair sorts the boxes by their minimum x and sweeps along it, so only boxes that
+// overlap on x get compared. Asking about one node just checks it against everything.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u64);
+
+#[derive(Default)]
+pub struct Broadphase {
+    nodes: HashMap<NodeId, Bounds>,
+    next_id: u64,
+}
+
+impl Broadphase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_node(&mut self, bounds: Bounds) -> NodeId {
+        let node = NodeId(self.next_id);
+        self.next_id += 1;
+        self.nodes.insert(node, bounds);
+        node
+    }
+
+    // For when it moves
+    pub fn set_bounds(&mut self, node: NodeId, bounds: Bounds) -> bool {
+        let Some(old_bounds) = self.nodes.get_mut(&node) else {
+            warn!("Can't move node {node:?}, it isn't registered");
+            return false;
+        };
+        *old_bounds = bounds;
+        true
+    }
+
+    pub fn remove_node(&mut self, node: NodeId) -> bool {
+        if self.nodes.remove(&node).is_none() {
+            warn!("Can't remove node {node:?}, it isn't registered");
+            return false;
+        }
+        true
+    }
+
+    pub fn bounds(&self, node: NodeId) -> Option<Bounds> {
+        self.nodes.get(&node).copied()
+    }
+
+    // Everything overlapping the node, not including itself
+    pub fn overlaps(&self, node: NodeId) -> Vec<NodeId> {
+        let Some(bounds) = self.nodes.get(&node) else {
+            warn!("Can't find overlaps for node {node:?}, it isn't registered");
+            return Vec::new();
+        };
+
+        let mut overlaps: Vec<NodeId> = self
+            .nodes
+            .iter()
+            .filter(|(other, other_bounds)| **other != node && bounds.overlaps(other_bounds))
+            .map(|(other, _)| *other)
+            .collect();
+        overlaps.sort();
+        overlaps
+    }
+
+    // Every overlapping pair once, with the lower ID first
+    pub fn overlapping_pairs(&self) -> Vec<(NodeId, NodeId)> {
+        let mut sorted: Vec<(&NodeId, &Bounds)> = self.nodes.iter().collect();
+        sorted.sort_by(|(_, a), (_, b)| a.min.x.total_cmp(&b.min.x));
+
+        let mut pairs = Vec::new();
+        for (i, (node, bounds)) in sorted.iter().enumerate() {
+            for (other, other_bounds) in &sorted[i + 1..] {
+                // everything after this starts further along x than this ends
+                if other_bounds.min.x > bounds.max.x {
+                    break;
+                }
+                if bounds.overlaps(other_bounds) {
+                    let (a, b) = (**node, **other);
+                    pairs.push((a.min(b), a.max(b)));
+                }
+            }
+        }
+        pairs.sort();
+        pairs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::*;
+
+    fn cube(center: Vector3<f32>, size: f32) -> Bounds {
+        Bounds {
+            min: center - Vector3::repeat(size / 2.0),
+            max: center + Vector3::repeat(size / 2.0),
+        }
+    }
+
+    #[test]
+    fn overlapping_boxes_report_each_other() {
+        let mut broadphase = Broadphase::new();
+        let a = broadphase.add_node(cube(Vector3::zeros(), 2.0));
+        let b = broadphase.add_node(cube(Vector3::new(1.5, 0.5, 0.0), 2.0));
+        let far = broadphase.add_node(cube(Vector3::new(10.0, 0.0, 0.0), 2.0));
+        // lined up with a on x, so the sweep has to compare it, but nowhere near on z
+        let behind = broadphase.add_node(cube(Vector3::new(0.0, 0.0, -10.0), 2.0));
+
+        assert_eq!(broadphase.overlaps(a), vec![b]);
+        assert_eq!(broadphase.overlaps(b), vec![a]);
+        assert!(broadphase.overlaps(far).is_empty());
+        assert!(broadphase.overlaps(behind).is_empty());
+        assert_eq!(broadphase.overlapping_pairs(), vec![(a, b)]);
+
+        // moving it away separates them, and back onto far makes a new pair
+        assert!(broadphase.set_bounds(b, cube(Vector3::new(9.0, 0.0, 0.0), 2.0)));
+        assert!(broadphase.overlaps(a).is_empty());
+        assert_eq!(broadphase.overlaps(far), vec![b]);
+        assert_eq!(broadphase.overlapping_pairs(), vec![(b, far)]);
+
+        assert!(broadphase.remove_node(far));
+        assert!(broadphase.overlapping_pairs().is_empty());
+        assert!(broadphase.overlaps(far).is_empty());
+    }
+
+    #[test]
+    fn touching_boxes_overlap() {
+        let mut broadphase = Broadphase::new();
+        let a = broadphase.add_node(cube(Vector3::zeros(), 2.0));
+        let b = broadphase.add_node(cube(Vector3::new(2.0, 0.0, 0.0), 2.0));
+
+        assert_eq!(broadphase.overlaps(a), vec![b]);
+        assert_eq!(broadphase.overlapping_pairs(), vec![(a, b)]);
+    }
+}
@@ -1,12 +1,26 @@
+pub mod collision;
 pub mod rendersystem;
+pub mod vfs;
 
 use crate::platform;
 use chrono::Local;
 use fern::colors::{Color, ColoredLevelConfig};
-use log::{debug, info};
-use std::{fs, io};
+use log::{debug, error, info};
+use std::{fs, io, thread, time};
 
 const FRAME_SMOOTHING: f64 = 0.9;
+// Fixed steps in one frame before giving up on catching up, otherwise a slow frame means more
+// steps next frame, which makes that one slow too
+const MAX_FIXED_STEPS: u32 = 8;
+// Skipped frames don't wait on vsync, so this keeps a minimized window from spinning
+const SKIPPED_FRAME_SLEEP: time::Duration = time::Duration::from_millis(50);
+
+// The window everything runs in. The winit feature swaps the native one for winit's, they have the
+// same interface.
+#[cfg(not(feature = "winit"))]
+pub type Video = platform::video::State;
+#[cfg(feature = "winit")]
+pub type Video = platform::winit::WinitSurfaceProvider;
 
 pub struct State {
     game_dir: String,
@@ -16,8 +30,14 @@ pub struct State {
     fps: f64,
     delta: i64,
 
-    video: platform::video::State,
+    fixed_timestep: Option<f32>,
+    accumulator: f32,
+    fixed_alpha: f32,
+
+    video: Video,
     render: rendersystem::State,
+    vfs: vfs::Vfs,
+    collision: collision::Broadphase,
 }
 
 impl State {
@@ -80,28 +100,114 @@ impl State {
 
         info!("Engine initialization started");
 
-        let video = platform::video::State::init();
-        let render = rendersystem::State::init(&video);
+        let video = Video::init();
+        let mut render = rendersystem::State::init(&video, rendersystem::RenderConfig::default());
+
+        // the game's own files, mods and overrides go above it
+        let vfs = vfs::Vfs::new();
+        vfs.mount(&args.game, 0);
+        render.set_vfs(vfs.clone());
 
-        Self {
+        #[allow(unused_mut)]
+        let mut self_ = Self {
             game_dir: args.game,
             start_time: 0,
             last_time: 0,
             runtime: 0,
             fps: 0.0,
             delta: 0,
+            fixed_timestep: None,
+            accumulator: 0.0,
+            fixed_alpha: 1.0,
             video,
             render,
+            vfs,
+            collision: collision::Broadphase::new(),
+        };
+
+        #[cfg(feature = "egui")]
+        if let Err(err) =
+            self_
+                .read_shader_binaries("ui")
+                .and_then(|(vertex_binary, fragment_binary)| {
+                    self_.render.init_gui(vertex_binary, fragment_binary)
+                })
+        {
+            error!("Failed to set up the GUI, it won't be drawn: {err:?}");
+        }
+
+        if let Err(err) =
+            self_
+                .read_shader_binaries("grid")
+                .and_then(|(vertex_binary, fragment_binary)| {
+                    self_.render.init_grid(vertex_binary, fragment_binary)
+                })
+        {
+            error!("Failed to set up the grid, it won't be drawn: {err:?}");
+        }
+
+        if let Err(err) =
+            self_
+                .read_shader_binaries("lighting")
+                .and_then(|(vertex_binary, fragment_binary)| {
+                    self_.render.init_lighting(vertex_binary, fragment_binary)
+                })
+        {
+            error!("Failed to set up the lighting pass, rendering will stay forward: {err:?}");
         }
+
+        self_
+    }
+
+    // The compiled vertex and fragment shader called name
+    fn read_shader_binaries(
+        &self,
+        name: &str,
+    ) -> Result<(Vec<u8>, Vec<u8>), rendersystem::ShaderError> {
+        let read = |extension| {
+            self.vfs
+                .read(&format!("shaders/{name}{extension}"))
+                .map_err(rendersystem::ShaderError::Io)
+        };
+        Ok((read(".vert.spv")?, read(".frag.spv")?))
+    }
+
+    // Window events first, then anything the renderer ran into
+    pub fn poll_events(&mut self) -> impl Iterator<Item = rendersystem::RenderEvent> {
+        let mut events: Vec<rendersystem::RenderEvent> = self.video.drain_events().collect();
+        events.extend(self.render.drain_events());
+        events.into_iter()
     }
 
     pub fn update(&mut self) {
-        if !self.video.focused() || self.video.resized() {
+        self.update_with(|_, _| {});
+    }
+
+    // tick gets the time to simulate, once for every fixed step if there's a fixed timestep and
+    // once a frame with the frame time if there isn't. It runs before the frame is rendered.
+    pub fn update_with(&mut self, mut tick: impl FnMut(&mut Self, f32)) {
+        let mut resized = false;
+        for event in self.poll_events() {
+            #[cfg(feature = "egui")]
+            self.render.handle_gui_event(&event);
+            match event {
+                rendersystem::RenderEvent::Resized(..) => resized = true,
+                rendersystem::RenderEvent::DeviceLost => error!("Rendering stopped, device lost"),
+                _ => {}
+            }
+        }
+        if resized {
+            self.render.window_resized();
+            return;
+        }
+        let (width, height) = self.video.get_size();
+        if !self.video.focused() || self.video.is_minimized() || width == 0 || height == 0 {
+            self.skip_frame();
             return;
         }
 
-        if self.last_time == 0 {
-            let now = chrono::Local::now().timestamp_millis();
+        let now = chrono::Local::now().timestamp_millis();
+        if self.last_time != 0 {
             self.delta = now - self.last_time;
             self.runtime += self.delta;
             self.fps = if self.delta > 0 {
@@ -110,17 +216,55 @@ impl State {
             } else {
                 f64::INFINITY
             };
-
-            self.last_time = now;
         } else {
             self.start_time = chrono::Local::now().timestamp();
         }
+        self.last_time = now;
+
+        match self.fixed_timestep {
+            Some(step) => {
+                self.accumulator += self.delta_time();
+                let mut steps = 0;
+                while self.accumulator >= step {
+                    if steps == MAX_FIXED_STEPS {
+                        debug!(
+                            "Dropping {:.3}s of fixed updates, more than {MAX_FIXED_STEPS} steps \
+                             behind",
+                            self.accumulator
+                        );
+                        self.accumulator %= step;
+                        break;
+                    }
+                    tick(self, step);
+                    self.accumulator -= step;
+                    steps += 1;
+                }
+                self.fixed_alpha = self.accumulator / step;
+            }
+            None => tick(self, self.delta_time()),
+        }
 
         self.render.begin_cmds(&self.video);
 
         self.render.present();
     }
 
+    // Nothing runs while paused, so the time goes nowhere instead of being one long frame later
+    fn skip_frame(&mut self) {
+        if self.last_time != 0 {
+            self.last_time = chrono::Local::now().timestamp_millis();
+        }
+        self.render.skip_frame();
+        thread::sleep(SKIPPED_FRAME_SLEEP);
+    }
+
+    pub fn run_benchmark(&mut self, frame_count: u32) -> rendersystem::BenchmarkStats {
+        if let Err(err) = self.render.load_resources() {
+            error!("Failed to load resources for benchmark: {err:?}");
+        }
+        self.render.run_benchmark(&self.video, frame_count)
+    }
+
     pub fn shutdown(mut self) {
         info!("Engine shutdown started");
 
@@ -130,13 +274,55 @@ impl State {
         info!("Engine shutdown succeeded");
     }
 
-    pub fn video(&mut self) -> &mut platform::video::State {
+    // Seconds since the last frame
+    pub fn delta_time(&self) -> f32 {
+        self.delta as f32 / 1000.0
+    }
+
+    // In seconds, None goes back to one update per frame
+    pub fn set_fixed_timestep(&mut self, timestep: Option<f32>) {
+        let timestep = timestep.filter(|timestep| *timestep > 0.0);
+        info!("Changing fixed timestep to {timestep:?}");
+        self.fixed_timestep = timestep;
+        self.accumulator = 0.0;
+        self.fixed_alpha = 1.0;
+    }
+
+    pub fn fixed_timestep(&self) -> Option<f32> {
+        self.fixed_timestep
+    }
+
+    // How far between the last fixed step and the next one this frame is, for interpolating what
+    // gets rendered. Always 1 without a fixed timestep.
+    pub fn fixed_alpha(&self) -> f32 {
+        self.fixed_alpha
+    }
+
+    pub fn video(&mut self) -> &mut Video {
         &mut self.video
     }
 
     pub fn render(&mut self) -> &mut rendersystem::State {
         &mut self.render
     }
+
+    // Adds a directory to search for game files, higher priorities are searched first and the
+    // game directory is 0
+    pub fn mount(&mut self, path: &str, priority: i32) {
+        self.vfs.mount(path, priority);
+    }
+
+    pub fn vfs(&self) -> &vfs::Vfs {
+        &self.vfs
+    }
+
+    pub fn collision(&mut self) -> &mut collision::Broadphase {
+        &mut self.collision
+    }
+
+    pub fn overlaps(&self, node: collision::NodeId) -> Vec<collision::NodeId> {
+        self.collision.overlaps(node)
+    }
 }
 
 use crate::GAME_NAME;
@@ -0,0 +1,262 @@
+use image::GenericImage;
+use log::{debug, error, info};
+use nalgebra::*;
+use std::collections::HashMap;
+
+use super::{render_impl, TextureColorSpace};
+
+// Big enough for lots of UI elements, small enough for any Vulkan GPU (the spec requires 4096)
+pub const ATLAS_PAGE_SIZE: u32 = 2048;
+
+// Space between images so filtering doesn't bleed neighbours into each other
+const ATLAS_PADDING: u32 = 1;
+
+#[derive(Debug)]
+pub enum AtlasError {
+    TooLarge {
+        name: String,
+        width: u32,
+        height: u32,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AtlasRegion {
+    page: usize,
+    uv_min: Vector2<f32>,
+    uv_max: Vector2<f32>,
+}
+
+impl AtlasRegion {
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    pub fn uv_min(&self) -> Vector2<f32> {
+        self.uv_min
+    }
+
+    pub fn uv_max(&self) -> Vector2<f32> {
+        self.uv_max
+    }
+}
+
+// Bottom-left skyline packer, the skyline is a list of (x, y, width) segments covering the page
+struct Skyline {
+    segments: Vec<(u32, u32, u32)>,
+}
+
+impl Skyline {
+    fn new() -> Self {
+        Self {
+            segments: vec![(0, 0, ATLAS_PAGE_SIZE)],
+        }
+    }
+
+    // Height the rectangle would sit at if placed at the start of the given segment
+    fn fit(&self, index: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.segments[index].0;
+        if x + width > ATLAS_PAGE_SIZE {
+            return None;
+        }
+
+        let mut y = 0;
+        let mut remaining = width as i64;
+        for &(_, segment_y, segment_width) in &self.segments[index..] {
+            if remaining <= 0 {
+                break;
+            }
+            y = y.max(segment_y);
+            remaining -= segment_width as i64;
+        }
+
+        if y + height > ATLAS_PAGE_SIZE {
+            None
+        } else {
+            Some(y)
+        }
+    }
+
+    fn insert(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        // lowest top edge first, then the narrowest segment to waste less space
+        let (index, y) = (0..self.segments.len())
+            .filter_map(|index| self.fit(index, width, height).map(|y| (index, y)))
+            .min_by_key(|&(index, y)| (y + height, self.segments[index].2))?;
+        let x = self.segments[index].0;
+
+        self.segments.insert(index, (x, y + height, width));
+        let right = x + width;
+        while index + 1 < self.segments.len() {
+            let (next_x, next_y, next_width) = self.segments[index + 1];
+            if next_x >= right {
+                break;
+            }
+            let next_right = next_x + next_width;
+            if next_right <= right {
+                self.segments.remove(index + 1);
+            } else {
+                self.segments[index + 1] = (right, next_y, next_right - right);
+                break;
+            }
+        }
+
+        let mut i = 0;
+        while i + 1 < self.segments.len() {
+            if self.segments[i].1 == self.segments[i + 1].1 {
+                self.segments[i].2 += self.segments[i + 1].2;
+                self.segments.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+
+        Some((x, y))
+    }
+}
+
+pub struct TextureAtlas {
+    name: String,
+    pages: Vec<image::RgbaImage>,
+    regions: HashMap<String, AtlasRegion>,
+    // One per page, uploaded with the other textures
+    handles: Vec<render_impl::TextureData>,
+}
+
+impl TextureAtlas {
+    pub fn new(name: &str, images: Vec<(String, image::RgbaImage)>) -> Result<Self, AtlasError> {
+        info!("Packing {} image(s) into atlas {name}", images.len());
+
+        let mut images = images;
+        for (image_name, image) in &images {
+            let (width, height) = image.dimensions();
+            if width + ATLAS_PADDING * 2 > ATLAS_PAGE_SIZE
+                || height + ATLAS_PADDING * 2 > ATLAS_PAGE_SIZE
+            {
+                error!(
+                    "Image {image_name} ({width}x{height}) is too large for a \
+                     {ATLAS_PAGE_SIZE}x{ATLAS_PAGE_SIZE} atlas page"
+                );
+                return Err(AtlasError::TooLarge {
+                    name: image_name.clone(),
+                    width,
+                    height,
+                });
+            }
+        }
+
+        // tallest first packs a lot tighter
+        images.sort_by_key(|(_, image)| std::cmp::Reverse(image.height()));
+
+        let mut pages: Vec<(Skyline, image::RgbaImage)> = Vec::new();
+        let mut regions = HashMap::new();
+        for (image_name, image) in images {
+            let (width, height) = image.dimensions();
+            let padded = (width + ATLAS_PADDING * 2, height + ATLAS_PADDING * 2);
+
+            let mut placed = None;
+            for (page, (skyline, _)) in pages.iter_mut().enumerate() {
+                if let Some(position) = skyline.insert(padded.0, padded.1) {
+                    placed = Some((page, position));
+                    break;
+                }
+            }
+            let (page, (x, y)) = match placed {
+                Some(placed) => placed,
+                None => {
+                    debug!("Adding page {} to atlas {name}", pages.len());
+                    let mut skyline = Skyline::new();
+                    // can't fail, the size was checked above
+                    let position = skyline.insert(padded.0, padded.1).unwrap();
+                    pages.push((
+                        skyline,
+                        image::RgbaImage::new(ATLAS_PAGE_SIZE, ATLAS_PAGE_SIZE),
+                    ));
+                    (pages.len() - 1, position)
+                }
+            };
+
+            let (x, y) = (x + ATLAS_PADDING, y + ATLAS_PADDING);
+            // can't fail either, the skyline never goes past the edges
+            pages[page].1.copy_from(&image, x, y).unwrap();
+
+            let size = ATLAS_PAGE_SIZE as f32;
+            regions.insert(
+                image_name,
+                AtlasRegion {
+                    page,
+                    uv_min: Vector2::new(x as f32 / size, y as f32 / size),
+                    uv_max: Vector2::new((x + width) as f32 / size, (y + height) as f32 / size),
+                },
+            );
+        }
+
+        info!(
+            "Packed atlas {name} into {} page(s) successfully",
+            pages.len()
+        );
+
+        let handles = pages
+            .iter()
+            .map(|_| render_impl::TextureData::default())
+            .collect();
+        Ok(Self {
+            name: String::from(name),
+            pages: pages.into_iter().map(|(_, page)| page).collect(),
+            regions,
+            handles,
+        })
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn pages(&self) -> &Vec<image::RgbaImage> {
+        &self.pages
+    }
+
+    pub fn region(&self, name: &str) -> Option<AtlasRegion> {
+        self.regions.get(name).copied()
+    }
+
+    pub fn regions(&self) -> &HashMap<String, AtlasRegion> {
+        &self.regions
+    }
+
+    // Pages are small enough to not bother streaming, and without mips so filtering can't blend
+    // neighbouring images together
+    // Every page gets a go even if one fails, the failed ones are drawn with the default texture
+    pub(super) fn upload(&mut self, backend: &mut render_impl::State) -> Result<(), ()> {
+        let mut result = Ok(());
+        for (page, (image, handle)) in self.pages.iter().zip(&mut self.handles).enumerate() {
+            let name = format!("{} page {page}", self.name);
+            if let Err(err) = handle.upload(
+                backend,
+                &name,
+                TextureColorSpace::Srgb,
+                0,
+                std::slice::from_ref(image),
+            ) {
+                error!("Failed to upload atlas {name}: {err}");
+                result = Err(());
+            }
+        }
+        result
+    }
+
+    pub(super) fn write_descriptors(&self, backend: &render_impl::State) {
+        for handle in &self.handles {
+            handle.write_descriptor(backend);
+        }
+    }
+
+    pub(super) fn destroy(&mut self, backend: &render_impl::State) {
+        for handle in &mut self.handles {
+            handle.destroy(backend);
+        }
+    }
+
+    pub(super) fn page_handle(&self, page: usize) -> Option<&render_impl::TextureData> {
+        self.handles.get(page)
+    }
+}
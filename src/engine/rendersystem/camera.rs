@@ -0,0 +1,106 @@
+use super::{CoordinateConvention, UniformData};
+use nalgebra::*;
+
+// The projection is made for each viewport, so the aspect ratio always matches what it's drawn into
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: Point3<f32>,
+    pub target: Point3<f32>,
+    pub up: Vector3<f32>,
+    // Vertical, in radians
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: Point3::new(0.0, 0.0, 5.0),
+            target: Point3::origin(),
+            up: CoordinateConvention::up(),
+            fov: 70.0f32.to_radians(),
+            near: 0.1,
+            far: 1000.0,
+        }
+    }
+}
+
+impl Camera {
+    // Looking at target with the usual up, fov is vertical and in radians
+    pub fn from_perspective(
+        position: Point3<f32>,
+        target: Point3<f32>,
+        fov: f32,
+        near: f32,
+        far: f32,
+    ) -> Self {
+        Self {
+            position,
+            target,
+            up: CoordinateConvention::up(),
+            fov,
+            near,
+            far,
+        }
+    }
+
+    // See coordinates.rs for which way everything points
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        CoordinateConvention::view(&self.position, &self.target, &self.up)
+    }
+
+    // In Vulkan's clip space, so Y is flipped and depth goes from 0 to 1
+    pub fn projection_matrix(&self, aspect: f32) -> Matrix4<f32> {
+        CoordinateConvention::perspective(aspect, self.fov, self.near, self.far)
+    }
+
+    pub fn uniform_data(&self, aspect: f32) -> UniformData {
+        UniformData {
+            model: Matrix4::identity(),
+            view: self.view_matrix(),
+            projection: self.projection_matrix(aspect),
+            logarithmic_depth: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_matrices_match_hand_computed() {
+        let camera = Camera::from_perspective(
+            Point3::new(0.0, 0.0, 5.0),
+            Point3::origin(),
+            std::f32::consts::FRAC_PI_2,
+            1.0,
+            3.0,
+        );
+
+        // 90 degrees makes the focal length 1, so x is just divided by the aspect ratio. Y is
+        // flipped, and depth is far / (near - far) * z + near * far / (near - far) so it goes from
+        // 0 at z = -near to 1 at z = -far after dividing by w = -z.
+        #[rustfmt::skip]
+        let projection = Matrix4::new(
+            0.5, 0.0, 0.0, 0.0,
+            0.0, -1.0, 0.0, 0.0,
+            0.0, 0.0, -1.5, -1.5,
+            0.0, 0.0, -1.0, 0.0,
+        );
+        assert!(
+            (camera.projection_matrix(2.0) - projection).abs().max() < 1e-6,
+            "{:?} isn't {projection:?}",
+            camera.projection_matrix(2.0)
+        );
+
+        // nothing to turn, just moving the world 5 away from the camera
+        let view = Matrix4::new_translation(&Vector3::new(0.0, 0.0, -5.0));
+        assert!(
+            (camera.view_matrix() - view).abs().max() < 1e-6,
+            "{:?} isn't {view:?}",
+            camera.view_matrix()
+        );
+    }
+}
@@ -0,0 +1,77 @@
+// Colors are given in sRGB unless a function says otherwise (that's what colour pickers and
+// image editors use), and kept linear since that's what shading and blending need. The backend
+// converts back to sRGB if the render target doesn't do it by itself.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Color {
+    r: f32,
+    g: f32,
+    b: f32,
+    a: f32,
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl Color {
+    pub const BLACK: Self = Self::from_linear(0.0, 0.0, 0.0, 1.0);
+    pub const WHITE: Self = Self::from_linear(1.0, 1.0, 1.0, 1.0);
+
+    pub fn from_srgb8(r: u8, g: u8, b: u8) -> Self {
+        Self::from_srgba8(r, g, b, u8::MAX)
+    }
+
+    // Alpha is always linear
+    pub fn from_srgba8(r: u8, g: u8, b: u8, a: u8) -> Self {
+        let max = u8::MAX as f32;
+        Self::from_srgb(
+            r as f32 / max,
+            g as f32 / max,
+            b as f32 / max,
+            a as f32 / max,
+        )
+    }
+
+    pub fn from_srgb(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self {
+            r: srgb_to_linear(r),
+            g: srgb_to_linear(g),
+            b: srgb_to_linear(b),
+            a,
+        }
+    }
+
+    pub const fn from_linear(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub fn to_linear(self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+
+    pub fn to_srgb(self) -> [f32; 4] {
+        [
+            linear_to_srgb(self.r),
+            linear_to_srgb(self.g),
+            linear_to_srgb(self.b),
+            self.a,
+        ]
+    }
+
+    pub fn to_srgb8(self) -> [u8; 4] {
+        self.to_srgb()
+            .map(|value| (value.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8)
+    }
+}
@@ -0,0 +1,164 @@
+use nalgebra::*;
+
+// The one coordinate convention everything uses, so nothing has to guess at signs:
+//
+// - World and view space are right handed with Y up. The camera looks down -Z, +X is right.
+// - Clip space is Vulkan's. Y points down and depth goes from 0 at the near plane to 1 at the far
+//   plane, where nalgebra (like OpenGL) has Y up and depth from -1 to 1. The projections here
+//   already account for that, so shaders just do projection * view * model * position.
+// - Triangles that are counter clockwise as the camera sees them are front faces, which is what
+//   the pipelines' front face means with the Y flip.
+//
+// Anything making a view, projection or model matrix should go through here instead of calling
+// nalgebra directly.
+pub struct CoordinateConvention;
+
+impl CoordinateConvention {
+    pub fn up() -> Vector3<f32> {
+        Vector3::y()
+    }
+
+    pub fn right() -> Vector3<f32> {
+        Vector3::x()
+    }
+
+    // What an unrotated camera looks at
+    pub fn forward() -> Vector3<f32> {
+        -Vector3::z()
+    }
+
+    // Takes nalgebra's (OpenGL's) clip space to Vulkan's
+    pub fn clip_correction() -> Matrix4<f32> {
+        #[rustfmt::skip]
+        let clip = Matrix4::new(
+            1.0, 0.0, 0.0, 0.0,
+            0.0, -1.0, 0.0, 0.0,
+            0.0, 0.0, 0.5, 0.5,
+            0.0, 0.0, 0.0, 1.0,
+        );
+        clip
+    }
+
+    pub fn view(position: &Point3<f32>, target: &Point3<f32>, up: &Vector3<f32>) -> Matrix4<f32> {
+        Matrix4::look_at_rh(position, target, up)
+    }
+
+    // Vertical fov in radians
+    pub fn perspective(aspect: f32, fov: f32, near: f32, far: f32) -> Matrix4<f32> {
+        Self::clip_correction() * Matrix4::new_perspective(aspect, fov, near, far)
+    }
+
+    // The box is in view space, so near and far are distances in front of the camera like with
+    // perspective
+    pub fn orthographic(
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        near: f32,
+        far: f32,
+    ) -> Matrix4<f32> {
+        Self::clip_correction() * Matrix4::new_orthographic(left, right, bottom, top, near, far)
+    }
+
+    // Scaled, then rotated, then moved
+    pub fn model(
+        translation: &Vector3<f32>,
+        rotation: &UnitQuaternion<f32>,
+        scale: &Vector3<f32>,
+    ) -> Matrix4<f32> {
+        Matrix4::new_translation(translation)
+            * rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip_to_ndc(matrix: &Matrix4<f32>, point: Point3<f32>) -> Vector3<f32> {
+        let clip = matrix * point.to_homogeneous();
+        clip.xyz() / clip.w
+    }
+
+    fn assert_near(actual: Vector3<f32>, expected: Vector3<f32>) {
+        assert!(
+            (actual - expected).norm() < 1e-5,
+            "{actual:?} isn't {expected:?}"
+        );
+    }
+
+    #[test]
+    fn perspective_is_vulkan_clip_space() {
+        let projection =
+            CoordinateConvention::perspective(1.0, std::f32::consts::FRAC_PI_2, 1.0, 10.0);
+
+        // depth is 0 on the near plane and 1 on the far plane, straight ahead is the middle
+        assert_near(
+            clip_to_ndc(&projection, Point3::new(0.0, 0.0, -1.0)),
+            Vector3::new(0.0, 0.0, 0.0),
+        );
+        assert_near(
+            clip_to_ndc(&projection, Point3::new(0.0, 0.0, -10.0)),
+            Vector3::new(0.0, 0.0, 1.0),
+        );
+        // with a 90 degree fov the corner at the near plane is the corner of the screen, and up in
+        // the world is up on screen, which is -Y in Vulkan
+        assert_near(
+            clip_to_ndc(&projection, Point3::new(1.0, 1.0, -1.0)),
+            Vector3::new(1.0, -1.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn orthographic_is_vulkan_clip_space() {
+        let projection = CoordinateConvention::orthographic(-2.0, 2.0, -1.0, 1.0, 0.0, 4.0);
+
+        assert_near(
+            clip_to_ndc(&projection, Point3::new(2.0, 1.0, 0.0)),
+            Vector3::new(1.0, -1.0, 0.0),
+        );
+        assert_near(
+            clip_to_ndc(&projection, Point3::new(-1.0, -0.5, -2.0)),
+            Vector3::new(-0.5, 0.5, 0.5),
+        );
+    }
+
+    #[test]
+    fn view_looks_down_forward() {
+        let view = CoordinateConvention::view(
+            &Point3::new(0.0, 0.0, 5.0),
+            &Point3::origin(),
+            &CoordinateConvention::up(),
+        );
+
+        // the target ends up straight ahead, and right and up stay right and up
+        assert_near(
+            view.transform_point(&Point3::origin()).coords,
+            CoordinateConvention::forward() * 5.0,
+        );
+        assert_near(
+            view.transform_point(&Point3::new(1.0, 2.0, 0.0)).coords,
+            CoordinateConvention::right()
+                + CoordinateConvention::up() * 2.0
+                + CoordinateConvention::forward() * 5.0,
+        );
+    }
+
+    #[test]
+    fn model_scales_then_rotates_then_moves() {
+        let model = CoordinateConvention::model(
+            &Vector3::new(10.0, 0.0, 0.0),
+            &UnitQuaternion::from_axis_angle(&Vector3::z_axis(), std::f32::consts::FRAC_PI_2),
+            &Vector3::new(2.0, 1.0, 1.0),
+        );
+
+        // (1, 0, 0) is scaled to (2, 0, 0), turned a quarter counter clockwise to (0, 2, 0) and
+        // then moved
+        assert_near(
+            model.transform_point(&Point3::new(1.0, 0.0, 0.0)).coords,
+            Vector3::new(10.0, 2.0, 0.0),
+        );
+    }
+}
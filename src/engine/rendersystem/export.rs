@@ -0,0 +1,412 @@
+// Writing models back out, for tools and procedural geometry. Everything gets written in the
+// engine's space, which is already what OBJ and glTF expect, apart from glTF's V going down.
+
+use super::{Topology, Vertex, VertexFormat, PRIMITIVE_RESTART_INDEX};
+use nalgebra::*;
+use std::fmt::Write;
+
+// Neither format has restarts (glTF doesn't allow the index at all), so strips become lists.
+// Every other triangle in a strip is backwards, and the degenerate ones used to join strips are
+// dropped.
+pub fn triangle_list(indices: &[u32], topology: Topology) -> Vec<u32> {
+    if topology != Topology::TriangleStrip {
+        return Vec::from(indices);
+    }
+
+    let mut list = Vec::new();
+    for strip in indices.split(|&index| index == PRIMITIVE_RESTART_INDEX) {
+        for (i, triangle) in strip.windows(3).enumerate() {
+            let (a, b, c) = (triangle[0], triangle[1], triangle[2]);
+            if a == b || b == c || a == c {
+                continue;
+            }
+            if i % 2 == 0 {
+                list.extend([a, b, c]);
+            } else {
+                list.extend([b, a, c]);
+            }
+        }
+    }
+
+    list
+}
+
+fn topology_after_export(topology: Topology) -> Topology {
+    match topology {
+        Topology::TriangleStrip => Topology::TriangleList,
+        topology => topology,
+    }
+}
+
+// Colours go after the position like tobj reads them, without alpha. There's only one set of
+// texture coordinates, so the second one is lost.
+pub fn obj(name: &str, vertices: &[Vertex], indices: &[u32], topology: Topology) -> String {
+    let mut obj = String::new();
+    let format = VertexFormat::of(vertices);
+    let indices = triangle_list(indices, topology);
+
+    writeln!(obj, "# Exported from purpl").unwrap();
+    writeln!(obj, "o {name}").unwrap();
+    for vertex in vertices {
+        let position = vertex.position;
+        write!(obj, "v {} {} {}", position.x, position.y, position.z).unwrap();
+        if format.color {
+            let color = vertex.color.unwrap_or(Vector4::new(1.0, 1.0, 1.0, 1.0));
+            write!(obj, " {} {} {}", color.x, color.y, color.z).unwrap();
+        }
+        writeln!(obj).unwrap();
+    }
+    for vertex in vertices {
+        let texture_coordinate = vertex.texture_coordinate;
+        writeln!(obj, "vt {} {}", texture_coordinate.x, texture_coordinate.y).unwrap();
+    }
+    for vertex in vertices {
+        let normal = vertex.normal;
+        writeln!(obj, "vn {} {} {}", normal.x, normal.y, normal.z).unwrap();
+    }
+
+    // OBJ counts from 1, and every vertex has all three
+    let corner = |index: u32| format!("{0}/{0}/{0}", index + 1);
+    let (keyword, size) = match topology_after_export(topology) {
+        Topology::TriangleList => ("f", 3),
+        Topology::LineList => ("l", 2),
+        Topology::PointList => ("p", 1),
+        Topology::TriangleStrip => unreachable!(),
+    };
+    for primitive in indices.chunks_exact(size) {
+        let corners: Vec<String> = primitive.iter().map(|&index| corner(index)).collect();
+        writeln!(obj, "{keyword} {}", corners.join(" ")).unwrap();
+    }
+
+    obj
+}
+
+// A single .gltf with the buffer embedded, so there's no .bin to keep next to it. Each attribute
+// gets its own buffer view.
+pub fn gltf(name: &str, vertices: &[Vertex], indices: &[u32], topology: Topology) -> String {
+    const FLOAT: u32 = 5126;
+    const UNSIGNED_INT: u32 = 5125;
+    const ARRAY_BUFFER: u32 = 34962;
+    const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+    let format = VertexFormat::of(vertices);
+    let indices = triangle_list(indices, topology);
+    let mode = match topology_after_export(topology) {
+        Topology::PointList => 0,
+        Topology::LineList => 1,
+        Topology::TriangleList => 4,
+        Topology::TriangleStrip => unreachable!(),
+    };
+
+    let mut buffer = Vec::new();
+    let mut buffer_views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut add_accessor = |buffer: &mut Vec<u8>, data: Vec<u8>, accessor: String, target| {
+        buffer_views.push(format!(
+            r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{target}}}"#,
+            buffer.len(),
+            data.len()
+        ));
+        buffer.extend(data);
+        accessors.push(format!(
+            r#"{{"bufferView":{},{accessor}}}"#,
+            buffer_views.len() - 1
+        ));
+        accessors.len() - 1
+    };
+    let float_accessor = |kind: &str| {
+        format!(
+            r#""componentType":{FLOAT},"count":{},"type":"{kind}""#,
+            vertices.len()
+        )
+    };
+    // V goes down in glTF
+    let flip = |uv: Vector2<f32>| [uv.x, 1.0 - uv.y];
+
+    let mut attributes = Vec::new();
+
+    // Positions need bounds
+    let (min, max) = vertices.iter().fold(
+        (
+            Vector3::repeat(f32::INFINITY),
+            Vector3::repeat(f32::NEG_INFINITY),
+        ),
+        |(min, max), vertex| (min.inf(&vertex.position), max.sup(&vertex.position)),
+    );
+    let position = add_accessor(
+        &mut buffer,
+        floats(
+            vertices
+                .iter()
+                .flat_map(|vertex| <[f32; 3]>::from(vertex.position)),
+        ),
+        format!(
+            r#"{},"min":[{},{},{}],"max":[{},{},{}]"#,
+            float_accessor("VEC3"),
+            min.x,
+            min.y,
+            min.z,
+            max.x,
+            max.y,
+            max.z
+        ),
+        ARRAY_BUFFER,
+    );
+    attributes.push(format!(r#""POSITION":{position}"#));
+    let normal = add_accessor(
+        &mut buffer,
+        floats(
+            vertices
+                .iter()
+                .flat_map(|vertex| <[f32; 3]>::from(vertex.normal)),
+        ),
+        float_accessor("VEC3"),
+        ARRAY_BUFFER,
+    );
+    attributes.push(format!(r#""NORMAL":{normal}"#));
+    let texture_coordinate = add_accessor(
+        &mut buffer,
+        floats(
+            vertices
+                .iter()
+                .flat_map(|vertex| flip(vertex.texture_coordinate)),
+        ),
+        float_accessor("VEC2"),
+        ARRAY_BUFFER,
+    );
+    attributes.push(format!(r#""TEXCOORD_0":{texture_coordinate}"#));
+    if format.texture_coordinate_1 {
+        let texture_coordinate_1 = add_accessor(
+            &mut buffer,
+            floats(vertices.iter().flat_map(|vertex| {
+                flip(vertex.texture_coordinate_1.unwrap_or_else(Vector2::zeros))
+            })),
+            float_accessor("VEC2"),
+            ARRAY_BUFFER,
+        );
+        attributes.push(format!(r#""TEXCOORD_1":{texture_coordinate_1}"#));
+    }
+    if format.color {
+        let color = add_accessor(
+            &mut buffer,
+            floats(vertices.iter().flat_map(|vertex| {
+                <[f32; 4]>::from(vertex.color.unwrap_or(Vector4::new(1.0, 1.0, 1.0, 1.0)))
+            })),
+            float_accessor("VEC4"),
+            ARRAY_BUFFER,
+        );
+        attributes.push(format!(r#""COLOR_0":{color}"#));
+    }
+    let indices = add_accessor(
+        &mut buffer,
+        indices
+            .iter()
+            .flat_map(|index| index.to_le_bytes())
+            .collect(),
+        format!(
+            r#""componentType":{UNSIGNED_INT},"count":{},"type":"SCALAR""#,
+            indices.len()
+        ),
+        ELEMENT_ARRAY_BUFFER,
+    );
+
+    let name = json_string(name);
+    format!(
+        concat!(
+            r#"{{"asset":{{"version":"2.0","generator":"purpl"}},"#,
+            r#""scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0,"name":{name}}}],"#,
+            r#""meshes":[{{"name":{name},"primitives":[{{"attributes":{{{attributes}}},"#,
+            r#""indices":{indices},"mode":{mode}}}]}}],"#,
+            r#""buffers":[{{"byteLength":{length},"#,
+            r#""uri":"data:application/octet-stream;base64,{data}"}}],"#,
+            r#""bufferViews":[{buffer_views}],"accessors":[{accessors}]}}"#,
+            "\n"
+        ),
+        name = name,
+        attributes = attributes.join(","),
+        indices = indices,
+        mode = mode,
+        length = buffer.len(),
+        data = base64(&buffer),
+        buffer_views = buffer_views.join(","),
+        accessors = accessors.join(","),
+    )
+}
+
+fn floats(values: impl Iterator<Item = f32>) -> Vec<u8> {
+    values.flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::from("\"");
+    for character in string.chars() {
+        match character {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            character if character.is_control() => {
+                write!(json, "\\u{:04x}", character as u32).unwrap()
+            }
+            character => json.push(character),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    // Position, texture coordinate, normal and colour of each corner of each primitive, which is
+    // what has to survive even if the vertices get reordered or merged
+    type Corner = [f32; 11];
+
+    fn corners(vertices: &[Vertex], indices: &[u32]) -> Vec<Corner> {
+        indices
+            .iter()
+            .map(|&index| {
+                let vertex = &vertices[index as usize];
+                let (position, uv, normal) =
+                    (vertex.position, vertex.texture_coordinate, vertex.normal);
+                let color = vertex.color.unwrap();
+                [
+                    position.x, position.y, position.z, uv.x, uv.y, normal.x, normal.y, normal.z,
+                    color.x, color.y, color.z,
+                ]
+            })
+            .collect()
+    }
+
+    fn reimported_corners(obj: &str) -> Vec<Corner> {
+        let (models, _) = tobj::load_obj_buf(
+            &mut io::Cursor::new(obj.as_bytes()),
+            &tobj::GPU_LOAD_OPTIONS,
+            |_| Err(tobj::LoadError::OpenFileFailed),
+        )
+        .unwrap();
+        assert_eq!(models.len(), 1);
+        let mesh = &models[0].mesh;
+        mesh.indices
+            .iter()
+            .map(|&index| {
+                let (i, j) = (index as usize * 3, index as usize * 2);
+                [
+                    mesh.positions[i],
+                    mesh.positions[i + 1],
+                    mesh.positions[i + 2],
+                    mesh.texcoords[j],
+                    mesh.texcoords[j + 1],
+                    mesh.normals[i],
+                    mesh.normals[i + 1],
+                    mesh.normals[i + 2],
+                    mesh.vertex_color[i],
+                    mesh.vertex_color[i + 1],
+                    mesh.vertex_color[i + 2],
+                ]
+            })
+            .collect()
+    }
+
+    fn reimported_gltf_corners(gltf: &str) -> Vec<Corner> {
+        let (document, buffers, _) = gltf::import_slice(gltf.as_bytes()).unwrap();
+        assert_eq!(document.meshes().len(), 1);
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        assert_eq!(primitive.mode(), gltf::mesh::Mode::Triangles);
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions: Vec<_> = reader.read_positions().unwrap().collect();
+        let uvs: Vec<_> = reader.read_tex_coords(0).unwrap().into_f32().collect();
+        let normals: Vec<_> = reader.read_normals().unwrap().collect();
+        let colors: Vec<_> = reader.read_colors(0).unwrap().into_rgba_f32().collect();
+        reader
+            .read_indices()
+            .unwrap()
+            .into_u32()
+            .map(|index| {
+                let index = index as usize;
+                let ([x, y, z], [u, v], [nx, ny, nz], [r, g, b, _]) =
+                    (positions[index], uvs[index], normals[index], colors[index]);
+                // V goes down in glTF
+                [x, y, z, u, 1.0 - v, nx, ny, nz, r, g, b]
+            })
+            .collect()
+    }
+
+    // A few triangles with everything different at each vertex, as a list and as a strip
+    fn round_trip_meshes() -> (Vec<Vertex>, [(Topology, Vec<u32>); 2]) {
+        let vertices = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (0.5, 2.0)]
+            .iter()
+            .enumerate()
+            .map(|(i, &(x, y))| {
+                Vertex::new(
+                    Vector3::new(x, y, i as f32 * 0.1),
+                    Vector2::new(x, y / 2.0),
+                    Vector3::new(x, y, 1.0).normalize(),
+                )
+                .with_color(Vector4::new(x, y / 2.0, 0.3, 1.0))
+            })
+            .collect();
+
+        (
+            vertices,
+            [
+                (Topology::TriangleList, vec![0, 1, 2, 2, 3, 0, 3, 2, 4]),
+                (
+                    Topology::TriangleStrip,
+                    vec![0, 1, 3, 2, PRIMITIVE_RESTART_INDEX, 3, 2, 4],
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn obj_round_trips() {
+        let (vertices, meshes) = round_trip_meshes();
+        for (topology, indices) in meshes {
+            let obj = obj("round_trip", &vertices, &indices, topology);
+            let expected = corners(&vertices, &triangle_list(&indices, topology));
+            assert_eq!(expected.len(), 9);
+            assert_eq!(reimported_corners(&obj), expected, "{topology:?}");
+        }
+    }
+
+    #[test]
+    fn gltf_round_trips() {
+        let (vertices, meshes) = round_trip_meshes();
+        for (topology, indices) in meshes {
+            let gltf = gltf("round_trip", &vertices, &indices, topology);
+            let expected = corners(&vertices, &triangle_list(&indices, topology));
+            assert_eq!(expected.len(), 9);
+            assert_eq!(reimported_gltf_corners(&gltf), expected, "{topology:?}");
+        }
+    }
+}
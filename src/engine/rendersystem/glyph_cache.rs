@@ -0,0 +1,353 @@
+use image::GenericImage;
+use log::{debug, trace, warn};
+use nalgebra::*;
+use std::collections::HashMap;
+
+// Persistent glyph atlas for text. Glyphs get rasterized the first time they're used and stay in
+// the atlas until it's full and they're the least recently used, so text that doesn't change only
+// needs its quads rebuilt, never the atlas. The rasterizer is passed in, the cache doesn't care
+// where the glyphs come from.
+//
+// The atlas is a grid of cells as big as the biggest glyph, which wastes some space but means any
+// cell can be reused for any glyph when evicting. Anything uploading the pages to the GPU only has
+// to copy what drain_dirty returns.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GlyphKey {
+    pub character: char,
+    // Pixel size it was rasterized at, so one cache can hold several sizes
+    pub size: u32,
+}
+
+pub struct RasterizedGlyph {
+    pub image: image::GrayImage,
+    // From the pen position to the top left of the image
+    pub bearing: Vector2<f32>,
+    pub advance: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CachedGlyph {
+    pub page: usize,
+    pub uv_min: Vector2<f32>,
+    pub uv_max: Vector2<f32>,
+    pub size: Vector2<f32>,
+    pub bearing: Vector2<f32>,
+    pub advance: f32,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GlyphCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    // Glyphs that couldn't be rasterized, were too big or had nowhere to go
+    pub rejected: u64,
+    pub evictions: u64,
+    pub glyphs: usize,
+    pub pages: usize,
+}
+
+// A rectangle of a page that changed since the last drain, in pixels
+#[derive(Clone, Copy, Debug)]
+pub struct DirtyRegion {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+struct Cell {
+    key: Option<GlyphKey>,
+    last_used: u64,
+}
+
+struct Entry {
+    cell: usize,
+    glyph: CachedGlyph,
+}
+
+pub struct GlyphCache {
+    page_size: u32,
+    cell_size: u32,
+    max_pages: usize,
+
+    pages: Vec<image::GrayImage>,
+    cells: Vec<Cell>,
+    glyphs: HashMap<GlyphKey, Entry>,
+    dirty: Vec<DirtyRegion>,
+
+    frame: u64,
+    stats: GlyphCacheStats,
+}
+
+impl GlyphCache {
+    // Glyphs bigger than cell_size don't get cached. Once max_pages are full, glyphs not used
+    // this frame get evicted instead of adding pages.
+    pub fn new(page_size: u32, cell_size: u32, max_pages: usize) -> Self {
+        if page_size == 0 {
+            warn!("Glyph cache pages can't be empty, making them 1x1");
+        }
+        let page_size = page_size.max(1);
+        let cell_size = cell_size.clamp(1, page_size);
+        debug!(
+            "Creating glyph cache with {page_size}x{page_size} pages of {cell_size}x{cell_size} \
+             cells, up to {max_pages} page(s)"
+        );
+
+        Self {
+            page_size,
+            cell_size,
+            max_pages: max_pages.max(1),
+
+            pages: Vec::new(),
+            cells: Vec::new(),
+            glyphs: HashMap::new(),
+            dirty: Vec::new(),
+
+            frame: 0,
+            stats: GlyphCacheStats::default(),
+        }
+    }
+
+    // Glyphs used since the last call are safe from eviction
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn get(
+        &mut self,
+        key: GlyphKey,
+        rasterize: impl FnOnce() -> Option<RasterizedGlyph>,
+    ) -> Option<CachedGlyph> {
+        if let Some(entry) = self.glyphs.get(&key) {
+            self.stats.hits += 1;
+            self.cells[entry.cell].last_used = self.frame;
+            return Some(entry.glyph);
+        }
+
+        let Some(rasterized) = rasterize() else {
+            self.stats.rejected += 1;
+            return None;
+        };
+        let (width, height) = rasterized.image.dimensions();
+        if width > self.cell_size || height > self.cell_size {
+            warn!(
+                "Glyph {key:?} is {width}x{height}, bigger than the {0}x{0} cells of the glyph \
+                 cache",
+                self.cell_size
+            );
+            self.stats.rejected += 1;
+            return None;
+        }
+
+        let Some(cell) = self.allocate_cell() else {
+            self.stats.rejected += 1;
+            return None;
+        };
+        self.stats.misses += 1;
+        if let Some(old_key) = self.cells[cell].key.take() {
+            trace!("Evicting glyph {old_key:?}");
+            self.glyphs.remove(&old_key);
+            self.stats.evictions += 1;
+        }
+
+        let cells_per_row = self.page_size / self.cell_size;
+        let cells_per_page = (cells_per_row * cells_per_row) as usize;
+        let page = cell / cells_per_page;
+        let index = (cell % cells_per_page) as u32;
+        let x = index % cells_per_row * self.cell_size;
+        let y = index / cells_per_row * self.cell_size;
+
+        // clear what the old glyph left behind in the rest of the cell
+        let cell_image = image::GrayImage::new(self.cell_size, self.cell_size);
+        // can't fail, cells never go past the edges
+        self.pages[page].copy_from(&cell_image, x, y).unwrap();
+        self.pages[page].copy_from(&rasterized.image, x, y).unwrap();
+        self.dirty.push(DirtyRegion {
+            page,
+            x,
+            y,
+            width: self.cell_size,
+            height: self.cell_size,
+        });
+
+        let size = self.page_size as f32;
+        let glyph = CachedGlyph {
+            page,
+            uv_min: Vector2::new(x as f32 / size, y as f32 / size),
+            uv_max: Vector2::new((x + width) as f32 / size, (y + height) as f32 / size),
+            size: Vector2::new(width as f32, height as f32),
+            bearing: rasterized.bearing,
+            advance: rasterized.advance,
+        };
+        self.cells[cell] = Cell {
+            key: Some(key),
+            last_used: self.frame,
+        };
+        self.glyphs.insert(key, Entry { cell, glyph });
+
+        Some(glyph)
+    }
+
+    // An empty cell, then a new page, then the least recently used glyph from an earlier frame
+    fn allocate_cell(&mut self) -> Option<usize> {
+        if let Some(cell) = self.cells.iter().position(|cell| cell.key.is_none()) {
+            return Some(cell);
+        }
+
+        if self.pages.len() < self.max_pages {
+            debug!("Adding page {} to glyph cache", self.pages.len());
+            let cells_per_row = self.page_size / self.cell_size;
+            let first = self.cells.len();
+            self.pages
+                .push(image::GrayImage::new(self.page_size, self.page_size));
+            self.cells
+                .extend((0..cells_per_row * cells_per_row).map(|_| Cell {
+                    key: None,
+                    last_used: 0,
+                }));
+            return Some(first);
+        }
+
+        let cell = self
+            .cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.last_used < self.frame)
+            .min_by_key(|(_, cell)| cell.last_used)
+            .map(|(index, _)| index);
+        if cell.is_none() {
+            warn!("Glyph cache is full of glyphs used this frame");
+        }
+        cell
+    }
+
+    pub fn pages(&self) -> &[image::GrayImage] {
+        &self.pages
+    }
+
+    // What has to be uploaded again since the last call
+    pub fn drain_dirty(&mut self) -> std::vec::Drain<'_, DirtyRegion> {
+        self.dirty.drain(..)
+    }
+
+    pub fn stats(&self) -> GlyphCacheStats {
+        GlyphCacheStats {
+            glyphs: self.glyphs.len(),
+            pages: self.pages.len(),
+            ..self.stats
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A solid square, so every glyph fits the same cells
+    fn square(size: u32) -> Option<RasterizedGlyph> {
+        Some(RasterizedGlyph {
+            image: image::GrayImage::from_pixel(size, size, image::Luma([255])),
+            bearing: Vector2::new(0.0, -(size as f32)),
+            advance: size as f32,
+        })
+    }
+
+    fn key(character: char) -> GlyphKey {
+        GlyphKey {
+            character,
+            size: 16,
+        }
+    }
+
+    // 4 cells a page
+    fn small_cache(max_pages: usize) -> GlyphCache {
+        GlyphCache::new(32, 16, max_pages)
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let mut cache = small_cache(1);
+        let first = cache.get(key('a'), || square(16)).unwrap();
+        let second = cache
+            .get(key('a'), || panic!("cached glyphs aren't rasterized again"))
+            .unwrap();
+        assert_eq!(first.uv_min, second.uv_min);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.rejected), (1, 1, 0));
+        assert_eq!((stats.glyphs, stats.pages), (1, 1));
+        assert_eq!(cache.drain_dirty().count(), 1);
+        assert_eq!(cache.drain_dirty().count(), 0);
+    }
+
+    #[test]
+    fn rejected_glyphs_arent_misses() {
+        let mut cache = small_cache(1);
+        assert!(cache.get(key('a'), || square(17)).is_none());
+        assert!(cache.get(key('b'), || None).is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.rejected), (0, 0, 2));
+        assert_eq!(stats.glyphs, 0);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = small_cache(1);
+        let mut cached = Vec::new();
+        for character in ['a', 'b', 'c', 'd'] {
+            cached.push(cache.get(key(character), || square(16)).unwrap());
+            cache.begin_frame();
+        }
+
+        // a is the oldest but gets used again, so b goes instead
+        cache.get(key('a'), || square(16)).unwrap();
+        let e = cache.get(key('e'), || square(16)).unwrap();
+        assert_eq!(e.uv_min, cached[1].uv_min);
+        assert_eq!(cache.stats().evictions, 1);
+
+        cache.begin_frame();
+        let f = cache.get(key('f'), || square(16)).unwrap();
+        assert_eq!(f.uv_min, cached[2].uv_min);
+        assert!(cache
+            .get(key('a'), || panic!("a should still be cached"))
+            .is_some());
+        assert_eq!(cache.stats().evictions, 2);
+    }
+
+    #[test]
+    fn adds_page_when_full() {
+        let mut cache = small_cache(2);
+        let glyphs: Vec<_> = ['a', 'b', 'c', 'd', 'e']
+            .into_iter()
+            .map(|character| cache.get(key(character), || square(16)).unwrap())
+            .collect();
+        assert!(glyphs[..4].iter().all(|glyph| glyph.page == 0));
+        assert_eq!(glyphs[4].page, 1);
+
+        let stats = cache.stats();
+        assert_eq!((stats.pages, stats.glyphs, stats.evictions), (2, 5, 0));
+        assert_eq!(cache.pages().len(), 2);
+        let dirty: Vec<_> = cache.drain_dirty().collect();
+        assert_eq!(dirty.len(), 5);
+        assert_eq!(dirty[4].page, 1);
+    }
+
+    #[test]
+    fn full_of_glyphs_used_this_frame() {
+        let mut cache = small_cache(1);
+        for character in ['a', 'b', 'c', 'd'] {
+            cache.get(key(character), || square(16)).unwrap();
+        }
+        assert!(cache.get(key('e'), || square(16)).is_none());
+        let stats = cache.stats();
+        assert_eq!((stats.rejected, stats.evictions, stats.glyphs), (1, 0, 4));
+
+        // they're fair game once the frame's over
+        cache.begin_frame();
+        assert!(cache.get(key('e'), || square(16)).is_some());
+        assert_eq!(cache.stats().evictions, 1);
+    }
+}
@@ -0,0 +1,153 @@
+use super::{render_impl, RenderEvent, ShaderError};
+use log::{debug, info};
+use std::{mem, time};
+
+// egui for debug tools. The platform's pointer, focus and window size get fed to it, key codes
+// are platform specific so there's no keyboard input yet. What it draws goes on top of the frame
+// right before it's presented.
+pub struct Gui {
+    context: egui::Context,
+    vertex_binary: Vec<u8>,
+    fragment_binary: Vec<u8>,
+    handle: render_impl::GuiData,
+
+    events: Vec<egui::Event>,
+    pointer: egui::Pos2,
+    focused: bool,
+    start: time::Instant,
+
+    primitives: Vec<egui::ClippedPrimitive>,
+    pixels_per_point: f32,
+}
+
+impl Gui {
+    pub fn new(
+        backend: &render_impl::State,
+        vertex_binary: Vec<u8>,
+        fragment_binary: Vec<u8>,
+    ) -> Result<Self, ShaderError> {
+        info!("Creating GUI");
+
+        let handle = render_impl::GuiData::new(backend, &vertex_binary, &fragment_binary)?;
+
+        Ok(Self {
+            context: egui::Context::default(),
+            vertex_binary,
+            fragment_binary,
+            handle,
+
+            events: Vec::new(),
+            pointer: egui::Pos2::ZERO,
+            focused: true,
+            start: time::Instant::now(),
+
+            primitives: Vec::new(),
+            pixels_per_point: 1.0,
+        })
+    }
+
+    // Destroys the backend side and gives back the SPIR-V, for when the device is recreated. The
+    // new one gets a new context, so egui sends its textures again.
+    pub fn into_binaries(self, backend: &render_impl::State) -> (Vec<u8>, Vec<u8>) {
+        debug!("Destroying GUI to recreate it");
+        self.handle.destroy(backend);
+        (self.vertex_binary, self.fragment_binary)
+    }
+
+    pub fn is_stale(&self, backend: &render_impl::State) -> bool {
+        self.handle.is_stale(backend)
+    }
+
+    pub fn recreate_pipeline(&mut self, backend: &render_impl::State) -> Result<(), ShaderError> {
+        self.handle
+            .recreate_pipeline(backend, &self.vertex_binary, &self.fragment_binary)
+    }
+
+    pub fn handle_event(&mut self, event: &RenderEvent) {
+        match *event {
+            RenderEvent::MouseMoved { x, y } => {
+                self.pointer = egui::pos2(x, y);
+                self.events.push(egui::Event::PointerMoved(self.pointer));
+            }
+            RenderEvent::MouseButton { button, pressed } => {
+                let button = match button {
+                    0 => egui::PointerButton::Primary,
+                    1 => egui::PointerButton::Secondary,
+                    2 => egui::PointerButton::Middle,
+                    _ => return,
+                };
+                self.events.push(egui::Event::PointerButton {
+                    pos: self.pointer,
+                    button,
+                    pressed,
+                    modifiers: egui::Modifiers::default(),
+                });
+            }
+            RenderEvent::Scroll { x, y } => {
+                // egui wants points, a notch is about three lines
+                self.events
+                    .push(egui::Event::Scroll(egui::vec2(x, y) * 50.0));
+            }
+            RenderEvent::Focused(focused) => self.focused = focused,
+            _ => {}
+        }
+    }
+
+    // A point is a window pixel, the UI is drawn at the render scale like everything else
+    pub fn run(&mut self, backend: &mut render_impl::State, run_ui: impl FnOnce(&egui::Context)) {
+        let (width, height) = backend.render_size();
+        self.pixels_per_point = backend.render_scale();
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(width as f32, height as f32) / self.pixels_per_point,
+            )),
+            pixels_per_point: Some(self.pixels_per_point),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            focused: self.focused,
+            events: mem::take(&mut self.events),
+            ..Default::default()
+        };
+
+        let output = self.context.run(input, run_ui);
+        self.handle
+            .update_textures(backend, &output.textures_delta.set);
+        self.handle
+            .free_textures(backend, &output.textures_delta.free);
+        self.primitives = self.context.tessellate(output.shapes);
+    }
+
+    pub fn draw(&mut self, backend: &mut render_impl::State) {
+        self.frame().draw(backend);
+    }
+
+    // For drawing on the render thread, which can't borrow the whole thing
+    pub fn frame(&mut self) -> GuiFrame {
+        GuiFrame {
+            handle: &mut self.handle,
+            primitives: mem::take(&mut self.primitives),
+            pixels_per_point: self.pixels_per_point,
+        }
+    }
+
+    pub fn destroy(self, backend: &render_impl::State) {
+        self.handle.destroy(backend);
+    }
+}
+
+// What the render thread needs to draw the UI. The handle is only used by run and draw, and
+// neither of those can happen until the render thread is done with it.
+pub struct GuiFrame {
+    handle: *mut render_impl::GuiData,
+    primitives: Vec<egui::ClippedPrimitive>,
+    pixels_per_point: f32,
+}
+
+unsafe impl Send for GuiFrame {}
+
+impl GuiFrame {
+    pub fn draw(self, backend: &mut render_impl::State) {
+        let handle = unsafe { &mut *self.handle };
+        backend.draw_gui(handle, &self.primitives, self.pixels_per_point);
+    }
+}
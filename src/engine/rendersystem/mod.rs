@@ -1,7 +1,29 @@
-use log::{error, info};
+use log::{debug, error, info, warn};
 use nalgebra::*;
-use std::{cell::SyncUnsafeCell, collections::HashMap, fs, io, mem, sync::Arc};
+use rayon::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io, mem,
+    path::Path,
+    sync::Arc,
+    time,
+};
 
+mod atlas;
+mod camera;
+mod color;
+mod coordinates;
+mod export;
+mod glyph_cache;
+#[cfg(feature = "egui")]
+mod gui;
+mod render_thread;
+mod spirv;
+#[cfg(test)]
+mod test_util;
+mod text;
+mod thing;
+mod vertex_cache;
 #[cfg(not(any(target_os = "macos", target_os = "ios", xbox)))]
 mod vulkan;
 
@@ -10,283 +32,5368 @@ mod render_impl {
     pub use crate::engine::rendersystem::vulkan::*;
 }
 
-pub type ThingHolder<T> = Arc<SyncUnsafeCell<T>>;
+pub use atlas::{AtlasError, AtlasRegion, TextureAtlas};
+pub use camera::Camera;
+pub use color::Color;
+pub use coordinates::CoordinateConvention;
+pub use glyph_cache::{
+    CachedGlyph, DirtyRegion, GlyphCache, GlyphCacheStats, GlyphKey, RasterizedGlyph,
+};
+pub use render_impl::{GpuCapabilities, GpuDescription, SurfaceProvider};
+pub use thing::{Poison, Thing};
+
+pub type ThingHolder<T> = Arc<Thing<T>>;
+
+// How much of the old average frame time is kept each frame
+const HITCH_SMOOTHING: f64 = 0.95;
+// Without a set threshold, frames this many times longer than average are hitches
+const HITCH_FACTOR: f64 = 2.0;
+
+// A frame capture from State::request_readback
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ReadbackHandle(u64);
+
+// Everything the main loop has to react to, from the window and from the renderer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderEvent {
+    Resized(u32, u32),
+    Focused(bool),
+    Closed,
+    // code is whatever the platform uses (X keycode, virtual key, scancode)
+    Key { code: u32, pressed: bool },
+    // In pixels from the top left of the window
+    MouseMoved { x: f32, y: f32 },
+    // 0 is left, 1 is right, 2 is middle
+    MouseButton { button: u32, pressed: bool },
+    // Sent after the second press, which still gets its own MouseButton
+    DoubleClick { button: u32 },
+    // In notches (or lines), positive is right and up
+    Scroll { x: f32, y: f32 },
+    DeviceLost,
+}
+
+// Every usable GPU and what it supports, without initializing anything. For picking the GPU and
+// settings that go in the RenderConfig passed to init, so it identifies as the default app.
+pub fn enumerate_gpus(video: &dyn SurfaceProvider) -> Vec<GpuCapabilities> {
+    render_impl::State::enumerate_gpus(video, &RenderConfig::default())
+}
+
+// Options that have to be known before the backend is initialized. Most of them can still be
+// changed with the setters afterwards, this is just so nothing has to be recreated right away.
+#[derive(Clone, Debug)]
+pub struct RenderConfig {
+    // What the driver is told the game is, some of them have per-game profiles keyed on it. The
+    // game's constants by default.
+    pub app_name: String,
+    pub app_version: (u32, u32, u32),
+    pub allocator: AllocatorConfig,
+    // Tried in order, the first one the GPU supports is used. FIFO is always supported, so it's
+    // the last resort even if it isn't in the list.
+    pub present_modes: Vec<PresentMode>,
+    // Same idea, if none of them are supported it's whatever the driver lists first
+    pub surface_formats: Vec<SurfaceFormat>,
+    pub swapchain_sharing: SwapchainSharing,
+    // How many images to ask the swapchain for, within what the surface allows. None is one per
+    // frame in flight.
+    pub swapchain_images: Option<u32>,
+    // Index into the devices sorted by score, None is the highest scoring one
+    pub gpu: Option<usize>,
+    // Validation layers, on by default in graphics_debug builds. Falls back to off if they aren't
+    // installed.
+    pub validation: bool,
+    pub max_frame_latency: usize,
+    // Wait for frames to be on screen instead of just rendered when limiting latency, if the
+    // device supports it
+    pub present_wait: bool,
+    pub render_scale: f32,
+    pub sample_shading: Option<f32>,
+    pub texture_filtering: TextureFiltering,
+    pub loader_threads: usize,
+    pub optimize_vertex_cache: bool,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            app_name: String::from(crate::GAME_NAME),
+            app_version: (
+                crate::GAME_VERSION_MAJOR.into(),
+                crate::GAME_VERSION_MINOR.into(),
+                crate::GAME_VERSION_PATCH.into(),
+            ),
+            allocator: AllocatorConfig::default(),
+            present_modes: vec![PresentMode::Mailbox, PresentMode::Fifo],
+            surface_formats: vec![SurfaceFormat::Bgra8Unorm],
+            swapchain_sharing: SwapchainSharing::default(),
+            swapchain_images: None,
+            gpu: None,
+            validation: cfg!(feature = "graphics_debug"),
+            max_frame_latency: render_impl::FRAME_COUNT,
+            present_wait: true,
+            render_scale: 1.0,
+            sample_shading: None,
+            texture_filtering: TextureFiltering::default(),
+            loader_threads: 0,
+            optimize_vertex_cache: false,
+        }
+    }
+}
+
+// How the swapchain images are shared between the graphics and present queue families, if they
+// aren't the same one. Exclusive is faster but each image has to be handed over to the present
+// family every frame, concurrent skips that.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SwapchainSharing {
+    #[default]
+    Exclusive,
+    Concurrent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentMode {
+    // No vsync, can tear
+    Immediate,
+    // Vsync that replaces queued frames instead of waiting, so no tearing and low latency
+    Mailbox,
+    // Vsync
+    Fifo,
+    // Vsync, but tears instead of waiting when a frame is late
+    FifoRelaxed,
+}
+
+// All in the sRGB colour space. The Srgb ones convert what's written to them from linear, the
+// Unorm ones store it as is. The 10 bit ones band less.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SurfaceFormat {
+    Bgra8Unorm,
+    Rgba8Unorm,
+    Bgra8Srgb,
+    Rgba8Srgb,
+    A2b10g10r10Unorm,
+    A2r10g10b10Unorm,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextureFiltering {
+    Nearest,
+    Bilinear,
+    #[default]
+    Trilinear,
+    // Trilinear plus this many samples along the direction the texture is stretched in, clamped
+    // to what the GPU supports
+    Anisotropic(u8),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AllocatorConfig {
+    // Size of the blocks GPU memory is allocated in, 0 uses the allocator's default (256 MiB).
+    // Bigger blocks mean fewer allocations when there are lots of small resources, smaller ones
+    // waste less memory on GPUs that don't have much. Gets clamped to 1/8 of the biggest heap.
+    pub preferred_large_heap_block_size: u64,
+}
+
+struct PendingModel {
+    name: String,
+    path: String,
+    material: String,
+    options: ImportOptions,
+}
+
+// How the last load_resources went for the queued models
+#[derive(Clone, Copy, Debug)]
+pub struct ModelLoadStats {
+    pub models: usize,
+    pub threads: usize,
+    // Reading and parsing the files, on the loader threads
+    pub parse_time: time::Duration,
+    // Parsing and then creating and uploading the models
+    pub total_time: time::Duration,
+}
 
 pub struct State {
-    backend: render_impl::State,
+    backend: render_thread::Backend,
     shaders: HashMap<String, ThingHolder<Shader>>,
+    compute_shaders: HashMap<String, ThingHolder<ComputeShader>>,
     models: HashMap<String, ThingHolder<Model>>,
     materials: HashMap<String, ThingHolder<Material>>,
+    textures: HashMap<String, ThingHolder<RenderTexture>>,
+    atlases: HashMap<String, ThingHolder<TextureAtlas>>,
+    render_targets: HashMap<String, ThingHolder<RenderTarget>>,
+    // In bytes, None keeps every mip of every texture resident
+    texture_budget: Option<u64>,
+
+    pending_models: Vec<PendingModel>,
+    // None if it couldn't be made, rayon's global pool is used then
+    loader_pool: Option<rayon::ThreadPool>,
+    model_load_stats: Option<ModelLoadStats>,
+    optimize_vertex_cache: bool,
+
+    vfs: Option<crate::engine::vfs::Vfs>,
+
+    render_thread: Option<render_thread::RenderThread>,
+    commands: Vec<render_thread::Command>,
+
+    // Set inside render_viewport, models outside it aren't drawn
+    frustum: Option<Frustum>,
+    // Inside render_to_gbuffer, which can't be nested
+    in_target: bool,
+    stencil_mask: StencilMaskSettings,
+    // Inside render_with_stencil_mask, which can't be nested either
+    in_stencil_mask: bool,
+    render_size: (u32, u32),
+    visible: HashSet<String>,
+    last_visible: HashSet<String>,
+    triangles_submitted: u64,
+    last_triangles_submitted: u64,
+
+    // In milliseconds
+    hitch_threshold: Option<f64>,
+    average_frame_time: f64,
+    frame_start: Option<time::Instant>,
+    last_present: Option<time::Instant>,
+
+    // The grid's SPIR-V, kept to recreate its pipeline
+    grid_binaries: Option<(Vec<u8>, Vec<u8>)>,
+    // Same for the lighting pass
+    lighting_binaries: Option<(Vec<u8>, Vec<u8>)>,
+    rendering_mode: RenderingMode,
+    // Only there in deferred mode
+    gbuffer: Option<ThingHolder<RenderTarget>>,
+    #[cfg(feature = "egui")]
+    gui: Option<gui::Gui>,
+    text: Option<text::Text>,
 }
 
 impl State {
-    pub fn init(video: &crate::platform::video::State) -> Self {
+    pub fn init(video: &dyn SurfaceProvider, config: RenderConfig) -> Self {
         info!("Render system initialization started");
-        let backend = render_impl::State::init(video);
+        debug!("{config:#?}");
+        let backend = render_impl::State::init(video, &config);
         info!("Render system initialization succeeded");
 
+        let render_size = backend.render_size();
         Self {
-            backend,
+            backend: render_thread::Backend::new(backend),
             shaders: HashMap::new(),
+            compute_shaders: HashMap::new(),
             models: HashMap::new(),
             materials: HashMap::new(),
+            textures: HashMap::new(),
+            atlases: HashMap::new(),
+            render_targets: HashMap::new(),
+            texture_budget: None,
+
+            pending_models: Vec::new(),
+            loader_pool: Self::build_loader_pool(config.loader_threads),
+            model_load_stats: None,
+            optimize_vertex_cache: config.optimize_vertex_cache,
+
+            vfs: None,
+
+            render_thread: None,
+            commands: Vec::new(),
+
+            frustum: None,
+            in_target: false,
+            stencil_mask: StencilMaskSettings::default(),
+            in_stencil_mask: false,
+            render_size,
+            visible: HashSet::new(),
+            last_visible: HashSet::new(),
+            triangles_submitted: 0,
+            last_triangles_submitted: 0,
+
+            hitch_threshold: None,
+            average_frame_time: 0.0,
+            frame_start: None,
+            last_present: None,
+
+            grid_binaries: None,
+            lighting_binaries: None,
+            rendering_mode: RenderingMode::Forward,
+            gbuffer: None,
+            #[cfg(feature = "egui")]
+            gui: None,
+            text: None,
         }
     }
 
-    pub fn load_resources(&mut self) {
-        if self.backend.is_initialized() && !self.backend.is_loaded() {
-            info!("Loading resources");
-            self.backend.load_resources(&mut self.models);
-            info!("Done loading resources");
+    // Queued model paths get looked up in it first, anything it doesn't have is used as is
+    pub fn set_vfs(&mut self, vfs: crate::engine::vfs::Vfs) {
+        self.vfs = Some(vfs);
+    }
+
+    // 0 lets rayon pick based on the number of CPUs
+    pub fn set_loader_threads(&mut self, thread_count: usize) {
+        self.loader_pool = Self::build_loader_pool(thread_count);
+    }
+
+    fn build_loader_pool(thread_count: usize) -> Option<rayon::ThreadPool> {
+        match rayon::ThreadPoolBuilder::new()
+            .num_threads(thread_count)
+            .thread_name(|index| format!("loader {index}"))
+            .build()
+        {
+            Ok(pool) => Some(pool),
+            Err(err) => {
+                error!("Failed to create loader thread pool: {err}");
+                None
+            }
         }
     }
 
-    pub fn begin_cmds(&mut self, video: &crate::platform::video::State) {
-        self.backend.begin_cmds(video)
+    pub fn model_load_stats(&self) -> Option<ModelLoadStats> {
+        self.model_load_stats
     }
 
-    pub fn present(&mut self) {
-        self.backend.present()
+    // Reorder the triangles and vertices of models created after this for the GPU's vertex cache,
+    // makes loading slower but rendering dense meshes faster
+    pub fn set_optimize_vertex_cache(&mut self, optimize: bool) {
+        self.optimize_vertex_cache = optimize;
     }
 
-    pub fn unload_resources(&mut self) {
-        if self.backend.is_initialized() && self.backend.is_loaded() {
-            info!("Unloading resources");
-            self.backend.unload_resources();
-            info!("Done unloading resources");
+    pub fn create_atlas(
+        &mut self,
+        name: &str,
+        images: Vec<(String, image::RgbaImage)>,
+    ) -> Result<ThingHolder<TextureAtlas>, AtlasError> {
+        let atlas = Arc::new(Thing::new(TextureAtlas::new(name, images)?));
+        // otherwise load_resources does it
+        self.sync();
+        if self.backend.get().is_loaded() {
+            let _ = atlas.get_mut().upload(&mut self.backend.get());
         }
+        self.atlases.insert(String::from(name), atlas.clone());
+        Ok(atlas)
     }
 
-    pub fn shutdown(mut self) {
-        info!("Render system shutdown started");
-        self.unload_resources();
-        self.backend.shutdown();
-        info!("Render system shutdown succeeded");
+    // Records and presents on a separate thread, see render_thread.rs for how that works. The
+    // setters can still be called whenever, they just wait for the render thread first.
+    pub fn render_threaded(&mut self, threaded: bool) {
+        if threaded == self.render_thread.is_some() {
+            return;
+        }
+
+        info!(
+            "{} render thread",
+            if threaded { "Enabling" } else { "Disabling" }
+        );
+        if threaded {
+            self.render_thread = Some(render_thread::RenderThread::new());
+        } else {
+            // dropping it waits for the last frame
+            self.render_thread = None;
+        }
     }
-}
 
-#[derive(Debug)]
-pub enum ShaderError {
-    Io(io::Error),
-    Backend(render_impl::ShaderErrorType),
-}
+    pub fn is_render_threaded(&self) -> bool {
+        self.render_thread.is_some()
+    }
 
-pub struct Shader {
-    name: String,
-    handle: render_impl::ShaderData,
-}
+    // Waits for the render thread to be done with the backend
+    fn sync(&self) {
+        if let Some(render_thread) = &self.render_thread {
+            render_thread.wait();
+        }
+    }
 
-impl Shader {
-    pub fn new(
-        state: &mut crate::engine::State,
-        name: &str,
-    ) -> Result<ThingHolder<Self>, ShaderError> {
-        info!("Creating shader {name}");
+    // Same as rendering the model directly without the render thread. With it, the model is
+    // queued for the next present, which works whether or not the frame has begun.
+    pub fn draw(&mut self, model: &ThingHolder<Model>) {
+        if model.is_poisoned() {
+            warn!(
+                "Not drawing {}, it's from a render system that was shut down",
+                model.get().name
+            );
+            return;
+        }
 
-        let vertex_path = format!(
-            "{}{name}{}",
-            crate::engine::GameDirs::shaders(state),
-            render_impl::ShaderData::vertex_extension()
-        );
-        let fragment_path = format!(
-            "{}{name}{}",
-            crate::engine::GameDirs::shaders(state),
-            render_impl::ShaderData::fragment_extension()
+        if self.render_thread.is_some() {
+            self.track_visibility(&model.get());
+            self.commands
+                .push(render_thread::Command::Draw(model.clone()));
+        } else {
+            model.get().render(self);
+        }
+    }
+
+    pub fn draw_batch(&mut self, batch: &Batch) {
+        for model in &batch.models {
+            self.draw(model);
+        }
+    }
+
+    // Merges static models with the same material into as few models as possible, with their
+    // transforms baked into the vertices, so they take one draw (and one set of binds) instead of
+    // one each. Models with another material or raw vertices, and lines and points, are left
+    // out. It only makes new models, so like any other model it has to be done before
+    // load_resources.
+    pub fn build_static_batch(
+        &mut self,
+        name: &str,
+        models: &[(ThingHolder<Model>, Matrix4<f32>)],
+        material: &str,
+    ) -> Result<Batch, ResourceError> {
+        info!(
+            "Building static batch {name} from {} model(s)",
+            models.len()
         );
-        let vertex_binary = match fs::read(&vertex_path) {
-            Ok(data) => data,
-            Err(err) => {
-                error!("Failed to read vertex binary {vertex_path} for shader {name}: {err}");
-                return Err(ShaderError::Io(err));
+
+        let mut parts = Vec::new();
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut indices = Vec::new();
+        for (model, transform) in models {
+            let model = model.get();
+            if model.material.get().name() != material {
+                warn!(
+                    "Model {} doesn't use material {material}, leaving it out of batch {name}",
+                    model.name
+                );
+                continue;
             }
-        };
-        let fragment_binary = match fs::read(&fragment_path) {
-            Ok(data) => data,
-            Err(err) => {
-                error!("Failed to read fragment binary {fragment_path} for shader {name}: {err}");
-                return Err(ShaderError::Io(err));
+            if !matches!(
+                model.topology,
+                Topology::TriangleList | Topology::TriangleStrip
+            ) {
+                warn!(
+                    "Model {} isn't made of triangles, leaving it out of batch {name}",
+                    model.name
+                );
+                continue;
             }
-        };
-        let handle = match render_impl::ShaderData::new(
-            &state.render().backend,
-            name,
-            vertex_binary,
-            fragment_binary,
-        ) {
-            Ok(handle) => handle,
-            Err(err) => {
-                error!("Failed to create shader {name}: {err:?}");
-                return Err(err);
+            let Some(model_vertices) = model.vertices() else {
+                warn!(
+                    "Model {} is made of raw vertices, leaving it out of batch {name}",
+                    model.name
+                );
+                continue;
+            };
+
+            // Indices have to stay under the restart index, past that it becomes another model
+            if !vertices.is_empty()
+                && vertices.len() + model_vertices.len() > PRIMITIVE_RESTART_INDEX as usize
+            {
+                parts.push((mem::take(&mut vertices), mem::take(&mut indices)));
             }
-        };
 
-        let shader = Arc::new(SyncUnsafeCell::new(Self {
+            let normal_matrix = transform
+                .fixed_view::<3, 3>(0, 0)
+                .try_inverse()
+                .map(|matrix| matrix.transpose())
+                .unwrap_or_else(Matrix3::identity);
+            let base = vertices.len() as u32;
+            let mut model_indices = export::triangle_list(&model.indices(), model.topology);
+            // mirroring turns the triangles inside out, so they have to be wound the other way
+            if transform.determinant() < 0.0 {
+                for triangle in model_indices.chunks_exact_mut(3) {
+                    triangle.swap(1, 2);
+                }
+            }
+            indices.extend(model_indices.iter().map(|index| index + base));
+            vertices.extend(model_vertices.into_iter().map(|vertex| {
+                Vertex {
+                    position: transform
+                        .transform_point(&Point3::from(vertex.position))
+                        .coords,
+                    normal: (normal_matrix * vertex.normal)
+                        .try_normalize(f32::EPSILON)
+                        .unwrap_or_else(Vector3::zeros),
+                    ..vertex
+                }
+            }));
+        }
+        if !vertices.is_empty() {
+            parts.push((vertices, indices));
+        }
+        if parts.is_empty() {
+            error!("Static batch {name} has nothing in it");
+            return Err(ResourceError::EmptyGeometry);
+        }
+
+        let part_count = parts.len();
+        let models = parts
+            .into_iter()
+            .enumerate()
+            .map(|(i, (vertices, indices))| {
+                let name = if part_count == 1 {
+                    String::from(name)
+                } else {
+                    format!("{name}.{i}")
+                };
+                Model::from_vertices(
+                    self,
+                    &name,
+                    vertices,
+                    indices,
+                    Topology::TriangleList,
+                    material,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        info!("Built static batch {name} as {part_count} model(s)");
+
+        Ok(Batch {
             name: String::from(name),
-            handle,
-        }));
-        state
-            .render()
-            .shaders
-            .insert(String::from(name), shader.clone());
+            models,
+        })
+    }
 
-        info!("Shader {name} created successfully");
+    // Clips the draws after it to a rectangle in window pixels, intersected with the ones pushed
+    // before it. The stack is cleared every frame.
+    pub fn push_scissor(&mut self, x: i32, y: i32, width: u32, height: u32) {
+        if self.render_thread.is_some() {
+            self.commands.push(render_thread::Command::PushScissor {
+                x,
+                y,
+                width,
+                height,
+            });
+        } else {
+            self.backend.get().push_scissor(x, y, width, height);
+        }
+    }
 
-        Ok(shader)
+    pub fn pop_scissor(&mut self) {
+        if self.render_thread.is_some() {
+            self.commands.push(render_thread::Command::PopScissor);
+        } else {
+            self.backend.get().pop_scissor();
+        }
     }
 
-    pub fn destroy(&self, state: &State) {
-        self.handle.destroy(&state.backend);
+    // Offsets the depth of everything drawn after it in this frame, for shadow maps and decals.
+    // All zeros turns it off again, and it's off at the start of every frame.
+    pub fn set_depth_bias(&mut self, constant: f32, slope: f32, clamp: f32) {
+        if self.render_thread.is_some() {
+            self.commands.push(render_thread::Command::SetDepthBias {
+                constant,
+                slope,
+                clamp,
+            });
+        } else {
+            self.backend.get().set_depth_bias(constant, slope, clamp);
+        }
     }
 
-    pub fn name(&self) -> &String {
-        &self.name
+    // The matrices for everything drawn outside render_viewport and render_to_gbuffer, which
+    // use their camera's instead. They stay set until they're changed, so it can be done before
+    // begin_cmds or in the middle of a frame, but changing them mid-frame changes them for the
+    // draws before too. There's no far plane to go by, so logarithmic depth is off for them.
+    pub fn update_uniforms(
+        &mut self,
+        model: Matrix4<f32>,
+        view: Matrix4<f32>,
+        projection: Matrix4<f32>,
+    ) {
+        let data = UniformData {
+            model,
+            view,
+            projection,
+            logarithmic_depth: 0.0,
+        };
+        if self.render_thread.is_some() {
+            self.commands
+                .push(render_thread::Command::UpdateUniforms(data));
+        } else {
+            self.backend.get().update_uniforms(data);
+        }
     }
-}
 
-#[repr(C)]
-pub struct UniformData {
-    model: Matrix4<f64>,
-    view: Matrix4<f64>,
-    projection: Matrix4<f64>,
-}
+    // Draws draw_masked only where draw_mask covered, for portals, magic windows and UI masks.
+    // The mask is drawn like anything else, so it shows wherever the masked things don't cover
+    // it (the inside of a portal, say), but by default it doesn't write depth so it can't hide
+    // them. The stencil is cleared in the current viewport (or scissor) first. How the mask is
+    // written and tested is set with set_stencil_mask.
+    pub fn render_with_stencil_mask(
+        &mut self,
+        draw_mask: impl FnOnce(&mut Self),
+        draw_masked: impl FnOnce(&mut Self),
+    ) {
+        if self.in_stencil_mask {
+            warn!("Stencil masks can't be nested, drawing without the inner mask");
+            draw_mask(self);
+            draw_masked(self);
+            return;
+        }
 
-pub struct RenderTexture {
-    name: String,
-    //texture: crate::texture::Texture,
-    //handle: render_impl::TextureData
-}
+        let settings = self.stencil_mask;
+        self.in_stencil_mask = true;
+        self.clear_stencil();
+        self.set_stencil(Some(settings.mask), settings.mask_writes_depth);
+        draw_mask(self);
+        self.set_stencil(Some(settings.masked), true);
+        draw_masked(self);
+        self.set_stencil(None, true);
+        self.in_stencil_mask = false;
+    }
 
-pub struct Material {
-    name: String,
-    shader: ThingHolder<Shader>,
-    //   texture: Arc<RenderTexture>,
-}
+    pub fn set_stencil_mask(&mut self, settings: StencilMaskSettings) {
+        self.stencil_mask = settings;
+    }
 
-impl Material {
-    pub fn new(state: &mut State, name: &str, shader: &str) -> Result<ThingHolder<Self>, ()> {
-        let material = Arc::new(SyncUnsafeCell::new(Self {
-            name: String::from(name),
-            shader: match state.shaders.get(&String::from(shader)) {
-                Some(thing) => thing,
-                None => {
-                    return Err(());
-                }
-            }
-            .clone(),
-        }));
-        state.materials.insert(String::from(name), material.clone());
-        Ok(material)
+    pub fn stencil_mask(&self) -> StencilMaskSettings {
+        self.stencil_mask
     }
 
-    pub fn name(&self) -> &String {
-        &self.name
+    fn set_stencil(&mut self, stencil: Option<StencilState>, depth_write: bool) {
+        if self.render_thread.is_some() {
+            self.commands.push(render_thread::Command::SetStencil {
+                stencil,
+                depth_write,
+            });
+        } else {
+            self.backend.get().set_stencil(stencil, depth_write);
+        }
     }
-}
 
-pub trait Renderable {
-    fn render(&self, state: &mut State);
-}
+    fn clear_stencil(&mut self) {
+        if self.render_thread.is_some() {
+            self.commands.push(render_thread::Command::ClearStencil);
+        } else {
+            self.backend.get().clear_stencil();
+        }
+    }
 
-#[derive(PartialEq)]
-pub struct Vertex {
-    position: Vector3<f32>,
-    texture_coordinate: Vector2<f32>,
-    normal: Vector3<f32>,
-}
+    // Everything drawn in draw_viewport goes to that part of the window, seen from the camera.
+    // Can be called a few times a frame for split screen.
+    pub fn render_viewport(
+        &mut self,
+        camera: &Camera,
+        rect: ViewportRect,
+        draw_viewport: impl FnOnce(&mut Self),
+    ) {
+        let aspect = (rect.width * self.render_size.0 as f32)
+            / (rect.height * self.render_size.1 as f32).max(1.0);
+        self.frustum = Some(Frustum::new(&camera.uniform_data(aspect)));
 
-pub struct Model {
-    name: String,
-    data: Vec<u8>,
-    material: ThingHolder<Material>,
-    handle: render_impl::ModelData,
-}
+        if self.render_thread.is_some() {
+            self.commands.push(render_thread::Command::BeginViewport {
+                camera: *camera,
+                rect,
+            });
+            draw_viewport(self);
+            self.commands.push(render_thread::Command::EndViewport);
+        } else {
+            self.backend.get().begin_viewport(camera, rect);
+            draw_viewport(self);
+            self.backend.get().end_viewport();
+        }
 
-impl Model {
-    pub fn new(
-        state: &mut State,
-        name: &str,
-        models: Vec<tobj::Model>,
-        material: &str,
-    ) -> Result<ThingHolder<Self>, ()> {
-        info!("Creating model {name}");
+        self.frustum = None;
+    }
 
-        // largely based on https://github.com/bwasty/learn-opengl-rs/blob/master/src/model.rs
-        let mut all_vertices = Vec::new();
-        let mut all_indices: Vec<u32> = Vec::new();
-        for model in models {
-            let mut mesh = model.mesh;
+    // Everything drawn in draw_target goes to the render target instead of the window, seen from
+    // the camera, for G-buffers and other offscreen passes. Only shaders made for the target's
+    // formats (see Shader::set_color_formats) can draw to it. Can't be called inside
+    // render_viewport or another render_to_gbuffer, and takes one of the frame's viewport slots.
+    pub fn render_to_gbuffer(
+        &mut self,
+        target: &ThingHolder<RenderTarget>,
+        camera: &Camera,
+        draw_target: impl FnOnce(&mut Self),
+    ) {
+        if self.in_target || self.frustum.is_some() {
+            warn!(
+                "Can't render to {} inside a viewport or another render target",
+                target.get().name
+            );
+            return;
+        }
 
-            assert!(!mesh.normals.is_empty() && !mesh.texcoords.is_empty());
+        let (width, height) = self.render_size;
+        self.frustum = Some(Frustum::new(
+            &camera.uniform_data(width as f32 / height.max(1) as f32),
+        ));
+        self.in_target = true;
 
-            let vertex_count = mesh.positions.len() / 3;
-            let mut vertices = Vec::with_capacity(vertex_count);
-            let (p, t, n) = (&mesh.positions, &mesh.texcoords, &mesh.normals);
-            for i in 0..vertex_count {
-                let position = Vector3::new(p[i * 3], p[i * 3 + 1], p[i * 3 + 2]);
-                let texture_coordinate = Vector2::new(t[i * 2], t[i * 2 + 1]);
-                let normal = Vector3::new(n[i * 3], n[i * 3 + 1], n[i * 3 + 2]);
-                vertices.push(Vertex {
-                    position,
-                    texture_coordinate,
-                    normal,
-                })
+        if self.render_thread.is_some() {
+            self.commands.push(render_thread::Command::BeginTarget {
+                target: target.clone(),
+                camera: *camera,
+            });
+            draw_target(self);
+            self.commands
+                .push(render_thread::Command::EndTarget(target.clone()));
+        } else {
+            self.backend
+                .get()
+                .begin_target(&target.get().handle, camera);
+            draw_target(self);
+            self.backend.get().end_target(&target.get().handle);
+        }
+
+        self.in_target = false;
+        self.frustum = None;
+    }
+
+    // Draws the scene from the camera into the whole window the way the rendering mode says.
+    // Forward just draws both in a viewport. Deferred draws draw_opaque into the G-buffer with
+    // shaders made for GBUFFER_FORMATS (like gbuffer.vert and gbuffer.frag), lights it into the
+    // window and then draws draw_transparent forward on top, since blending can't go through
+    // the G-buffer. The transparent pass is depth tested against the opaque geometry either way.
+    pub fn render_scene(
+        &mut self,
+        camera: &Camera,
+        draw_opaque: impl FnOnce(&mut Self),
+        draw_transparent: impl FnOnce(&mut Self),
+    ) {
+        let Some(gbuffer) = self.gbuffer.clone() else {
+            self.render_viewport(camera, ViewportRect::FULL, |state| {
+                draw_opaque(state);
+                draw_transparent(state);
+            });
+            return;
+        };
+
+        self.render_to_gbuffer(&gbuffer, camera, draw_opaque);
+        self.render_viewport(camera, ViewportRect::FULL, |state| {
+            if state.render_thread.is_some() {
+                state
+                    .commands
+                    .push(render_thread::Command::DrawLighting(gbuffer.clone()));
+            } else {
+                state.backend.get().draw_lighting(&gbuffer.get().handle);
             }
+            draw_transparent(state);
+        });
+    }
 
-            all_vertices.append(&mut vertices);
-            all_indices.append(&mut mesh.indices);
+    // Remembers whether the model is in the frustum, it gets drawn either way
+    fn track_visibility(&mut self, model: &Model) {
+        self.triangles_submitted += model.triangle_count();
+        if self
+            .frustum
+            .as_ref()
+            .map_or(true, |frustum| frustum.contains(&model.bounds))
+        {
+            self.visible.insert(model.name.clone());
         }
+    }
 
-        let vertices_size = all_vertices.len() * mem::size_of::<Vertex>();
-        let indices_size = all_indices.len() * mem::size_of::<u32>();
+    // Whether the model was in view of any viewport it was drawn in last frame, for skipping work
+    // on things that are off screen. Nothing gets culled, this is only reported. It's only the
+    // frustum, so it's exact for the last frame and a frame behind for the current one. Anything
+    // drawn outside of render_viewport counts as visible.
+    pub fn is_model_visible(&self, name: &str) -> bool {
+        self.last_visible.contains(name)
+    }
 
-        let mut data = Vec::new();
-        let vertices = all_vertices.into_raw_parts();
-        data.append(&mut unsafe {
-            Vec::from_raw_parts(vertices.0 as *mut u8, vertices_size, vertices_size)
-        });
-        let indices = all_indices.into_raw_parts();
-        data.append(&mut unsafe {
-            Vec::from_raw_parts(indices.0 as *mut u8, indices_size, indices_size)
-        });
+    pub fn visible_models(&self) -> impl Iterator<Item = &String> {
+        self.last_visible.iter()
+    }
 
-        let handle = render_impl::ModelData::new(&state.backend, name, vertices_size, indices_size);
+    pub fn present_mode(&self) -> PresentMode {
+        self.sync();
+        self.backend.get().present_mode()
+    }
 
-        let model = Arc::new(SyncUnsafeCell::new(Self {
+    // Parses the file with the others in load_resources, on the loader threads. .gltf and .glb
+    // files are glTF, anything else is OBJ. The path goes through the virtual file system if there
+    // is one.
+    pub fn queue_model(&mut self, name: &str, path: &str, material: &str, options: ImportOptions) {
+        self.pending_models.push(PendingModel {
             name: String::from(name),
-            material: match state.materials.get(&String::from(material)) {
-                Some(thing) => thing,
-                None => {
-                    return Err(());
+            path: String::from(path),
+            material: String::from(material),
+            options,
+        });
+    }
+
+    fn load_pending_models(&mut self) {
+        if self.pending_models.is_empty() {
+            return;
+        }
+
+        let pending = mem::take(&mut self.pending_models);
+        let threads = self
+            .loader_pool
+            .as_ref()
+            .map_or_else(rayon::current_num_threads, |pool| {
+                pool.current_num_threads()
+            });
+        debug!("Parsing {} model(s) on {threads} thread(s)", pending.len());
+
+        let start = time::Instant::now();
+        let paths: Vec<String> = pending
+            .iter()
+            .map(|model| {
+                self.vfs
+                    .as_ref()
+                    .and_then(|vfs| vfs.resolve(&model.path))
+                    .unwrap_or_else(|| model.path.clone())
+            })
+            .collect();
+        // collect keeps the queue order, so the models get created in the same order every time
+        let parse = || -> Vec<_> {
+            pending
+                .par_iter()
+                .zip(&paths)
+                .map(|(model, path)| Model::parse_file(&model.name, path, model.options))
+                .collect()
+        };
+        let parsed = match &self.loader_pool {
+            Some(pool) => pool.install(parse),
+            None => parse(),
+        };
+        let parse_time = start.elapsed();
+        debug!("Parsed models in {parse_time:?}");
+
+        for (model, result) in pending.iter().zip(parsed) {
+            match result {
+                Ok((vertices, indices)) => {
+                    info!("Creating model {}", model.name);
+                    if let Err(err) =
+                        Model::from_imported(self, &model.name, vertices, indices, &model.material)
+                    {
+                        error!("Failed to create model {}: {err:?}", model.name);
+                    }
                 }
+                Err(err) => error!(
+                    "Failed to parse model {} from {}: {err:?}",
+                    model.name, model.path
+                ),
             }
-            .clone(),
-            data,
-            handle,
-        }));
-        state.models.insert(String::from(name), model.clone());
-
-        info!("Created model {name} successfully");
+        }
 
-        Ok(model)
+        let total_time = start.elapsed();
+        debug!("Loaded models in {total_time:?}");
+        self.model_load_stats = Some(ModelLoadStats {
+            models: pending.len(),
+            threads,
+            parse_time,
+            total_time,
+        });
     }
 
-    pub fn name(&self) -> &String {
-        &self.name
+    // Models that fail to load are logged and left out, and textures or atlas pages that fail to
+    // upload are logged and drawn with the default texture. Only failing to upload the models'
+    // geometry is an error.
+    pub fn load_resources(&mut self) -> Result<(), ResourceError> {
+        self.sync();
+        let backend = self.backend.get();
+        let unloaded = backend.is_initialized() && !backend.is_loaded();
+        drop(backend);
+        if unloaded {
+            info!("Loading resources");
+            self.load_pending_models();
+            if let Err(err) = self.backend.get().load_resources(&mut self.models) {
+                error!("Failed to upload resources: {err}");
+                return Err(ResourceError::UploadFailed);
+            }
+            // the ones that failed were logged, materials using them get the default texture
+            for texture in self.textures.values() {
+                let _ = texture.get_mut().upload(&mut self.backend.get());
+            }
+            // the pages that failed were logged, the rest of the atlas is still usable
+            for (name, atlas) in &self.atlases {
+                if atlas.get_mut().upload(&mut self.backend.get()).is_err() {
+                    warn!(
+                        "Atlas {name} is missing pages, they'll be drawn with the default texture"
+                    );
+                }
+            }
+            info!("Done loading resources");
+        }
+        Ok(())
     }
 
-    pub fn data(&self) -> &Vec<u8> {
-        &self.data
+    pub fn begin_cmds(&mut self, video: &dyn SurfaceProvider) {
+        self.sync();
+        self.frame_start = Some(time::Instant::now());
+        self.render_size = self.backend.get().render_size();
+        self.stream_textures();
+        // the guards have to outlive the references when resources are locked
+        let materials: Vec<_> = self
+            .materials
+            .values()
+            .map(|material| material.get())
+            .collect();
+        let prepasses: Vec<_> = materials
+            .iter()
+            .filter_map(|material| material.prepass.as_ref())
+            .filter(|prepass| prepass.handle.is_some())
+            .collect();
+        let shaders: Vec<_> = prepasses
+            .iter()
+            .map(|prepass| prepass.shader.get())
+            .collect();
+        let prepasses: Vec<_> = shaders
+            .iter()
+            .zip(prepasses)
+            .map(|(shader, prepass)| (&shader.handle, prepass.handle.as_ref().unwrap()))
+            .collect();
+        self.backend.get().begin_cmds(video, &prepasses);
+        // the shaders might have to be locked again to recreate them
+        drop(shaders);
+        drop(materials);
+        self.recreate_stale_pipelines();
+        self.recreate_stale_targets();
+        // after the G-buffer's been remade at the new size, if it had to be
+        let gbuffer = self.gbuffer.as_ref().map(|gbuffer| gbuffer.get());
+        self.backend
+            .get()
+            .prepare_lighting(gbuffer.as_ref().map(|gbuffer| &gbuffer.handle));
     }
 
-    pub fn size(&self) -> u64 {
-        self.data.len() as u64
-    }
-}
+    // Render targets are the size of what's rendered, so they follow it when the window resizes.
+    // They wait while the window doesn't have a real size, they'd only be made again once it does.
+    fn recreate_stale_targets(&mut self) {
+        if !self.backend.get().has_valid_size() {
+            return;
+        }
+
+        let stale: Vec<_> = self
+            .render_targets
+            .values()
+            .filter(|target| target.get().handle.is_stale(&self.backend.get()))
+            .cloned()
+            .collect();
+        if stale.is_empty() {
+            return;
+        }
+
+        debug!(
+            "Recreating {} render target(s) at the new size",
+            stale.len()
+        );
+        self.backend.get().wait_idle();
+        for target in stale {
+            let target = &mut *target.get_mut();
+            // the old one is still stale, so it gets another try next frame
+            let handle = render_impl::RenderTargetData::new(
+                &self.backend.get(),
+                &target.name,
+                &target.formats,
+            );
+            match handle {
+                Ok(handle) => mem::replace(&mut target.handle, handle).destroy(&self.backend.get()),
+                Err(err) => error!(
+                    "Failed to recreate render target {}, keeping the old one: {err}",
+                    target.name
+                ),
+            }
+        }
+    }
+
+    // Pipelines are made for the swapchain's formats and the sample shading, so they have to be
+    // made again if the swapchain comes back with different formats or sample shading changes.
+    // Shader objects only care about the latter.
+    fn recreate_stale_pipelines(&mut self) {
+        let stale: Vec<_> = self
+            .shaders
+            .values()
+            .filter(|shader| shader.get().handle.is_stale(&self.backend.get()))
+            .cloned()
+            .collect();
+        #[cfg(feature = "egui")]
+        let gui_stale = self
+            .gui
+            .as_ref()
+            .is_some_and(|gui| gui.is_stale(&self.backend.get()));
+        #[cfg(not(feature = "egui"))]
+        let gui_stale = false;
+        let text_stale = self
+            .text
+            .as_ref()
+            .is_some_and(|text| text.is_stale(&self.backend.get()));
+        let grid_stale = self.backend.get().is_grid_stale();
+        let lighting_stale = self.backend.get().is_lighting_stale();
+        if stale.is_empty() && !gui_stale && !text_stale && !grid_stale && !lighting_stale {
+            return;
+        }
+
+        debug!(
+            "Recreating pipelines for {} shader(s) after the swapchain or sample shading changed",
+            stale.len()
+        );
+        self.backend.get().wait_idle();
+        for shader in stale {
+            let shader = &mut *shader.get_mut();
+            // the old pipelines still work for the formats they were made for
+            let handle = shader.create_handle(&self.backend.get());
+            match handle {
+                Ok(handle) => mem::replace(&mut shader.handle, handle).destroy(&self.backend.get()),
+                Err(err) => error!(
+                    "Failed to recreate shader {}, keeping the old pipelines: {err:?}",
+                    shader.name
+                ),
+            }
+        }
+        #[cfg(feature = "egui")]
+        if let Some(gui) = self.gui.as_mut().filter(|_| gui_stale) {
+            if let Err(err) = gui.recreate_pipeline(&self.backend.get()) {
+                error!("Failed to recreate GUI pipeline: {err:?}");
+            }
+        }
+        if let Some(text) = self.text.as_mut().filter(|_| text_stale) {
+            if let Err(err) = text.recreate_pipeline(&self.backend.get()) {
+                error!("Failed to recreate text pipeline: {err:?}");
+            }
+        }
+        if let Some((vertex_binary, fragment_binary)) = self.grid_binaries.as_ref() {
+            if let Err(err) = self
+                .backend
+                .get()
+                .recreate_grid_pipeline(vertex_binary, fragment_binary)
+            {
+                error!("Failed to recreate grid pipeline: {err:?}");
+            }
+        }
+        if let Some((vertex_binary, fragment_binary)) = self.lighting_binaries.as_ref() {
+            if let Err(err) = self
+                .backend
+                .get()
+                .recreate_lighting_pipeline(vertex_binary, fragment_binary)
+            {
+                error!("Failed to recreate lighting pipeline: {err:?}");
+            }
+        }
+    }
+
+    pub fn present(&mut self) {
+        self.last_visible = mem::take(&mut self.visible);
+        self.last_triangles_submitted = mem::take(&mut self.triangles_submitted);
+        self.detect_hitch();
+
+        if let Some(render_thread) = &self.render_thread {
+            self.commands.push(render_thread::Command::DrawGrid);
+            if let Some(text) = self.text.as_mut() {
+                // the pages get uploaded here, the render thread only draws
+                render_thread.wait();
+                let frame = text.frame(&mut self.backend.get());
+                self.commands.push(render_thread::Command::DrawText(frame));
+            }
+            render_thread.submit(
+                &self.backend,
+                mem::take(&mut self.commands),
+                #[cfg(feature = "egui")]
+                self.gui.as_mut().map(|gui| gui.frame()),
+            );
+            return;
+        }
+
+        self.backend.get().draw_grid();
+        if let Some(text) = self.text.as_mut() {
+            text.draw(&mut self.backend.get());
+        }
+        #[cfg(feature = "egui")]
+        if let Some(gui) = self.gui.as_mut() {
+            gui.draw(&mut self.backend.get());
+        }
+        self.backend.get().present()
+    }
+
+    // For RenderEvent::Resized, the swap chain gets recreated in the next begin_cmds
+    pub fn window_resized(&mut self) {
+        self.sync();
+        self.backend.get().window_resized()
+    }
+
+    // Instead of begin_cmds and present, for when there's nothing to show (minimized, paused in
+    // the background). Nothing is acquired or submitted, so the backend's frames in flight stay
+    // where they were and the next real frame carries on from there. Draws queued since the last
+    // present are dropped, and the time until the next present doesn't count as a hitch.
+    pub fn skip_frame(&mut self) {
+        self.sync();
+        self.commands.clear();
+        self.last_visible = mem::take(&mut self.visible);
+        self.last_triangles_submitted = mem::take(&mut self.triangles_submitted);
+        self.frame_start = None;
+        self.last_present = Some(time::Instant::now());
+        self.backend.get().skip_frame();
+    }
+
+    // Frames that take longer than the threshold get logged with where the time went, for catching
+    // stutters without a profiler. None goes back to twice the average frame time, infinity turns
+    // it off.
+    pub fn set_hitch_threshold(&mut self, ms: Option<f64>) {
+        debug!(
+            "Changing hitch threshold from {:?} to {ms:?}",
+            self.hitch_threshold
+        );
+        self.hitch_threshold = ms;
+    }
+
+    pub fn hitch_threshold(&self) -> Option<f64> {
+        self.hitch_threshold
+    }
+
+    // Time between presents, the CPU part is begin_cmds to present
+    fn detect_hitch(&mut self) {
+        let now = time::Instant::now();
+        let Some(last_present) = self.last_present.replace(now) else {
+            return;
+        };
+        let frame_time = (now - last_present).as_secs_f64() * 1000.0;
+        if self.average_frame_time == 0.0 {
+            self.average_frame_time = frame_time;
+            return;
+        }
+
+        let threshold = self
+            .hitch_threshold
+            .unwrap_or(self.average_frame_time * HITCH_FACTOR);
+        if frame_time > threshold {
+            let cpu_time = self
+                .frame_start
+                .map_or(0.0, |start| (now - start).as_secs_f64() * 1000.0);
+            // the GPU time is from the last frame that has results, not this one
+            warn!(
+                "Frame took {frame_time:.2}ms, over the {threshold:.2}ms hitch threshold (average \
+                 {:.2}ms, CPU {cpu_time:.2}ms, GPU {:.2}ms)",
+                self.average_frame_time,
+                self.backend.get().gpu_time()
+            );
+        }
+        self.average_frame_time =
+            self.average_frame_time * HITCH_SMOOTHING + frame_time * (1.0 - HITCH_SMOOTHING);
+    }
+
+    // Sets up egui with the UI shader, egui_pass does nothing until this is called
+    #[cfg(feature = "egui")]
+    pub fn init_gui(
+        &mut self,
+        vertex_binary: Vec<u8>,
+        fragment_binary: Vec<u8>,
+    ) -> Result<(), ShaderError> {
+        self.sync();
+        if let Some(gui) = self.gui.take() {
+            gui.destroy(&self.backend.get());
+        }
+        self.gui = Some(gui::Gui::new(
+            &self.backend.get(),
+            vertex_binary,
+            fragment_binary,
+        )?);
+        Ok(())
+    }
+
+    // Window events for egui, everything from the platform should go through here
+    #[cfg(feature = "egui")]
+    pub fn handle_gui_event(&mut self, event: &RenderEvent) {
+        if let Some(gui) = self.gui.as_mut() {
+            gui.handle_event(event);
+        }
+    }
+
+    // Builds the UI for this frame, it gets drawn over the frame when it's presented. Call it
+    // once per frame between begin_cmds and present.
+    #[cfg(feature = "egui")]
+    pub fn egui_pass(&mut self, run_ui: impl FnOnce(&egui::Context)) {
+        self.sync();
+        if let Some(gui) = self.gui.as_mut() {
+            gui.run(&mut self.backend.get(), run_ui);
+        }
+    }
+
+    // Sets up text with its shader and the cache its glyphs go in, draw_text does nothing until
+    // this is called
+    pub fn init_text(
+        &mut self,
+        vertex_binary: Vec<u8>,
+        fragment_binary: Vec<u8>,
+        cache: GlyphCache,
+    ) -> Result<(), ShaderError> {
+        self.sync();
+        if let Some(text) = self.text.take() {
+            self.backend.get().wait_idle();
+            text.destroy(&self.backend.get());
+        }
+        self.text = Some(text::Text::new(
+            &self.backend.get(),
+            vertex_binary,
+            fragment_binary,
+            cache,
+        )?);
+        Ok(())
+    }
+
+    // Queues text to go over the main view when the frame is presented, with the first line's
+    // baseline starting at position in render pixels. Glyphs the cache doesn't have yet get
+    // rasterized at size pixels with rasterize.
+    pub fn draw_text(
+        &mut self,
+        text: &str,
+        position: Vector2<f32>,
+        size: u32,
+        color: Color,
+        rasterize: impl FnMut(GlyphKey) -> Option<RasterizedGlyph>,
+    ) {
+        match self.text.as_mut() {
+            Some(text_state) => text_state.queue(text, position, size, color, rasterize),
+            None => warn!("Drawing text before it's set up, it won't be drawn"),
+        }
+    }
+
+    pub fn glyph_cache_stats(&self) -> Option<GlyphCacheStats> {
+        self.text.as_ref().map(text::Text::stats)
+    }
+
+    pub fn set_clear_color(&mut self, color: Color) {
+        self.sync();
+        self.backend.get().set_clear_color(color)
+    }
+
+    // Depth goes up with the log of the distance instead of 1 / distance, so there's about as much
+    // precision far away as up close, for scenes that go from a cockpit to a planet. It's the
+    // vertex shader's job, it gets the coefficient in the uniform buffer and has to end with
+    //
+    //     if (uniform_buffer.logarithmic_depth != 0.0) {
+    //         gl_Position.z = log2(max(1e-6, 1.0 + gl_Position.w)) *
+    //             uniform_buffer.logarithmic_depth * gl_Position.w;
+    //     }
+    //
+    // or what it draws won't line up with everything else, basic.vert and the grid do. Depth still
+    // goes from 0 to 1 and gets bigger further away, so the pipelines don't change. It's per
+    // vertex, so big triangles close to the camera can poke through things a bit. Anything else
+    // that writes depth, like a depth prepass or shadow maps, has to use the same mapping too
+    // (shadow maps have their own orthographic projection and shouldn't use it at all).
+    pub fn set_logarithmic_depth(&mut self, enabled: bool) {
+        self.sync();
+        self.backend.get().set_logarithmic_depth(enabled)
+    }
+
+    pub fn logarithmic_depth(&self) -> bool {
+        self.sync();
+        self.backend.get().logarithmic_depth()
+    }
+
+    // Sets up the grid with its shader, set_grid only changes the settings until this is called
+    pub fn init_grid(
+        &mut self,
+        vertex_binary: Vec<u8>,
+        fragment_binary: Vec<u8>,
+    ) -> Result<(), ShaderError> {
+        self.sync();
+        self.backend
+            .get()
+            .init_grid(&vertex_binary, &fragment_binary)?;
+        self.grid_binaries = Some((vertex_binary, fragment_binary));
+        Ok(())
+    }
+
+    // A grid on the ground (y = 0) with lines every spacing units, fading out by fade_distance
+    // away from the camera. It's drawn in the main view after the models, so they cover it.
+    pub fn set_grid(&mut self, enabled: bool, spacing: f32, color: Color, fade_distance: f32) {
+        if !spacing.is_normal() || spacing < 0.0 || fade_distance.is_nan() || fade_distance <= 0.0 {
+            warn!(
+                "Grid spacing and fade distance have to be positive, not {spacing} and \
+                 {fade_distance}"
+            );
+            return;
+        }
+        if enabled && self.grid_binaries.is_none() {
+            warn!("Enabling grid before it's set up, it won't be drawn");
+        }
+
+        self.sync();
+        self.backend.get().set_grid(GridSettings {
+            enabled,
+            spacing,
+            color,
+            fade_distance,
+        })
+    }
+
+    pub fn grid(&self) -> GridSettings {
+        self.sync();
+        self.backend.get().grid()
+    }
+
+    // Sets up the deferred lighting pass with its shader, deferred rendering can't be turned on
+    // until this is called
+    pub fn init_lighting(
+        &mut self,
+        vertex_binary: Vec<u8>,
+        fragment_binary: Vec<u8>,
+    ) -> Result<(), ShaderError> {
+        self.sync();
+        self.backend
+            .get()
+            .init_lighting(&vertex_binary, &fragment_binary)?;
+        self.lighting_binaries = Some((vertex_binary, fragment_binary));
+        Ok(())
+    }
+
+    // How render_scene draws, returns the mode that's actually in use. Deferred makes the G-buffer
+    // (a render target called gbuffer with GBUFFER_FORMATS), forward gets rid of it again.
+    pub fn set_rendering_mode(&mut self, mode: RenderingMode) -> RenderingMode {
+        if mode == self.rendering_mode {
+            return mode;
+        }
+        if mode == RenderingMode::Deferred && self.lighting_binaries.is_none() {
+            warn!("Deferred rendering needs the lighting pass set up, staying forward");
+            return self.rendering_mode;
+        }
+
+        info!("Switching to {mode:?} rendering");
+        match mode {
+            RenderingMode::Forward => {
+                if let Some(gbuffer) = self.gbuffer.take() {
+                    self.render_targets.remove(gbuffer.get().name());
+                    self.sync();
+                    self.backend.get().wait_idle();
+                    gbuffer.get_mut().destroy(self);
+                    gbuffer.poison();
+                }
+            }
+            RenderingMode::Deferred => {
+                match RenderTarget::new_multi(self, "gbuffer", &GBUFFER_FORMATS) {
+                    Ok(gbuffer) => self.gbuffer = Some(gbuffer),
+                    Err(err) => {
+                        error!("Failed to create G-buffer, staying forward: {err:?}");
+                        return self.rendering_mode;
+                    }
+                }
+            }
+        }
+        self.rendering_mode = mode;
+        mode
+    }
+
+    pub fn rendering_mode(&self) -> RenderingMode {
+        self.rendering_mode
+    }
+
+    // Every light deferred rendering adds up, it doesn't limit how many there are. They're kept
+    // with the lighting pass, so it has to be set up first.
+    pub fn set_lights(&mut self, lights: Vec<Light>) {
+        self.sync();
+        self.backend.get().set_lights(lights)
+    }
+
+    pub fn lights(&self) -> Vec<Light> {
+        self.sync();
+        self.backend.get().lights().to_vec()
+    }
+
+    // Added to every lit pixel, lights only add on top of it
+    pub fn set_ambient_light(&mut self, color: Color) {
+        self.sync();
+        self.backend.get().set_ambient_light(color)
+    }
+
+    pub fn ambient_light(&self) -> Color {
+        self.sync();
+        self.backend.get().ambient_light()
+    }
+
+    // Returns whether exclusive fullscreen is in use, see the backend for when it's available and
+    // what happens on alt-tab. Without it the window goes borderless fullscreen instead.
+    pub fn set_fullscreen_exclusive(
+        &mut self,
+        video: &mut dyn SurfaceProvider,
+        enabled: bool,
+    ) -> bool {
+        self.sync();
+        self.backend.get().set_fullscreen_exclusive(video, enabled)
+    }
+
+    pub fn fullscreen_exclusive(&self) -> bool {
+        self.sync();
+        self.backend.get().fullscreen_exclusive()
+    }
+
+    pub fn fullscreen_exclusive_supported(&self) -> bool {
+        self.sync();
+        self.backend.get().fullscreen_exclusive_supported()
+    }
+
+    // How many frames the CPU can get ahead of the GPU, between 1 and the backend's frame count
+    pub fn set_max_frame_latency(&mut self, frames: usize) {
+        self.sync();
+        self.backend.get().set_max_frame_latency(frames)
+    }
+
+    // Whether the latency limit waits for presents, returns whether it's actually being used
+    pub fn set_present_wait(&mut self, enabled: bool) -> bool {
+        self.sync();
+        self.backend.get().set_present_wait(enabled)
+    }
+
+    pub fn set_render_scale(&mut self, scale: f32) {
+        self.sync();
+        self.backend.get().set_render_scale(scale)
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.sync();
+        self.backend.get().render_scale()
+    }
+
+    pub fn swapchain_image_count(&self) -> usize {
+        self.sync();
+        self.backend.get().swapchain_image_count()
+    }
+
+    // How many frames can be recorded before the oldest one has to finish on the GPU, so anything
+    // written every frame needs this many copies to not stomp on one still in use
+    pub fn frames_in_flight(&self) -> usize {
+        render_impl::FRAME_COUNT
+    }
+
+    // Which of the frames_in_flight copies this frame should use. Moves on by one every present,
+    // so read it after begin_cmds.
+    pub fn frame_index(&self) -> usize {
+        self.sync();
+        self.backend.get().frame_index()
+    }
+
+    // The swap chain image being drawn to, from 0 to swapchain_image_count. It's whatever the
+    // presentation engine hands back, so it doesn't follow frame_index and can repeat or skip,
+    // which makes it only good for things tied to the image itself. Only valid between
+    // begin_cmds and present.
+    pub fn image_index(&self) -> usize {
+        self.sync();
+        self.backend.get().image_index()
+    }
+
+    pub fn gpu_time(&self) -> f64 {
+        self.sync();
+        self.backend.get().gpu_time()
+    }
+
+    // Utilization, temperature and VRAM use, for overlays and thermal throttling reports. Needs
+    // the gpu_telemetry feature and NVML (NVIDIA) or the driver's hwmon files (Linux, mainly AMD),
+    // otherwise it's None. It asks the driver every time, so not every frame.
+    pub fn gpu_telemetry(&self) -> Option<GpuTelemetry> {
+        #[cfg(feature = "gpu_telemetry")]
+        {
+            self.sync();
+            let (vendor_id, device_id) = self.backend.get().gpu_ids();
+            crate::platform::telemetry::query(vendor_id, device_id)
+        }
+        #[cfg(not(feature = "gpu_telemetry"))]
+        None
+    }
+
+    // For the last frame presented
+    pub fn render_stats(&self) -> RenderStats {
+        self.sync();
+        RenderStats {
+            triangles_submitted: self.last_triangles_submitted,
+            ..self.backend.get().render_stats()
+        }
+    }
+
+    // Captures the next frame that gets presented without waiting for the GPU. Poll it with
+    // poll_readback, it's ready a few frames later (however many frames the GPU is behind), so
+    // the pixels are from an earlier frame than whatever's on screen by then.
+    pub fn request_readback(&mut self) -> ReadbackHandle {
+        self.sync();
+        ReadbackHandle(self.backend.get().request_readback())
+    }
+
+    // None while the GPU is still working on it, the pixels once it's done. It can only be taken
+    // once.
+    pub fn poll_readback(&mut self, handle: ReadbackHandle) -> Option<image::RgbaImage> {
+        self.sync();
+        self.backend.get().take_readback(handle.0)
+    }
+
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, RenderEvent> {
+        self.sync();
+        self.backend.get().drain_events()
+    }
+
+    pub fn queue_info(&self) -> render_impl::QueueInfo {
+        self.sync();
+        self.backend.get().queue_info()
+    }
+
+    pub fn fence_pool_stats(&self) -> render_impl::FencePoolStats {
+        self.sync();
+        self.backend.get().fence_pool_stats()
+    }
+
+    pub fn frame_resource_stats(&self) -> render_impl::FrameResourceStats {
+        self.sync();
+        self.backend.get().frame_resource_stats()
+    }
+
+    // Errors the validation layers reported for this render system since it was made, always 0
+    // without them
+    pub fn validation_errors(&self) -> usize {
+        self.sync();
+        self.backend.get().validation_errors()
+    }
+
+    // A command buffer of its own for a pass this frame (shadows, say), already begun. The same
+    // purpose gives the same one, and they're all submitted before the frame's main one. None
+    // outside of a frame.
+    pub fn pass_cmds(&mut self, purpose: &str) -> Option<render_impl::PassCommandBuffer> {
+        self.sync();
+        self.backend.get().pass_cmds(purpose)
+    }
+
+    // Renders every loaded model for frame_count frames without touching window events
+    pub fn run_benchmark(
+        &mut self,
+        video: &dyn SurfaceProvider,
+        frame_count: u32,
+    ) -> BenchmarkStats {
+        info!("Running benchmark for {frame_count} frame(s)");
+
+        let models: Vec<ThingHolder<Model>> = self.models.values().cloned().collect();
+        let mut frame_times = Vec::with_capacity(frame_count as usize);
+        self.sync();
+        self.backend.get().reset_gpu_time_total();
+
+        for _ in 0..frame_count {
+            let start = time::Instant::now();
+
+            self.begin_cmds(video);
+            for model in &models {
+                model.get().render(self);
+            }
+            self.present();
+
+            frame_times.push(start.elapsed().as_secs_f64() * 1000.0);
+        }
+
+        self.sync();
+        let stats = BenchmarkStats::new(frame_times, self.backend.get().gpu_time_total());
+        info!("Benchmark finished: {stats:#?}");
+
+        stats
+    }
+
+    pub fn unload_resources(&mut self) {
+        self.sync();
+        let backend = self.backend.get();
+        let loaded = backend.is_initialized() && backend.is_loaded();
+        drop(backend);
+        if loaded {
+            info!("Unloading resources");
+            // frames in flight could still be sampling them
+            self.backend.get().wait_idle();
+            for texture in self.textures.values() {
+                texture.get_mut().handle.destroy(&self.backend.get());
+            }
+            for atlas in self.atlases.values() {
+                atlas.get_mut().destroy(&self.backend.get());
+            }
+            self.backend.get().unload_resources();
+            info!("Done unloading resources");
+        }
+    }
+
+    // The GPUs the renderer could be using, best first, for listing in a settings menu. Unlike
+    // enumerate_gpus this doesn't make another instance.
+    pub fn available_gpus(&self) -> Vec<GpuDescription> {
+        self.sync();
+        self.backend.get().available_gpus()
+    }
+
+    // Moves everything to the first GPU with this name, see reinit_on_gpu. False if there isn't
+    // one, true without doing anything if it's already the one being used.
+    pub fn select_gpu_by_name(&mut self, video: &dyn SurfaceProvider, name: &str) -> bool {
+        let Some(gpu) = self
+            .available_gpus()
+            .into_iter()
+            .find(|gpu| gpu.name == name)
+        else {
+            warn!("There's no GPU called {name}");
+            return false;
+        };
+
+        if !gpu.selected {
+            self.reinit_on_gpu(video, gpu.index);
+        }
+        true
+    }
+
+    // Recreates the device on another GPU and rebuilds every resource from its retained CPU data,
+    // mainly useful for testing that nothing holds on to handles from the old device
+    pub(crate) fn reinit_on_gpu(&mut self, video: &dyn SurfaceProvider, gpu_idx: usize) {
+        info!("Reinitializing render system on GPU {gpu_idx}");
+        self.sync();
+
+        let loaded = self.backend.get().is_loaded();
+        self.unload_resources();
+        for shader in self.shaders.values() {
+            shader.get().destroy(self);
+        }
+        self.destroy_compute_handles();
+        #[cfg(feature = "egui")]
+        let gui = self
+            .gui
+            .take()
+            .map(|gui| gui.into_binaries(&self.backend.get()));
+        let text = self
+            .text
+            .take()
+            .map(|text| text.into_parts(&self.backend.get()));
+        let grid = self.grid_binaries.take();
+        // the lights go with the lighting pass
+        let lighting = self
+            .lighting_binaries
+            .take()
+            .map(|binaries| (binaries, self.backend.get().lights().to_vec()));
+
+        // made again from scratch, anything holding onto the old ones can't use them
+        let old_targets: Vec<_> = self
+            .render_targets
+            .drain()
+            .map(|(_, target)| target)
+            .collect();
+        let targets: Vec<_> = old_targets
+            .iter()
+            .map(|target| {
+                let target = &mut *target.get_mut();
+                target.destroy(self);
+                (target.name.clone(), target.formats.clone())
+            })
+            .collect();
+        old_targets.iter().for_each(|target| target.poison());
+
+        self.backend.get().recreate_device(video, gpu_idx);
+        self.create_shader_handles();
+        for (name, formats) in targets {
+            match RenderTarget::new_multi(self, &name, &formats) {
+                Ok(target) if self.gbuffer.is_some() && name == "gbuffer" => {
+                    self.gbuffer = Some(target)
+                }
+                Ok(_) => {}
+                Err(err) => error!("Failed to recreate render target {name}: {err:?}"),
+            }
+        }
+        self.create_compute_handles();
+        #[cfg(feature = "egui")]
+        if let Some((vertex_binary, fragment_binary)) = gui {
+            if let Err(err) = self.init_gui(vertex_binary, fragment_binary) {
+                error!("Failed to recreate GUI: {err:?}");
+            }
+        }
+        if let Some((vertex_binary, fragment_binary, cache)) = text {
+            if let Err(err) = self.init_text(vertex_binary, fragment_binary, cache) {
+                error!("Failed to recreate text: {err:?}");
+            }
+        }
+        if let Some((vertex_binary, fragment_binary)) = grid {
+            if let Err(err) = self.init_grid(vertex_binary, fragment_binary) {
+                error!("Failed to recreate grid: {err:?}");
+            }
+        }
+        if let Some(((vertex_binary, fragment_binary), lights)) = lighting {
+            match self.init_lighting(vertex_binary, fragment_binary) {
+                Ok(()) => self.set_lights(lights),
+                Err(err) => error!("Failed to recreate lighting pass: {err:?}"),
+            }
+        }
+
+        if loaded {
+            if let Err(err) = self.load_resources() {
+                error!("Failed to reload resources on GPU {gpu_idx}: {err:?}");
+            }
+        }
+
+        info!("Render system reinitialized on GPU {gpu_idx}");
+    }
+
+    // Rebuilds the backend side of every shader from the SPIR-V kept around on the CPU
+    fn create_shader_handles(&mut self) {
+        for shader in self.shaders.values() {
+            let shader = &mut *shader.get_mut();
+            shader.handle = match shader.create_handle(&self.backend.get()) {
+                Ok(handle) => handle,
+                Err(err) => panic!("Failed to recreate shader {}: {err:?}", shader.name),
+            };
+        }
+    }
+
+    fn destroy_compute_handles(&mut self) {
+        for material in self.materials.values() {
+            let material = &mut *material.get_mut();
+            if let Some(handle) = material
+                .prepass
+                .as_mut()
+                .and_then(|prepass| prepass.handle.take())
+            {
+                handle.destroy(&self.backend.get());
+            }
+        }
+        for shader in self.compute_shaders.values() {
+            shader.get().destroy(self);
+        }
+    }
+
+    fn create_compute_handles(&mut self) {
+        for shader in self.compute_shaders.values() {
+            let shader = &mut *shader.get_mut();
+            shader.handle = match render_impl::ComputeShaderData::new(
+                &self.backend.get(),
+                &shader.name,
+                shader.binary.clone(),
+            ) {
+                Ok(handle) => handle,
+                Err(err) => panic!("Failed to recreate compute shader {}: {err:?}", shader.name),
+            };
+        }
+        for material in self.materials.values() {
+            let material = &mut *material.get_mut();
+            if let Some(prepass) = material.prepass.as_mut() {
+                prepass.handle = Some(render_impl::PrepassData::new(
+                    &self.backend.get(),
+                    &material.name,
+                    prepass.buffer_size,
+                    prepass.group_count,
+                ));
+            }
+        }
+    }
+
+    // Overrides the filtering on every material's textures at once, falls back to the closest
+    // thing the GPU supports and returns that
+    pub fn set_texture_filtering(&mut self, filtering: TextureFiltering) -> TextureFiltering {
+        self.sync();
+        let filtering = self.backend.get().set_texture_filtering(filtering);
+        for texture in self.textures.values() {
+            texture.get().handle.write_descriptor(&self.backend.get());
+        }
+        for atlas in self.atlases.values() {
+            atlas.get().write_descriptors(&self.backend.get());
+        }
+        filtering
+    }
+
+    pub fn texture_filtering(&self) -> TextureFiltering {
+        self.sync();
+        self.backend.get().texture_filtering()
+    }
+
+    // How much texture memory streaming gets to use, see stream_textures. The small mips are
+    // always resident, so the real total can go over if the budget's too small for even those.
+    pub fn set_texture_budget(&mut self, budget: Option<u64>) {
+        info!(
+            "Setting texture budget to {}",
+            budget.map_or(String::from("unlimited"), |budget| format!(
+                "{budget} bytes"
+            ))
+        );
+        self.texture_budget = budget;
+    }
+
+    pub fn texture_budget(&self) -> Option<u64> {
+        self.texture_budget
+    }
+
+    // What the resident mips of every texture add up to
+    pub fn texture_memory(&self) -> u64 {
+        self.textures
+            .values()
+            .map(|texture| texture.get().resident_size())
+            .sum()
+    }
+
+    // Decides which mips of each texture are resident, once a frame. Every texture gets its
+    // small mips, then the rest of the budget goes to the highest priority textures first, a
+    // level at a time from the bottom. Anything that doesn't fit anymore gets its high mips
+    // evicted, so a texture that loses priority gives its memory back the next frame.
+    fn stream_textures(&mut self) {
+        let mut textures: Vec<_> = self.textures.values().collect();
+        let Some(budget) = self.texture_budget else {
+            for texture in textures {
+                texture.get_mut().set_resident_mip(0);
+            }
+            self.upload_streamed_textures();
+            return;
+        };
+
+        textures.sort_by(|a, b| {
+            let (a, b) = (a.get(), b.get());
+            b.stream_priority
+                .total_cmp(&a.stream_priority)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        let mut used: u64 = textures
+            .iter()
+            .map(|texture| {
+                let texture = texture.get();
+                texture.size_from(texture.tail_mip())
+            })
+            .sum();
+        for texture in textures {
+            let texture = &mut *texture.get_mut();
+            let mut level = texture.tail_mip();
+            while level > 0 {
+                let size = texture.mip_size(level - 1);
+                if used + size > budget {
+                    break;
+                }
+                used += size;
+                level -= 1;
+            }
+            texture.set_resident_mip(level);
+        }
+        self.upload_streamed_textures();
+    }
+
+    // Textures whose resident mips changed get their whole image replaced. That waits for the
+    // GPU to be idle, same as changing the filtering, but it only happens when the budget or
+    // priorities change.
+    fn upload_streamed_textures(&mut self) {
+        if !self.backend.get().is_loaded() {
+            return;
+        }
+        for texture in self.textures.values() {
+            let texture = &mut *texture.get_mut();
+            if texture.handle.base_mip() != Some(texture.resident_mip) {
+                // logged already, it's tried again next frame
+                let _ = texture.upload(&mut self.backend.get());
+            }
+        }
+    }
+
+    // Runs the fragment shader per sample instead of per pixel for at least min_fraction of the
+    // samples when MSAA is on, gets rid of specular sparkle at a GPU cost. None turns it off.
+    pub fn set_sample_shading(&mut self, min_fraction: Option<f32>) {
+        self.sync();
+        if min_fraction == self.backend.get().sample_shading() {
+            return;
+        }
+
+        self.backend.get().set_sample_shading(min_fraction);
+        for shader in self.shaders.values() {
+            shader.get().destroy(self);
+        }
+        self.create_shader_handles();
+    }
+
+    // Anything still holding onto a resource after this can't use it, see thing.rs
+    fn poison_resources(&mut self) {
+        debug!("Poisoning resources");
+        self.shaders.drain().for_each(|(_, shader)| shader.poison());
+        self.compute_shaders
+            .drain()
+            .for_each(|(_, shader)| shader.poison());
+        self.models.drain().for_each(|(_, model)| model.poison());
+        self.materials
+            .drain()
+            .for_each(|(_, material)| material.poison());
+        self.textures
+            .drain()
+            .for_each(|(_, texture)| texture.poison());
+        self.atlases.drain().for_each(|(_, atlas)| atlas.poison());
+        self.render_targets
+            .drain()
+            .for_each(|(_, target)| target.poison());
+    }
+
+    pub fn shutdown(mut self) {
+        info!("Render system shutdown started");
+        self.render_thread = None;
+        self.unload_resources();
+        self.backend.get().wait_idle();
+        for shader in self.shaders.values() {
+            shader.get().destroy(&self);
+        }
+        self.destroy_compute_handles();
+        for target in self.render_targets.values() {
+            target.get_mut().destroy(&self);
+        }
+        #[cfg(feature = "egui")]
+        if let Some(gui) = self.gui.take() {
+            gui.destroy(&self.backend.get());
+        }
+        if let Some(text) = self.text.take() {
+            text.destroy(&self.backend.get());
+        }
+        self.poison_resources();
+        self.backend.get().shutdown();
+        info!("Render system shutdown succeeded");
+    }
+}
+
+// Counts for one frame's models. Submitted is everything passed to draw, drawn is what was
+// recorded, which is less only when drawing outside of a frame. Binds are the calls recorded, so
+// they show how well draws are sorted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    pub draw_calls: u64,
+    pub triangles_submitted: u64,
+    pub triangles_drawn: u64,
+    pub pipeline_binds: u64,
+    pub descriptor_binds: u64,
+    pub buffer_binds: u64,
+}
+
+// All times are in milliseconds
+#[derive(Debug, Default)]
+pub struct BenchmarkStats {
+    pub frame_count: usize,
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub total_gpu_time: f64,
+}
+
+impl BenchmarkStats {
+    fn new(mut frame_times: Vec<f64>, total_gpu_time: f64) -> Self {
+        if frame_times.is_empty() {
+            return Self::default();
+        }
+
+        frame_times.sort_by(|a, b| a.total_cmp(b));
+        let last = frame_times.len() - 1;
+        let percentile = |p: f64| frame_times[((last as f64 * p).round() as usize).min(last)];
+
+        Self {
+            frame_count: frame_times.len(),
+            min: frame_times[0],
+            avg: frame_times.iter().sum::<f64>() / frame_times.len() as f64,
+            max: frame_times[last],
+            p50: percentile(0.50),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            total_gpu_time,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Io(io::Error),
+    Backend(render_impl::ShaderErrorType),
+    // The binary isn't SPIR-V, or it's cut off
+    InvalidSpirv(io::Error),
+    // The GPU is missing a feature the shader needs
+    Unsupported(&'static str),
+    // The vertex descriptor is broken or doesn't have what the vertex shader reads
+    VertexInput(String),
+}
+
+#[derive(Debug)]
+pub enum ResourceError {
+    ShaderNotFound(String),
+    MaterialNotFound(String),
+    TextureNotFound(String),
+    EmptyGeometry,
+    Parse(tobj::LoadError),
+    ParseGltf(gltf::Error),
+    // The backend couldn't make the buffers, everything in load_resources fails together
+    UploadFailed,
+    // Raw vertices that don't fit their descriptor
+    VertexData(String),
+    // Bad formats, or the backend couldn't make the images
+    RenderTarget(String),
+    Io(io::Error),
+    // The resource can't do what was asked of it
+    Unsupported(&'static str),
+}
+
+// Control and evaluation stages, which make the shader draw patches instead of whatever the
+// model's topology is. Without tessellation support on the GPU they're left out.
+#[derive(Clone)]
+pub struct Tessellation {
+    pub control_binary: Vec<u8>,
+    pub evaluation_binary: Vec<u8>,
+    pub patch_control_points: u32,
+}
+
+impl Tessellation {
+    // Triangles, so models made for the shader without tessellation still make sense
+    pub const DEFAULT_PATCH_CONTROL_POINTS: u32 = 3;
+}
+
+pub struct Shader {
+    name: String,
+    vertex_binary: Vec<u8>,
+    fragment_binary: Vec<u8>,
+    tessellation: Option<Tessellation>,
+    geometry_binary: Option<Vec<u8>>,
+    // None for models made of Vertex
+    vertex_descriptor: Option<VertexDescriptor>,
+    // What it draws to, None for the window
+    color_formats: Option<Vec<TargetFormat>>,
+    handle: render_impl::ShaderData,
+    poison: Poison,
+}
+
+impl Shader {
+    pub fn new(
+        state: &mut crate::engine::State,
+        name: &str,
+    ) -> Result<ThingHolder<Self>, ShaderError> {
+        info!("Creating shader {name}");
+
+        let vertex_path = format!(
+            "shaders/{name}{}",
+            render_impl::ShaderData::vertex_extension()
+        );
+        let fragment_path = format!(
+            "shaders/{name}{}",
+            render_impl::ShaderData::fragment_extension()
+        );
+        let vertex_binary = match state.vfs().read(&vertex_path) {
+            Ok(data) => data,
+            Err(err) => {
+                error!("Failed to read vertex binary {vertex_path} for shader {name}: {err}");
+                return Err(ShaderError::Io(err));
+            }
+        };
+        let fragment_binary = match state.vfs().read(&fragment_path) {
+            Ok(data) => data,
+            Err(err) => {
+                error!("Failed to read fragment binary {fragment_path} for shader {name}: {err}");
+                return Err(ShaderError::Io(err));
+            }
+        };
+        let tessellation = Self::read_tessellation(state, name)?;
+        let geometry_path = format!(
+            "shaders/{name}{}",
+            render_impl::ShaderData::geometry_extension()
+        );
+        let geometry_binary = match state.vfs().read(&geometry_path) {
+            Ok(data) => {
+                debug!("Shader {name} has a geometry stage");
+                Some(data)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                error!("Failed to read geometry binary {geometry_path} for shader {name}: {err}");
+                return Err(ShaderError::Io(err));
+            }
+        };
+        Self::from_bytes(
+            state.render(),
+            name,
+            vertex_binary,
+            fragment_binary,
+            tessellation,
+            geometry_binary,
+        )
+    }
+
+    // The tessellation stages are optional, but it's both or neither
+    fn read_tessellation(
+        state: &crate::engine::State,
+        name: &str,
+    ) -> Result<Option<Tessellation>, ShaderError> {
+        let control_path = format!(
+            "shaders/{name}{}",
+            render_impl::ShaderData::tessellation_control_extension()
+        );
+        let evaluation_path = format!(
+            "shaders/{name}{}",
+            render_impl::ShaderData::tessellation_evaluation_extension()
+        );
+        match (
+            state.vfs().read(&control_path),
+            state.vfs().read(&evaluation_path),
+        ) {
+            (Ok(control_binary), Ok(evaluation_binary)) => {
+                debug!("Shader {name} has tessellation stages");
+                Ok(Some(Tessellation {
+                    control_binary,
+                    evaluation_binary,
+                    patch_control_points: Tessellation::DEFAULT_PATCH_CONTROL_POINTS,
+                }))
+            }
+            (Err(control_err), Err(evaluation_err))
+                if control_err.kind() == io::ErrorKind::NotFound
+                    && evaluation_err.kind() == io::ErrorKind::NotFound =>
+            {
+                Ok(None)
+            }
+            (Err(err), _) => {
+                error!(
+                    "Failed to read tessellation control binary {control_path} for shader \
+                     {name}: {err}"
+                );
+                Err(ShaderError::Io(err))
+            }
+            (_, Err(err)) => {
+                error!(
+                    "Failed to read tessellation evaluation binary {evaluation_path} for shader \
+                     {name}: {err}"
+                );
+                Err(ShaderError::Io(err))
+            }
+        }
+    }
+
+    pub fn from_bytes(
+        state: &mut State,
+        name: &str,
+        vertex_binary: Vec<u8>,
+        fragment_binary: Vec<u8>,
+        tessellation: Option<Tessellation>,
+        geometry_binary: Option<Vec<u8>>,
+    ) -> Result<ThingHolder<Self>, ShaderError> {
+        state.sync();
+        let handle = match render_impl::ShaderData::new(
+            &state.backend.get(),
+            name,
+            vertex_binary.clone(),
+            fragment_binary.clone(),
+            tessellation.as_ref(),
+            geometry_binary.as_deref(),
+            None,
+            None,
+        ) {
+            Ok(handle) => handle,
+            Err(err) => {
+                error!("Failed to create shader {name}: {err:?}");
+                return Err(err);
+            }
+        };
+
+        let poison = Poison::default();
+        let shader = Arc::new(Thing::with_poison(
+            Self {
+                name: String::from(name),
+                vertex_binary,
+                fragment_binary,
+                tessellation,
+                geometry_binary,
+                vertex_descriptor: None,
+                color_formats: None,
+                handle,
+                poison: poison.clone(),
+            },
+            poison,
+        ));
+        state.shaders.insert(String::from(name), shader.clone());
+
+        info!("Shader {name} created successfully");
+
+        Ok(shader)
+    }
+
+    fn create_handle(
+        &self,
+        backend: &render_impl::State,
+    ) -> Result<render_impl::ShaderData, ShaderError> {
+        render_impl::ShaderData::new(
+            backend,
+            &self.name,
+            self.vertex_binary.clone(),
+            self.fragment_binary.clone(),
+            self.tessellation.as_ref(),
+            self.geometry_binary.as_deref(),
+            self.vertex_descriptor.as_ref(),
+            self.color_formats.as_deref(),
+        )
+    }
+
+    // Rebuilds the shader to read vertices laid out like the descriptor instead of Vertex, or
+    // back to Vertex with None. The descriptor has to have everything the vertex shader reads.
+    pub fn set_vertex_descriptor(
+        &mut self,
+        state: &State,
+        vertex_descriptor: Option<VertexDescriptor>,
+    ) -> Result<(), ShaderError> {
+        if vertex_descriptor == self.vertex_descriptor {
+            return Ok(());
+        }
+
+        if let Some(descriptor) = &vertex_descriptor {
+            let result = descriptor.validate().and_then(|_| {
+                let inputs = spirv::vertex_inputs(&self.vertex_binary)?;
+                descriptor.check_inputs(&inputs)
+            });
+            if let Err(err) = result {
+                error!(
+                    "Vertex descriptor doesn't work for shader {}: {err}",
+                    self.name
+                );
+                return Err(ShaderError::VertexInput(err));
+            }
+        }
+
+        debug!(
+            "Setting vertex descriptor of shader {} to {vertex_descriptor:?}",
+            self.name
+        );
+        let old_descriptor = mem::replace(&mut self.vertex_descriptor, vertex_descriptor);
+        state.sync();
+        let handle = match self.create_handle(&state.backend.get()) {
+            Ok(handle) => handle,
+            Err(err) => {
+                self.vertex_descriptor = old_descriptor;
+                return Err(err);
+            }
+        };
+        state.backend.get().wait_idle();
+        mem::replace(&mut self.handle, handle).destroy(&state.backend.get());
+        Ok(())
+    }
+
+    pub fn vertex_descriptor(&self) -> Option<&VertexDescriptor> {
+        self.vertex_descriptor.as_ref()
+    }
+
+    // Rebuilds the shader to draw to render targets with these formats, in the order of the
+    // fragment shader's outputs (layout(location = N) goes to formats[N]), or back to the window
+    // with None. It can only draw to one or the other.
+    pub fn set_color_formats(
+        &mut self,
+        state: &State,
+        color_formats: Option<Vec<TargetFormat>>,
+    ) -> Result<(), ShaderError> {
+        if color_formats == self.color_formats {
+            return Ok(());
+        }
+        if let Some(formats) = &color_formats {
+            if formats.is_empty() {
+                warn!(
+                    "Shader {} needs at least one colour format, keeping it as it is",
+                    self.name
+                );
+                return Ok(());
+            }
+            let max = state.backend.get().max_color_attachments();
+            if formats.len() > max as usize {
+                error!(
+                    "Shader {} has {} colour attachments, the GPU only supports {max}",
+                    self.name,
+                    formats.len()
+                );
+                return Err(ShaderError::Unsupported("that many colour attachments"));
+            }
+        }
+
+        debug!(
+            "Setting colour formats of shader {} to {color_formats:?}",
+            self.name
+        );
+        let old_formats = mem::replace(&mut self.color_formats, color_formats);
+        state.sync();
+        let handle = match self.create_handle(&state.backend.get()) {
+            Ok(handle) => handle,
+            Err(err) => {
+                self.color_formats = old_formats;
+                return Err(err);
+            }
+        };
+        state.backend.get().wait_idle();
+        mem::replace(&mut self.handle, handle).destroy(&state.backend.get());
+        Ok(())
+    }
+
+    pub fn color_formats(&self) -> Option<&[TargetFormat]> {
+        self.color_formats.as_deref()
+    }
+
+    // Rebuilds the shader, since pipelines have the patch size baked in
+    pub fn set_patch_control_points(
+        &mut self,
+        state: &State,
+        patch_control_points: u32,
+    ) -> Result<(), ShaderError> {
+        let Some(tessellation) = self.tessellation.as_mut() else {
+            warn!(
+                "Shader {} has no tessellation stages, ignoring the patch size",
+                self.name
+            );
+            return Ok(());
+        };
+        if tessellation.patch_control_points == patch_control_points {
+            return Ok(());
+        }
+
+        debug!(
+            "Setting patch size of shader {} to {patch_control_points}",
+            self.name
+        );
+        tessellation.patch_control_points = patch_control_points;
+        state.sync();
+        let handle = self.create_handle(&state.backend.get())?;
+        state.backend.get().wait_idle();
+        mem::replace(&mut self.handle, handle).destroy(&state.backend.get());
+        Ok(())
+    }
+
+    pub fn destroy(&self, state: &State) {
+        if self.poison.is_poisoned() {
+            warn!(
+                "Not destroying shader {}, it's from a render system that was shut down",
+                self.name
+            );
+            return;
+        }
+        state.sync();
+        self.handle.destroy(&state.backend.get());
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn tessellation(&self) -> Option<&Tessellation> {
+        self.tessellation.as_ref()
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct UniformData {
+    model: Matrix4<f32>,
+    view: Matrix4<f32>,
+    projection: Matrix4<f32>,
+    // 1 / log2(far + 1), or 0 without logarithmic depth
+    logarithmic_depth: f32,
+}
+
+impl UniformData {
+    pub fn set_logarithmic_depth(&mut self, far: f32) {
+        self.logarithmic_depth = 1.0 / (far + 1.0).log2();
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(self as *const Self as *const u8, mem::size_of::<Self>())
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GridSettings {
+    pub enabled: bool,
+    pub spacing: f32,
+    pub color: Color,
+    pub fade_distance: f32,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            spacing: 1.0,
+            color: Color::from_srgba8(128, 128, 128, 192),
+            fade_distance: 100.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompareOp {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    IncrementAndClamp,
+    DecrementAndClamp,
+    Invert,
+    IncrementAndWrap,
+    DecrementAndWrap,
+}
+
+// The stencil value is compared as (reference & compare_mask) compare (stencil & compare_mask),
+// then one of the ops is applied depending on whether that and the depth test passed. Only
+// write_mask's bits get written. The stencil buffer is 8 bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StencilState {
+    pub compare: CompareOp,
+    pub pass: StencilOp,
+    pub fail: StencilOp,
+    pub depth_fail: StencilOp,
+    pub reference: u32,
+    pub compare_mask: u32,
+    pub write_mask: u32,
+}
+
+// Passes everything and writes nothing
+impl Default for StencilState {
+    fn default() -> Self {
+        Self {
+            compare: CompareOp::Always,
+            pass: StencilOp::Keep,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            reference: 0,
+            compare_mask: 0xff,
+            write_mask: 0xff,
+        }
+    }
+}
+
+// How render_with_stencil_mask draws the mask and then tests what's masked against it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StencilMaskSettings {
+    pub mask: StencilState,
+    pub masked: StencilState,
+    pub mask_writes_depth: bool,
+}
+
+// The mask writes 1 wherever it's drawn and isn't hidden, what's masked is drawn where it's 1
+impl Default for StencilMaskSettings {
+    fn default() -> Self {
+        Self {
+            mask: StencilState {
+                pass: StencilOp::Replace,
+                reference: 1,
+                ..Default::default()
+            },
+            masked: StencilState {
+                compare: CompareOp::Equal,
+                reference: 1,
+                ..Default::default()
+            },
+            mask_writes_depth: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderingMode {
+    #[default]
+    Forward,
+    // Opaque things go through the G-buffer and get lit all at once, so lots of lights are cheap
+    Deferred,
+}
+
+// Albedo, normal, position (w is 0 where nothing was drawn) and material (specular strength and
+// shininess / 256), what the lighting pass reads
+pub const GBUFFER_FORMATS: [TargetFormat; 4] = [
+    TargetFormat::Rgba8Srgb,
+    TargetFormat::Rgba16Float,
+    TargetFormat::Rgba32Float,
+    TargetFormat::Rgba8,
+];
+
+// A point light, it fades out smoothly to nothing at its range
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub position: Point3<f32>,
+    pub color: Color,
+    pub intensity: f32,
+    pub range: f32,
+}
+
+// Axis aligned, in model space for models
+#[derive(Clone, Copy, Debug)]
+pub struct Bounds {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Bounds {
+    pub fn of(vertices: &[Vertex]) -> Self {
+        let mut bounds = Self {
+            min: Vector3::repeat(f32::INFINITY),
+            max: Vector3::repeat(f32::NEG_INFINITY),
+        };
+        for vertex in vertices {
+            bounds.min = bounds.min.inf(&vertex.position);
+            bounds.max = bounds.max.sup(&vertex.position);
+        }
+        bounds
+    }
+
+    // Three floats at offset in every vertex
+    fn of_raw(vertices: &[u8], stride: usize, offset: usize) -> Self {
+        let mut bounds = Self {
+            min: Vector3::repeat(f32::INFINITY),
+            max: Vector3::repeat(f32::NEG_INFINITY),
+        };
+        for vertex in vertices.chunks_exact(stride) {
+            let mut position = [0.0; 3];
+            for (i, component) in position.iter_mut().enumerate() {
+                let start = offset + i * mem::size_of::<f32>();
+                *component = f32::from_ne_bytes(
+                    vertex[start..start + mem::size_of::<f32>()]
+                        .try_into()
+                        .unwrap(),
+                );
+            }
+            let position = Vector3::from(position);
+            bounds.min = bounds.min.inf(&position);
+            bounds.max = bounds.max.sup(&position);
+        }
+        bounds
+    }
+
+    // Not infinite, that would give NaNs in the frustum check
+    fn everything() -> Self {
+        Self {
+            min: Vector3::repeat(-f32::MAX),
+            max: Vector3::repeat(f32::MAX),
+        }
+    }
+
+    // The box around all eight corners after the transform, so it grows when rotated
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Self {
+        let mut bounds = Self {
+            min: Vector3::repeat(f32::INFINITY),
+            max: Vector3::repeat(f32::NEG_INFINITY),
+        };
+        for corner in 0..8 {
+            let point = Point3::new(
+                if corner & 1 == 0 {
+                    self.min.x
+                } else {
+                    self.max.x
+                },
+                if corner & 2 == 0 {
+                    self.min.y
+                } else {
+                    self.max.y
+                },
+                if corner & 4 == 0 {
+                    self.min.z
+                } else {
+                    self.max.z
+                },
+            );
+            let point = transform.transform_point(&point).coords;
+            bounds.min = bounds.min.inf(&point);
+            bounds.max = bounds.max.sup(&point);
+        }
+        bounds
+    }
+
+    // Touching counts
+    pub fn overlaps(&self, other: &Self) -> bool {
+        (0..3).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+}
+
+// The planes of the view-projection matrix, pointing in
+struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    fn new(uniform_data: &UniformData) -> Self {
+        let matrix = uniform_data.projection * uniform_data.view * uniform_data.model;
+        let row = |index: usize| matrix.row(index).transpose();
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            // Vulkan's depth goes from 0 to 1
+            row(2),
+            row(3) - row(2),
+        ];
+        Self { planes }
+    }
+
+    // Only false if it's entirely outside one of the planes, so big boxes near the corners can
+    // still get through
+    fn contains(&self, bounds: &Bounds) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane's normal
+            let corner = Vector3::new(
+                if plane.x >= 0.0 {
+                    bounds.max.x
+                } else {
+                    bounds.min.x
+                },
+                if plane.y >= 0.0 {
+                    bounds.max.y
+                } else {
+                    bounds.min.y
+                },
+                if plane.z >= 0.0 {
+                    bounds.max.z
+                } else {
+                    bounds.min.z
+                },
+            );
+            plane.xyz().dot(&corner) + plane.w >= 0.0
+        })
+    }
+}
+
+// In fractions of the window, so it doesn't have to change when the window is resized
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewportRect {
+    pub const FULL: Self = Self {
+        x: 0.0,
+        y: 0.0,
+        width: 1.0,
+        height: 1.0,
+    };
+
+    // Split screen for local multiplayer. 2 is side by side, 3 and 4 are quarters (the last one is
+    // left empty for 3), anything else is the whole window.
+    pub fn split(count: usize, index: usize) -> Self {
+        match count {
+            2 => Self {
+                x: (index % 2) as f32 * 0.5,
+                y: 0.0,
+                width: 0.5,
+                height: 1.0,
+            },
+            3 | 4 => Self {
+                x: (index % 2) as f32 * 0.5,
+                y: (index / 2 % 2) as f32 * 0.5,
+                width: 0.5,
+                height: 0.5,
+            },
+            _ => Self::FULL,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpuType {
+    Discrete,
+    Integrated,
+    Virtual,
+    Cpu,
+    Other,
+}
+
+// Anything the platform can't tell is None
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GpuTelemetry {
+    // Percent of the time the GPU was busy recently
+    pub utilization: Option<f32>,
+    // In degrees Celsius
+    pub temperature: Option<f32>,
+    // In bytes
+    pub vram_used: Option<u64>,
+    pub vram_total: Option<u64>,
+}
+
+// Formats a render target's colour attachments can have. Rgb10A2 and the float ones are what
+// G-buffer normals and HDR colour usually go in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetFormat {
+    Rgba8,
+    Rgba8Srgb,
+    Rgb10A2,
+    Rg16Float,
+    Rgba16Float,
+    R32Float,
+    Rgba32Float,
+}
+
+// Colour attachments that get drawn to in render_to_gbuffer instead of the window, with a depth
+// buffer of their own. They're always the render size and get made again when it changes.
+pub struct RenderTarget {
+    name: String,
+    formats: Vec<TargetFormat>,
+    handle: render_impl::RenderTargetData,
+    poison: Poison,
+}
+
+impl RenderTarget {
+    pub fn new(
+        state: &mut State,
+        name: &str,
+        format: TargetFormat,
+    ) -> Result<ThingHolder<Self>, ResourceError> {
+        Self::new_multi(state, name, &[format])
+    }
+
+    // An attachment for each format, the shaders drawing to it write them with
+    // layout(location = N) in the same order
+    pub fn new_multi(
+        state: &mut State,
+        name: &str,
+        formats: &[TargetFormat],
+    ) -> Result<ThingHolder<Self>, ResourceError> {
+        info!(
+            "Creating render target {name} with {} attachment(s)",
+            formats.len()
+        );
+
+        let max = state.backend.get().max_color_attachments();
+        if formats.is_empty() || formats.len() > max as usize {
+            error!(
+                "Render target {name} has {} colour attachments, it needs 1-{max}",
+                formats.len()
+            );
+            return Err(ResourceError::RenderTarget(format!(
+                "{} colour attachments, the GPU supports 1-{max}",
+                formats.len()
+            )));
+        }
+
+        state.sync();
+        let handle = match render_impl::RenderTargetData::new(&state.backend.get(), name, formats) {
+            Ok(handle) => handle,
+            Err(err) => {
+                error!("Failed to create render target {name}: {err}");
+                return Err(ResourceError::RenderTarget(err.to_string()));
+            }
+        };
+
+        let poison = Poison::default();
+        let target = Arc::new(Thing::with_poison(
+            Self {
+                name: String::from(name),
+                formats: formats.to_vec(),
+                handle,
+                poison: poison.clone(),
+            },
+            poison,
+        ));
+        if let Some(old_target) = state
+            .render_targets
+            .insert(String::from(name), target.clone())
+        {
+            warn!("Replacing render target {name}");
+            state.backend.get().wait_idle();
+            old_target.get_mut().destroy(state);
+            old_target.poison();
+        }
+
+        info!("Render target {name} created successfully");
+
+        Ok(target)
+    }
+
+    pub fn destroy(&mut self, state: &State) {
+        if self.poison.is_poisoned() {
+            warn!(
+                "Not destroying render target {}, it's from a render system that was shut down",
+                self.name
+            );
+            return;
+        }
+        state.sync();
+        self.handle.destroy(&state.backend.get());
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn formats(&self) -> &[TargetFormat] {
+        &self.formats
+    }
+}
+
+// How the texels of a texture are meant to be read. THIS MATTERS: anything that's a colour a
+// person looked at (albedo/diffuse, emissive, UI) was authored in sRGB and has to be Srgb so the
+// GPU converts it to linear when sampling, and anything that's data (normal, roughness, metallic,
+// AO, height maps) has to be Linear or it gets "decoded" when it was never encoded. Getting it
+// backwards doesn't look broken, just subtly off: washed out or too dark colours, lighting that's
+// wrong in a way that's hard to track down.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextureColorSpace {
+    // Albedo and other colour maps, the default
+    #[default]
+    Srgb,
+    // Normal maps and other data
+    Linear,
+}
+
+pub struct RenderTexture {
+    name: String,
+    image: image::RgbaImage,
+    color_space: TextureColorSpace,
+    stream_priority: f32,
+    // The biggest mip that's resident, everything smaller than it is too
+    resident_mip: u32,
+    handle: render_impl::TextureData,
+}
+
+impl RenderTexture {
+    // Mips this size or smaller are always resident
+    const STREAMING_TAIL_SIZE: u32 = 64;
+    const EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+    pub fn new(
+        state: &mut crate::engine::State,
+        name: &str,
+        color_space: TextureColorSpace,
+    ) -> Result<ThingHolder<Self>, image::ImageError> {
+        let mut data = None;
+        for extension in Self::EXTENSIONS {
+            let path = format!("textures/{name}.{extension}");
+            match state.vfs().read(&path) {
+                Ok(bytes) => {
+                    debug!("Found texture {name} at {path}");
+                    data = Some(bytes);
+                    break;
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => {
+                    error!("Failed to read {path} for texture {name}: {err}");
+                    return Err(image::ImageError::IoError(err));
+                }
+            }
+        }
+        let Some(data) = data else {
+            error!(
+                "Texture {name} doesn't exist as any of textures/{name}.{{{}}}",
+                Self::EXTENSIONS.join(",")
+            );
+            return Err(image::ImageError::IoError(io::Error::from(
+                io::ErrorKind::NotFound,
+            )));
+        };
+        Self::from_bytes(state.render(), name, &data, color_space)
+    }
+
+    pub fn from_bytes(
+        state: &mut State,
+        name: &str,
+        data: &[u8],
+        color_space: TextureColorSpace,
+    ) -> Result<ThingHolder<Self>, image::ImageError> {
+        info!("Creating {color_space:?} texture {name}");
+
+        let image = match image::load_from_memory(data) {
+            Ok(image) => image.into_rgba8(),
+            Err(err) => {
+                error!("Failed to decode texture {name}: {err}");
+                return Err(err);
+            }
+        };
+
+        let texture = Arc::new(Thing::new(Self {
+            name: String::from(name),
+            image,
+            color_space,
+            stream_priority: 0.0,
+            resident_mip: 0,
+            handle: render_impl::TextureData::default(),
+        }));
+        {
+            // starts with just the small mips, streaming brings in the rest when there's room
+            let texture = &mut *texture.get_mut();
+            texture.resident_mip = texture.tail_mip();
+            // otherwise load_resources does it, and if this fails streaming tries again
+            state.sync();
+            if state.backend.get().is_loaded() {
+                let _ = texture.upload(&mut state.backend.get());
+            }
+        }
+        state.textures.insert(String::from(name), texture.clone());
+
+        info!("Texture {name} created successfully");
+
+        Ok(texture)
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    pub fn color_space(&self) -> TextureColorSpace {
+        self.color_space
+    }
+
+    // Higher gets its mips streamed in first when there's a texture budget, something like how
+    // much of the screen it covers. Takes effect at the next begin_cmds.
+    pub fn set_stream_priority(&mut self, priority: f32) {
+        if !priority.is_finite() || priority < 0.0 {
+            warn!(
+                "Ignoring stream priority {priority} for texture {}, it has to be finite and not \
+                 negative",
+                self.name
+            );
+            return;
+        }
+        self.stream_priority = priority;
+    }
+
+    pub fn stream_priority(&self) -> f32 {
+        self.stream_priority
+    }
+
+    pub fn mip_count(&self) -> u32 {
+        let (width, height) = self.size();
+        u32::BITS - width.max(height).max(1).leading_zeros()
+    }
+
+    pub fn resident_mip(&self) -> u32 {
+        self.resident_mip
+    }
+
+    pub fn resident_size(&self) -> u64 {
+        self.size_from(self.resident_mip)
+    }
+
+    // In bytes, the image is always RGBA8
+    fn mip_size(&self, level: u32) -> u64 {
+        let (width, height) = self.size();
+        (width >> level).max(1) as u64 * (height >> level).max(1) as u64 * 4
+    }
+
+    fn size_from(&self, level: u32) -> u64 {
+        (level..self.mip_count())
+            .map(|level| self.mip_size(level))
+            .sum()
+    }
+
+    // The first mip that's small enough to always be resident
+    fn tail_mip(&self) -> u32 {
+        let (width, height) = self.size();
+        let largest = width.max(height);
+        let mut level = 0;
+        while largest >> level > Self::STREAMING_TAIL_SIZE {
+            level += 1;
+        }
+        level
+    }
+
+    // From the resident mip down to 1x1. Each level is filtered from the one above it, in the
+    // stored colour space even for sRGB, which darkens high contrast detail a little.
+    fn resident_mips(&self) -> Vec<image::RgbaImage> {
+        let (width, height) = self.size();
+        let mut mips = Vec::new();
+        for level in self.resident_mip..self.mip_count() {
+            let size = ((width >> level).max(1), (height >> level).max(1));
+            let mip = match mips.last() {
+                None if level == 0 => self.image.clone(),
+                None => image::imageops::resize(
+                    &self.image,
+                    size.0,
+                    size.1,
+                    image::imageops::FilterType::Triangle,
+                ),
+                Some(above) => image::imageops::resize(
+                    above,
+                    size.0,
+                    size.1,
+                    image::imageops::FilterType::Triangle,
+                ),
+            };
+            mips.push(mip);
+        }
+        mips
+    }
+
+    // Replaces what's on the GPU with the resident mips
+    fn upload(&mut self, backend: &mut render_impl::State) -> Result<(), ResourceError> {
+        let mips = self.resident_mips();
+        match self.handle.upload(
+            backend,
+            &self.name,
+            self.color_space,
+            self.resident_mip,
+            &mips,
+        ) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                error!("Failed to upload texture {}: {err}", self.name);
+                Err(ResourceError::UploadFailed)
+            }
+        }
+    }
+
+    fn set_resident_mip(&mut self, level: u32) {
+        if level == self.resident_mip {
+            return;
+        }
+        if level < self.resident_mip {
+            debug!(
+                "Streaming in texture {} from mip {} to {level}",
+                self.name, self.resident_mip
+            );
+        } else {
+            debug!(
+                "Evicting texture {} from mip {} to {level}",
+                self.name, self.resident_mip
+            );
+        }
+        self.resident_mip = level;
+    }
+}
+
+pub struct ComputeShader {
+    name: String,
+    binary: Vec<u8>,
+    handle: render_impl::ComputeShaderData,
+    poison: Poison,
+}
+
+impl ComputeShader {
+    pub fn new(
+        state: &mut crate::engine::State,
+        name: &str,
+    ) -> Result<ThingHolder<Self>, ShaderError> {
+        info!("Creating compute shader {name}");
+
+        let path = format!(
+            "shaders/{name}{}",
+            render_impl::ComputeShaderData::extension()
+        );
+        let binary = match state.vfs().read(&path) {
+            Ok(data) => data,
+            Err(err) => {
+                error!("Failed to read binary {path} for compute shader {name}: {err}");
+                return Err(ShaderError::Io(err));
+            }
+        };
+        Self::from_bytes(state.render(), name, binary)
+    }
+
+    pub fn from_bytes(
+        state: &mut State,
+        name: &str,
+        binary: Vec<u8>,
+    ) -> Result<ThingHolder<Self>, ShaderError> {
+        state.sync();
+        let handle =
+            match render_impl::ComputeShaderData::new(&state.backend.get(), name, binary.clone()) {
+                Ok(handle) => handle,
+                Err(err) => {
+                    error!("Failed to create compute shader {name}: {err:?}");
+                    return Err(err);
+                }
+            };
+
+        let poison = Poison::default();
+        let shader = Arc::new(Thing::with_poison(
+            Self {
+                name: String::from(name),
+                binary,
+                handle,
+                poison: poison.clone(),
+            },
+            poison,
+        ));
+        state
+            .compute_shaders
+            .insert(String::from(name), shader.clone());
+
+        info!("Compute shader {name} created successfully");
+
+        Ok(shader)
+    }
+
+    pub fn destroy(&self, state: &State) {
+        if self.poison.is_poisoned() {
+            warn!(
+                "Not destroying compute shader {}, it's from a render system that was shut down",
+                self.name
+            );
+            return;
+        }
+        state.sync();
+        self.handle.destroy(&state.backend.get());
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+}
+
+struct ComputePrepass {
+    shader: ThingHolder<ComputeShader>,
+    buffer_size: u64,
+    group_count: (u32, u32, u32),
+    handle: Option<render_impl::PrepassData>,
+}
+
+pub struct Material {
+    name: String,
+    shader: ThingHolder<Shader>,
+    // Sampled at set 2, binding 0, white without one
+    texture: Option<ThingHolder<RenderTexture>>,
+    // Its page is sampled instead of the texture, the UVs have to be in the region already
+    atlas_region: Option<(ThingHolder<TextureAtlas>, AtlasRegion)>,
+    prepass: Option<ComputePrepass>,
+}
+
+impl Material {
+    pub fn new(
+        state: &mut State,
+        name: &str,
+        shader: &str,
+        texture: Option<&str>,
+    ) -> Result<ThingHolder<Self>, ResourceError> {
+        let texture = match texture {
+            Some(texture) => match state.textures.get(&String::from(texture)) {
+                Some(thing) => Some(thing.clone()),
+                None => {
+                    error!("Texture {texture} for material {name} doesn't exist");
+                    return Err(ResourceError::TextureNotFound(String::from(texture)));
+                }
+            },
+            None => None,
+        };
+        let material = Arc::new(Thing::new(Self {
+            name: String::from(name),
+            shader: match state.shaders.get(&String::from(shader)) {
+                Some(thing) => thing,
+                None => {
+                    error!("Shader {shader} for material {name} doesn't exist");
+                    return Err(ResourceError::ShaderNotFound(String::from(shader)));
+                }
+            }
+            .clone(),
+            texture,
+            atlas_region: None,
+            prepass: None,
+        }));
+        state.materials.insert(String::from(name), material.clone());
+        Ok(material)
+    }
+
+    pub fn set_atlas_region(&mut self, state: &State, atlas: &str, region: &str) -> Result<(), ()> {
+        let atlas = match state.atlases.get(&String::from(atlas)) {
+            Some(thing) => thing,
+            None => {
+                error!("Atlas {atlas} for material {} doesn't exist", self.name);
+                return Err(());
+            }
+        };
+        let region = match atlas.get().region(region) {
+            Some(region) => region,
+            None => {
+                error!(
+                    "Atlas region {region} for material {} doesn't exist",
+                    self.name
+                );
+                return Err(());
+            }
+        };
+
+        self.atlas_region = Some((atlas.clone(), region));
+        Ok(())
+    }
+
+    pub fn texture(&self) -> Option<&ThingHolder<RenderTexture>> {
+        self.texture.as_ref()
+    }
+
+    pub fn atlas_region(&self) -> Option<AtlasRegion> {
+        self.atlas_region.as_ref().map(|(_, region)| *region)
+    }
+
+    // Runs the compute shader every frame before anything gets drawn. It writes to a storage
+    // buffer of buffer_size bytes (set 1, binding 0) that this material's vertex shader can read.
+    pub fn set_compute_prepass(
+        &mut self,
+        state: &mut State,
+        shader: &str,
+        buffer_size: u64,
+        group_count: (u32, u32, u32),
+    ) -> Result<(), ()> {
+        let shader = match state.compute_shaders.get(&String::from(shader)) {
+            Some(thing) => thing.clone(),
+            None => {
+                error!(
+                    "Compute shader {shader} for material {} doesn't exist",
+                    self.name
+                );
+                return Err(());
+            }
+        };
+
+        state.sync();
+        if let Some(handle) = self.prepass.take().and_then(|prepass| prepass.handle) {
+            state.backend.get().wait_idle();
+            handle.destroy(&state.backend.get());
+        }
+
+        self.prepass = Some(ComputePrepass {
+            shader,
+            buffer_size,
+            group_count,
+            handle: Some(render_impl::PrepassData::new(
+                &state.backend.get(),
+                &self.name,
+                buffer_size,
+                group_count,
+            )),
+        });
+        Ok(())
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+}
+
+pub trait Renderable {
+    fn render(&self, state: &mut State);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Vertex {
+    position: Vector3<f32>,
+    texture_coordinate: Vector2<f32>,
+    normal: Vector3<f32>,
+    // Second UV set, for lightmaps and detail textures
+    texture_coordinate_1: Option<Vector2<f32>>,
+    // Linear RGBA, multiplied with the material's colour
+    color: Option<Vector4<f32>>,
+}
+
+impl Vertex {
+    pub fn new(
+        position: Vector3<f32>,
+        texture_coordinate: Vector2<f32>,
+        normal: Vector3<f32>,
+    ) -> Self {
+        Self {
+            position,
+            texture_coordinate,
+            normal,
+            texture_coordinate_1: None,
+            color: None,
+        }
+    }
+
+    pub fn with_texture_coordinate_1(mut self, texture_coordinate_1: Vector2<f32>) -> Self {
+        self.texture_coordinate_1 = Some(texture_coordinate_1);
+        self
+    }
+
+    pub fn with_color(mut self, color: Vector4<f32>) -> Self {
+        self.color = Some(color);
+        self
+    }
+}
+
+// Which optional attributes a model's vertices have. On the GPU a vertex is the position
+// (location 0), texture coordinate (1) and normal (2), followed by whichever optional attributes
+// the format has in the order they're declared here, tightly packed. Models without any of them
+// keep the original 32 byte layout. The second texture coordinate is at location 3, so a shader
+// sampling it (a lightmap, say) should only be used on models that have it. The colour is at
+// location 4, and always there for shaders: models without it get white from the backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VertexFormat {
+    pub texture_coordinate_1: bool,
+    pub color: bool,
+    pub layout: VertexLayout,
+}
+
+// Interleaved is best when shaders use the whole vertex. With the positions in a buffer of their
+// own, shaders whose vertex stage reads nothing but the position (a depth prepass or shadows, say)
+// only read that stream, which is a third as much. The rest of the vertex is still interleaved
+// after the positions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VertexLayout {
+    #[default]
+    Interleaved,
+    SeparatePosition,
+}
+
+impl VertexFormat {
+    // Every format, so the backend can make pipelines for all of them. Same order as the bits of
+    // the index: second texture coordinate, colour, then layout.
+    pub const ALL: [Self; 8] = {
+        let mut all = [Self {
+            texture_coordinate_1: false,
+            color: false,
+            layout: VertexLayout::Interleaved,
+        }; 8];
+        let mut i = 0;
+        while i < all.len() {
+            all[i] = Self {
+                texture_coordinate_1: i & 1 != 0,
+                color: i & 2 != 0,
+                layout: if i & 4 != 0 {
+                    VertexLayout::SeparatePosition
+                } else {
+                    VertexLayout::Interleaved
+                },
+            };
+            i += 1;
+        }
+        all
+    };
+
+    // An attribute is in the format if any vertex has it, the ones that don't get zero (or white
+    // for colours)
+    pub fn of(vertices: &[Vertex]) -> Self {
+        Self {
+            texture_coordinate_1: vertices
+                .iter()
+                .any(|vertex| vertex.texture_coordinate_1.is_some()),
+            color: vertices.iter().any(|vertex| vertex.color.is_some()),
+            layout: VertexLayout::default(),
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        Self::ALL.iter().position(|format| format == self).unwrap()
+    }
+
+    pub fn stride(&self) -> usize {
+        let mut floats = 3 + 2 + 3;
+        if self.texture_coordinate_1 {
+            floats += 2;
+        }
+        if self.color {
+            floats += 4;
+        }
+        floats * mem::size_of::<f32>()
+    }
+
+    // Where the rest of the vertex starts, relative to the start of the vertices, with
+    // SeparatePosition
+    pub fn attributes_offset(&self, vertex_count: usize) -> usize {
+        match self.layout {
+            VertexLayout::Interleaved => 0,
+            VertexLayout::SeparatePosition => vertex_count * Self::POSITION_SIZE,
+        }
+    }
+
+    pub const POSITION_SIZE: usize = 3 * mem::size_of::<f32>();
+
+    // What the backend reads from the model's vertices, not counting the default colour for
+    // models without one. Positions are in here too, even when they're separate.
+    pub fn descriptor(&self) -> VertexDescriptor {
+        let float_size = mem::size_of::<f32>() as u32;
+        let mut attributes = vec![
+            VertexAttribute::new(0, AttributeFormat::Float3, 0),
+            VertexAttribute::new(1, AttributeFormat::Float2, float_size * 3),
+            VertexAttribute::new(2, AttributeFormat::Float3, float_size * 5),
+        ];
+        let mut offset = float_size * 8;
+        if self.texture_coordinate_1 {
+            attributes.push(VertexAttribute::new(3, AttributeFormat::Float2, offset));
+            offset += float_size * 2;
+        }
+        if self.color {
+            attributes.push(VertexAttribute::new(4, AttributeFormat::Float4, offset));
+        }
+
+        VertexDescriptor {
+            stride: self.stride() as u32,
+            attributes,
+        }
+    }
+}
+
+// For vertices that aren't Vertex, laid out however a shader wants them. They're interleaved in
+// one buffer, stride bytes apart. A shader with a descriptor can only draw models with the same
+// one, and models with one can only be drawn by shaders that have it, see
+// Shader::set_vertex_descriptor and Model::from_raw_vertices.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VertexDescriptor {
+    pub stride: u32,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub format: AttributeFormat,
+    // In bytes from the start of the vertex
+    pub offset: u32,
+}
+
+// Float shader inputs read Float and the normalized formats, ivec and uvec read Int and Uint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttributeFormat {
+    Float,
+    Float2,
+    Float3,
+    Float4,
+    Int,
+    Int2,
+    Int3,
+    Int4,
+    Uint,
+    Uint2,
+    Uint3,
+    Uint4,
+    // 0 to 1, for colours
+    Unorm8x4,
+    // -1 to 1, for packed normals and tangents
+    Snorm8x4,
+    Unorm16x2,
+    Snorm16x2,
+}
+
+impl AttributeFormat {
+    pub fn size(&self) -> u32 {
+        match self {
+            Self::Float | Self::Int | Self::Uint => 4,
+            Self::Float2 | Self::Int2 | Self::Uint2 => 8,
+            Self::Float3 | Self::Int3 | Self::Uint3 => 12,
+            Self::Float4 | Self::Int4 | Self::Uint4 => 16,
+            Self::Unorm8x4 | Self::Snorm8x4 | Self::Unorm16x2 | Self::Snorm16x2 => 4,
+        }
+    }
+
+    fn component_type(&self) -> spirv::ComponentType {
+        match self {
+            Self::Int | Self::Int2 | Self::Int3 | Self::Int4 => spirv::ComponentType::Int,
+            Self::Uint | Self::Uint2 | Self::Uint3 | Self::Uint4 => spirv::ComponentType::Uint,
+            _ => spirv::ComponentType::Float,
+        }
+    }
+}
+
+impl VertexAttribute {
+    pub fn new(location: u32, format: AttributeFormat, offset: u32) -> Self {
+        Self {
+            location,
+            format,
+            offset,
+        }
+    }
+}
+
+impl VertexDescriptor {
+    // The least every GPU supports
+    pub const MAX_STRIDE: u32 = 2048;
+
+    // The built in Vertex with every attribute, packed the same way models are
+    pub fn vertex() -> Self {
+        VertexFormat {
+            texture_coordinate_1: true,
+            color: true,
+            layout: VertexLayout::Interleaved,
+        }
+        .descriptor()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.stride == 0 || self.stride > Self::MAX_STRIDE {
+            return Err(format!(
+                "Stride {} isn't between 1 and {}",
+                self.stride,
+                Self::MAX_STRIDE
+            ));
+        }
+        // the indices go right after the vertices, and they have to be aligned
+        if self.stride % 4 != 0 {
+            return Err(format!(
+                "Stride {} isn't a multiple of 4 bytes",
+                self.stride
+            ));
+        }
+        for (i, attribute) in self.attributes.iter().enumerate() {
+            if attribute.offset + attribute.format.size() > self.stride {
+                return Err(format!(
+                    "Attribute at location {} goes past the {} byte stride",
+                    attribute.location, self.stride
+                ));
+            }
+            if self.attributes[..i]
+                .iter()
+                .any(|other| other.location == attribute.location)
+            {
+                return Err(format!(
+                    "There's more than one attribute at location {}",
+                    attribute.location
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Every input the shader reads has to be there with the same kind of number, the component
+    // counts can differ (missing ones are 0, or 1 for alpha)
+    fn check_inputs(&self, inputs: &[spirv::VertexInput]) -> Result<(), String> {
+        for input in inputs {
+            let Some(attribute) = self
+                .attributes
+                .iter()
+                .find(|attribute| attribute.location == input.location)
+            else {
+                return Err(format!(
+                    "The shader reads {} component(s) from location {}, which isn't in the \
+                     descriptor",
+                    input.component_count, input.location
+                ));
+            };
+            if attribute.format.component_type() != input.component_type {
+                return Err(format!(
+                    "The shader reads location {} as {:?}, but it's {:?}",
+                    input.location, input.component_type, attribute.format
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Where the positions are for bounds, location 0 if it's floats
+    fn position(&self) -> Option<u32> {
+        self.attributes
+            .iter()
+            .find(|attribute| {
+                attribute.location == 0
+                    && matches!(
+                        attribute.format,
+                        AttributeFormat::Float3 | AttributeFormat::Float4
+                    )
+            })
+            .map(|attribute| attribute.offset)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Topology {
+    TriangleList,
+    TriangleStrip,
+    LineList,
+    PointList,
+}
+
+pub const PRIMITIVE_RESTART_INDEX: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CoordinateSystem {
+    #[default]
+    YUp,
+    // Like Blender, gets rotated so Z ends up as Y
+    ZUp,
+}
+
+// How to get a model into the engine's space, which is Y up and right handed with counterclockwise
+// front faces and V going up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImportOptions {
+    pub flip_winding: bool,
+    pub flip_uv_y: bool,
+    pub coordinate_system: CoordinateSystem,
+}
+
+impl ImportOptions {
+    // What most exporters write, already the same as the engine
+    pub const OBJ: Self = Self {
+        flip_winding: false,
+        flip_uv_y: false,
+        coordinate_system: CoordinateSystem::YUp,
+    };
+    // Also Y up and right handed, but V goes down
+    pub const GLTF: Self = Self {
+        flip_winding: false,
+        flip_uv_y: true,
+        coordinate_system: CoordinateSystem::YUp,
+    };
+
+    fn convert(&self, vector: Vector3<f32>) -> Vector3<f32> {
+        match self.coordinate_system {
+            CoordinateSystem::YUp => vector,
+            CoordinateSystem::ZUp => Vector3::new(vector.x, vector.z, -vector.y),
+        }
+    }
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self::OBJ
+    }
+}
+
+pub struct Model {
+    name: String,
+    data: Vec<u8>,
+    topology: Topology,
+    vertex_format: VertexFormat,
+    // Set for models made from raw vertices, vertex_format doesn't mean anything then
+    vertex_descriptor: Option<VertexDescriptor>,
+    material: ThingHolder<Material>,
+    bounds: Bounds,
+    handle: render_impl::ModelData,
+    poison: Poison,
+}
+
+impl Model {
+    pub fn new(
+        state: &mut State,
+        name: &str,
+        models: Vec<tobj::Model>,
+        material: &str,
+        options: ImportOptions,
+    ) -> Result<ThingHolder<Self>, ResourceError> {
+        info!("Creating model {name}");
+        let (vertices, indices) = Self::obj_geometry(name, models, options);
+        Self::from_imported(state, name, vertices, indices, material)
+    }
+
+    // glTF can have a second set of texture coordinates (TEXCOORD_1), unlike OBJ. Every triangle
+    // primitive of every mesh ends up in the model, without the nodes' transforms.
+    pub fn from_gltf_bytes(
+        state: &mut State,
+        name: &str,
+        data: &[u8],
+        material: &str,
+        options: ImportOptions,
+    ) -> Result<ThingHolder<Self>, ResourceError> {
+        info!("Creating model {name} from glTF");
+        let (document, buffers, _) = match gltf::import_slice(data) {
+            Ok(imported) => imported,
+            Err(err) => {
+                error!("Failed to parse model {name}: {err}");
+                return Err(ResourceError::ParseGltf(err));
+            }
+        };
+
+        let (vertices, indices) = Self::gltf_geometry(name, &document, &buffers, options);
+        Self::from_imported(state, name, vertices, indices, material)
+    }
+
+    // Doesn't need the state, so queued models can be parsed on the loader threads
+    fn parse_file(
+        name: &str,
+        path: &str,
+        options: ImportOptions,
+    ) -> Result<(Vec<Vertex>, Vec<u32>), ResourceError> {
+        let gltf = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb")
+            });
+        if gltf {
+            let (document, buffers, _) = gltf::import(path).map_err(ResourceError::ParseGltf)?;
+            Ok(Self::gltf_geometry(name, &document, &buffers, options))
+        } else {
+            let (models, _) =
+                tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS).map_err(ResourceError::Parse)?;
+            Ok(Self::obj_geometry(name, models, options))
+        }
+    }
+
+    // Vertices and indices in the engine's space
+    fn obj_geometry(
+        name: &str,
+        models: Vec<tobj::Model>,
+        options: ImportOptions,
+    ) -> (Vec<Vertex>, Vec<u32>) {
+        if options != ImportOptions::default() {
+            debug!("Importing model {name} with {options:?}");
+        }
+
+        // largely based on https://github.com/bwasty/learn-opengl-rs/blob/master/src/model.rs
+        let mut all_vertices = Vec::new();
+        let mut all_indices: Vec<u32> = Vec::new();
+        for model in models {
+            let mesh = model.mesh;
+
+            assert!(!mesh.normals.is_empty() && !mesh.texcoords.is_empty());
+
+            let vertex_count = mesh.positions.len() / 3;
+            let mut vertices = Vec::with_capacity(vertex_count);
+            let (p, t, n, c) = (
+                &mesh.positions,
+                &mesh.texcoords,
+                &mesh.normals,
+                &mesh.vertex_color,
+            );
+            for i in 0..vertex_count {
+                let position = options.convert(Vector3::new(p[i * 3], p[i * 3 + 1], p[i * 3 + 2]));
+                let texture_coordinate = Vector2::new(
+                    t[i * 2],
+                    if options.flip_uv_y {
+                        1.0 - t[i * 2 + 1]
+                    } else {
+                        t[i * 2 + 1]
+                    },
+                );
+                let normal = options.convert(Vector3::new(n[i * 3], n[i * 3 + 1], n[i * 3 + 2]));
+                // OBJ only has one set of texture coordinates
+                let mut vertex = Vertex::new(position, texture_coordinate, normal);
+                // colours after the position (v x y z r g b) are an extension, without alpha
+                if !c.is_empty() {
+                    vertex =
+                        vertex.with_color(Vector4::new(c[i * 3], c[i * 3 + 1], c[i * 3 + 2], 1.0));
+                }
+                vertices.push(vertex)
+            }
+
+            let base = all_vertices.len() as u32;
+            all_vertices.append(&mut vertices);
+            let first_index = all_indices.len();
+            all_indices.extend(mesh.indices.iter().map(|index| index + base));
+            if options.flip_winding {
+                all_indices[first_index..]
+                    .chunks_exact_mut(3)
+                    .for_each(|triangle| triangle.swap(1, 2));
+            }
+        }
+
+        (all_vertices, all_indices)
+    }
+
+    fn gltf_geometry(
+        name: &str,
+        document: &gltf::Document,
+        buffers: &[gltf::buffer::Data],
+        options: ImportOptions,
+    ) -> (Vec<Vertex>, Vec<u32>) {
+        let flip_uv =
+            |[u, v]: [f32; 2]| Vector2::new(u, if options.flip_uv_y { 1.0 - v } else { v });
+        let mut all_vertices = Vec::new();
+        let mut all_indices: Vec<u32> = Vec::new();
+        for primitive in document.meshes().flat_map(|mesh| mesh.primitives()) {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                warn!(
+                    "Skipping {:?} primitive in model {name}, only triangles are supported",
+                    primitive.mode()
+                );
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let positions: Vec<[f32; 3]> = positions.collect();
+            let normals: Vec<[f32; 3]> = reader
+                .read_normals()
+                .map_or_else(|| vec![[0.0; 3]; positions.len()], Iterator::collect);
+            let texture_coordinates: Vec<[f32; 2]> = reader.read_tex_coords(0).map_or_else(
+                || vec![[0.0; 2]; positions.len()],
+                |t| t.into_f32().collect(),
+            );
+            let texture_coordinates_1: Option<Vec<[f32; 2]>> =
+                reader.read_tex_coords(1).map(|t| t.into_f32().collect());
+            // already linear, like Vertex wants
+            let colors: Option<Vec<[f32; 4]>> =
+                reader.read_colors(0).map(|c| c.into_rgba_f32().collect());
+
+            let base = all_vertices.len() as u32;
+            for i in 0..positions.len() {
+                let mut vertex = Vertex::new(
+                    options.convert(Vector3::from(positions[i])),
+                    flip_uv(texture_coordinates[i]),
+                    options.convert(Vector3::from(normals[i])),
+                );
+                if let Some(texture_coordinates_1) = &texture_coordinates_1 {
+                    vertex = vertex.with_texture_coordinate_1(flip_uv(texture_coordinates_1[i]));
+                }
+                if let Some(colors) = &colors {
+                    vertex = vertex.with_color(Vector4::from(colors[i]));
+                }
+                all_vertices.push(vertex);
+            }
+
+            let first_index = all_indices.len();
+            match reader.read_indices() {
+                Some(indices) => all_indices.extend(indices.into_u32().map(|index| index + base)),
+                None => all_indices.extend(base..all_vertices.len() as u32),
+            }
+            if options.flip_winding {
+                all_indices[first_index..]
+                    .chunks_exact_mut(3)
+                    .for_each(|triangle| triangle.swap(1, 2));
+            }
+        }
+
+        (all_vertices, all_indices)
+    }
+
+    // The rest of loading a file once it's in the engine's space
+    fn from_imported(
+        state: &mut State,
+        name: &str,
+        mut all_vertices: Vec<Vertex>,
+        mut all_indices: Vec<u32>,
+        material: &str,
+    ) -> Result<ThingHolder<Self>, ResourceError> {
+        if state.optimize_vertex_cache {
+            let old_acmr = vertex_cache::acmr(&all_indices, 32);
+            all_indices = vertex_cache::optimize_indices(&all_indices, all_vertices.len());
+            let order = vertex_cache::optimize_vertex_order(&mut all_indices, all_vertices.len());
+            all_vertices = order.iter().map(|&vertex| all_vertices[vertex]).collect();
+            debug!(
+                "Optimized vertex cache usage of model {name}, ACMR {old_acmr:.3} -> {:.3}",
+                vertex_cache::acmr(&all_indices, 32)
+            );
+        }
+
+        Self::from_vertices(
+            state,
+            name,
+            all_vertices,
+            all_indices,
+            Topology::TriangleList,
+            material,
+        )
+    }
+
+    // For geometry that doesn't come from a file, strip topologies can use
+    // PRIMITIVE_RESTART_INDEX to start a new strip
+    pub fn from_vertices(
+        state: &mut State,
+        name: &str,
+        all_vertices: Vec<Vertex>,
+        all_indices: Vec<u32>,
+        topology: Topology,
+        material: &str,
+    ) -> Result<ThingHolder<Self>, ResourceError> {
+        if all_vertices.is_empty() {
+            error!("Model {name} has no vertices");
+            return Err(ResourceError::EmptyGeometry);
+        }
+        let Some(material_thing) = state.materials.get(&String::from(material)).cloned() else {
+            error!("Material {material} for model {name} doesn't exist");
+            return Err(ResourceError::MaterialNotFound(String::from(material)));
+        };
+
+        let vertex_format = VertexFormat::of(&all_vertices);
+        let (data, vertices_size, indices_size) =
+            Self::pack(&all_vertices, &all_indices, vertex_format);
+
+        state.sync();
+        let handle =
+            render_impl::ModelData::new(&state.backend.get(), name, vertices_size, indices_size);
+
+        let poison = Poison::default();
+        let model = Arc::new(Thing::with_poison(
+            Self {
+                name: String::from(name),
+                material: material_thing,
+                data,
+                topology,
+                vertex_format,
+                vertex_descriptor: None,
+                bounds: Bounds::of(&all_vertices),
+                handle,
+                poison: poison.clone(),
+            },
+            poison,
+        ));
+        state.models.insert(String::from(name), model.clone());
+
+        info!("Created model {name} successfully");
+
+        Ok(model)
+    }
+
+    // Vertices laid out like the descriptor, for shaders with the same one (see
+    // Shader::set_vertex_descriptor). If location 0 is a Float3 or Float4 it's taken as the
+    // position for visibility, otherwise the model always counts as visible.
+    pub fn from_raw_vertices(
+        state: &mut State,
+        name: &str,
+        vertex_descriptor: VertexDescriptor,
+        vertices: Vec<u8>,
+        indices: Vec<u32>,
+        topology: Topology,
+        material: &str,
+    ) -> Result<ThingHolder<Self>, ResourceError> {
+        if vertices.is_empty() {
+            error!("Model {name} has no vertices");
+            return Err(ResourceError::EmptyGeometry);
+        }
+        if let Err(err) = vertex_descriptor.validate() {
+            error!("Vertex descriptor for model {name} is invalid: {err}");
+            return Err(ResourceError::VertexData(err));
+        }
+        let stride = vertex_descriptor.stride as usize;
+        if vertices.len() % stride != 0 {
+            let err = format!(
+                "{} bytes of vertices isn't a multiple of the {stride} byte stride",
+                vertices.len()
+            );
+            error!("Vertices for model {name} don't fit the descriptor: {err}");
+            return Err(ResourceError::VertexData(err));
+        }
+        let Some(material_thing) = state.materials.get(&String::from(material)).cloned() else {
+            error!("Material {material} for model {name} doesn't exist");
+            return Err(ResourceError::MaterialNotFound(String::from(material)));
+        };
+
+        let bounds = match vertex_descriptor.position() {
+            Some(offset) => Bounds::of_raw(&vertices, stride, offset as usize),
+            None => {
+                debug!("Model {name} has no float positions at location 0, it's always visible");
+                Bounds::everything()
+            }
+        };
+        let vertices_size = vertices.len();
+        let indices_size = indices.len() * mem::size_of::<u32>();
+        let mut data = vertices;
+        data.extend(indices.iter().flat_map(|index| index.to_ne_bytes()));
+
+        state.sync();
+        let handle =
+            render_impl::ModelData::new(&state.backend.get(), name, vertices_size, indices_size);
+
+        let poison = Poison::default();
+        let model = Arc::new(Thing::with_poison(
+            Self {
+                name: String::from(name),
+                material: material_thing,
+                data,
+                topology,
+                vertex_format: VertexFormat::default(),
+                vertex_descriptor: Some(vertex_descriptor),
+                bounds,
+                handle,
+                poison: poison.clone(),
+            },
+            poison,
+        ));
+        state.models.insert(String::from(name), model.clone());
+
+        info!("Created model {name} from raw vertices successfully");
+
+        Ok(model)
+    }
+
+    // Replaces the geometry without making a new model, so anything holding the model keeps
+    // working. The topology and vertex layout stay the same.
+    pub fn update_geometry(&mut self, state: &mut State, vertices: Vec<Vertex>, indices: Vec<u32>) {
+        if self.poison.is_poisoned() {
+            warn!(
+                "Not updating model {}, it's from a render system that was shut down",
+                self.name
+            );
+            return;
+        }
+        if self.vertex_descriptor.is_some() {
+            warn!(
+                "Model {} is made of raw vertices, can't update it with Vertex",
+                self.name
+            );
+            return;
+        }
+        debug!(
+            "Updating geometry of model {} to {} vertices and {} indices",
+            self.name,
+            vertices.len(),
+            indices.len()
+        );
+
+        self.vertex_format = VertexFormat {
+            layout: self.vertex_format.layout,
+            ..VertexFormat::of(&vertices)
+        };
+        self.bounds = Bounds::of(&vertices);
+        let (data, vertices_size, indices_size) =
+            Self::pack(&vertices, &indices, self.vertex_format);
+        self.data = data;
+        state.sync();
+        state.backend.get().update_model(
+            &self.name,
+            &mut self.handle,
+            &self.data,
+            vertices_size,
+            indices_size,
+        );
+    }
+
+    // Moves the positions in or out of the rest of the vertices, the size doesn't change so it
+    // goes in the same place on the GPU
+    pub fn set_vertex_layout(&mut self, state: &mut State, layout: VertexLayout) {
+        if self.poison.is_poisoned() {
+            warn!(
+                "Not updating model {}, it's from a render system that was shut down",
+                self.name
+            );
+            return;
+        }
+        if layout == self.vertex_format.layout {
+            return;
+        }
+        if self.vertex_descriptor.is_some() {
+            warn!(
+                "Model {} is made of raw vertices, its layout is whatever its descriptor says",
+                self.name
+            );
+            return;
+        }
+
+        debug!(
+            "Changing vertex layout of model {} from {:?} to {layout:?}",
+            self.name, self.vertex_format.layout
+        );
+        let stride = self.vertex_format.stride();
+        let indices_size = self.handle.index_count() as usize * mem::size_of::<u32>();
+        let vertices_size = self.data.len() - indices_size;
+        let vertices = &self.data[..vertices_size];
+        let vertices = match layout {
+            VertexLayout::Interleaved => Self::interleave_positions(vertices, stride),
+            VertexLayout::SeparatePosition => Self::separate_positions(vertices, stride),
+        };
+        self.data.splice(..vertices_size, vertices);
+        self.vertex_format.layout = layout;
+
+        state.sync();
+        state.backend.get().update_model(
+            &self.name,
+            &mut self.handle,
+            &self.data,
+            vertices_size,
+            indices_size,
+        );
+    }
+
+    fn separate_positions(vertices: &[u8], stride: usize) -> Vec<u8> {
+        let positions = vertices
+            .chunks_exact(stride)
+            .flat_map(|vertex| &vertex[..VertexFormat::POSITION_SIZE]);
+        let rest = vertices
+            .chunks_exact(stride)
+            .flat_map(|vertex| &vertex[VertexFormat::POSITION_SIZE..]);
+        positions.chain(rest).copied().collect()
+    }
+
+    fn interleave_positions(vertices: &[u8], stride: usize) -> Vec<u8> {
+        let vertex_count = vertices.len() / stride;
+        let (positions, rest) = vertices.split_at(vertex_count * VertexFormat::POSITION_SIZE);
+        positions
+            .chunks_exact(VertexFormat::POSITION_SIZE)
+            .zip(rest.chunks_exact(stride - VertexFormat::POSITION_SIZE))
+            .flat_map(|(position, rest)| position.iter().chain(rest))
+            .copied()
+            .collect()
+    }
+
+    // Vertices in the given format followed by indices, how the backend wants them
+    fn pack(vertices: &[Vertex], indices: &[u32], format: VertexFormat) -> (Vec<u8>, usize, usize) {
+        let vertices_size = vertices.len() * format.stride();
+        let indices_size = indices.len() * mem::size_of::<u32>();
+
+        let mut data = Vec::with_capacity(vertices_size + indices_size);
+        let mut push = |values: &[f32]| {
+            data.extend(values.iter().flat_map(|value| value.to_ne_bytes()));
+        };
+        for vertex in vertices {
+            push(vertex.position.as_slice());
+            push(vertex.texture_coordinate.as_slice());
+            push(vertex.normal.as_slice());
+            if format.texture_coordinate_1 {
+                push(
+                    vertex
+                        .texture_coordinate_1
+                        .unwrap_or_else(Vector2::zeros)
+                        .as_slice(),
+                );
+            }
+            if format.color {
+                push(
+                    vertex
+                        .color
+                        .unwrap_or(Vector4::new(1.0, 1.0, 1.0, 1.0))
+                        .as_slice(),
+                );
+            }
+        }
+        if format.layout == VertexLayout::SeparatePosition {
+            data = Self::separate_positions(&data, format.stride());
+        }
+        data.extend(indices.iter().flat_map(|index| index.to_ne_bytes()));
+
+        (data, vertices_size, indices_size)
+    }
+
+    pub fn from_obj_bytes(
+        state: &mut State,
+        name: &str,
+        data: &[u8],
+        material: &str,
+        options: ImportOptions,
+    ) -> Result<ThingHolder<Self>, ResourceError> {
+        let models = match tobj::load_obj_buf(
+            &mut io::BufReader::new(data),
+            &tobj::GPU_LOAD_OPTIONS,
+            |_| Err(tobj::LoadError::OpenFileFailed),
+        ) {
+            Ok((models, _)) => models,
+            Err(err) => {
+                error!("Failed to parse model {name}: {err}");
+                return Err(ResourceError::Parse(err));
+            }
+        };
+
+        Self::new(state, name, models, material, options)
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn data(&self) -> &Vec<u8> {
+        &self.data
+    }
+
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+
+    pub fn vertex_format(&self) -> VertexFormat {
+        self.vertex_format
+    }
+
+    pub fn vertex_descriptor(&self) -> Option<&VertexDescriptor> {
+        self.vertex_descriptor.as_ref()
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        self.bounds
+    }
+
+    // The vertices back out of data, whatever the layout. None for raw vertices, which could be
+    // anything.
+    pub fn vertices(&self) -> Option<Vec<Vertex>> {
+        if self.vertex_descriptor.is_some() {
+            return None;
+        }
+
+        let format = self.vertex_format;
+        let stride = format.stride();
+        let indices_size = self.handle.index_count() as usize * mem::size_of::<u32>();
+        let vertices = &self.data[..self.data.len() - indices_size];
+        let interleaved;
+        let vertices = match format.layout {
+            VertexLayout::Interleaved => vertices,
+            VertexLayout::SeparatePosition => {
+                interleaved = Self::interleave_positions(vertices, stride);
+                &interleaved
+            }
+        };
+
+        Some(
+            vertices
+                .chunks_exact(stride)
+                .map(|vertex| {
+                    let mut floats = vertex
+                        .chunks_exact(mem::size_of::<f32>())
+                        .map(|float| f32::from_ne_bytes(float.try_into().unwrap()));
+                    let mut next = || floats.next().unwrap();
+                    let mut vertex = Vertex::new(
+                        Vector3::new(next(), next(), next()),
+                        Vector2::new(next(), next()),
+                        Vector3::new(next(), next(), next()),
+                    );
+                    if format.texture_coordinate_1 {
+                        vertex = vertex.with_texture_coordinate_1(Vector2::new(next(), next()));
+                    }
+                    if format.color {
+                        vertex = vertex.with_color(Vector4::new(next(), next(), next(), next()));
+                    }
+                    vertex
+                })
+                .collect(),
+        )
+    }
+
+    pub fn indices(&self) -> Vec<u32> {
+        let indices_size = self.handle.index_count() as usize * mem::size_of::<u32>();
+        self.data[self.data.len() - indices_size..]
+            .chunks_exact(mem::size_of::<u32>())
+            .map(|index| u32::from_ne_bytes(index.try_into().unwrap()))
+            .collect()
+    }
+
+    // Loses the second texture coordinate and the alpha of colours. Strips are written as lists.
+    pub fn export_obj(&self, path: impl AsRef<Path>) -> Result<(), ResourceError> {
+        self.export(path.as_ref(), "OBJ", export::obj)
+    }
+
+    // Everything goes in the one file, buffer included
+    pub fn export_gltf(&self, path: impl AsRef<Path>) -> Result<(), ResourceError> {
+        self.export(path.as_ref(), "glTF", export::gltf)
+    }
+
+    fn export(
+        &self,
+        path: &Path,
+        kind: &str,
+        write: fn(&str, &[Vertex], &[u32], Topology) -> String,
+    ) -> Result<(), ResourceError> {
+        info!("Exporting model {} to {kind} {}", self.name, path.display());
+        let Some(vertices) = self.vertices() else {
+            error!(
+                "Model {} is made of raw vertices, can't export it",
+                self.name
+            );
+            return Err(ResourceError::Unsupported("raw vertices can't be exported"));
+        };
+        // glTF needs bounds for the positions, and an empty OBJ is no use either
+        if vertices.is_empty() {
+            error!("Model {} has no vertices, can't export it", self.name);
+            return Err(ResourceError::Unsupported("empty models can't be exported"));
+        }
+
+        let contents = write(&self.name, &vertices, &self.indices(), self.topology);
+        if let Err(err) = fs::write(path, contents) {
+            error!(
+                "Failed to write model {} to {}: {err}",
+                self.name,
+                path.display()
+            );
+            return Err(ResourceError::Io(err));
+        }
+
+        Ok(())
+    }
+
+    // Strips count restarts as triangles, close enough for stats. Lines and points have none.
+    pub fn triangle_count(&self) -> u64 {
+        let index_count = self.handle.index_count();
+        match self.topology {
+            Topology::TriangleList => index_count / 3,
+            Topology::TriangleStrip => index_count.saturating_sub(2),
+            Topology::LineList | Topology::PointList => 0,
+        }
+    }
+}
+
+// Made by State::build_static_batch. It's more than one model if there were too many vertices
+// for 32 bit indices.
+pub struct Batch {
+    name: String,
+    models: Vec<ThingHolder<Model>>,
+}
+
+impl Batch {
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn models(&self) -> &[ThingHolder<Model>] {
+        &self.models
+    }
+}
 
 impl Renderable for Model {
     fn render(&self, state: &mut State) {
-        if state.backend.is_in_frame() {
-            state.backend.render_model(self);
+        if self.poison.is_poisoned() {
+            warn!(
+                "Not drawing model {}, it's from a render system that was shut down",
+                self.name
+            );
+            return;
+        }
+        state.sync();
+        let in_frame = state.backend.get().is_in_frame();
+        if in_frame {
+            state.track_visibility(self);
+            state.backend.get().render_model(self);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_util::*;
+    use super::*;
+
+    #[test]
+    fn reinit_on_gpu_reuploads_everything() {
+        let Some((video, mut state)) = headless_state(64, 64) else {
+            return;
+        };
+
+        let checker = image::RgbaImage::from_fn(4, 4, |x, y| {
+            if (x + y) % 2 == 0 {
+                image::Rgba([255, 0, 0, 255])
+            } else {
+                image::Rgba([0, 0, 255, 255])
+            }
+        });
+        let texture = texture(&mut state, "checker", checker);
+        basic_material(&mut state, "checker", Some("checker"));
+        let model = quad(
+            &mut state,
+            "quad",
+            "checker",
+            (-1.0, -1.0, 0.0),
+            2.0,
+            Vector4::new(1.0, 1.0, 1.0, 1.0),
+        );
+        state.load_resources().unwrap();
+
+        let camera = Camera::default();
+        let before = capture(&mut state, &video, &camera, |state| state.draw(&model));
+
+        // the last one is the worst scoring GPU, which is the same one if there's only one
+        let gpu = state.available_gpus().len() - 1;
+        state.reinit_on_gpu(&video, gpu);
+        assert!(state.backend.get().is_loaded());
+        assert!(texture.get().handle.is_uploaded());
+        assert!(!model.is_poisoned());
+
+        let after = capture(&mut state, &video, &camera, |state| state.draw(&model));
+        assert!(
+            before == after,
+            "The frame changed after moving to GPU {gpu}"
+        );
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn uncapped_frames_stay_bounded() {
+        let config = RenderConfig {
+            present_modes: vec![PresentMode::Immediate],
+            validation: true,
+            ..Default::default()
+        };
+        let Some((video, mut state)) = headless_state_with(64, 64, config) else {
+            return;
+        };
+        if state.present_mode() != PresentMode::Immediate {
+            eprintln!("Skipping, immediate present mode isn't supported");
+            state.shutdown();
+            return;
+        }
+
+        basic_material(&mut state, "basic", None);
+        let model = quad(
+            &mut state,
+            "quad",
+            "basic",
+            (-1.0, -1.0, 0.0),
+            2.0,
+            Vector4::new(1.0, 1.0, 1.0, 1.0),
+        );
+        state.load_resources().unwrap();
+
+        let camera = Camera::default();
+        let mut settled = None;
+        for frame in 0..1000 {
+            state.begin_cmds(&video);
+            state.render_viewport(&camera, ViewportRect::FULL, |state| state.draw(&model));
+            state.present();
+            let stats = state.frame_resource_stats();
+            assert!(
+                stats.in_flight <= state.frames_in_flight(),
+                "{} frames in flight",
+                stats.in_flight
+            );
+            // anything still being made after the first few frames would keep growing forever
+            if frame == 100 {
+                settled = Some(stats);
+            } else if let Some(settled) = settled {
+                assert_eq!(
+                    render_impl::FrameResourceStats {
+                        in_flight: settled.in_flight,
+                        ..stats
+                    },
+                    settled,
+                    "on frame {frame}"
+                );
+            }
+        }
+        assert_eq!(state.validation_errors(), 0);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn frame_latency_bounds_frames_in_flight() {
+        let config = RenderConfig {
+            present_modes: vec![PresentMode::Immediate, PresentMode::Mailbox],
+            present_wait: false,
+            validation: true,
+            ..Default::default()
+        };
+        let Some((video, mut state)) = headless_state_with(64, 64, config) else {
+            return;
+        };
+
+        basic_material(&mut state, "basic", None);
+        let model = quad(
+            &mut state,
+            "quad",
+            "basic",
+            (-1.0, -1.0, 0.0),
+            2.0,
+            Vector4::new(1.0, 1.0, 1.0, 1.0),
+        );
+        state.load_resources().unwrap();
+
+        let camera = Camera::default();
+        for latency in [1, 2] {
+            state.set_max_frame_latency(latency);
+            let mut most = 0;
+            for _ in 0..100 {
+                state.begin_cmds(&video);
+                state.render_viewport(&camera, ViewportRect::FULL, |state| state.draw(&model));
+                state.present();
+                most = most.max(state.frame_resource_stats().in_flight);
+            }
+            assert!(
+                most <= latency,
+                "{most} frames in flight with a latency of {latency}"
+            );
+        }
+        assert_eq!(state.validation_errors(), 0);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn exclusive_swapchain_hands_images_over_cleanly() {
+        let config = RenderConfig {
+            swapchain_sharing: SwapchainSharing::Exclusive,
+            validation: true,
+            ..Default::default()
+        };
+        let Some((video, mut state)) = headless_state_with(64, 64, config) else {
+            return;
+        };
+        // there's nothing to hand over when one family does both
+        if !state.queue_info().distinct {
+            eprintln!("Skipping, the graphics and present queue families are the same");
+            state.shutdown();
+            return;
+        }
+
+        basic_material(&mut state, "basic", None);
+        let model = quad(
+            &mut state,
+            "quad",
+            "basic",
+            (-1.0, -1.0, 0.0),
+            2.0,
+            Vector4::new(1.0, 1.0, 1.0, 1.0),
+        );
+        state.load_resources().unwrap();
+
+        // enough frames to go through every swapchain image more than once
+        let camera = Camera::default();
+        for _ in 0..10 {
+            let frame = capture(&mut state, &video, &camera, |state| state.draw(&model));
+            assert_eq!(frame.get_pixel(32, 32).0, [255, 255, 255, 255]);
+        }
+        assert_eq!(state.validation_errors(), 0);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn update_geometry_changes_what_renders() {
+        let Some((video, mut state)) = headless_state(64, 64) else {
+            return;
+        };
+
+        basic_material(&mut state, "basic", None);
+        let model = quad(
+            &mut state,
+            "quad",
+            "basic",
+            (-1.0, -1.0, 0.0),
+            2.0,
+            Vector4::new(1.0, 0.0, 0.0, 1.0),
+        );
+        state.load_resources().unwrap();
+
+        // the same quad in another colour, then with a second one under it so it doesn't fit
+        // anymore. The depth test keeps the first one on top.
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let square = |color: Vector4<f32>| {
+            [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(x, y)| {
+                Vertex::new(Vector3::new(x, y, 0.0), Vector2::new(0.0, 0.0), normal)
+                    .with_color(color)
+            })
+        };
+        let green = Vector4::new(0.0, 1.0, 0.0, 1.0);
+        let blue = Vector4::new(0.0, 0.0, 1.0, 1.0);
+        let camera = Camera::default();
+        let center = |frame: &image::RgbaImage| frame.get_pixel(32, 32).0;
+
+        let red = capture(&mut state, &video, &camera, |state| state.draw(&model));
+        assert_eq!(center(&red), [255, 0, 0, 255]);
+
+        let holder = Arc::clone(&model);
+        model
+            .get_mut()
+            .update_geometry(&mut state, square(green).to_vec(), vec![0, 1, 2, 2, 3, 0]);
+        let frame = capture(&mut state, &video, &camera, |state| state.draw(&model));
+        assert_eq!(center(&frame), [0, 255, 0, 255]);
+
+        let mut vertices = square(blue).to_vec();
+        vertices.extend(square(green));
+        model.get_mut().update_geometry(
+            &mut state,
+            vertices,
+            vec![0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4],
+        );
+        let frame = capture(&mut state, &video, &camera, |state| state.draw(&model));
+        assert_eq!(center(&frame), [0, 0, 255, 255]);
+        assert!(Arc::ptr_eq(&holder, &model));
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn gltf_imports_the_second_texture_coordinate() {
+        let Some((_video, mut state)) = headless_state(64, 64) else {
+            return;
+        };
+
+        basic_material(&mut state, "basic", None);
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let vertices: Vec<Vertex> = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]
+            .iter()
+            .map(|&(u, v)| {
+                Vertex::new(Vector3::new(u, v, 0.0), Vector2::new(u, v), normal)
+                    .with_texture_coordinate_1(Vector2::new(u * 0.5, v * 0.25))
+            })
+            .collect();
+        let indices = vec![0, 1, 2, 2, 3, 0];
+        let gltf = export::gltf("quad", &vertices, &indices, Topology::TriangleList);
+
+        let model = Model::from_gltf_bytes(
+            &mut state,
+            "quad",
+            gltf.as_bytes(),
+            "basic",
+            ImportOptions::GLTF,
+        )
+        .unwrap();
+        assert!(model.get().vertex_format().texture_coordinate_1);
+        assert!(model.get().vertices() == Some(vertices));
+        assert_eq!(model.get().indices(), indices);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn vertex_colors_tint_the_model() {
+        let Some((video, mut state)) = headless_state(64, 64) else {
+            return;
+        };
+
+        basic_material(&mut state, "basic", None);
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        let triangle = Model::from_vertices(
+            &mut state,
+            "triangle",
+            [(-1.0, -1.0), (3.0, -1.0), (-1.0, 3.0)]
+                .iter()
+                .map(|&(x, y)| {
+                    Vertex::new(Vector3::new(x, y, 0.0), Vector2::new(0.0, 0.0), normal)
+                        .with_color(red)
+                })
+                .collect(),
+            vec![0, 1, 2],
+            Topology::TriangleList,
+            "basic",
+        )
+        .unwrap();
+        // without colours it gets white, so only the texture (also white) is left
+        let plain = Model::from_vertices(
+            &mut state,
+            "plain",
+            [(-1.0, -1.0), (3.0, -1.0), (-1.0, 3.0)]
+                .iter()
+                .map(|&(x, y)| Vertex::new(Vector3::new(x, y, 0.0), Vector2::new(0.0, 0.0), normal))
+                .collect(),
+            vec![0, 1, 2],
+            Topology::TriangleList,
+            "basic",
+        )
+        .unwrap();
+        assert!(triangle.get().vertex_format().color);
+        assert!(!plain.get().vertex_format().color);
+        state.load_resources().unwrap();
+
+        let camera = Camera::default();
+        let frame = capture(&mut state, &video, &camera, |state| state.draw(&triangle));
+        assert_eq!(frame.get_pixel(32, 32).0, [255, 0, 0, 255]);
+        let frame = capture(&mut state, &video, &camera, |state| state.draw(&plain));
+        assert_eq!(frame.get_pixel(32, 32).0, [255, 255, 255, 255]);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn triangle_strip_restarts_between_quads() {
+        let config = RenderConfig {
+            validation: true,
+            ..Default::default()
+        };
+        let Some((video, mut state)) = headless_state_with(64, 64, config) else {
+            return;
+        };
+
+        basic_material(&mut state, "basic", None);
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let red = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        // two quads with a gap between them, the restart stops the strip from filling it in
+        let vertices = [-2.5, 0.5]
+            .iter()
+            .flat_map(|&left| {
+                [(0.0, -1.0), (2.0, -1.0), (0.0, 1.0), (2.0, 1.0)].map(|(x, y)| {
+                    Vertex::new(
+                        Vector3::new(left + x, y, 0.0),
+                        Vector2::new(0.0, 0.0),
+                        normal,
+                    )
+                    .with_color(red)
+                })
+            })
+            .collect();
+        let strip = Model::from_vertices(
+            &mut state,
+            "strip",
+            vertices,
+            vec![0, 1, 2, 3, PRIMITIVE_RESTART_INDEX, 4, 5, 6, 7],
+            Topology::TriangleStrip,
+            "basic",
+        )
+        .unwrap();
+        state.load_resources().unwrap();
+
+        let frame = capture(&mut state, &video, &Camera::default(), |state| {
+            state.draw(&strip)
+        });
+        // the middle of each quad, and the gap in the middle of the frame
+        for (x, y) in [(18, 32), (46, 32), (18, 26), (46, 38)] {
+            assert_eq!(frame.get_pixel(x, y).0, [255, 0, 0, 255], "at ({x}, {y})");
+        }
+        for (x, y) in [(32, 32), (32, 26), (32, 38)] {
+            assert_eq!(frame.get_pixel(x, y).0[..3], [0, 0, 0], "at ({x}, {y})");
+        }
+        assert_eq!(state.validation_errors(), 0);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn text_draws_glyphs_from_the_cache() {
+        let config = RenderConfig {
+            validation: true,
+            ..Default::default()
+        };
+        let Some((video, mut state)) = headless_state_with(64, 64, config) else {
+            return;
+        };
+
+        state
+            .init_text(
+                shader_binary("text.vert"),
+                shader_binary("text.frag"),
+                GlyphCache::new(64, 16, 1),
+            )
+            .unwrap();
+        state.load_resources().unwrap();
+
+        // every glyph is an 8x8 square sitting on the baseline, with a 2 pixel gap after it
+        let square = |_: GlyphKey| {
+            Some(RasterizedGlyph {
+                image: image::GrayImage::from_pixel(8, 8, image::Luma([255])),
+                bearing: Vector2::new(0.0, -8.0),
+                advance: 10.0,
+            })
+        };
+        let frame = capture(&mut state, &video, &Camera::default(), |state| {
+            state.draw_text("ab", Vector2::new(10.0, 30.0), 8, Color::WHITE, square)
+        });
+        for (x, y) in [(14, 26), (24, 26), (11, 23), (27, 29)] {
+            assert_eq!(
+                frame.get_pixel(x, y).0[..3],
+                [255, 255, 255],
+                "at ({x}, {y})"
+            );
+        }
+        for (x, y) in [(19, 26), (14, 20), (14, 31), (40, 40)] {
+            assert_eq!(frame.get_pixel(x, y).0[..3], [0, 0, 0], "at ({x}, {y})");
+        }
+
+        // the same text again is all hits, and comes out the same
+        let again = capture(&mut state, &video, &Camera::default(), |state| {
+            state.draw_text("ab", Vector2::new(10.0, 30.0), 8, Color::WHITE, |_| {
+                panic!("cached glyphs aren't rasterized again")
+            })
+        });
+        assert_eq!(frame, again);
+        let stats = state.glyph_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.glyphs), (2, 2, 2));
+        assert_eq!(state.validation_errors(), 0);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn depth_bias_stops_z_fighting() {
+        let config = RenderConfig {
+            validation: true,
+            ..Default::default()
+        };
+        let Some((video, mut state)) = headless_state_with(64, 64, config) else {
+            return;
+        };
+
+        basic_material(&mut state, "basic", None);
+        // two floors on the same plane, with different corners and split along different
+        // diagonals so their depths round differently
+        fn floor(
+            state: &mut State,
+            name: &str,
+            (left, right): (f32, f32),
+            (far, near): (f32, f32),
+            indices: Vec<u32>,
+            color: Vector4<f32>,
+        ) -> ThingHolder<Model> {
+            let normal = Vector3::new(0.0, 1.0, 0.0);
+            let vertices = [(left, far), (right, far), (left, near), (right, near)]
+                .map(|(x, z)| {
+                    Vertex::new(Vector3::new(x, -1.0, z), Vector2::new(0.0, 0.0), normal)
+                        .with_color(color)
+                })
+                .to_vec();
+            Model::from_vertices(
+                state,
+                name,
+                vertices,
+                indices,
+                Topology::TriangleList,
+                "basic",
+            )
+            .unwrap()
+        }
+        let red = floor(
+            &mut state,
+            "red",
+            (-10.0, 10.0),
+            (-20.0, 4.0),
+            vec![0, 2, 3, 3, 1, 0],
+            Vector4::new(1.0, 0.0, 0.0, 1.0),
+        );
+        let green = floor(
+            &mut state,
+            "green",
+            (-13.0, 11.0),
+            (-25.0, 4.5),
+            vec![0, 2, 1, 1, 2, 3],
+            Vector4::new(0.0, 1.0, 0.0, 1.0),
+        );
+        state.load_resources().unwrap();
+
+        // below the horizon, where the floors are
+        let count = |frame: &image::RgbaImage| {
+            let (mut red, mut green) = (0, 0);
+            for y in 36..64 {
+                for x in 0..64 {
+                    match frame.get_pixel(x, y).0[..3] {
+                        [255, 0, 0] => red += 1,
+                        [0, 255, 0] => green += 1,
+                        _ => {}
+                    }
+                }
+            }
+            (red, green)
+        };
+
+        let camera = Camera::default();
+        let frame = capture(&mut state, &video, &camera, |state| {
+            state.draw(&red);
+            state.draw(&green);
+        });
+        let (red_pixels, green_pixels) = count(&frame);
+        assert!(
+            red_pixels > 0 && green_pixels > 0,
+            "The floors should z-fight without a bias, {red_pixels} red and {green_pixels} green"
+        );
+
+        // pulled towards the camera, green wins everywhere
+        let frame = capture(&mut state, &video, &camera, |state| {
+            state.draw(&red);
+            state.set_depth_bias(-4.0, -2.0, 0.0);
+            state.draw(&green);
+            state.set_depth_bias(0.0, 0.0, 0.0);
+        });
+        let (red_pixels, green_pixels) = count(&frame);
+        assert_eq!(red_pixels, 0);
+        assert!(green_pixels > 0);
+        assert_eq!(state.validation_errors(), 0);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn scissors_clip_and_intersect() {
+        let Some((video, mut state)) = headless_state(64, 64) else {
+            return;
+        };
+
+        basic_material(&mut state, "basic", None);
+        // both cover the whole window, green behind red
+        let red = quad(
+            &mut state,
+            "red",
+            "basic",
+            (-100.0, -100.0, 0.0),
+            200.0,
+            Vector4::new(1.0, 0.0, 0.0, 1.0),
+        );
+        let green = quad(
+            &mut state,
+            "green",
+            "basic",
+            (-100.0, -100.0, -1.0),
+            200.0,
+            Vector4::new(0.0, 1.0, 0.0, 1.0),
+        );
+        state.load_resources().unwrap();
+
+        let frame = capture(&mut state, &video, &Camera::default(), |state| {
+            state.push_scissor(0, 0, 32, 32);
+            state.push_scissor(16, 16, 32, 32);
+            state.draw(&red);
+            state.pop_scissor();
+            state.draw(&green);
+            state.pop_scissor();
+        });
+        // red only where the two overlap, green in the rest of the outer one
+        assert_eq!(frame.get_pixel(24, 24).0, [255, 0, 0, 255]);
+        assert_eq!(frame.get_pixel(8, 8).0, [0, 255, 0, 255]);
+        assert_eq!(frame.get_pixel(24, 8).0, [0, 255, 0, 255]);
+        for (x, y) in [(40, 40), (40, 24), (24, 40), (60, 4)] {
+            assert_eq!(frame.get_pixel(x, y).0[..3], [0, 0, 0], "at {x}, {y}");
+        }
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn viewports_show_their_own_camera() {
+        let Some((video, mut state)) = headless_state(64, 64) else {
+            return;
+        };
+
+        basic_material(&mut state, "basic", None);
+        // far enough apart that each camera only sees its own
+        let colors: [[u8; 4]; 4] = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+            [255, 255, 0, 255],
+        ];
+        let models: Vec<_> = colors
+            .iter()
+            .enumerate()
+            .map(|(i, color)| {
+                let x = i as f32 * 100.0;
+                let color = Vector4::from(color.map(|channel| channel as f32 / 255.0));
+                quad(
+                    &mut state,
+                    &format!("quad {i}"),
+                    "basic",
+                    (x - 2.0, -2.0, 0.0),
+                    4.0,
+                    color,
+                )
+            })
+            .collect();
+        let cameras: Vec<_> = (0..4)
+            .map(|i| {
+                let x = i as f32 * 100.0;
+                Camera {
+                    position: Point3::new(x, 0.0, 5.0),
+                    target: Point3::new(x, 0.0, 0.0),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        state.load_resources().unwrap();
+
+        for (count, centers) in [
+            (2, vec![(16, 32), (48, 32)]),
+            (4, vec![(16, 16), (48, 16), (16, 48), (48, 48)]),
+        ] {
+            state.begin_cmds(&video);
+            for i in 0..count {
+                state.render_viewport(&cameras[i], ViewportRect::split(count, i), |state| {
+                    state.draw(&models[i])
+                });
+            }
+            let handle = state.request_readback();
+            state.present();
+            state.sync();
+            state.backend.get().wait_idle();
+            let frame = state.poll_readback(handle).unwrap();
+
+            for (i, &(x, y)) in centers.iter().enumerate() {
+                assert_eq!(
+                    frame.get_pixel(x, y).0,
+                    colors[i],
+                    "viewport {i} of {count}"
+                );
+            }
+        }
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn resizing_then_rendering_is_valid() {
+        let config = RenderConfig {
+            validation: true,
+            ..Default::default()
+        };
+        let Some((mut video, mut state)) = headless_state_with(64, 64, config) else {
+            return;
+        };
+
+        basic_material(&mut state, "basic", None);
+        let model = quad(
+            &mut state,
+            "quad",
+            "basic",
+            (-1.0, -1.0, 0.0),
+            2.0,
+            Vector4::new(1.0, 0.0, 0.0, 1.0),
+        );
+        state.load_resources().unwrap();
+
+        let camera = Camera::default();
+        let frame = capture(&mut state, &video, &camera, |state| state.draw(&model));
+        assert_eq!(frame.dimensions(), (64, 64));
+        for (width, height) in [(96, 48), (32, 80), (64, 64)] {
+            video.resize(width, height);
+            state.window_resized();
+            let frame = capture(&mut state, &video, &camera, |state| state.draw(&model));
+            assert_eq!(frame.dimensions(), (width, height));
+            assert_eq!(
+                frame.get_pixel(width / 2, height / 2).0,
+                [255, 0, 0, 255],
+                "after resizing to {width}x{height}"
+            );
+        }
+        assert_eq!(state.validation_errors(), 0);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn queued_z_up_model_faces_the_camera() {
+        let Some((video, mut state)) = headless_state(64, 64) else {
+            return;
+        };
+
+        // a quad facing -Y with Z up, like the front view in Blender, wound clockwise
+        let obj = "\
+            v -1 0 -1\nv 1 0 -1\nv 1 0 1\nv -1 0 1\n\
+            vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+            vn 0 -1 0\n\
+            f 1/1/1 3/3/1 2/2/1\nf 1/1/1 4/4/1 3/3/1\n";
+        let path = std::env::temp_dir().join(format!("purpl_facing_{}.obj", std::process::id()));
+        std::fs::write(&path, obj).unwrap();
+
+        basic_material(&mut state, "basic", None);
+        let options = ImportOptions {
+            flip_winding: true,
+            flip_uv_y: false,
+            coordinate_system: CoordinateSystem::ZUp,
+        };
+        state.queue_model("quad", path.to_str().unwrap(), "basic", options);
+        state.load_resources().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let model = state.models["quad"].clone();
+
+        // counterclockwise around the normal, which points at the default camera
+        let vertices = model.get().vertices().unwrap();
+        for triangle in model.get().indices().chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| &vertices[triangle[i] as usize]);
+            let facing = (b.position - a.position).cross(&(c.position - a.position));
+            assert!(
+                facing.dot(&a.normal) > 0.0,
+                "{triangle:?} is wound backwards"
+            );
+            assert!((a.normal - Vector3::new(0.0, 0.0, 1.0)).norm() < 1e-6);
+        }
+
+        let frame = capture(&mut state, &video, &Camera::default(), |state| {
+            state.draw(&model)
+        });
+        assert_eq!(frame.get_pixel(32, 32).0, [255, 255, 255, 255]);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn queued_models_parse_on_the_loader_threads() {
+        let Some((_video, mut state)) = headless_state(64, 64) else {
+            return;
+        };
+
+        // a directory of grids, dense enough that parsing them takes a while, every other one glTF
+        let size = 64;
+        let vertices: Vec<Vertex> = (0..size * size)
+            .map(|i| {
+                let (u, v) = (
+                    (i % size) as f32 / size as f32,
+                    (i / size) as f32 / size as f32,
+                );
+                Vertex::new(
+                    Vector3::new(u, v, 0.0),
+                    Vector2::new(u, v),
+                    Vector3::new(0.0, 0.0, 1.0),
+                )
+            })
+            .collect();
+        let indices: Vec<u32> = (0..size - 1)
+            .flat_map(|y| (0..size - 1).map(move |x| y * size + x))
+            .flat_map(|i| [i, i + 1, i + size + 1, i + size + 1, i + size, i])
+            .collect();
+        let directory = std::env::temp_dir().join(format!("purpl_loader_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let paths: Vec<_> = (0..16)
+            .map(|i| {
+                let (extension, contents) = if i % 2 == 0 {
+                    (
+                        "obj",
+                        export::obj("grid", &vertices, &indices, Topology::TriangleList),
+                    )
+                } else {
+                    (
+                        "gltf",
+                        export::gltf("grid", &vertices, &indices, Topology::TriangleList),
+                    )
+                };
+                let path = directory.join(format!("grid{i}.{extension}"));
+                fs::write(&path, contents).unwrap();
+                path
+            })
+            .collect();
+
+        basic_material(&mut state, "basic", None);
+        let mut times = Vec::new();
+        for threads in [1, 4] {
+            state.unload_resources();
+            state.set_loader_threads(threads);
+            for (i, path) in paths.iter().enumerate() {
+                let name = format!("grid {threads} {i}");
+                state.queue_model(
+                    &name,
+                    path.to_str().unwrap(),
+                    "basic",
+                    ImportOptions::default(),
+                );
+            }
+            state.load_resources().unwrap();
+
+            let stats = state.model_load_stats().unwrap();
+            assert_eq!(stats.models, paths.len());
+            assert_eq!(stats.threads, threads);
+            assert!(stats.parse_time <= stats.total_time);
+            times.push(stats.parse_time);
+            // the same models in the same order, whichever thread parsed them
+            for i in 0..paths.len() {
+                let model = state.models[&format!("grid {threads} {i}")].get();
+                let first = state.models[&format!("grid 1 {i}")].get();
+                assert_eq!(model.vertices().unwrap().len(), vertices.len());
+                assert_eq!(model.indices().len(), indices.len());
+                assert!(model.vertices() == first.vertices());
+                assert_eq!(model.indices(), first.indices());
+            }
+        }
+        debug!(
+            "Parsing took {:?} on 1 thread and {:?} on 4",
+            times[0], times[1]
+        );
+        fs::remove_dir_all(&directory).unwrap();
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn resources_are_ignored_after_shutdown() {
+        let config = RenderConfig {
+            validation: true,
+            ..Default::default()
+        };
+        let Some((_video, mut state)) = headless_state_with(64, 64, config.clone()) else {
+            return;
+        };
+        basic_material(&mut state, "basic", None);
+        let shader = state.shaders["basic"].clone();
+        let model = quad(
+            &mut state,
+            "quad",
+            "basic",
+            (-1.0, -1.0, 0.0),
+            2.0,
+            Vector4::new(1.0, 1.0, 1.0, 1.0),
+        );
+        let target = RenderTarget::new(&mut state, "target", TargetFormat::Rgba8).unwrap();
+        state.load_resources().unwrap();
+        state.shutdown();
+        assert!(shader.is_poisoned() && model.is_poisoned() && target.is_poisoned());
+
+        // everything handed to another render system has to be left alone
+        let Some((video, mut state)) = headless_state_with(64, 64, config) else {
+            return;
+        };
+        shader.get().destroy(&state);
+        target.get_mut().destroy(&state);
+        let vertices = model.get().vertices().unwrap();
+        model
+            .get_mut()
+            .update_geometry(&mut state, vertices, vec![0, 1, 2]);
+        state.begin_cmds(&video);
+        state.render_viewport(&Camera::default(), ViewportRect::FULL, |state| {
+            model.get().render(state);
+            state.draw(&model);
+        });
+        state.present();
+        state.sync();
+        state.backend.get().wait_idle();
+        assert_eq!(model.get().indices().len(), 6);
+        assert_eq!(state.validation_errors(), 0);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn tessellated_pipeline_builds_and_draws() {
+        let config = RenderConfig {
+            validation: true,
+            ..Default::default()
+        };
+        let Some((video, mut state)) = headless_state_with(64, 64, config) else {
+            return;
+        };
+        if !selected_gpu(&state, &video).tessellation_shader {
+            eprintln!("Skipping, the GPU doesn't support tessellation");
+            state.shutdown();
+            return;
+        }
+
+        let shader = Shader::from_bytes(
+            &mut state,
+            "tessellated",
+            shader_binary("basic.vert"),
+            shader_binary("basic.frag"),
+            Some(Tessellation {
+                control_binary: shader_binary("passthrough.tesc"),
+                evaluation_binary: shader_binary("passthrough.tese"),
+                patch_control_points: Tessellation::DEFAULT_PATCH_CONTROL_POINTS,
+            }),
+            None,
+        )
+        .unwrap();
+        assert!(shader.get().tessellation().is_some());
+        Material::new(&mut state, "tessellated", "tessellated", None).unwrap();
+        let model = quad(
+            &mut state,
+            "quad",
+            "tessellated",
+            (-1.0, -1.0, 0.0),
+            2.0,
+            Vector4::new(1.0, 0.0, 0.0, 1.0),
+        );
+        state.load_resources().unwrap();
+
+        let frame = capture(&mut state, &video, &Camera::default(), |state| {
+            state.draw(&model)
+        });
+        assert_eq!(frame.get_pixel(32, 32).0, [255, 0, 0, 255]);
+        assert_eq!(frame.get_pixel(2, 2).0[..3], [0, 0, 0]);
+        assert_eq!(state.validation_errors(), 0);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn geometry_pipeline_builds_and_draws() {
+        let config = RenderConfig {
+            validation: true,
+            ..Default::default()
+        };
+        let Some((video, mut state)) = headless_state_with(64, 64, config) else {
+            return;
+        };
+        if !selected_gpu(&state, &video).geometry_shader {
+            eprintln!("Skipping, the GPU doesn't support geometry shaders");
+            state.shutdown();
+            return;
+        }
+
+        Shader::from_bytes(
+            &mut state,
+            "geometry",
+            shader_binary("basic.vert"),
+            shader_binary("basic.frag"),
+            None,
+            Some(shader_binary("passthrough.geom")),
+        )
+        .unwrap();
+        Material::new(&mut state, "geometry", "geometry", None).unwrap();
+        let model = quad(
+            &mut state,
+            "quad",
+            "geometry",
+            (-1.0, -1.0, 0.0),
+            2.0,
+            Vector4::new(0.0, 1.0, 0.0, 1.0),
+        );
+        state.load_resources().unwrap();
+
+        let frame = capture(&mut state, &video, &Camera::default(), |state| {
+            state.draw(&model)
+        });
+        assert_eq!(frame.get_pixel(32, 32).0, [0, 255, 0, 255]);
+        assert_eq!(frame.get_pixel(2, 2).0[..3], [0, 0, 0]);
+        assert_eq!(state.validation_errors(), 0);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn position_only_shader_draws_separate_positions() {
+        let config = RenderConfig {
+            validation: true,
+            ..Default::default()
+        };
+        let Some((video, mut state)) = headless_state_with(64, 64, config) else {
+            return;
+        };
+
+        Shader::from_bytes(
+            &mut state,
+            "position_only",
+            shader_binary("position_only.vert"),
+            shader_binary("position_only.frag"),
+            None,
+            None,
+        )
+        .unwrap();
+        Material::new(&mut state, "position_only", "position_only", None).unwrap();
+        basic_material(&mut state, "basic", None);
+        let model = quad(
+            &mut state,
+            "quad",
+            "position_only",
+            (-1.0, -1.0, 0.0),
+            2.0,
+            Vector4::new(0.0, 1.0, 0.0, 1.0),
+        );
+        let full = quad(
+            &mut state,
+            "full",
+            "basic",
+            (-1.0, -1.0, 0.0),
+            2.0,
+            Vector4::new(0.0, 1.0, 0.0, 1.0),
+        );
+        model
+            .get_mut()
+            .set_vertex_layout(&mut state, VertexLayout::SeparatePosition);
+        full.get_mut()
+            .set_vertex_layout(&mut state, VertexLayout::SeparatePosition);
+        state.load_resources().unwrap();
+
+        let frame = capture(&mut state, &video, &Camera::default(), |state| {
+            state.draw(&model)
+        });
+        assert_eq!(frame.get_pixel(32, 32).0, [255, 255, 255, 255]);
+        assert_eq!(frame.get_pixel(2, 2).0[..3], [0, 0, 0]);
+
+        // the same layout still works with a shader that reads the whole vertex
+        let frame = capture(&mut state, &video, &Camera::default(), |state| {
+            state.draw(&full)
+        });
+        assert_eq!(frame.get_pixel(32, 32).0, [0, 255, 0, 255]);
+        assert_eq!(state.validation_errors(), 0);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn stencil_mask_hides_everything_outside_it() {
+        let config = RenderConfig {
+            validation: true,
+            ..Default::default()
+        };
+        let Some((video, mut state)) = headless_state_with(64, 64, config) else {
+            return;
+        };
+
+        basic_material(&mut state, "basic", None);
+        let mask = quad(
+            &mut state,
+            "mask",
+            "basic",
+            (-0.5, -0.5, 0.0),
+            1.0,
+            Vector4::new(1.0, 0.0, 0.0, 1.0),
+        );
+        // in front of the mask and a lot bigger
+        let masked = quad(
+            &mut state,
+            "masked",
+            "basic",
+            (-2.0, -2.0, 0.1),
+            4.0,
+            Vector4::new(0.0, 1.0, 0.0, 1.0),
+        );
+        state.load_resources().unwrap();
+
+        // without the mask it covers the whole middle of the frame
+        let frame = capture(&mut state, &video, &Camera::default(), |state| {
+            state.draw(&masked)
+        });
+        assert_eq!(frame.get_pixel(32, 32).0, [0, 255, 0, 255]);
+        assert_eq!(frame.get_pixel(20, 32).0, [0, 255, 0, 255]);
+
+        let frame = capture(&mut state, &video, &Camera::default(), |state| {
+            state.render_with_stencil_mask(|state| state.draw(&mask), |state| state.draw(&masked))
+        });
+        assert_eq!(frame.get_pixel(32, 32).0, [0, 255, 0, 255]);
+        for (x, y) in [(20, 32), (32, 20), (44, 32), (32, 44), (2, 2)] {
+            assert_eq!(
+                frame.get_pixel(x, y).0[..3],
+                [0, 0, 0],
+                "({x}, {y}) is outside the mask"
+            );
+        }
+        assert_eq!(state.validation_errors(), 0);
+
+        state.shutdown();
+    }
+
+    #[test]
+    fn semaphores_work_with_any_image_count() {
+        for images in [1, 2, 3, 4, 5] {
+            let config = RenderConfig {
+                swapchain_images: Some(images),
+                present_modes: vec![PresentMode::Immediate, PresentMode::Mailbox],
+                validation: true,
+                ..Default::default()
+            };
+            let Some((video, mut state)) = headless_state_with(64, 64, config) else {
+                return;
+            };
+            basic_material(&mut state, "basic", None);
+            let model = quad(
+                &mut state,
+                "quad",
+                "basic",
+                (-1.0, -1.0, 0.0),
+                2.0,
+                Vector4::new(1.0, 1.0, 1.0, 1.0),
+            );
+            state.load_resources().unwrap();
+
+            // enough frames for every image and frame in flight to come around a few times
+            let camera = Camera::default();
+            for _ in 0..30 {
+                state.begin_cmds(&video);
+                state.render_viewport(&camera, ViewportRect::FULL, |state| state.draw(&model));
+                state.present();
+            }
+            state.sync();
+            state.backend.get().wait_idle();
+            assert_eq!(
+                state.validation_errors(),
+                0,
+                "with {} swapchain images for {} frames in flight",
+                state.swapchain_image_count(),
+                state.frames_in_flight()
+            );
+
+            state.shutdown();
         }
     }
 }
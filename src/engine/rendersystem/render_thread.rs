@@ -0,0 +1,209 @@
+use super::{
+    render_impl, Camera, Model, RenderTarget, StencilState, ThingHolder, UniformData, ViewportRect,
+};
+use log::{debug, error};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+};
+
+// Optional render thread. present hands it the draws for the frame, then it records them, ends
+// the command buffer, submits and presents while the logic thread carries on with the next frame.
+// Draws for that frame get queued up in the meantime, so there's always one list being filled and
+// one being rendered.
+//
+// Synchronization: the backend is behind a Mutex, and the thread holds the lock from when it gets
+// a frame until it's presented. Everything in the frontend that touches the backend calls
+// State::sync first, which blocks until the thread is idle, so the lock is never fought over.
+// begin_cmds is one of them, so the logic thread can only get one frame ahead of the render
+// thread.
+//
+// The models are ThingHolders, so they stay alive until they're rendered, but the render thread
+// reads them without the logic thread knowing. With the default resources, get_mut on a queued
+// model before the next sync panics if the render thread is reading it right then. With
+// safe_resources it blocks until the render thread is done with it instead.
+
+// Everything that has to happen in order with the draws
+pub enum Command {
+    Draw(ThingHolder<Model>),
+    PushScissor {
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    },
+    PopScissor,
+    SetDepthBias {
+        constant: f32,
+        slope: f32,
+        clamp: f32,
+    },
+    SetStencil {
+        stencil: Option<StencilState>,
+        depth_write: bool,
+    },
+    ClearStencil,
+    UpdateUniforms(UniformData),
+    BeginViewport {
+        camera: Camera,
+        rect: ViewportRect,
+    },
+    EndViewport,
+    BeginTarget {
+        target: ThingHolder<RenderTarget>,
+        camera: Camera,
+    },
+    EndTarget(ThingHolder<RenderTarget>),
+    DrawLighting(ThingHolder<RenderTarget>),
+    DrawGrid,
+    DrawText(super::text::TextFrame),
+}
+
+// The frontend's handle to the backend, shared with the render thread
+#[derive(Clone)]
+pub struct Backend(Arc<Mutex<render_impl::State>>);
+
+impl Backend {
+    pub fn new(backend: render_impl::State) -> Self {
+        Self(Arc::new(Mutex::new(backend)))
+    }
+
+    // A panic on the render thread is already logged when it's joined, the state itself is still
+    // fine to use and shut down
+    pub fn get(&self) -> MutexGuard<'_, render_impl::State> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+struct Frame {
+    backend: Backend,
+    commands: Vec<Command>,
+    #[cfg(feature = "egui")]
+    gui: Option<super::gui::GuiFrame>,
+}
+
+pub struct RenderThread {
+    sender: Option<mpsc::Sender<Frame>>,
+    done: mpsc::Receiver<()>,
+    busy: AtomicBool,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl RenderThread {
+    pub fn new() -> Self {
+        debug!("Starting render thread");
+
+        let (sender, frames) = mpsc::channel::<Frame>();
+        let (done_sender, done) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name(String::from("render"))
+            .spawn(move || {
+                for frame in frames {
+                    let mut backend = frame.backend.get();
+                    if backend.is_in_frame() {
+                        for command in &frame.commands {
+                            match command {
+                                Command::Draw(model) => backend.render_model(&model.get()),
+                                Command::PushScissor {
+                                    x,
+                                    y,
+                                    width,
+                                    height,
+                                } => backend.push_scissor(*x, *y, *width, *height),
+                                Command::PopScissor => backend.pop_scissor(),
+                                Command::SetDepthBias {
+                                    constant,
+                                    slope,
+                                    clamp,
+                                } => backend.set_depth_bias(*constant, *slope, *clamp),
+                                Command::SetStencil {
+                                    stencil,
+                                    depth_write,
+                                } => backend.set_stencil(*stencil, *depth_write),
+                                Command::ClearStencil => backend.clear_stencil(),
+                                Command::UpdateUniforms(data) => backend.update_uniforms(*data),
+                                Command::BeginViewport { camera, rect } => {
+                                    backend.begin_viewport(camera, *rect)
+                                }
+                                Command::EndViewport => backend.end_viewport(),
+                                Command::BeginTarget { target, camera } => {
+                                    backend.begin_target(&target.get().handle, camera)
+                                }
+                                Command::EndTarget(target) => {
+                                    backend.end_target(&target.get().handle)
+                                }
+                                Command::DrawLighting(target) => {
+                                    backend.draw_lighting(&target.get().handle)
+                                }
+                                Command::DrawGrid => backend.draw_grid(),
+                                Command::DrawText(text) => text.draw(&mut backend),
+                            }
+                        }
+                        #[cfg(feature = "egui")]
+                        if let Some(gui) = frame.gui {
+                            gui.draw(&mut backend);
+                        }
+                    }
+                    backend.present();
+                    drop(backend);
+                    drop(frame.commands);
+
+                    if done_sender.send(()).is_err() {
+                        break;
+                    }
+                }
+            })
+            .unwrap();
+
+        Self {
+            sender: Some(sender),
+            done,
+            busy: AtomicBool::new(false),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn submit(
+        &self,
+        backend: &Backend,
+        commands: Vec<Command>,
+        #[cfg(feature = "egui")] gui: Option<super::gui::GuiFrame>,
+    ) {
+        self.wait();
+        let frame = Frame {
+            backend: backend.clone(),
+            commands,
+            #[cfg(feature = "egui")]
+            gui,
+        };
+        if self.sender.as_ref().unwrap().send(frame).is_err() {
+            error!("Render thread is gone, frame dropped");
+            return;
+        }
+        self.busy.store(true, Ordering::Release);
+    }
+
+    // Blocks until the last frame submitted is presented
+    pub fn wait(&self) {
+        if self.busy.swap(false, Ordering::Acquire) && self.done.recv().is_err() {
+            error!("Render thread exited without finishing its frame");
+        }
+    }
+}
+
+impl Drop for RenderThread {
+    fn drop(&mut self) {
+        debug!("Stopping render thread");
+        self.wait();
+        // dropping the sender ends the loop
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Render thread panicked");
+            }
+        }
+    }
+}
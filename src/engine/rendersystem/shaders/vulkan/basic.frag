@@ -1,9 +1,15 @@
 #version 460
 
 layout (location = 0) in vec4 fragment_color;
+layout (location = 1) in vec4 vertex_color;
+layout (location = 2) in vec2 texture_coordinate;
+
+layout (set = 2, binding = 0) uniform sampler2D material_texture;
 
 layout (location = 0) out vec4 out_color;
 
 void main() {
-    out_color = fragment_color;
+    // V goes up in models, down in images
+    vec2 uv = vec2(texture_coordinate.x, 1.0 - texture_coordinate.y);
+    out_color = fragment_color * vertex_color * texture(material_texture, uv);
 }
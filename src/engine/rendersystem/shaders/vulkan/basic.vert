@@ -4,14 +4,25 @@ layout (binding = 0) uniform ubo {
     mat4 model;
     mat4 view;
     mat4 projection;
+    float logarithmic_depth;
 } uniform_buffer;
 
 layout (location = 0) in vec3 in_position;
+layout (location = 1) in vec2 in_texture_coordinate;
+layout (location = 4) in vec4 in_color;
 
 layout (location = 0) out vec4 fragment_color;
+layout (location = 1) out vec4 vertex_color;
+layout (location = 2) out vec2 texture_coordinate;
 
 void main() {
     mat4 mvp = uniform_buffer.projection * uniform_buffer.view * uniform_buffer.model;
     gl_Position = mvp * vec4(in_position, 1);
+    if (uniform_buffer.logarithmic_depth != 0.0) {
+        gl_Position.z = log2(max(1e-6, 1.0 + gl_Position.w)) *
+            uniform_buffer.logarithmic_depth * gl_Position.w;
+    }
     fragment_color = vec4(1.0, 1.0, 1.0, 1.0);
+    vertex_color = in_color;
+    texture_coordinate = in_texture_coordinate;
 }
@@ -0,0 +1,21 @@
+#version 460
+
+// The geometry pass of deferred rendering, in the order of rendersystem::GBUFFER_FORMATS
+
+layout (location = 0) in vec3 world_position;
+layout (location = 1) in vec3 world_normal;
+layout (location = 2) in vec4 vertex_color;
+
+layout (location = 0) out vec4 out_albedo;
+layout (location = 1) out vec4 out_normal;
+// w is 1 wherever something was drawn, the lighting pass skips the rest
+layout (location = 2) out vec4 out_position;
+// specular intensity and shininess / 256
+layout (location = 3) out vec4 out_material;
+
+void main() {
+    out_albedo = vertex_color;
+    out_normal = vec4(normalize(world_normal), 0.0);
+    out_position = vec4(world_position, 1.0);
+    out_material = vec4(0.25, 32.0 / 256.0, 0.0, 0.0);
+}
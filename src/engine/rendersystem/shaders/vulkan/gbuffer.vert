@@ -0,0 +1,28 @@
+#version 460
+
+layout (binding = 0) uniform ubo {
+    mat4 model;
+    mat4 view;
+    mat4 projection;
+    float logarithmic_depth;
+} uniform_buffer;
+
+layout (location = 0) in vec3 in_position;
+layout (location = 2) in vec3 in_normal;
+layout (location = 4) in vec4 in_color;
+
+layout (location = 0) out vec3 world_position;
+layout (location = 1) out vec3 world_normal;
+layout (location = 2) out vec4 vertex_color;
+
+void main() {
+    vec4 position = uniform_buffer.model * vec4(in_position, 1);
+    gl_Position = uniform_buffer.projection * uniform_buffer.view * position;
+    if (uniform_buffer.logarithmic_depth != 0.0) {
+        gl_Position.z = log2(max(1e-6, 1.0 + gl_Position.w)) *
+            uniform_buffer.logarithmic_depth * gl_Position.w;
+    }
+    world_position = position.xyz;
+    world_normal = mat3(uniform_buffer.model) * in_normal;
+    vertex_color = in_color;
+}
@@ -0,0 +1,55 @@
+#version 460
+
+layout (binding = 0) uniform ubo {
+    mat4 model;
+    mat4 view;
+    mat4 projection;
+    float logarithmic_depth;
+} uniform_buffer;
+
+layout (push_constant) uniform constants {
+    vec4 color;
+    float spacing;
+    float fade_distance;
+} push_constants;
+
+layout (location = 0) in vec4 near_point;
+layout (location = 1) in vec4 far_point;
+
+layout (location = 0) out vec4 out_color;
+
+void main() {
+    vec3 near = near_point.xyz / near_point.w;
+    vec3 far = far_point.xyz / far_point.w;
+
+    // where the ray hits the ground (y = 0), if it does between the near and far planes
+    float t = near.y / (near.y - far.y);
+    if (!(t > 0.0 && t <= 1.0)) {
+        discard;
+    }
+    vec3 position = near + t * (far - near);
+
+    // distance to the closest line in pixels, from how much the grid coordinate changes between
+    // neighbouring pixels, so lines are a pixel wide and smooth at any distance
+    vec2 coordinate = position.xz / push_constants.spacing;
+    vec2 derivative = fwidth(coordinate);
+    vec2 grid = abs(fract(coordinate - 0.5) - 0.5) / derivative;
+    float line = 1.0 - min(min(grid.x, grid.y), 1.0);
+
+    // lines closer together than a few pixels turn into moire, so they fade out before that
+    line *= 1.0 - clamp(max(derivative.x, derivative.y) * 2.0 - 0.5, 0.0, 1.0);
+    line *= 1.0 - clamp(distance(near, position) / push_constants.fade_distance, 0.0, 1.0);
+
+    out_color = vec4(push_constants.color.rgb, push_constants.color.a * line);
+    if (out_color.a <= 0.0) {
+        discard;
+    }
+
+    // the ground's depth instead of the triangle's, so models in front of it cover it
+    vec4 clip = uniform_buffer.projection * uniform_buffer.view * vec4(position, 1.0);
+    if (uniform_buffer.logarithmic_depth != 0.0) {
+        gl_FragDepth = log2(max(1e-6, 1.0 + clip.w)) * uniform_buffer.logarithmic_depth;
+    } else {
+        gl_FragDepth = clip.z / clip.w;
+    }
+}
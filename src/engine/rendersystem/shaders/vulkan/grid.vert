@@ -0,0 +1,23 @@
+#version 460
+
+layout (binding = 0) uniform ubo {
+    mat4 model;
+    mat4 view;
+    mat4 projection;
+    float logarithmic_depth;
+} uniform_buffer;
+
+// Where the pixel's view ray starts and ends in world space. They're not divided yet, so they
+// interpolate linearly across the screen.
+layout (location = 0) out vec4 near_point;
+layout (location = 1) out vec4 far_point;
+
+void main() {
+    // one triangle that covers the whole screen
+    vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    gl_Position = vec4(position, 0.0, 1.0);
+
+    mat4 inverse_view_projection = inverse(uniform_buffer.projection * uniform_buffer.view);
+    near_point = inverse_view_projection * vec4(position, 0.0, 1.0);
+    far_point = inverse_view_projection * vec4(position, 1.0, 1.0);
+}
@@ -0,0 +1,88 @@
+#version 460
+
+layout (binding = 0) uniform ubo {
+    mat4 model;
+    mat4 view;
+    mat4 projection;
+    float logarithmic_depth;
+} uniform_buffer;
+
+// The G-buffer is the same size as what's being drawn, so each pixel reads its own texel
+layout (set = 1, binding = 0) uniform sampler2D albedo_buffer;
+layout (set = 1, binding = 1) uniform sampler2D normal_buffer;
+layout (set = 1, binding = 2) uniform sampler2D position_buffer;
+layout (set = 1, binding = 3) uniform sampler2D material_buffer;
+
+struct Light {
+    // xyz is the position, w is the range
+    vec4 position;
+    // linear rgb, a is the intensity
+    vec4 color;
+};
+
+layout (std430, set = 1, binding = 4) readonly buffer lights {
+    Light lights[];
+} light_buffer;
+
+layout (push_constant) uniform constants {
+    vec4 ambient;
+    uint light_count;
+    uint srgb_target;
+} push_constants;
+
+layout (location = 0) out vec4 out_color;
+
+vec3 linear_to_srgb(vec3 linear) {
+    return mix(linear * 12.92, 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055,
+        step(0.0031308, linear));
+}
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    vec4 position = texelFetch(position_buffer, texel, 0);
+    if (position.w == 0.0) {
+        discard;
+    }
+    vec4 albedo = texelFetch(albedo_buffer, texel, 0);
+    vec3 normal = normalize(texelFetch(normal_buffer, texel, 0).xyz);
+    vec4 material = texelFetch(material_buffer, texel, 0);
+
+    vec3 camera = inverse(uniform_buffer.view)[3].xyz;
+    vec3 to_camera = normalize(camera - position.xyz);
+    float shininess = max(material.g * 256.0, 1.0);
+
+    vec3 color = push_constants.ambient.rgb * albedo.rgb;
+    for (uint i = 0; i < push_constants.light_count; i++) {
+        Light light = light_buffer.lights[i];
+        vec3 to_light = light.position.xyz - position.xyz;
+        float distance = length(to_light);
+        if (distance >= light.position.w) {
+            continue;
+        }
+        to_light /= max(distance, 1e-6);
+
+        // smooth falloff that reaches zero at the range instead of going on forever
+        float falloff = 1.0 - distance / light.position.w;
+        float attenuation = falloff * falloff / (1.0 + distance * distance);
+        vec3 radiance = light.color.rgb * light.color.a * attenuation;
+
+        float diffuse = max(dot(normal, to_light), 0.0);
+        vec3 halfway = normalize(to_light + to_camera);
+        float specular = diffuse > 0.0 ?
+            pow(max(dot(normal, halfway), 0.0), shininess) * material.r : 0.0;
+        color += (albedo.rgb * diffuse + specular) * radiance;
+    }
+    // everything's linear up to here, UNORM targets store what they're given
+    out_color = vec4(color, albedo.a);
+    if (push_constants.srgb_target == 0) {
+        out_color.rgb = linear_to_srgb(out_color.rgb);
+    }
+
+    // the geometry's depth, so what's drawn after (transparent things) gets hidden behind it
+    vec4 clip = uniform_buffer.projection * uniform_buffer.view * vec4(position.xyz, 1.0);
+    if (uniform_buffer.logarithmic_depth != 0.0) {
+        gl_FragDepth = log2(max(1e-6, 1.0 + clip.w)) * uniform_buffer.logarithmic_depth;
+    } else {
+        gl_FragDepth = clip.z / clip.w;
+    }
+}
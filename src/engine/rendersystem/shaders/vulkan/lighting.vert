@@ -0,0 +1,7 @@
+#version 460
+
+void main() {
+    // one triangle that covers the whole screen
+    vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 460
+
+layout (binding = 0) uniform ubo {
+    mat4 model;
+    mat4 view;
+    mat4 projection;
+    float logarithmic_depth;
+} uniform_buffer;
+
+// Nothing but the position, so models with separate positions only have that stream read
+layout (location = 0) in vec3 in_position;
+
+void main() {
+    mat4 mvp = uniform_buffer.projection * uniform_buffer.view * uniform_buffer.model;
+    gl_Position = mvp * vec4(in_position, 1);
+    if (uniform_buffer.logarithmic_depth != 0.0) {
+        gl_Position.z = log2(max(1e-6, 1.0 + gl_Position.w)) *
+            uniform_buffer.logarithmic_depth * gl_Position.w;
+    }
+}
@@ -0,0 +1,28 @@
+#version 460
+
+layout (push_constant) uniform constants {
+    vec2 screen_size;
+    uint srgb_target;
+} push_constants;
+
+// A glyph cache page, only the red channel has anything in it
+layout (set = 0, binding = 0) uniform sampler2D glyph_page;
+
+layout (location = 0) in vec2 texture_coordinate;
+layout (location = 1) in vec4 color;
+
+layout (location = 0) out vec4 out_color;
+
+vec3 linear_to_srgb(vec3 linear) {
+    return mix(linear * 12.92, 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055,
+        step(0.0031308, linear));
+}
+
+void main() {
+    // the colour is linear, with straight alpha
+    float coverage = texture(glyph_page, texture_coordinate).r;
+    out_color = vec4(color.rgb, color.a * coverage);
+    if (push_constants.srgb_target == 0) {
+        out_color.rgb = linear_to_srgb(out_color.rgb);
+    }
+}
@@ -0,0 +1,25 @@
+#version 460
+
+layout (push_constant) uniform constants {
+    vec2 screen_size;
+    uint srgb_target;
+} push_constants;
+
+layout (set = 0, binding = 0) uniform sampler2D ui_texture;
+
+layout (location = 0) in vec2 texture_coordinate;
+layout (location = 1) in vec4 color;
+
+layout (location = 0) out vec4 out_color;
+
+vec3 srgb_to_linear(vec3 srgb) {
+    return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, srgb));
+}
+
+void main() {
+    // egui's colours and textures are premultiplied sRGB
+    out_color = color * texture(ui_texture, texture_coordinate);
+    if (push_constants.srgb_target != 0) {
+        out_color.rgb = srgb_to_linear(out_color.rgb);
+    }
+}
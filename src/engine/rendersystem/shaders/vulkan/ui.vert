@@ -0,0 +1,19 @@
+#version 460
+
+layout (push_constant) uniform constants {
+    vec2 screen_size;
+    uint srgb_target;
+} push_constants;
+
+layout (location = 0) in vec2 in_position;
+layout (location = 1) in vec2 in_texture_coordinate;
+layout (location = 2) in vec4 in_color;
+
+layout (location = 0) out vec2 texture_coordinate;
+layout (location = 1) out vec4 color;
+
+void main() {
+    gl_Position = vec4(2.0 * in_position / push_constants.screen_size - 1.0, 0.0, 1.0);
+    texture_coordinate = in_texture_coordinate;
+    color = in_color;
+}
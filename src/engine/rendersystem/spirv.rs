@@ -0,0 +1,161 @@
+use log::trace;
+
+// Just enough SPIR-V parsing to find a vertex shader's inputs, so vertex descriptors can be
+// checked against what the shader actually reads. Built in inputs (gl_VertexIndex and so on)
+// don't come from vertex buffers and get skipped, and so do arrays, which nothing uses yet.
+//
+// See https://registry.khronos.org/SPIR-V/specs/unified1/SPIRV.html for the instructions.
+
+const MAGIC: u32 = 0x07230203;
+const HEADER_SIZE: usize = 5;
+
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const STORAGE_CLASS_INPUT: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComponentType {
+    Float,
+    Int,
+    Uint,
+}
+
+// Matrices take a location for each column, so they come out as one input per column
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub component_type: ComponentType,
+    pub component_count: u32,
+}
+
+#[derive(Clone, Copy)]
+enum Type {
+    Scalar(ComponentType),
+    Vector(ComponentType, u32),
+    Matrix(ComponentType, u32, u32),
+    Pointer(u32, u32),
+}
+
+pub fn vertex_inputs(binary: &[u8]) -> Result<Vec<VertexInput>, String> {
+    if binary.len() % 4 != 0 || binary.len() < HEADER_SIZE * 4 {
+        return Err(format!("{} bytes isn't a SPIR-V module", binary.len()));
+    }
+    let mut words: Vec<u32> = binary
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+    if words[0] == MAGIC.swap_bytes() {
+        words.iter_mut().for_each(|word| *word = word.swap_bytes());
+    } else if words[0] != MAGIC {
+        return Err(format!("Bad SPIR-V magic number {:#010x}", words[0]));
+    }
+
+    let mut types = Vec::new();
+    let mut variables = Vec::new();
+    let mut locations = Vec::new();
+    let mut built_ins = Vec::new();
+
+    let mut offset = HEADER_SIZE;
+    while offset < words.len() {
+        let word_count = (words[offset] >> 16) as usize;
+        let opcode = words[offset] & 0xffff;
+        if word_count == 0 || offset + word_count > words.len() {
+            return Err(format!("Truncated SPIR-V instruction at word {offset}"));
+        }
+        let operands = &words[offset + 1..offset + word_count];
+        offset += word_count;
+
+        let operand = |index: usize| {
+            operands
+                .get(index)
+                .copied()
+                .ok_or_else(|| format!("SPIR-V instruction {opcode} is missing operands"))
+        };
+        match opcode {
+            OP_TYPE_INT => {
+                let component_type = if operand(2)? != 0 {
+                    ComponentType::Int
+                } else {
+                    ComponentType::Uint
+                };
+                types.push((operand(0)?, Type::Scalar(component_type)));
+            }
+            OP_TYPE_FLOAT => types.push((operand(0)?, Type::Scalar(ComponentType::Float))),
+            OP_TYPE_VECTOR => {
+                if let Some(Type::Scalar(component_type)) = find(&types, operand(1)?) {
+                    types.push((operand(0)?, Type::Vector(component_type, operand(2)?)));
+                }
+            }
+            OP_TYPE_MATRIX => {
+                if let Some(Type::Vector(component_type, rows)) = find(&types, operand(1)?) {
+                    types.push((operand(0)?, Type::Matrix(component_type, rows, operand(2)?)));
+                }
+            }
+            OP_TYPE_POINTER => {
+                types.push((operand(0)?, Type::Pointer(operand(1)?, operand(2)?)));
+            }
+            OP_VARIABLE if operand(2)? == STORAGE_CLASS_INPUT => {
+                variables.push((operand(1)?, operand(0)?));
+            }
+            OP_DECORATE => match operand(1)? {
+                DECORATION_LOCATION => locations.push((operand(0)?, operand(2)?)),
+                DECORATION_BUILT_IN => built_ins.push(operand(0)?),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    let mut inputs = Vec::new();
+    for (id, pointer_type) in variables {
+        if built_ins.contains(&id) {
+            continue;
+        }
+        let Some(&(_, location)) = locations.iter().find(|(target, _)| *target == id) else {
+            trace!("Skipping input {id} without a location");
+            continue;
+        };
+        let pointee = match find(&types, pointer_type) {
+            Some(Type::Pointer(_, pointee)) => find(&types, pointee),
+            _ => None,
+        };
+        match pointee {
+            Some(Type::Scalar(component_type)) => inputs.push(VertexInput {
+                location,
+                component_type,
+                component_count: 1,
+            }),
+            Some(Type::Vector(component_type, component_count)) => inputs.push(VertexInput {
+                location,
+                component_type,
+                component_count,
+            }),
+            Some(Type::Matrix(component_type, rows, columns)) => {
+                inputs.extend((0..columns).map(|column| VertexInput {
+                    location: location + column,
+                    component_type,
+                    component_count: rows,
+                }))
+            }
+            _ => trace!("Skipping input {id} at location {location}, its type isn't supported"),
+        }
+    }
+    inputs.sort_by_key(|input| input.location);
+
+    Ok(inputs)
+}
+
+fn find(types: &[(u32, Type)], id: u32) -> Option<Type> {
+    types
+        .iter()
+        .find(|(other, _)| *other == id)
+        .map(|(_, ty)| *ty)
+}
@@ -0,0 +1,196 @@
+// A render system on a surface with no window, for tests that need a GPU. The tests skip
+// themselves (with a message) when there isn't a usable one or the driver can't do headless
+// surfaces, so they still pass on machines without Vulkan.
+
+use ash::{extensions, vk};
+use nalgebra::*;
+use std::{ffi, fs, io};
+
+use super::*;
+
+pub struct HeadlessSurface {
+    width: u32,
+    height: u32,
+}
+
+impl HeadlessSurface {
+    // Like the window being resized, the render system still needs window_resized
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+}
+
+impl SurfaceProvider for HeadlessSurface {
+    fn create_surface(
+        &self,
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        alloc_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> vk::SurfaceKHR {
+        unsafe {
+            extensions::ext::HeadlessSurface::new(entry, instance)
+                .create_headless_surface(
+                    &vk::HeadlessSurfaceCreateInfoEXT::default(),
+                    alloc_callbacks,
+                )
+                .unwrap_or_else(|err| panic!("Failed to create headless surface: {}", err))
+        }
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn instance_extensions(&self) -> Vec<String> {
+        vec![String::from("VK_EXT_headless_surface")]
+    }
+}
+
+// None if there's nothing to test on
+pub fn headless_state(width: u32, height: u32) -> Option<(HeadlessSurface, State)> {
+    headless_state_with(width, height, RenderConfig::default())
+}
+
+pub fn headless_state_with(
+    width: u32,
+    height: u32,
+    config: RenderConfig,
+) -> Option<(HeadlessSurface, State)> {
+    let Ok(entry) = (unsafe { ash::Entry::load() }) else {
+        eprintln!("Skipping, there's no Vulkan loader");
+        return None;
+    };
+    let headless =
+        unsafe { entry.enumerate_instance_extension_properties(None) }.is_ok_and(|properties| {
+            properties.iter().any(|properties| {
+                unsafe { ffi::CStr::from_ptr(properties.extension_name.as_ptr()) }.to_bytes()
+                    == b"VK_EXT_headless_surface"
+            })
+        });
+    if !headless {
+        eprintln!("Skipping, VK_EXT_headless_surface isn't supported");
+        return None;
+    }
+
+    let video = HeadlessSurface { width, height };
+    if enumerate_gpus(&video).is_empty() {
+        eprintln!("Skipping, there's no usable GPU");
+        return None;
+    }
+    let state = State::init(&video, config);
+    Some((video, state))
+}
+
+// What the GPU the render system picked supports
+pub fn selected_gpu(state: &State, video: &HeadlessSurface) -> GpuCapabilities {
+    let selected = state
+        .available_gpus()
+        .into_iter()
+        .find(|gpu| gpu.selected)
+        .unwrap()
+        .index;
+    enumerate_gpus(video)
+        .into_iter()
+        .find(|gpu| gpu.index == selected)
+        .unwrap()
+}
+
+// What build.rs compiled, like basic.vert
+pub fn shader_binary(file: &str) -> Vec<u8> {
+    let profile = if cfg!(build = "debug") {
+        "debug"
+    } else {
+        "release"
+    };
+    let path = format!(
+        "{}/target/{profile}/{}/shaders/{file}.spv",
+        env!("CARGO_MANIFEST_DIR"),
+        crate::GAME_EXECUTABLE_NAME
+    );
+    fs::read(&path).unwrap_or_else(|err| panic!("Failed to read {path}: {err}"))
+}
+
+// The basic shader and a material using it, with the texture if there is one
+pub fn basic_material(state: &mut State, name: &str, texture: Option<&str>) {
+    if !state.shaders.contains_key("basic") {
+        Shader::from_bytes(
+            state,
+            "basic",
+            shader_binary("basic.vert"),
+            shader_binary("basic.frag"),
+            None,
+            None,
+        )
+        .unwrap();
+    }
+    Material::new(state, name, "basic", texture).unwrap();
+}
+
+// A square from (x, y) to (x + size, y + size) at z, facing the default camera
+pub fn quad(
+    state: &mut State,
+    name: &str,
+    material: &str,
+    (x, y, z): (f32, f32, f32),
+    size: f32,
+    color: Vector4<f32>,
+) -> ThingHolder<Model> {
+    let normal = Vector3::new(0.0, 0.0, 1.0);
+    let corner = |u: f32, v: f32| {
+        Vertex::new(
+            Vector3::new(x + u * size, y + v * size, z),
+            Vector2::new(u, v),
+            normal,
+        )
+        .with_color(color)
+    };
+    Model::from_vertices(
+        state,
+        name,
+        vec![
+            corner(0.0, 0.0),
+            corner(1.0, 0.0),
+            corner(1.0, 1.0),
+            corner(0.0, 1.0),
+        ],
+        vec![0, 1, 2, 2, 3, 0],
+        Topology::TriangleList,
+        material,
+    )
+    .unwrap()
+}
+
+// A texture made from pixels instead of a file
+pub fn texture(
+    state: &mut State,
+    name: &str,
+    image: image::RgbaImage,
+) -> ThingHolder<RenderTexture> {
+    let mut data = Vec::new();
+    image::DynamicImage::ImageRgba8(image)
+        .write_to(
+            &mut io::Cursor::new(&mut data),
+            image::ImageOutputFormat::Png,
+        )
+        .unwrap();
+    RenderTexture::from_bytes(state, name, &data, TextureColorSpace::Srgb).unwrap()
+}
+
+// Renders one frame and waits for it, draw is called inside a full window viewport
+pub fn capture(
+    state: &mut State,
+    video: &HeadlessSurface,
+    camera: &Camera,
+    draw: impl FnOnce(&mut State),
+) -> image::RgbaImage {
+    state.begin_cmds(video);
+    state.render_viewport(camera, ViewportRect::FULL, draw);
+    let handle = state.request_readback();
+    state.present();
+    state.sync();
+    state.backend.get().wait_idle();
+    state
+        .poll_readback(handle)
+        .expect("The readback wasn't done after waiting for the GPU")
+}
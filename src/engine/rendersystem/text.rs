@@ -0,0 +1,158 @@
+use super::{
+    render_impl, Color, GlyphCache, GlyphCacheStats, GlyphKey, RasterizedGlyph, ShaderError,
+};
+use log::{debug, info};
+use nalgebra::*;
+use std::mem;
+
+// Text drawn from a glyph cache. Queued text gets turned into quads right away, so glyphs are
+// only rasterized on a miss, and whatever the cache changed gets uploaded when the frame is
+// presented.
+pub struct Text {
+    cache: GlyphCache,
+    vertex_binary: Vec<u8>,
+    fragment_binary: Vec<u8>,
+    handle: render_impl::TextData,
+
+    vertices: Vec<TextVertex>,
+    // Indices for each page, since each one is drawn separately
+    indices: Vec<Vec<u32>>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TextVertex {
+    // In render pixels from the top left
+    pub position: [f32; 2],
+    pub texture_coordinate: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl Text {
+    pub fn new(
+        backend: &render_impl::State,
+        vertex_binary: Vec<u8>,
+        fragment_binary: Vec<u8>,
+        cache: GlyphCache,
+    ) -> Result<Self, ShaderError> {
+        info!("Creating text");
+
+        let handle = render_impl::TextData::new(backend, &vertex_binary, &fragment_binary)?;
+
+        Ok(Self {
+            cache,
+            vertex_binary,
+            fragment_binary,
+            handle,
+
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        })
+    }
+
+    // Destroys the backend side and gives back the SPIR-V and the cache, for when the device is
+    // recreated. The new one has no pages, so they all get uploaded again.
+    pub fn into_parts(self, backend: &render_impl::State) -> (Vec<u8>, Vec<u8>, GlyphCache) {
+        debug!("Destroying text to recreate it");
+        self.handle.destroy(backend);
+        (self.vertex_binary, self.fragment_binary, self.cache)
+    }
+
+    pub fn is_stale(&self, backend: &render_impl::State) -> bool {
+        self.handle.is_stale(backend)
+    }
+
+    pub fn recreate_pipeline(&mut self, backend: &render_impl::State) -> Result<(), ShaderError> {
+        self.handle
+            .recreate_pipeline(backend, &self.vertex_binary, &self.fragment_binary)
+    }
+
+    // Lays text out from position, which is where the first line's baseline starts. A newline
+    // goes down size pixels. Glyphs the cache can't fit are left out.
+    pub fn queue(
+        &mut self,
+        text: &str,
+        position: Vector2<f32>,
+        size: u32,
+        color: Color,
+        mut rasterize: impl FnMut(GlyphKey) -> Option<RasterizedGlyph>,
+    ) {
+        let color = color.to_linear();
+        let mut pen = position;
+        for character in text.chars() {
+            if character == '\n' {
+                pen = Vector2::new(position.x, pen.y + size as f32);
+                continue;
+            }
+
+            let key = GlyphKey { character, size };
+            let Some(glyph) = self.cache.get(key, || rasterize(key)) else {
+                continue;
+            };
+            let origin = pen + glyph.bearing;
+            pen.x += glyph.advance;
+            if glyph.size.x == 0.0 || glyph.size.y == 0.0 {
+                continue;
+            }
+
+            if self.indices.len() <= glyph.page {
+                self.indices.resize_with(glyph.page + 1, Vec::new);
+            }
+            let first = self.vertices.len() as u32;
+            let corners = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)];
+            self.vertices.extend(corners.map(|(x, y)| TextVertex {
+                position: [origin.x + x * glyph.size.x, origin.y + y * glyph.size.y],
+                texture_coordinate: [
+                    glyph.uv_min.x + x * (glyph.uv_max.x - glyph.uv_min.x),
+                    glyph.uv_min.y + y * (glyph.uv_max.y - glyph.uv_min.y),
+                ],
+                color,
+            }));
+            self.indices[glyph.page].extend([0, 1, 2, 2, 1, 3].map(|index| first + index));
+        }
+    }
+
+    pub fn stats(&self) -> GlyphCacheStats {
+        self.cache.stats()
+    }
+
+    pub fn draw(&mut self, backend: &mut render_impl::State) {
+        self.frame(backend).draw(backend);
+    }
+
+    // Uploads what the cache changed, then hands over the frame's quads so the render thread can
+    // draw them. Glyphs used from here on are safe from eviction until the next frame.
+    pub fn frame(&mut self, backend: &mut render_impl::State) -> TextFrame {
+        let dirty: Vec<_> = self.cache.drain_dirty().collect();
+        self.handle
+            .update_pages(backend, self.cache.pages(), &dirty);
+        self.cache.begin_frame();
+
+        TextFrame {
+            handle: &mut self.handle,
+            vertices: mem::take(&mut self.vertices),
+            indices: mem::take(&mut self.indices),
+        }
+    }
+
+    pub fn destroy(self, backend: &render_impl::State) {
+        self.handle.destroy(backend);
+    }
+}
+
+// What the render thread needs to draw the text. The handle is only used by frame and draw, and
+// neither of those can happen until the render thread is done with it.
+pub struct TextFrame {
+    handle: *mut render_impl::TextData,
+    vertices: Vec<TextVertex>,
+    indices: Vec<Vec<u32>>,
+}
+
+unsafe impl Send for TextFrame {}
+
+impl TextFrame {
+    pub fn draw(&self, backend: &mut render_impl::State) {
+        let handle = unsafe { &mut *self.handle };
+        backend.draw_text(handle, &self.vertices, &self.indices);
+    }
+}
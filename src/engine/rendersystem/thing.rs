@@ -0,0 +1,158 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicIsize, Ordering},
+    Arc,
+};
+
+// What ThingHolder wraps resources in. By default it's checked like a RefCell that works across
+// threads: get_mut while anything else has a reference to the same resource (another get/get_mut,
+// or the renderer using it from another thread) panics instead of waiting. It's one atomic per
+// access and the engine itself never overlaps them, but anything holding onto handles has to be
+// careful. The safe_resources feature swaps it for an RwLock, which panics on poisoning and blocks
+// (or deadlocks on the same thread) instead.
+//
+// Separately, the render system poisons everything it made when it shuts down. The backend
+// handles inside are gone by then, so anything given a poisoned resource ignores it instead of
+// using freed Vulkan objects. Resources that get used without their ThingHolder (destroy, render)
+// keep a Poison of their own to check.
+
+#[cfg(not(feature = "safe_resources"))]
+pub struct Thing<T> {
+    value: std::cell::UnsafeCell<T>,
+    // How many gets there are, or -1 while there's a get_mut
+    borrows: AtomicIsize,
+    poisoned: Poison,
+}
+
+// The borrow count makes sure there's only ever one &mut, same as RwLock
+#[cfg(not(feature = "safe_resources"))]
+unsafe impl<T: Send + Sync> Sync for Thing<T> {}
+
+#[cfg(not(feature = "safe_resources"))]
+impl<T> Thing<T> {
+    pub fn with_poison(value: T, poisoned: Poison) -> Self {
+        Self {
+            value: std::cell::UnsafeCell::new(value),
+            borrows: AtomicIsize::new(0),
+            poisoned,
+        }
+    }
+
+    pub fn get(&self) -> ThingRef<'_, T> {
+        let borrows = self.borrows.fetch_add(1, Ordering::Acquire);
+        if borrows < 0 {
+            self.borrows.fetch_sub(1, Ordering::Release);
+            panic!("Resource is already borrowed mutably");
+        }
+        ThingRef { thing: self }
+    }
+
+    pub fn get_mut(&self) -> ThingMut<'_, T> {
+        if self
+            .borrows
+            .compare_exchange(0, -1, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            panic!("Resource is already borrowed");
+        }
+        ThingMut { thing: self }
+    }
+}
+
+#[cfg(not(feature = "safe_resources"))]
+pub struct ThingRef<'a, T> {
+    thing: &'a Thing<T>,
+}
+
+#[cfg(not(feature = "safe_resources"))]
+impl<T> std::ops::Deref for ThingRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // nothing can get_mut while this is around
+        unsafe { &*self.thing.value.get() }
+    }
+}
+
+#[cfg(not(feature = "safe_resources"))]
+impl<T> Drop for ThingRef<'_, T> {
+    fn drop(&mut self) {
+        self.thing.borrows.fetch_sub(1, Ordering::Release);
+    }
+}
+
+#[cfg(not(feature = "safe_resources"))]
+pub struct ThingMut<'a, T> {
+    thing: &'a Thing<T>,
+}
+
+#[cfg(not(feature = "safe_resources"))]
+impl<T> std::ops::Deref for ThingMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.thing.value.get() }
+    }
+}
+
+#[cfg(not(feature = "safe_resources"))]
+impl<T> std::ops::DerefMut for ThingMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // this is the only borrow there is
+        unsafe { &mut *self.thing.value.get() }
+    }
+}
+
+#[cfg(not(feature = "safe_resources"))]
+impl<T> Drop for ThingMut<'_, T> {
+    fn drop(&mut self) {
+        self.thing.borrows.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(feature = "safe_resources")]
+pub struct Thing<T> {
+    value: std::sync::RwLock<T>,
+    poisoned: Poison,
+}
+
+#[cfg(feature = "safe_resources")]
+impl<T> Thing<T> {
+    pub fn with_poison(value: T, poisoned: Poison) -> Self {
+        Self {
+            value: std::sync::RwLock::new(value),
+            poisoned,
+        }
+    }
+
+    pub fn get(&self) -> std::sync::RwLockReadGuard<'_, T> {
+        self.value.read().unwrap()
+    }
+
+    pub fn get_mut(&self) -> std::sync::RwLockWriteGuard<'_, T> {
+        self.value.write().unwrap()
+    }
+}
+
+impl<T> Thing<T> {
+    pub fn new(value: T) -> Self {
+        Self::with_poison(value, Poison::default())
+    }
+
+    pub fn poison(&self) {
+        self.poisoned.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.is_poisoned()
+    }
+}
+
+// A Thing's poisoned flag, shared with whatever else needs to see it
+#[derive(Clone, Default)]
+pub struct Poison(Arc<AtomicBool>);
+
+impl Poison {
+    pub fn is_poisoned(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
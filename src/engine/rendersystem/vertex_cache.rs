@@ -0,0 +1,246 @@
+// Tom Forsyth's linear-speed vertex cache optimisation
+// (https://tomforsyth1000.github.io/papers/fast_vert_cache_opt.html)
+
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+fn vertex_score(cache_position: Option<usize>, active_triangles: usize) -> f32 {
+    if active_triangles == 0 {
+        return -1.0;
+    }
+
+    let score = match cache_position {
+        None => 0.0,
+        // the last triangle's vertices get a fixed score so it doesn't just reuse them forever
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+
+    score + VALENCE_BOOST_SCALE * (active_triangles as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+// Reorders triangles to make better use of the GPU's post-transform vertex cache
+pub fn optimize_indices(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return Vec::from(indices);
+    }
+
+    let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+    for (triangle, vertices) in indices.chunks_exact(3).enumerate() {
+        for &vertex in vertices {
+            vertex_triangles[vertex as usize].push(triangle);
+        }
+    }
+
+    let mut vertex_scores: Vec<f32> = vertex_triangles
+        .iter()
+        .map(|triangles| vertex_score(None, triangles.len()))
+        .collect();
+    let triangle_score = |vertex_scores: &[f32], triangle: usize| -> f32 {
+        indices[triangle * 3..triangle * 3 + 3]
+            .iter()
+            .map(|&vertex| vertex_scores[vertex as usize])
+            .sum()
+    };
+
+    let mut added = vec![false; triangle_count];
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(indices.len());
+    let mut next_unadded = 0;
+
+    let mut best = (0..triangle_count)
+        .map(|triangle| (triangle, triangle_score(&vertex_scores, triangle)))
+        .fold((0, f32::MIN), |best, current| {
+            if current.1 > best.1 {
+                current
+            } else {
+                best
+            }
+        })
+        .0;
+
+    for _ in 0..triangle_count {
+        added[best] = true;
+        let triangle_vertices = &indices[best * 3..best * 3 + 3];
+        output.extend_from_slice(triangle_vertices);
+
+        for &vertex in triangle_vertices {
+            vertex_triangles[vertex as usize].retain(|&triangle| triangle != best);
+            cache.retain(|&cached| cached != vertex);
+        }
+        for &vertex in triangle_vertices.iter().rev() {
+            cache.insert(0, vertex);
+        }
+
+        for (position, &vertex) in cache.iter().enumerate() {
+            let position = if position < CACHE_SIZE {
+                Some(position)
+            } else {
+                None
+            };
+            vertex_scores[vertex as usize] =
+                vertex_score(position, vertex_triangles[vertex as usize].len());
+        }
+        cache.truncate(CACHE_SIZE);
+
+        // only triangles touching the cache can have changed score
+        let mut best_score = -1.0;
+        for &vertex in &cache {
+            for &triangle in &vertex_triangles[vertex as usize] {
+                let score = triangle_score(&vertex_scores, triangle);
+                if score > best_score {
+                    best_score = score;
+                    best = triangle;
+                }
+            }
+        }
+
+        if best_score < 0.0 {
+            while next_unadded < triangle_count && added[next_unadded] {
+                next_unadded += 1;
+            }
+            if next_unadded == triangle_count {
+                break;
+            }
+            best = next_unadded;
+        }
+    }
+
+    output
+}
+
+// Renumbers vertices in the order the indices first use them so fetches are mostly sequential,
+// returns the new order of the old vertices. Unused vertices end up at the end.
+pub fn optimize_vertex_order(indices: &mut [u32], vertex_count: usize) -> Vec<usize> {
+    let mut remap: Vec<Option<u32>> = vec![None; vertex_count];
+    let mut order = Vec::with_capacity(vertex_count);
+
+    for index in indices.iter_mut() {
+        let new_index = match remap[*index as usize] {
+            Some(new_index) => new_index,
+            None => {
+                let new_index = order.len() as u32;
+                remap[*index as usize] = Some(new_index);
+                order.push(*index as usize);
+                new_index
+            }
+        };
+        *index = new_index;
+    }
+
+    for (vertex, new_index) in remap.iter().enumerate() {
+        if new_index.is_none() {
+            order.push(vertex);
+        }
+    }
+
+    order
+}
+
+// Average cache miss ratio, the number of vertices transformed per triangle with a FIFO cache
+// of the given size. 0.5 is about the best possible, 3 is the worst.
+pub fn acmr(indices: &[u32], cache_size: usize) -> f32 {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return 0.0;
+    }
+
+    let mut cache = std::collections::VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for index in indices {
+        if !cache.contains(index) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(*index);
+        }
+    }
+
+    misses as f32 / triangle_count as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A grid of quads with its triangles shuffled, like a badly exported mesh
+    fn dense_grid(size: u32) -> (Vec<u32>, usize) {
+        let mut triangles = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let corner = y * (size + 1) + x;
+                triangles.push([corner, corner + 1, corner + size + 2]);
+                triangles.push([corner, corner + size + 2, corner + size + 1]);
+            }
+        }
+        // fixed seed, so it's the same mesh every time
+        let mut seed = 12345u64;
+        for i in (1..triangles.len()).rev() {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            triangles.swap(i, (seed >> 33) as usize % (i + 1));
+        }
+        (triangles.concat(), ((size + 1) * (size + 1)) as usize)
+    }
+
+    fn sorted_triangles(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut triangles: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                // rotated so the smallest index is first, which keeps the winding
+                let first = (0..3).min_by_key(|&i| triangle[i]).unwrap();
+                [
+                    triangle[first],
+                    triangle[(first + 1) % 3],
+                    triangle[(first + 2) % 3],
+                ]
+            })
+            .collect();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn optimizing_lowers_acmr() {
+        let (indices, vertex_count) = dense_grid(64);
+        let before = acmr(&indices, 16);
+        let optimized = optimize_indices(&indices, vertex_count);
+        let after = acmr(&optimized, 16);
+
+        assert!(
+            after < before * 0.5,
+            "ACMR only went from {before} to {after}"
+        );
+        assert!(after < 1.0, "ACMR is still {after}");
+        assert_eq!(sorted_triangles(&optimized), sorted_triangles(&indices));
+    }
+
+    #[test]
+    fn vertex_order_keeps_triangles() {
+        let (indices, vertex_count) = dense_grid(8);
+        let mut reordered = indices.clone();
+        let order = optimize_vertex_order(&mut reordered, vertex_count);
+
+        assert_eq!(order.len(), vertex_count);
+        let remapped: Vec<u32> = reordered
+            .iter()
+            .map(|&index| order[index as usize] as u32)
+            .collect();
+        assert_eq!(remapped, indices);
+        // first use order means every index is at most one more than the biggest before it
+        let mut next = 0;
+        for &index in &reordered {
+            assert!(index <= next);
+            next = next.max(index + 1);
+        }
+    }
+}
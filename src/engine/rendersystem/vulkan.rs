@@ -1,8 +1,15 @@
-use crate::{engine::rendersystem, platform};
+use crate::engine::rendersystem;
 use ash::{extensions, vk};
-use log::{debug, error, log, trace};
+use log::{debug, error, log, trace, warn};
 use std::rc::Rc;
-use std::{alloc, cell::SyncUnsafeCell, cmp, collections::HashMap, ffi, mem, ptr, sync::Arc};
+use std::{
+    alloc,
+    cell::RefCell,
+    cmp,
+    collections::HashMap,
+    ffi, io, mem, ptr,
+    sync::{atomic, Arc},
+};
 use vk_mem::*;
 
 macro_rules! vulkan_check {
@@ -11,63 +18,174 @@ macro_rules! vulkan_check {
     };
 }
 
+// Vulkan doesn't say how big an allocation was when it reallocates or frees it, but Rust's
+// allocator needs the exact layout it was allocated with, so that goes in a header right before
+// the pointer Vulkan gets. The header is padded to the alignment to keep the pointer aligned.
+#[derive(Clone, Copy)]
+struct AllocationHeader {
+    size: usize,
+    alignment: usize,
+}
+
+impl AllocationHeader {
+    // The whole allocation's layout, and where Vulkan's part starts in it
+    fn layout(&self) -> (alloc::Layout, usize) {
+        let offset = mem::size_of::<Self>().next_multiple_of(self.alignment);
+        (
+            alloc::Layout::from_size_align(offset + self.size, self.alignment).unwrap(),
+            offset,
+        )
+    }
+
+    fn new(size: usize, alignment: usize) -> Self {
+        Self {
+            size,
+            alignment: alignment.max(mem::align_of::<Self>()).next_power_of_two(),
+        }
+    }
+
+    unsafe fn read(p_memory: *mut ffi::c_void) -> Self {
+        (p_memory as *mut Self).sub(1).read_unaligned()
+    }
+
+    unsafe fn write(self, p_memory: *mut ffi::c_void) {
+        (p_memory as *mut Self).sub(1).write_unaligned(self)
+    }
+}
+
 extern "system" fn vulkan_alloc(
     _p_user_data: *mut ffi::c_void,
     size: usize,
     alignment: usize,
     _allocation_scope: vk::SystemAllocationScope,
 ) -> *mut ffi::c_void {
-    let alignment = if alignment.is_power_of_two() {
-        alignment
-    } else {
-        alignment + 1
-    };
-    let size = if size == 0 { alignment } else { size };
-    trace!("Allocating {size} byte(s) aligned to {alignment} for Vulkan");
+    let header = AllocationHeader::new(size, alignment);
+    trace!(
+        "Allocating {size} byte(s) aligned to {} for Vulkan",
+        header.alignment
+    );
+    let (layout, offset) = header.layout();
     unsafe {
-        alloc::alloc(alloc::Layout::from_size_align(size, alignment).unwrap()) as *mut ffi::c_void
+        let allocation = alloc::alloc(layout);
+        if allocation.is_null() {
+            return ptr::null_mut();
+        }
+        let p_memory = allocation.add(offset) as *mut ffi::c_void;
+        header.write(p_memory);
+        p_memory
     }
 }
 
+// Null is the same as allocating, and a size of 0 is the same as freeing. The alignment is
+// always the same as the original one.
 extern "system" fn vulkan_realloc(
-    _p_user_data: *mut ffi::c_void,
+    p_user_data: *mut ffi::c_void,
     p_original: *mut ffi::c_void,
     size: usize,
     alignment: usize,
-    _allocation_scope: vk::SystemAllocationScope,
+    allocation_scope: vk::SystemAllocationScope,
 ) -> *mut ffi::c_void {
-    let alignment = if alignment == 0 {
-        2
-    } else if alignment.is_power_of_two() {
-        alignment
-    } else {
-        alignment + 1
-    };
-    let size = if size == 0 { alignment } else { size };
+    if p_original.is_null() {
+        return vulkan_alloc(p_user_data, size, alignment, allocation_scope);
+    }
+    if size == 0 {
+        vulkan_dealloc(p_user_data, p_original);
+        return ptr::null_mut();
+    }
+
     trace!(
-        "Reallocating Vulkan allocation {:X} to {size} byte(s) aligned to {alignment}",
+        "Reallocating Vulkan allocation {:X} to {size} byte(s)",
         p_original as usize
     );
     unsafe {
-        alloc::realloc(
-            p_original as *mut u8,
-            alloc::Layout::from_size_align(size, alignment).unwrap(),
-            size,
-        ) as *mut ffi::c_void
+        let old_header = AllocationHeader::read(p_original);
+        let (old_layout, offset) = old_header.layout();
+        let header = AllocationHeader { size, ..old_header };
+        let allocation = alloc::realloc(
+            (p_original as *mut u8).sub(offset),
+            old_layout,
+            header.layout().0.size(),
+        );
+        if allocation.is_null() {
+            return ptr::null_mut();
+        }
+        let p_memory = allocation.add(offset) as *mut ffi::c_void;
+        header.write(p_memory);
+        p_memory
     }
 }
 
 extern "system" fn vulkan_dealloc(_p_user_data: *mut ffi::c_void, p_memory: *mut ffi::c_void) {
+    if p_memory.is_null() {
+        return;
+    }
+
     trace!("Freeing Vulkan allocation {:X}", p_memory as usize);
     unsafe {
-        alloc::dealloc(
-            p_memory as *mut u8,
-            alloc::Layout::from_size_align(0, 1).unwrap(),
-        )
+        let (layout, offset) = AllocationHeader::read(p_memory).layout();
+        alloc::dealloc((p_memory as *mut u8).sub(offset), layout)
     }
 }
 
-const FRAME_COUNT: usize = 3;
+pub const FRAME_COUNT: usize = 3;
+// Uniform data slots in each frame's uniform buffer, the first is used outside of viewports
+const MAX_VIEWPORTS: usize = 8;
+// In nanoseconds, long enough for a frame at any sensible refresh rate
+const PRESENT_WAIT_TIMEOUT: u64 = 100_000_000;
+// Descriptors of each type in a descriptor pool block, more blocks get added as they fill up
+const DESCRIPTOR_BLOCK_SIZE: u32 = 256;
+
+const COLOR_SUBRESOURCE_RANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::COLOR,
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+};
+
+// The depth formats all have stencil too, it's used by stencil masks
+const DEPTH_SUBRESOURCE_RANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::from_raw(
+        vk::ImageAspectFlags::DEPTH.as_raw() | vk::ImageAspectFlags::STENCIL.as_raw(),
+    ),
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+};
+
+// Anything that can give the renderer a surface to present to, like the platform window or a
+// canvas from some other toolkit
+pub trait SurfaceProvider {
+    fn create_surface(
+        &self,
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        alloc_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> vk::SurfaceKHR;
+
+    fn size(&self) -> (u32, u32);
+
+    // Instance extensions needed by create_surface, on top of VK_KHR_surface
+    fn instance_extensions(&self) -> Vec<String>;
+
+    // The monitor the window is on, for exclusive fullscreen. Only Windows has these.
+    fn monitor(&self) -> Option<vk::HMONITOR> {
+        None
+    }
+
+    // Covers the window's monitor without a border, for when exclusive fullscreen isn't there.
+    // Returns whether it's done, windows that can't do it say no.
+    fn set_borderless_fullscreen(&mut self, _enabled: bool) -> bool {
+        false
+    }
+
+    // Whether the window manager has given the window its size yet, the size before that (0x0 or
+    // 1x1 on some window managers) isn't real
+    fn configured(&self) -> bool {
+        true
+    }
+}
 
 struct GpuInfo {
     device: vk::PhysicalDevice,
@@ -76,9 +194,28 @@ struct GpuInfo {
 
     surface_formats: Vec<vk::SurfaceFormatKHR>,
     present_modes: Vec<vk::PresentModeKHR>,
+    // What the surface allows the swapchain to have, the maximum is 0 if there isn't one
+    min_image_count: u32,
+    max_image_count: u32,
 
     graphics_family_index: u32,
     compute_family_index: u32,
+    // Can present to the surface, the graphics family if it can
+    present_family_index: u32,
+    // Of the graphics family, the frame's timestamps only mean anything in these low bits
+    timestamp_valid_bits: u32,
+
+    // VK_EXT_shader_object is supported, otherwise shaders get compiled into pipelines
+    shader_object: bool,
+    // VK_KHR_present_id and VK_KHR_present_wait
+    present_wait: bool,
+    // VK_EXT_full_screen_exclusive, only on Windows
+    full_screen_exclusive: bool,
+    sample_rate_shading: bool,
+    sampler_anisotropy: bool,
+    depth_bias_clamp: bool,
+    tessellation_shader: bool,
+    geometry_shader: bool,
 
     // Vague guess at how powerful the GPU is
     performance_score: u32,
@@ -220,14 +357,49 @@ impl Buffer {
         device: &ash::Device,
         queue: &vk::Queue,
         transfer_pool: &vk::CommandPool,
+        fence_pool: &mut FencePool,
         destination: &Self,
     ) {
-        let transfer_buffer = unsafe {
+        let mut batch = TransferBatch::begin(device, transfer_pool);
+        batch.copy(device, self, destination);
+        batch.submit(device, queue, transfer_pool, fence_pool);
+    }
+
+    pub fn destroy(self, allocator: &vk_mem::Allocator) {
+        unsafe { allocator.destroy_buffer(self.handle, self.allocation) };
+    }
+
+    pub fn handle(&self) -> &vk::Buffer {
+        &self.handle
+    }
+
+    pub fn allocation(&self) -> &vk_mem::Allocation {
+        &self.allocation
+    }
+
+    pub fn allocation_mut(&mut self) -> &mut vk_mem::Allocation {
+        &mut self.allocation
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+}
+
+// Records any number of copies into one command buffer so they can be submitted and waited on once
+struct TransferBatch {
+    command_buffer: vk::CommandBuffer,
+    copy_count: usize,
+}
+
+impl TransferBatch {
+    pub fn begin(device: &ash::Device, transfer_pool: &vk::CommandPool) -> Self {
+        let command_buffer = unsafe {
             vulkan_check!(
                 device.allocate_command_buffers(&vk::CommandBufferAllocateInfo {
                     level: vk::CommandBufferLevel::PRIMARY,
                     command_pool: *transfer_pool,
-                    command_buffer_count: FRAME_COUNT as u32,
+                    command_buffer_count: 1,
                     ..Default::default()
                 })
             )
@@ -235,57 +407,360 @@ impl Buffer {
 
         unsafe {
             vulkan_check!(device.begin_command_buffer(
-                transfer_buffer,
+                command_buffer,
                 &vk::CommandBufferBeginInfo {
                     flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
                     ..Default::default()
                 }
-            ));
+            ))
+        };
+
+        Self {
+            command_buffer,
+            copy_count: 0,
+        }
+    }
+
+    pub fn copy(&mut self, device: &ash::Device, source: &Buffer, destination: &Buffer) {
+        self.copy_region(
+            device,
+            source,
+            destination,
+            vk::BufferCopy {
+                size: cmp::min(source.size, destination.size),
+                ..Default::default()
+            },
+        );
+    }
+
+    pub fn copy_region(
+        &mut self,
+        device: &ash::Device,
+        source: &Buffer,
+        destination: &Buffer,
+        region: vk::BufferCopy,
+    ) {
+        // Vulkan doesn't allow empty copies, and a model with no geometry has nothing to copy
+        if region.size == 0 {
+            return;
+        }
 
+        unsafe {
             device.cmd_copy_buffer(
-                transfer_buffer,
-                self.handle,
+                self.command_buffer,
+                source.handle,
                 destination.handle,
-                &[vk::BufferCopy {
-                    size: self.size,
-                    ..Default::default()
-                }],
-            );
+                &[region],
+            )
+        };
+        self.copy_count += 1;
+    }
+
+    pub fn submit(
+        self,
+        device: &ash::Device,
+        queue: &vk::Queue,
+        transfer_pool: &vk::CommandPool,
+        fence_pool: &mut FencePool,
+    ) {
+        trace!("Submitting transfer batch with {} copies", self.copy_count);
 
-            vulkan_check!(device.end_command_buffer(transfer_buffer));
+        let fence = fence_pool.acquire(device);
+        unsafe {
+            vulkan_check!(device.end_command_buffer(self.command_buffer));
             vulkan_check!(device.queue_submit(
                 *queue,
                 &[vk::SubmitInfo {
                     command_buffer_count: 1,
-                    p_command_buffers: ptr::addr_of!(transfer_buffer),
+                    p_command_buffers: ptr::addr_of!(self.command_buffer),
                     ..Default::default()
                 }],
-                vk::Fence::null()
+                fence
             ));
-            vulkan_check!(device.queue_wait_idle(*queue));
+            vulkan_check!(device.wait_for_fences(&[fence], true, u64::MAX));
 
-            device.free_command_buffers(*transfer_pool, &[transfer_buffer]);
+            device.free_command_buffers(*transfer_pool, &[self.command_buffer]);
         }
+        fence_pool.release(device, fence);
     }
 
-    pub fn destroy(self, allocator: &vk_mem::Allocator) {
-        unsafe { allocator.destroy_buffer(self.handle, self.allocation) };
+    // Leaves the image ready to be sampled in fragment shaders
+    pub fn copy_to_image(
+        &mut self,
+        device: &ash::Device,
+        source: &Buffer,
+        image: vk::Image,
+        old_layout: vk::ImageLayout,
+        offset: vk::Offset3D,
+        extent: vk::Extent3D,
+    ) {
+        self.copy_regions_to_image(
+            device,
+            source,
+            image,
+            old_layout,
+            1,
+            &[vk::BufferImageCopy {
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                image_offset: offset,
+                image_extent: extent,
+                ..Default::default()
+            }],
+        );
     }
 
-    pub fn handle(&self) -> &vk::Buffer {
-        &self.handle
+    // Same as copy_to_image, for images with mips. Every one of the levels gets transitioned.
+    pub fn copy_regions_to_image(
+        &mut self,
+        device: &ash::Device,
+        source: &Buffer,
+        image: vk::Image,
+        old_layout: vk::ImageLayout,
+        level_count: u32,
+        regions: &[vk::BufferImageCopy],
+    ) {
+        let subresource_range = vk::ImageSubresourceRange {
+            level_count,
+            ..COLOR_SUBRESOURCE_RANGE
+        };
+        let before = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::SHADER_READ,
+            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            old_layout,
+            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            image,
+            subresource_range,
+            ..Default::default()
+        };
+        let after = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::SHADER_READ,
+            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            image,
+            subresource_range,
+            ..Default::default()
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                self.command_buffer,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[before],
+            );
+            device.cmd_copy_buffer_to_image(
+                self.command_buffer,
+                source.handle,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                regions,
+            );
+            device.cmd_pipeline_barrier(
+                self.command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[after],
+            );
+        }
+        self.copy_count += 1;
     }
 
-    pub fn allocation(&self) -> &vk_mem::Allocation {
-        &self.allocation
+    pub fn copy_count(&self) -> usize {
+        self.copy_count
     }
+}
 
-    pub fn allocation_mut(&mut self) -> &mut vk_mem::Allocation {
-        &mut self.allocation
+#[derive(Clone, Copy, Debug)]
+pub struct QueueInfo {
+    pub graphics_family: u32,
+    pub compute_family: u32,
+    pub present_family: u32,
+    pub transfer_family: u32,
+    // Whether the graphics and present families differ, which means the swapchain images
+    // have to be shared concurrently or handed over
+    pub distinct: bool,
+    pub present_supported: bool,
+}
+
+// A device the renderer found, from State::available_gpus. The index is what reinit_on_gpu and
+// RenderConfig::gpu take.
+#[derive(Clone, Debug)]
+pub struct GpuDescription {
+    pub index: usize,
+    pub name: String,
+    pub gpu_type: rendersystem::GpuType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub performance_score: u32,
+    // Whether it's the one being used now
+    pub selected: bool,
+}
+
+// What a device supports, for settings menus before the renderer exists. The index is what
+// RenderConfig::gpu takes.
+#[derive(Clone, Debug)]
+pub struct GpuCapabilities {
+    pub index: usize,
+    pub name: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub discrete: bool,
+    pub present_modes: Vec<rendersystem::PresentMode>,
+    pub surface_formats: Vec<rendersystem::SurfaceFormat>,
+    // Sample counts that work for both colour and depth
+    pub msaa_samples: Vec<u32>,
+    pub max_texture_size: u32,
+    pub shader_object: bool,
+    pub full_screen_exclusive: bool,
+    pub sampler_anisotropy: bool,
+    pub tessellation_shader: bool,
+    pub geometry_shader: bool,
+    pub performance_score: u32,
+}
+
+// Everything that's kept per frame, none of it should grow once the frames have come around a few
+// times
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameResourceStats {
+    // Frames the GPU hadn't finished when the last one was submitted, counting that one
+    pub in_flight: usize,
+    pub pass_command_buffers: usize,
+    pub staging_buffers: usize,
+    pub retired_buffers: usize,
+    pub descriptor_pools: usize,
+    pub readbacks: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FencePoolStats {
+    pub created: usize,
+    pub free: usize,
+    pub acquired: u64,
+}
+
+// Keeps unsignalled fences around for transfers instead of making a new one every time
+struct FencePool {
+    free: Vec<vk::Fence>,
+    stats: FencePoolStats,
+}
+
+impl FencePool {
+    pub fn new() -> Self {
+        Self {
+            free: Vec::new(),
+            stats: FencePoolStats::default(),
+        }
     }
 
-    pub fn size(&self) -> vk::DeviceSize {
-        self.size
+    pub fn acquire(&mut self, device: &ash::Device) -> vk::Fence {
+        self.stats.acquired += 1;
+        match self.free.pop() {
+            Some(fence) => fence,
+            None => {
+                self.stats.created += 1;
+                trace!("Creating fence {} for fence pool", self.stats.created);
+                unsafe {
+                    vulkan_check!(device.create_fence(
+                        &vk::FenceCreateInfo::default(),
+                        Some(&State::get_allocation_callbacks())
+                    ))
+                }
+            }
+        }
+    }
+
+    // The fence has to be signalled (or never submitted) by now
+    pub fn release(&mut self, device: &ash::Device, fence: vk::Fence) {
+        unsafe { vulkan_check!(device.reset_fences(&[fence])) };
+        self.free.push(fence);
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        if self.free.len() != self.stats.created {
+            warn!(
+                "Destroying fence pool with {} fence(s) still in use",
+                self.stats.created - self.free.len()
+            );
+        }
+        for fence in self.free.drain(..) {
+            unsafe { device.destroy_fence(fence, Some(&State::get_allocation_callbacks())) };
+        }
+        self.stats = FencePoolStats::default();
+    }
+
+    pub fn stats(&self) -> FencePoolStats {
+        FencePoolStats {
+            free: self.free.len(),
+            ..self.stats
+        }
+    }
+}
+
+// Descriptor pools, with another block added whenever the newest one runs out, so there's no cap
+// on how many materials can be loaded. Sets have to be freed to the block they came from, so
+// allocating gives that back too. Anything allocating takes &State, hence the RefCell.
+struct DescriptorPools {
+    pools: RefCell<Vec<vk::DescriptorPool>>,
+}
+
+impl DescriptorPools {
+    pub fn new(device: &ash::Device) -> Self {
+        Self {
+            pools: RefCell::new(vec![State::create_descriptor_pool(device)]),
+        }
+    }
+
+    pub fn allocate(
+        &self,
+        device: &ash::Device,
+        layouts: &[vk::DescriptorSetLayout],
+    ) -> Result<(Vec<vk::DescriptorSet>, vk::DescriptorPool), vk::Result> {
+        let mut pools = self.pools.borrow_mut();
+        let allocate = |pool| unsafe {
+            device.allocate_descriptor_sets(&vk::DescriptorSetAllocateInfo {
+                descriptor_pool: pool,
+                descriptor_set_count: layouts.len() as u32,
+                p_set_layouts: layouts.as_ptr(),
+                ..Default::default()
+            })
+        };
+
+        let pool = *pools.last().unwrap();
+        match allocate(pool) {
+            Ok(sets) => Ok((sets, pool)),
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {
+                debug!(
+                    "Descriptor pool block {} is full, adding another",
+                    pools.len() - 1
+                );
+                let pool = State::create_descriptor_pool(device);
+                pools.push(pool);
+                allocate(pool).map(|sets| (sets, pool))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        let pools = self.pools.get_mut();
+        debug!("Destroying {} descriptor pool block(s)", pools.len());
+        for pool in pools.drain(..) {
+            unsafe {
+                device.destroy_descriptor_pool(pool, Some(&State::get_allocation_callbacks()))
+            };
+        }
     }
 }
 
@@ -322,9 +797,12 @@ impl HostBuffer {
     }
 
     pub unsafe fn read(&self, source: &[u8], offset: vk::DeviceSize) -> usize {
-        let size = cmp::min(self.buffer.size() as usize, source.len());
+        let offset = cmp::min(offset, self.buffer.size()) as usize;
+        let size = cmp::min(self.buffer.size() as usize - offset, source.len());
 
-        (self.address as *mut u8).copy_from(source.as_ptr(), size);
+        (self.address as *mut u8)
+            .add(offset)
+            .copy_from(source.as_ptr(), size);
 
         size
     }
@@ -347,59 +825,367 @@ impl HostBuffer {
     }
 }
 
-pub struct State {
-    entry: ash::Entry,
-    instance: ash::Instance,
-    device: ash::Device,
-    surface_loader: extensions::khr::Surface,
-    swapchain_loader: extensions::khr::Swapchain,
-    shader_object_loader: extensions::ext::ShaderObject,
-    surface: vk::SurfaceKHR,
-
-    allocator: vk_mem::Allocator,
-
-    gpu: usize,
-    gpus: Vec<GpuInfo>,
-    graphics_queue: vk::Queue,
-    compute_queue: vk::Queue,
-
-    command_pool: vk::CommandPool,
-    transfer_pool: vk::CommandPool,
-    command_buffers: Vec<vk::CommandBuffer>,
+// A copy of a finished frame, readable once the fence for the frame it was recorded in signals
+struct Readback {
+    id: u64,
+    buffer: HostBuffer,
+    slot: usize,
+    ready: bool,
+    width: u32,
+    height: u32,
+    format: vk::Format,
+}
 
-    fences: Vec<vk::Fence>,
-    acquire_semaphores: Vec<vk::Semaphore>,
+// Everything sized or formatted after the swapchain. It only gets created and destroyed as a
+// whole, so nothing can be left over from the old one. Viewports and scissors are worked out from
+// render_extent every frame, pipelines built for other formats find out through is_stale.
+struct SwapchainResources {
+    handle: vk::SwapchainKHR,
+    images: Vec<vk::Image>,
+    views: Vec<vk::ImageView>,
+    images_in_flight: Vec<vk::Fence>,
+    // Per image, not per frame, the presentation engine can still be waiting on one after its
+    // frame's fence is signalled, and only acquiring the image again means it's done with it
     render_complete_semaphores: Vec<vk::Semaphore>,
-
-    swapchain: vk::SwapchainKHR,
-    swapchain_images: Vec<vk::Image>,
-    swapchain_views: Vec<vk::ImageView>,
-    swapchain_index: usize,
+    // Only with exclusive sharing and separate graphics and present families
+    present_transfer: Option<PresentTransfer>,
+    // IDs only mean something to the swapchain they were presented to, so they start over
+    present_id: u64,
     surface_format: vk::SurfaceFormatKHR,
-    present_mode: vk::PresentModeKHR,
-    swapchain_extent: vk::Extent2D,
-
+    extent: vk::Extent2D,
+    render_extent: vk::Extent2D,
+    color_image: Image,
     depth_image: Image,
+    // The window didn't have a real size yet, so it's 1x1 until it does, or there's no swapchain
+    // at all (a null handle) if the surface can't have one
+    degenerate: bool,
+}
 
-    descriptor_layout: vk::DescriptorSetLayout,
-    descriptor_pool: vk::DescriptorPool,
-    descriptor_sets: Vec<vk::DescriptorSet>,
-
-    uniform_buffers: Vec<HostBuffer>,
+impl SwapchainResources {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        allocator: &vk_mem::Allocator,
+        gpu: &GpuInfo,
+        surface_loader: &extensions::khr::Surface,
+        surface: &vk::SurfaceKHR,
+        loader: &extensions::khr::Swapchain,
+        surface_format: vk::SurfaceFormatKHR,
+        present_mode: vk::PresentModeKHR,
+        sharing: rendersystem::SwapchainSharing,
+        image_count: Option<u32>,
+        full_screen_exclusive: Option<(vk::FullScreenExclusiveEXT, vk::HMONITOR)>,
+        extent: vk::Extent2D,
+        render_scale: f32,
+    ) -> Self {
+        let (extent, degenerate) = State::surface_extent(surface_loader, gpu, surface, extent);
+        let (handle, images, views) = match &extent {
+            Some(extent) => State::create_swapchain(
+                device,
+                gpu,
+                surface,
+                &present_mode,
+                sharing,
+                image_count,
+                full_screen_exclusive,
+                &surface_format,
+                extent,
+                loader,
+            ),
+            None => (vk::SwapchainKHR::null(), Vec::new(), Vec::new()),
+        };
+        // the render targets are only placeholders without a swapchain
+        let extent = extent.unwrap_or(vk::Extent2D {
+            width: 1,
+            height: 1,
+        });
+        let present_transfer = (sharing == rendersystem::SwapchainSharing::Exclusive
+            && gpu.graphics_family_index != gpu.present_family_index
+            && !images.is_empty())
+        .then(|| PresentTransfer::new(device, gpu, &images));
+        let render_extent = State::scale_extent(extent, render_scale);
+        let (color_image, depth_image) = State::create_render_targets(
+            &render_extent,
+            surface_format.format,
+            instance,
+            gpu,
+            device,
+            allocator,
+        );
 
-    initialized: bool,
-    loaded: bool,
+        Self {
+            handle,
+            images_in_flight: vec![vk::Fence::null(); images.len()],
+            render_complete_semaphores: State::create_semaphores(device, images.len()),
+            present_transfer,
+            present_id: 0,
+            images,
+            views,
+            surface_format,
+            extent,
+            render_extent,
+            color_image,
+            depth_image,
+            degenerate,
+        }
+    }
 
-    in_frame: bool,
-    frame_index: usize,
-    resized: bool,
+    // The colour and depth formats pipelines have to be created with
+    pub fn formats(&self) -> (vk::Format, vk::Format) {
+        (self.surface_format.format, self.depth_image.format())
+    }
 
-    model_buffer: Option<Buffer>,
+    pub fn destroy(
+        &mut self,
+        device: &ash::Device,
+        loader: &extensions::khr::Swapchain,
+        allocator: &vk_mem::Allocator,
+    ) {
+        debug!("Destroying render target images");
+        debug!("Destroying color image {:#?}", self.color_image.handle());
+        self.color_image.destroy(device, allocator);
+        debug!("Destroying depth image {:#?}", self.depth_image.handle());
+        self.depth_image.destroy(device, allocator);
+
+        State::destroy_semaphores(device, &mut self.render_complete_semaphores);
+        if let Some(mut present_transfer) = self.present_transfer.take() {
+            present_transfer.destroy(device);
+        }
+
+        debug!("Destroying {} swap chain image views", self.views.len());
+        self.views.drain(..).for_each(|view| unsafe {
+            device.destroy_image_view(view, Some(&State::get_allocation_callbacks()))
+        });
+
+        debug!("Destroying swap chain {:#?}", self.handle);
+        unsafe { loader.destroy_swapchain(self.handle, Some(&State::get_allocation_callbacks())) };
+    }
+}
+
+// Exclusive images belong to one queue family at a time, so after rendering the graphics family
+// releases the image and these acquire it on the present queue before it's presented. The
+// acquire is the same every frame, so there's one recorded command buffer per image. Nothing has
+// to go back the other way, the frame starts the image from undefined and throws out its contents.
+struct PresentTransfer {
+    pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+    // Signalled by the acquire, presenting waits on these instead of render_complete_semaphores
+    semaphores: Vec<vk::Semaphore>,
+}
+
+impl PresentTransfer {
+    pub fn new(device: &ash::Device, gpu: &GpuInfo, images: &[vk::Image]) -> Self {
+        debug!(
+            "Creating ownership transfers for {} swap chain images from queue family {} to {}",
+            images.len(),
+            gpu.graphics_family_index,
+            gpu.present_family_index
+        );
+
+        let pool = unsafe {
+            vulkan_check!(device.create_command_pool(
+                &vk::CommandPoolCreateInfo {
+                    queue_family_index: gpu.present_family_index,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+        let command_buffers = unsafe {
+            vulkan_check!(
+                device.allocate_command_buffers(&vk::CommandBufferAllocateInfo {
+                    level: vk::CommandBufferLevel::PRIMARY,
+                    command_pool: pool,
+                    command_buffer_count: images.len() as u32,
+                    ..Default::default()
+                })
+            )
+        };
+
+        for (&command_buffer, &image) in command_buffers.iter().zip(images) {
+            let acquire_barrier = State::present_transfer_barrier(gpu, image);
+            unsafe {
+                vulkan_check!(device.begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo {
+                        flags: vk::CommandBufferUsageFlags::SIMULTANEOUS_USE,
+                        ..Default::default()
+                    }
+                ));
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[acquire_barrier],
+                );
+                vulkan_check!(device.end_command_buffer(command_buffer));
+            }
+        }
+
+        Self {
+            pool,
+            command_buffers,
+            semaphores: State::create_semaphores(device, images.len()),
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        State::destroy_semaphores(device, &mut self.semaphores);
+
+        debug!("Destroying present transfer command pool {:#?}", self.pool);
+        unsafe { device.destroy_command_pool(self.pool, Some(&State::get_allocation_callbacks())) };
+    }
+}
+
+pub struct State {
+    config: rendersystem::RenderConfig,
+
+    entry: ash::Entry,
+    instance: ash::Instance,
+    // Counted by debug_log, which gets a pointer to it. Has to outlive the instance.
+    validation_errors: Arc<atomic::AtomicUsize>,
+    device: ash::Device,
+    surface_loader: extensions::khr::Surface,
+    swapchain_loader: extensions::khr::Swapchain,
+    shader_object_loader: extensions::ext::ShaderObject,
+    present_wait_loader: extensions::khr::PresentWait,
+    full_screen_exclusive_loader: extensions::ext::FullScreenExclusive,
+    #[cfg(feature = "graphics_debug")]
+    debug_utils_loader: extensions::ext::DebugUtils,
+    surface: vk::SurfaceKHR,
+
+    // dropped by hand before the device, see destroy_device_objects
+    allocator: mem::ManuallyDrop<vk_mem::Allocator>,
+
+    gpu: usize,
+    gpus: Vec<GpuInfo>,
+    graphics_queue: vk::Queue,
+    compute_queue: vk::Queue,
+    present_queue: vk::Queue,
+
+    command_pool: vk::CommandPool,
+    // Extra command buffers for other passes, a pool per frame so they can all be reset at once
+    // when the frame comes around again. Buffers are kept and reused, the purposes say which ones
+    // are in use this frame.
+    pass_pools: Vec<vk::CommandPool>,
+    pass_command_buffers: Vec<Vec<vk::CommandBuffer>>,
+    pass_purposes: Vec<Vec<String>>,
+    transfer_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+
+    fences: Vec<vk::Fence>,
+    // How many of them were unsignalled right after the last submit
+    last_in_flight: usize,
+    fence_pool: FencePool,
+    // Per frame in flight, which image gets acquired isn't known until it's too late to pick
+    acquire_semaphores: Vec<vk::Semaphore>,
+
+    swapchain: SwapchainResources,
+    swapchain_index: usize,
+    present_mode: vk::PresentModeKHR,
+    // The monitor exclusive fullscreen was asked for on, and whether the swapchain has it now
+    fullscreen_monitor: Option<vk::HMONITOR>,
+    fullscreen_acquired: bool,
+    // Exclusive fullscreen wasn't available, so the window was made borderless instead
+    borderless_fallback: bool,
+
+    clear_color: rendersystem::Color,
+    grid: Option<GridData>,
+    grid_settings: rendersystem::GridSettings,
+    lighting: Option<LightingData>,
+    ambient_light: rendersystem::Color,
+    logarithmic_depth: bool,
+    sample_shading: Option<f32>,
+    max_frame_latency: usize,
+    // Limit latency by waiting for presents instead of fences, if the device can
+    present_wait: bool,
+    // What the swapchain's render targets were made with, and one set_render_scale asked for that
+    // waits for the next begin_cmds so nothing recorded in between goes to the wrong size
+    render_scale: f32,
+    pending_render_scale: Option<f32>,
+
+    descriptor_layout: vk::DescriptorSetLayout,
+    storage_layout: vk::DescriptorSetLayout,
+    texture_layout: vk::DescriptorSetLayout,
+    descriptor_pools: DescriptorPools,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+
+    uniform_buffers: Vec<HostBuffer>,
+    default_color_buffer: Option<HostBuffer>,
+
+    timestamp_pool: vk::QueryPool,
+    timestamp_period: f32,
+    timestamps_written: Vec<bool>,
+    gpu_time: f64,
+    // Every frame's GPU time added up since reset_gpu_time_total, each one counted once
+    gpu_time_total: f64,
+    // The one being recorded and the last one presented
+    stats: rendersystem::RenderStats,
+    last_stats: rendersystem::RenderStats,
+    // Shared by every material texture so the filtering can be changed in one place
+    texture_filtering: rendersystem::TextureFiltering,
+    texture_sampler: vk::Sampler,
+    // White, for materials without a texture or with one that isn't uploaded
+    default_texture: TextureData,
+
+    initialized: bool,
+    loaded: bool,
+
+    in_frame: bool,
+    // draw_gui moved on to the swapchain image, see begin_gui_rendering
+    in_gui_pass: bool,
+    frame_index: usize,
+    // Frames presented since init, only for labelling captures
+    #[cfg(feature = "graphics_debug")]
+    frame_number: u64,
+    resized: bool,
+    out_of_date: bool,
+    suboptimal_count: u64,
+    device_lost: bool,
+    events: Vec<rendersystem::RenderEvent>,
+
+    model_buffer: Option<Buffer>,
+    // Buffers replaced while frames could still be using them, with how many frames are left
+    retired_buffers: Vec<(usize, Buffer)>,
+    // In-place model updates waiting for the next frame to copy them, with where they go
+    model_updates: Vec<(HostBuffer, vk::DeviceSize)>,
+    // What each frame copied them from, freed when the frame comes around again
+    model_staging: Vec<Vec<HostBuffer>>,
+    // Copied at the end of the next frame that gets presented
+    readback_request: Option<u64>,
+    readbacks: Vec<Readback>,
+    next_readback: u64,
 
     last_shader: Option<String>,
     last_model: Option<String>,
+    // Already intersected with each other, the top one is what's set
+    scissors: Vec<vk::Rect2D>,
+    viewport: Option<vk::Rect2D>,
+    viewport_count: usize,
+    uniform_offset: u32,
+    // What draws outside viewports and targets see, kept between frames
+    window_uniforms: Option<rendersystem::UniformData>,
+    // Constant, slope and clamp, always enabled but zero does nothing
+    depth_bias: (f32, f32, f32),
+    // Both reset at the start of every frame
+    stencil: Option<rendersystem::StencilState>,
+    depth_write: bool,
+    // Formats of the render target being drawn to instead of the window, if there is one
+    target_formats: Option<Vec<rendersystem::TargetFormat>>,
+    // The window's scissors, put back once the render target is done
+    window_scissors: Vec<vk::Rect2D>,
 }
 
+// The mapped pointers in the buffers and the allocator's Rcs are what stop this from being Send.
+// Nothing about the pointers is tied to the thread that mapped them, and the Rcs are the
+// allocator's own copies of the instance and device, which never leave the state. The render
+// thread only gets the state through a Mutex.
+unsafe impl Send for State {}
+
 impl State {
     fn get_allocation_callbacks<'a>() -> vk::AllocationCallbacks<'a> {
         vk::AllocationCallbacks {
@@ -417,7 +1203,7 @@ impl State {
         severity: vk::DebugUtilsMessageSeverityFlagsEXT,
         types: vk::DebugUtilsMessageTypeFlagsEXT,
         callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-        #[allow(unused_variables)] user_data: *mut ffi::c_void,
+        user_data: *mut ffi::c_void,
     ) -> u32 {
         let log_level = match severity {
             vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => log::Level::Trace,
@@ -437,6 +1223,10 @@ impl State {
         }
         if types.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
             location += "VALIDATION ";
+            if severity == vk::DebugUtilsMessageSeverityFlagsEXT::ERROR && !user_data.is_null() {
+                (*(user_data as *const atomic::AtomicUsize))
+                    .fetch_add(1, atomic::Ordering::Relaxed);
+            }
         }
 
         let message_ptr = (*callback_data).p_message as *const ffi::c_char;
@@ -447,18 +1237,36 @@ impl State {
         vk::TRUE
     }
 
-    fn create_instance(entry: &ash::Entry) -> ash::Instance {
-        debug!("Creating Vulkan instance");
+    // Also returns whether VK_KHR_get_surface_capabilities2 is on, which is only asked for on
+    // Windows and only if it's there. Validation errors get counted in validation_errors.
+    fn create_instance(
+        entry: &ash::Entry,
+        video: &dyn SurfaceProvider,
+        app_name: &str,
+        app_version: (u32, u32, u32),
+        validation: bool,
+        validation_errors: &Arc<atomic::AtomicUsize>,
+    ) -> (ash::Instance, bool) {
+        debug!(
+            "Creating Vulkan instance for {app_name} {}.{}.{}",
+            app_version.0, app_version.1, app_version.2
+        );
 
-        let app_name = ffi::CString::new(crate::GAME_NAME).unwrap();
+        let app_name = ffi::CString::new(app_name).unwrap_or_else(|_| {
+            warn!(
+                "App name {app_name:?} has a nul in it, using {}",
+                crate::GAME_NAME
+            );
+            ffi::CString::new(crate::GAME_NAME).unwrap()
+        });
         let engine_name = ffi::CString::new("Purpl Engine").unwrap();
         let app_info = vk::ApplicationInfo {
             p_application_name: app_name.as_ptr() as *const ffi::c_char,
             application_version: vk::make_api_version(
                 0,
-                crate::GAME_VERSION_MAJOR.into(),
-                crate::GAME_VERSION_MINOR.into(),
-                crate::GAME_VERSION_PATCH.into(),
+                app_version.0,
+                app_version.1,
+                app_version.2,
             ),
             p_engine_name: engine_name.as_ptr() as *const ffi::c_char,
             engine_version: 2,
@@ -466,21 +1274,35 @@ impl State {
             ..Default::default()
         };
 
-        let extensions = [
-            #[cfg(feature = "graphics_debug")]
-            "VK_EXT_debug_utils",
-            "VK_KHR_surface",
-            #[cfg(windows)]
-            "VK_KHR_win32_surface",
-            #[cfg(unix)]
-            "VK_KHR_xcb_surface",
-        ];
+        let mut extensions = vec![String::from("VK_KHR_surface")];
+        #[cfg(feature = "graphics_debug")]
+        extensions.push(String::from("VK_EXT_debug_utils"));
+        extensions.append(&mut video.instance_extensions());
+        // for VK_EXT_full_screen_exclusive, which is only on Windows
+        #[cfg(windows)]
+        if unsafe { entry.enumerate_instance_extension_properties(None) }.is_ok_and(|properties| {
+            properties.iter().any(|properties| {
+                unsafe { ffi::CStr::from_ptr(properties.extension_name.as_ptr()) }.to_bytes()
+                    == b"VK_KHR_get_surface_capabilities2"
+            })
+        }) {
+            extensions.push(String::from("VK_KHR_get_surface_capabilities2"));
+        }
 
-        let layers = ["VK_LAYER_KHRONOS_validation"];
+        // exclusive fullscreen can't be used without it
+        let surface_capabilities2 = extensions
+            .iter()
+            .any(|extension| extension == "VK_KHR_get_surface_capabilities2");
+
+        let layers: &[&str] = if validation {
+            &["VK_LAYER_KHRONOS_validation"]
+        } else {
+            &[]
+        };
 
         let extensions_cstr: Vec<ffi::CString> = extensions
             .iter()
-            .map(|extension_name| ffi::CString::new(*extension_name).unwrap())
+            .map(|extension_name| ffi::CString::new(extension_name.as_str()).unwrap())
             .collect();
         let extensions_raw: Vec<*const ffi::c_char> = extensions_cstr
             .iter()
@@ -504,16 +1326,15 @@ impl State {
                 | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
                 | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
             pfn_user_callback: Some(Self::debug_log),
+            p_user_data: Arc::as_ptr(validation_errors) as *mut ffi::c_void,
             ..Default::default()
         };
 
         let mut create_info = vk::InstanceCreateInfo {
             p_application_info: ptr::addr_of!(app_info),
-            enabled_extension_count: extensions.len() as u32,
+            enabled_extension_count: extensions_raw.len() as u32,
             pp_enabled_extension_names: extensions_raw.as_ptr(),
-            #[cfg(feature = "graphics_debug")]
             enabled_layer_count: layers_raw.len() as u32,
-            #[cfg(feature = "graphics_debug")]
             pp_enabled_layer_names: layers_raw.as_ptr(),
             #[cfg(feature = "graphics_debug")]
             p_next: ptr::addr_of!(debug_messenger_info) as *const ffi::c_void,
@@ -540,17 +1361,137 @@ impl State {
             "Created Vulkan instance {:?} successfully",
             instance.handle()
         );
-        instance
+        (instance, surface_capabilities2)
+    }
+
+    fn get_required_device_exts() -> [&'static str; 1] {
+        ["VK_KHR_swapchain"]
+    }
+
+    fn get_optional_device_exts(gpu: &GpuInfo) -> Vec<&'static str> {
+        let mut extensions = Vec::new();
+        if gpu.shader_object {
+            extensions.push("VK_EXT_shader_object");
+        }
+        if gpu.present_wait {
+            extensions.push("VK_KHR_present_id");
+            extensions.push("VK_KHR_present_wait");
+        }
+        if gpu.full_screen_exclusive {
+            extensions.push("VK_EXT_full_screen_exclusive");
+        }
+        extensions
+    }
+
+    // Uses its own instance and surface and destroys them afterwards, so it works without init
+    pub fn enumerate_gpus(
+        video: &dyn SurfaceProvider,
+        config: &rendersystem::RenderConfig,
+    ) -> Vec<GpuCapabilities> {
+        debug!("Enumerating devices without initializing");
+
+        let entry = unsafe { vulkan_check!(ash::Entry::load()) };
+        let validation_errors = Arc::new(atomic::AtomicUsize::new(0));
+        let (instance, surface_capabilities2) = Self::create_instance(
+            &entry,
+            video,
+            &config.app_name,
+            config.app_version,
+            false,
+            &validation_errors,
+        );
+        let surface_loader = extensions::khr::Surface::new(&entry, &instance);
+        let surface =
+            video.create_surface(&entry, &instance, Some(&State::get_allocation_callbacks()));
+
+        let capabilities =
+            Self::get_gpus(&instance, &surface_loader, &surface, surface_capabilities2)
+                .iter()
+                .enumerate()
+                .map(|(index, gpu)| Self::gpu_capabilities(index, gpu))
+                .collect();
+
+        unsafe {
+            surface_loader.destroy_surface(surface, Some(&State::get_allocation_callbacks()));
+            instance.destroy_instance(Some(&State::get_allocation_callbacks()));
+        }
+
+        capabilities
     }
 
-    fn get_required_device_exts() -> [&'static str; 2] {
-        ["VK_KHR_swapchain", "VK_EXT_shader_object"]
+    fn gpu_capabilities(index: usize, gpu: &GpuInfo) -> GpuCapabilities {
+        let limits = &gpu.properties.limits;
+
+        let present_modes = [
+            rendersystem::PresentMode::Immediate,
+            rendersystem::PresentMode::Mailbox,
+            rendersystem::PresentMode::Fifo,
+            rendersystem::PresentMode::FifoRelaxed,
+        ]
+        .into_iter()
+        .filter(|&mode| {
+            gpu.present_modes
+                .contains(&Self::convert_present_mode(mode))
+        })
+        .collect();
+
+        // UNDEFINED alone means anything goes
+        let any_format = gpu.surface_formats.len() == 1
+            && gpu.surface_formats[0].format == vk::Format::UNDEFINED;
+        let surface_formats = [
+            rendersystem::SurfaceFormat::Bgra8Unorm,
+            rendersystem::SurfaceFormat::Rgba8Unorm,
+            rendersystem::SurfaceFormat::Bgra8Srgb,
+            rendersystem::SurfaceFormat::Rgba8Srgb,
+            rendersystem::SurfaceFormat::A2b10g10r10Unorm,
+            rendersystem::SurfaceFormat::A2r10g10b10Unorm,
+        ]
+        .into_iter()
+        .filter(|&format| {
+            let wanted = Self::convert_surface_format(format);
+            any_format
+                || gpu.surface_formats.iter().any(|format| {
+                    format.format == wanted
+                        && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+                })
+        })
+        .collect();
+
+        let sample_counts =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+        let msaa_samples = [1, 2, 4, 8, 16, 32, 64]
+            .into_iter()
+            .filter(|&count| sample_counts.contains(vk::SampleCountFlags::from_raw(count)))
+            .collect();
+
+        GpuCapabilities {
+            index,
+            name: unsafe {
+                ffi::CStr::from_ptr(gpu.properties.device_name.as_ptr())
+                    .to_string_lossy()
+                    .into_owned()
+            },
+            vendor_id: gpu.properties.vendor_id,
+            device_id: gpu.properties.device_id,
+            discrete: gpu.properties.device_type == vk::PhysicalDeviceType::DISCRETE_GPU,
+            present_modes,
+            surface_formats,
+            msaa_samples,
+            max_texture_size: limits.max_image_dimension2_d,
+            shader_object: gpu.shader_object,
+            full_screen_exclusive: gpu.full_screen_exclusive,
+            sampler_anisotropy: gpu.sampler_anisotropy,
+            tessellation_shader: gpu.tessellation_shader,
+            geometry_shader: gpu.geometry_shader,
+            performance_score: gpu.performance_score,
+        }
     }
 
     fn get_gpus(
         instance: &ash::Instance,
         surface_loader: &extensions::khr::Surface,
         surface: &vk::SurfaceKHR,
+        surface_capabilities2: bool,
     ) -> Vec<GpuInfo> {
         debug!("Enumerating devices (scores loosely based on memory, maximum viewport size, and discrete/integrated/CPU)");
         let devices = unsafe { vulkan_check!(instance.enumerate_physical_devices()) };
@@ -599,6 +1540,26 @@ impl State {
                 continue;
             };
 
+            let Some(present_family_index) = [graphics_family_index]
+                .into_iter()
+                .chain(0..queue_family_props.len() as u32)
+                .find(|&family| {
+                    queue_family_props[family as usize].queue_count >= 1
+                        && unsafe {
+                            surface_loader
+                                .get_physical_device_surface_support(device, family, *surface)
+                                .unwrap_or(false)
+                        }
+                })
+            else {
+                error!("Ignoring GPU {i} because none of its queue families can present");
+                continue;
+            };
+
+            let mut shader_object_ext = false;
+            let mut present_id_ext = false;
+            let mut present_wait_ext = false;
+            let mut full_screen_exclusive_ext = false;
             let extension_properties =
                 unsafe { instance.enumerate_device_extension_properties(device) };
             match extension_properties {
@@ -620,6 +1581,18 @@ impl State {
                         {
                             debug!("\t{name} (required)");
                             required_found_count += 1;
+                        } else if name == "VK_EXT_shader_object" {
+                            debug!("\t{name} (optional)");
+                            shader_object_ext = true;
+                        } else if name == "VK_KHR_present_id" {
+                            debug!("\t{name} (optional)");
+                            present_id_ext = true;
+                        } else if name == "VK_KHR_present_wait" {
+                            debug!("\t{name} (optional)");
+                            present_wait_ext = true;
+                        } else if name == "VK_EXT_full_screen_exclusive" {
+                            debug!("\t{name} (optional)");
+                            full_screen_exclusive_ext = true;
                         } else {
                             trace!("\t{name}");
                         }
@@ -691,6 +1664,39 @@ impl State {
                 }
             };
 
+            // the extension being there doesn't mean the feature is
+            let mut shader_object_features = vk::PhysicalDeviceShaderObjectFeaturesEXT::default();
+            let mut present_wait_features = vk::PhysicalDevicePresentWaitFeaturesKHR::default();
+            let mut present_id_features = vk::PhysicalDevicePresentIdFeaturesKHR {
+                p_next: ptr::addr_of_mut!(present_wait_features) as *mut ffi::c_void,
+                ..Default::default()
+            };
+            if shader_object_ext || (present_id_ext && present_wait_ext) {
+                shader_object_features.p_next = if present_id_ext && present_wait_ext {
+                    ptr::addr_of_mut!(present_id_features) as *mut ffi::c_void
+                } else {
+                    ptr::null_mut()
+                };
+                let mut features2 = vk::PhysicalDeviceFeatures2 {
+                    p_next: ptr::addr_of_mut!(shader_object_features) as *mut ffi::c_void,
+                    ..Default::default()
+                };
+                unsafe { instance.get_physical_device_features2(device, &mut features2) };
+            }
+            let shader_object =
+                shader_object_ext && shader_object_features.shader_object == vk::TRUE;
+            let present_wait = present_id_features.present_id == vk::TRUE
+                && present_wait_features.present_wait == vk::TRUE;
+            // it needs VK_KHR_get_surface_capabilities2 on the instance, see create_instance
+            let full_screen_exclusive =
+                cfg!(windows) && full_screen_exclusive_ext && surface_capabilities2;
+            let features = unsafe { instance.get_physical_device_features(device) };
+            let sample_rate_shading = features.sample_rate_shading == vk::TRUE;
+            let sampler_anisotropy = features.sampler_anisotropy == vk::TRUE;
+            let depth_bias_clamp = features.depth_bias_clamp == vk::TRUE;
+            let tessellation_shader = features.tessellation_shader == vk::TRUE;
+            let geometry_shader = features.geometry_shader == vk::TRUE;
+
             let memory_properties =
                 unsafe { instance.get_physical_device_memory_properties(device) };
             let properties = unsafe { instance.get_physical_device_properties(device) };
@@ -744,14 +1750,31 @@ impl State {
             debug!("\tScore: {score}");
             debug!("\tType: {:#?}", properties.device_type);
             debug!("\tHandle: {device:#?}");
+            debug!("\tShader objects: {shader_object}");
+            debug!("\tExclusive fullscreen: {full_screen_exclusive}");
+            debug!("\tTessellation: {tessellation_shader}");
+            debug!("\tGeometry shaders: {geometry_shader}");
 
             gpus.push(GpuInfo {
                 device,
                 properties,
                 surface_formats,
                 present_modes,
+                min_image_count: surface_caps.min_image_count,
+                max_image_count: surface_caps.max_image_count,
                 graphics_family_index,
                 compute_family_index,
+                present_family_index,
+                timestamp_valid_bits: queue_family_props[graphics_family_index as usize]
+                    .timestamp_valid_bits,
+                shader_object,
+                present_wait,
+                full_screen_exclusive,
+                sample_rate_shading,
+                sampler_anisotropy,
+                depth_bias_clamp,
+                tessellation_shader,
+                geometry_shader,
                 performance_score: score,
             });
 
@@ -777,44 +1800,69 @@ impl State {
         instance: &ash::Instance,
 
         gpu: &GpuInfo,
-    ) -> (ash::Device, vk::Queue, vk::Queue) {
+    ) -> (ash::Device, vk::Queue, vk::Queue, vk::Queue) {
         debug!("Creating logical device");
 
         let queue_priority: f32 = 1.0;
-        let graphics_queue_info = vk::DeviceQueueCreateInfo {
-            queue_family_index: gpu.graphics_family_index,
-            p_queue_priorities: ptr::addr_of!(queue_priority),
-            queue_count: 1,
+        // one queue from each family, they can all be the same one
+        let mut families = vec![
+            gpu.graphics_family_index,
+            gpu.compute_family_index,
+            gpu.present_family_index,
+        ];
+        families.sort_unstable();
+        families.dedup();
+        let queue_create_infos: Vec<vk::DeviceQueueCreateInfo> = families
+            .iter()
+            .map(|&family| vk::DeviceQueueCreateInfo {
+                queue_family_index: family,
+                p_queue_priorities: ptr::addr_of!(queue_priority),
+                queue_count: 1,
+                ..Default::default()
+            })
+            .collect();
+
+        let device_features = vk::PhysicalDeviceFeatures {
+            sample_rate_shading: gpu.sample_rate_shading.into(),
+            sampler_anisotropy: gpu.sampler_anisotropy.into(),
+            depth_bias_clamp: gpu.depth_bias_clamp.into(),
+            tessellation_shader: gpu.tessellation_shader.into(),
+            geometry_shader: gpu.geometry_shader.into(),
             ..Default::default()
         };
-        let present_queue_info = vk::DeviceQueueCreateInfo {
-            queue_family_index: gpu.compute_family_index,
-            p_queue_priorities: ptr::addr_of!(queue_priority),
-            queue_count: 1,
+
+        let present_wait_features = vk::PhysicalDevicePresentWaitFeaturesKHR {
+            present_wait: vk::TRUE,
             ..Default::default()
         };
-        let queue_create_infos = if gpu.graphics_family_index != gpu.compute_family_index {
-            vec![graphics_queue_info, present_queue_info]
-        } else {
-            vec![graphics_queue_info]
-        };
-
-        let device_features = vk::PhysicalDeviceFeatures {
+        let present_id_features = vk::PhysicalDevicePresentIdFeaturesKHR {
+            present_id: vk::TRUE,
+            p_next: ptr::addr_of!(present_wait_features) as *mut ffi::c_void,
             ..Default::default()
         };
-
+        let present_features = if gpu.present_wait {
+            ptr::addr_of!(present_id_features) as *mut ffi::c_void
+        } else {
+            ptr::null_mut()
+        };
         let shader_object_features = vk::PhysicalDeviceShaderObjectFeaturesEXT {
             shader_object: vk::TRUE,
+            p_next: present_features,
             ..Default::default()
         };
         let device_13_features = vk::PhysicalDeviceVulkan13Features {
             dynamic_rendering: vk::TRUE,
-            p_next: ptr::addr_of!(shader_object_features) as *mut ffi::c_void,
+            p_next: if gpu.shader_object {
+                ptr::addr_of!(shader_object_features) as *mut ffi::c_void
+            } else {
+                present_features
+            },
             ..Default::default()
         };
 
         let extensions_cstr: Vec<ffi::CString> = Self::get_required_device_exts()
             .iter()
+            .chain(Self::get_optional_device_exts(gpu).iter())
             .map(|extension_name| ffi::CString::new(*extension_name).unwrap())
             .collect();
         let extensions_raw: Vec<*const ffi::c_char> = extensions_cstr
@@ -845,13 +1893,14 @@ impl State {
 
         debug!("Retrieving queues");
         let graphics_queue = unsafe { device.get_device_queue(gpu.graphics_family_index, 0) };
-        let present_queue = unsafe { device.get_device_queue(gpu.compute_family_index, 0) };
+        let compute_queue = unsafe { device.get_device_queue(gpu.compute_family_index, 0) };
+        let present_queue = unsafe { device.get_device_queue(gpu.present_family_index, 0) };
         debug!(
-            "Got graphics queue {:#?} and compute queue {:#?}",
-            graphics_queue, present_queue
+            "Got graphics queue {:#?}, compute queue {:#?} and present queue {:#?}",
+            graphics_queue, compute_queue, present_queue
         );
 
-        (device, graphics_queue, present_queue)
+        (device, graphics_queue, compute_queue, present_queue)
     }
 
     fn create_fences(device: &ash::Device) -> Vec<vk::Fence> {
@@ -872,28 +1921,40 @@ impl State {
         fences
     }
 
-    fn create_semaphores(device: &ash::Device) -> (Vec<vk::Semaphore>, Vec<vk::Semaphore>) {
-        debug!("Creating {} semaphores", FRAME_COUNT * 2);
+    fn create_semaphores(device: &ash::Device, count: usize) -> Vec<vk::Semaphore> {
+        debug!("Creating {count} semaphores");
 
         let semaphore_create_info = vk::SemaphoreCreateInfo {
             ..Default::default()
         };
-        let mut acquire_semaphores = Vec::new();
-        let mut complete_semaphores = Vec::new();
-        acquire_semaphores.resize_with(3, || unsafe {
-            vulkan_check!(device.create_semaphore(
-                &semaphore_create_info,
-                Some(&State::get_allocation_callbacks())
-            ))
-        });
-        complete_semaphores.resize_with(3, || unsafe {
-            vulkan_check!(device.create_semaphore(
-                &semaphore_create_info,
-                Some(&State::get_allocation_callbacks())
-            ))
-        });
+        (0..count)
+            .map(|_| unsafe {
+                vulkan_check!(device.create_semaphore(
+                    &semaphore_create_info,
+                    Some(&State::get_allocation_callbacks())
+                ))
+            })
+            .collect()
+    }
 
-        (acquire_semaphores, complete_semaphores)
+    // Releasing on the graphics queue and acquiring on the present queue both use the same barrier
+    fn present_transfer_barrier(gpu: &GpuInfo, image: vk::Image) -> vk::ImageMemoryBarrier {
+        vk::ImageMemoryBarrier {
+            old_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            src_queue_family_index: gpu.graphics_family_index,
+            dst_queue_family_index: gpu.present_family_index,
+            image,
+            subresource_range: COLOR_SUBRESOURCE_RANGE,
+            ..Default::default()
+        }
+    }
+
+    fn destroy_semaphores(device: &ash::Device, semaphores: &mut Vec<vk::Semaphore>) {
+        debug!("Destroying {} semaphores", semaphores.len());
+        semaphores.drain(..).for_each(|semaphore| unsafe {
+            device.destroy_semaphore(semaphore, Some(&State::get_allocation_callbacks()))
+        });
     }
 
     fn create_command_pools(
@@ -930,6 +1991,23 @@ impl State {
         (main_pool, transfer_pool)
     }
 
+    fn create_pass_pools(device: &ash::Device, gpu: &GpuInfo) -> Vec<vk::CommandPool> {
+        debug!("Creating {FRAME_COUNT} pass command pools");
+
+        (0..FRAME_COUNT)
+            .map(|_| unsafe {
+                vulkan_check!(device.create_command_pool(
+                    &vk::CommandPoolCreateInfo {
+                        flags: vk::CommandPoolCreateFlags::TRANSIENT,
+                        queue_family_index: gpu.graphics_family_index,
+                        ..Default::default()
+                    },
+                    Some(&State::get_allocation_callbacks())
+                ))
+            })
+            .collect()
+    }
+
     fn allocate_command_buffers(
         device: &ash::Device,
         cmd_pool: &vk::CommandPool,
@@ -952,44 +2030,98 @@ impl State {
         instance: &ash::Instance,
         device: &ash::Device,
         physical_device: vk::PhysicalDevice,
+        config: &rendersystem::AllocatorConfig,
     ) -> vk_mem::Allocator {
         debug!("Creating Vulkan allocator");
-        vulkan_check!(vk_mem::Allocator::new(vk_mem::AllocatorCreateInfo::new(
-            Rc::from(instance),
-            Rc::from(device),
-            physical_device
-        )))
+
+        let mut create_info =
+            vk_mem::AllocatorCreateInfo::new(Rc::from(instance), Rc::from(device), physical_device);
+        if config.preferred_large_heap_block_size > 0 {
+            let memory_properties =
+                unsafe { instance.get_physical_device_memory_properties(physical_device) };
+            let largest_heap = memory_properties.memory_heaps
+                [..memory_properties.memory_heap_count as usize]
+                .iter()
+                .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+                .map(|heap| heap.size)
+                .max()
+                .unwrap_or(0);
+
+            // a block that's a big chunk of the heap can't coexist with much else
+            let mut block_size = config.preferred_large_heap_block_size;
+            if largest_heap > 0 && block_size > largest_heap / 8 {
+                warn!(
+                    "Preferred allocation block size {block_size} is too big for a {largest_heap} byte heap, using {}",
+                    largest_heap / 8
+                );
+                block_size = largest_heap / 8;
+            }
+
+            debug!("Using preferred large heap block size of {block_size} bytes");
+            create_info = create_info.preferred_large_heap_block_size(block_size);
+        }
+
+        vulkan_check!(vk_mem::Allocator::new(create_info))
     }
 
-    fn choose_surface_format(gpu: &GpuInfo) -> vk::SurfaceFormatKHR {
-        debug!("Choosing surface format");
+    fn choose_surface_format(
+        gpu: &GpuInfo,
+        preferences: &[rendersystem::SurfaceFormat],
+    ) -> vk::SurfaceFormatKHR {
+        debug!("Choosing surface format from {preferences:?}");
 
+        // UNDEFINED alone means anything goes
         if gpu.surface_formats.len() == 1 && gpu.surface_formats[0].format == vk::Format::UNDEFINED
         {
-            return vk::SurfaceFormatKHR {
-                format: vk::Format::B8G8R8A8_UNORM,
+            let format = vk::SurfaceFormatKHR {
+                format: preferences
+                    .first()
+                    .map_or(vk::Format::B8G8R8A8_UNORM, |&preference| {
+                        Self::convert_surface_format(preference)
+                    }),
                 color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
             };
+            debug!("Chose format {format:#?}");
+            return format;
         }
 
-        for &format in &gpu.surface_formats {
-            if format.format == vk::Format::B8G8R8A8_UNORM
-                && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-            {
+        for &preference in preferences {
+            let wanted = Self::convert_surface_format(preference);
+            if let Some(&format) = gpu.surface_formats.iter().find(|format| {
+                format.format == wanted && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            }) {
                 debug!("Chose format {format:#?}");
                 return format;
             }
         }
 
-        debug!("Chose format {:#?}", gpu.surface_formats[0]);
+        debug!(
+            "None of the preferred formats are supported, chose format {:#?}",
+            gpu.surface_formats[0]
+        );
         gpu.surface_formats[0]
     }
 
-    fn choose_present_mode(gpu: &GpuInfo) -> vk::PresentModeKHR {
-        debug!("Choosing presentation mode");
+    fn convert_surface_format(format: rendersystem::SurfaceFormat) -> vk::Format {
+        match format {
+            rendersystem::SurfaceFormat::Bgra8Unorm => vk::Format::B8G8R8A8_UNORM,
+            rendersystem::SurfaceFormat::Rgba8Unorm => vk::Format::R8G8B8A8_UNORM,
+            rendersystem::SurfaceFormat::Bgra8Srgb => vk::Format::B8G8R8A8_SRGB,
+            rendersystem::SurfaceFormat::Rgba8Srgb => vk::Format::R8G8B8A8_SRGB,
+            rendersystem::SurfaceFormat::A2b10g10r10Unorm => vk::Format::A2B10G10R10_UNORM_PACK32,
+            rendersystem::SurfaceFormat::A2r10g10b10Unorm => vk::Format::A2R10G10B10_UNORM_PACK32,
+        }
+    }
+
+    fn choose_present_mode(
+        gpu: &GpuInfo,
+        preferences: &[rendersystem::PresentMode],
+    ) -> vk::PresentModeKHR {
+        debug!("Choosing presentation mode from {preferences:?}");
 
-        for &mode in &gpu.present_modes {
-            if mode == vk::PresentModeKHR::MAILBOX {
+        for &preference in preferences {
+            let mode = Self::convert_present_mode(preference);
+            if gpu.present_modes.contains(&mode) {
                 debug!("Chose present mode {mode:#?}");
                 return mode;
             }
@@ -999,38 +2131,89 @@ impl State {
         vk::PresentModeKHR::FIFO
     }
 
+    fn convert_present_mode(mode: rendersystem::PresentMode) -> vk::PresentModeKHR {
+        match mode {
+            rendersystem::PresentMode::Immediate => vk::PresentModeKHR::IMMEDIATE,
+            rendersystem::PresentMode::Mailbox => vk::PresentModeKHR::MAILBOX,
+            rendersystem::PresentMode::Fifo => vk::PresentModeKHR::FIFO,
+            rendersystem::PresentMode::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+        }
+    }
+
     fn create_swapchain(
         device: &ash::Device,
         gpu: &GpuInfo,
         surface: &vk::SurfaceKHR,
         present_mode: &vk::PresentModeKHR,
+        sharing: rendersystem::SwapchainSharing,
+        image_count: Option<u32>,
+        full_screen_exclusive: Option<(vk::FullScreenExclusiveEXT, vk::HMONITOR)>,
         surface_format: &vk::SurfaceFormatKHR,
         image_extent: &vk::Extent2D,
         loader: &extensions::khr::Swapchain,
     ) -> (vk::SwapchainKHR, Vec<vk::Image>, Vec<vk::ImageView>) {
-        debug!("Creating swap chain");
-
-        let queue_family_indices = [gpu.graphics_family_index, gpu.compute_family_index];
-        let (image_sharing_mode, queue_family_index_count, p_queue_family_indices) =
-            if gpu.graphics_family_index != gpu.compute_family_index {
-                (
-                    vk::SharingMode::CONCURRENT,
-                    2,
-                    queue_family_indices.as_ptr(),
-                )
-            } else {
-                (vk::SharingMode::EXCLUSIVE, 0, ptr::null())
-            };
+        debug!("Creating swap chain with {sharing:?} sharing");
+
+        // Concurrent lets the present family use the images without them changing hands,
+        // exclusive hands each one over every time it's presented (see PresentTransfer)
+        let queue_family_indices = [gpu.graphics_family_index, gpu.present_family_index];
+        let (image_sharing_mode, queue_family_index_count, p_queue_family_indices) = if sharing
+            == rendersystem::SwapchainSharing::Concurrent
+            && gpu.graphics_family_index != gpu.present_family_index
+        {
+            (
+                vk::SharingMode::CONCURRENT,
+                2,
+                queue_family_indices.as_ptr(),
+            )
+        } else {
+            (vk::SharingMode::EXCLUSIVE, 0, ptr::null())
+        };
+
+        // only a minimum, the driver can make more, so everything per image goes by how many
+        // images there really are
+        let mut min_image_count = cmp::max(
+            image_count.unwrap_or(FRAME_COUNT as u32),
+            gpu.min_image_count,
+        );
+        if gpu.max_image_count > 0 {
+            min_image_count = cmp::min(min_image_count, gpu.max_image_count);
+        }
+
+        // application controlled needs to know which monitor it's for
+        let win32_info =
+            full_screen_exclusive.map(|(_, hmonitor)| vk::SurfaceFullScreenExclusiveWin32InfoEXT {
+                hmonitor,
+                ..Default::default()
+            });
+        let full_screen_exclusive_info =
+            full_screen_exclusive.map(|(mode, _)| vk::SurfaceFullScreenExclusiveInfoEXT {
+                p_next: match &win32_info {
+                    Some(win32_info)
+                        if mode == vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED =>
+                    {
+                        win32_info as *const _ as *mut ffi::c_void
+                    }
+                    _ => ptr::null_mut(),
+                },
+                full_screen_exclusive: mode,
+                ..Default::default()
+            });
 
         let swapchain_info = vk::SwapchainCreateInfoKHR {
+            p_next: full_screen_exclusive_info
+                .as_ref()
+                .map_or(ptr::null(), |info| info as *const _ as *const ffi::c_void),
             surface: *surface,
-            min_image_count: FRAME_COUNT as u32,
+            min_image_count,
             image_format: surface_format.format,
             image_color_space: surface_format.color_space,
             image_extent: *image_extent,
             image_array_layers: 1,
 
-            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
 
             image_sharing_mode,
             queue_family_index_count,
@@ -1051,69 +2234,178 @@ impl State {
             )
         };
         let images = unsafe { vulkan_check!(loader.get_swapchain_images(swapchain)) };
+        debug!(
+            "Asked for {min_image_count} swap chain images, got {}",
+            images.len()
+        );
 
-        debug!("Creating swap chain image views");
-        let mut views = Vec::new();
-        let mut i = 0;
-        views.resize_with(FRAME_COUNT, || unsafe {
-            let image = images[i];
-            i += 1;
-            vulkan_check!(device.create_image_view(
-                &vk::ImageViewCreateInfo {
-                    image,
-
-                    view_type: vk::ImageViewType::TYPE_2D,
-
-                    format: surface_format.format,
-
-                    components: vk::ComponentMapping {
-                        r: vk::ComponentSwizzle::R,
-                        g: vk::ComponentSwizzle::G,
-                        b: vk::ComponentSwizzle::B,
-                        a: vk::ComponentSwizzle::A,
-                    },
-
-                    subresource_range: vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        base_mip_level: 0,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
+        debug!("Creating {} swap chain image views", images.len());
+        let views = images
+            .iter()
+            .map(|&image| unsafe {
+                vulkan_check!(device.create_image_view(
+                    &vk::ImageViewCreateInfo {
+                        image,
+
+                        view_type: vk::ImageViewType::TYPE_2D,
+
+                        format: surface_format.format,
+
+                        components: vk::ComponentMapping {
+                            r: vk::ComponentSwizzle::R,
+                            g: vk::ComponentSwizzle::G,
+                            b: vk::ComponentSwizzle::B,
+                            a: vk::ComponentSwizzle::A,
+                        },
+
+                        subresource_range: vk::ImageSubresourceRange {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        },
+
+                        ..Default::default()
                     },
-
-                    ..Default::default()
-                },
-                Some(&State::get_allocation_callbacks())
-            ))
-        });
+                    Some(&State::get_allocation_callbacks())
+                ))
+            })
+            .collect();
 
         debug!("Created swapchain {swapchain:#?}");
 
         (swapchain, images, views)
     }
 
-    fn destroy_swapchain(&mut self) {
-        debug!("Destroying {FRAME_COUNT} swap chain image views");
-        self.swapchain_views.iter().for_each(|view| unsafe {
-            self.device
-                .destroy_image_view(*view, Some(&State::get_allocation_callbacks()))
-        });
-
-        debug!("Destroying swap chain {:#?}", self.swapchain);
-        unsafe {
-            self.swapchain_loader
-                .destroy_swapchain(self.swapchain, Some(&State::get_allocation_callbacks()))
+    // Until the window's first configure it's asked for at 0x0 (see window_extent), but the
+    // swapchain can't be empty and has to fit the surface, so it's at least 1x1. If the surface
+    // can't have any size (its maximum is 0x0, like minimized windows on Windows) there can't be
+    // a swapchain at all, that's None. Also returns whether the size is degenerate, anything sized
+    // after it should wait for a real one.
+    fn surface_extent(
+        surface_loader: &extensions::khr::Surface,
+        gpu: &GpuInfo,
+        surface: &vk::SurfaceKHR,
+        requested: vk::Extent2D,
+    ) -> (Option<vk::Extent2D>, bool) {
+        let capabilities = unsafe {
+            vulkan_check!(
+                surface_loader.get_physical_device_surface_capabilities(gpu.device, *surface)
+            )
         };
-    }
 
-    fn create_render_targets(
-        video: &platform::video::State,
+        if Self::is_degenerate(capabilities.max_image_extent) {
+            warn!(
+                "Window is {}x{} and the surface can't have a swap chain, waiting for a real size",
+                requested.width, requested.height
+            );
+            return (None, true);
+        }
+
+        // the surface decides unless it's u32::MAX, then it goes by the swapchain
+        let extent = if capabilities.current_extent.width != u32::MAX {
+            capabilities.current_extent
+        } else {
+            vk::Extent2D {
+                width: requested.width.clamp(
+                    capabilities.min_image_extent.width,
+                    capabilities.max_image_extent.width,
+                ),
+                height: requested.height.clamp(
+                    capabilities.min_image_extent.height,
+                    capabilities.max_image_extent.height,
+                ),
+            }
+        };
+        let degenerate = Self::is_degenerate(requested) || Self::is_degenerate(extent);
+        let extent = vk::Extent2D {
+            width: cmp::max(extent.width, 1),
+            height: cmp::max(extent.height, 1),
+        };
+
+        if degenerate {
+            warn!(
+                "Window is {}x{}, waiting for a real size with a {}x{} swap chain",
+                requested.width, requested.height, extent.width, extent.height
+            );
+        } else if extent != requested {
+            debug!(
+                "Window is {}x{}, the surface needs a {}x{} swap chain",
+                requested.width, requested.height, extent.width, extent.height
+            );
+        }
+        (Some(extent), degenerate)
+    }
+
+    fn is_degenerate(extent: vk::Extent2D) -> bool {
+        extent.width == 0 || extent.height == 0
+    }
+
+    // The window's size, or 0x0 before its first configure since it isn't real until then
+    fn window_extent(video: &dyn SurfaceProvider) -> vk::Extent2D {
+        if !video.configured() {
+            return vk::Extent2D::default();
+        }
+        let (width, height) = video.size();
+        vk::Extent2D { width, height }
+    }
+
+    fn scale_extent(extent: vk::Extent2D, scale: f32) -> vk::Extent2D {
+        vk::Extent2D {
+            width: cmp::max((extent.width as f32 * scale).round() as u32, 1),
+            height: cmp::max((extent.height as f32 * scale).round() as u32, 1),
+        }
+    }
+
+    fn create_render_targets(
+        extent: &vk::Extent2D,
+        color_format: vk::Format,
         instance: &ash::Instance,
         gpu: &GpuInfo,
         device: &ash::Device,
         allocator: &vk_mem::Allocator,
-    ) -> Image {
-        debug!("Creating render target images");
+    ) -> (Image, Image) {
+        debug!(
+            "Creating {}x{} render target images",
+            extent.width, extent.height
+        );
+
+        debug!("Creating color image");
+        let color_image = vulkan_check!(Image::new(
+            device,
+            allocator,
+            color_format,
+            &mut vk::ImageCreateInfo {
+                extent: vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1
+                },
+                mip_levels: 1,
+                array_layers: 1,
+                samples: vk::SampleCountFlags::TYPE_1,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                image_type: vk::ImageType::TYPE_2D,
+                ..Default::default()
+            },
+            &mut vk::ImageViewCreateInfo {
+                view_type: vk::ImageViewType::TYPE_2D,
+                subresource_range: vk::ImageSubresourceRange {
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                },
+                ..Default::default()
+            },
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::AutoPreferDevice,
+                ..Default::default()
+            }
+        ));
+        debug!("Created color image {:#?}", color_image.handle());
 
         let depth_formats = vec![
             vk::Format::D32_SFLOAT_S8_UINT,
@@ -1132,15 +2424,14 @@ impl State {
         }
 
         debug!("Creating depth image");
-        let (width, height) = video.get_size();
         let depth_image = vulkan_check!(Image::new(
             device,
             allocator,
             depth_format,
             &mut vk::ImageCreateInfo {
                 extent: vk::Extent3D {
-                    width,
-                    height,
+                    width: extent.width,
+                    height: extent.height,
                     depth: 1
                 },
                 mip_levels: 1,
@@ -1152,13 +2443,7 @@ impl State {
             },
             &mut vk::ImageViewCreateInfo {
                 view_type: vk::ImageViewType::TYPE_2D,
-                subresource_range: vk::ImageSubresourceRange {
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                    aspect_mask: vk::ImageAspectFlags::DEPTH,
-                },
+                subresource_range: DEPTH_SUBRESOURCE_RANGE,
                 ..Default::default()
             },
             &vk_mem::AllocationCreateInfo {
@@ -1168,13 +2453,7 @@ impl State {
         ));
         debug!("Created depth image {:#?}", depth_image.handle());
 
-        depth_image
-    }
-
-    fn destroy_render_targets(&mut self) {
-        debug!("Destroying render target images");
-        debug!("Destroying depth image {:#?}", self.depth_image.handle());
-        self.depth_image.destroy(&self.device, &self.allocator);
+        (color_image, depth_image)
     }
 
     fn create_descriptor_layout(device: &ash::Device) -> vk::DescriptorSetLayout {
@@ -1182,9 +2461,12 @@ impl State {
 
         let ubo_layout_binding = vk::DescriptorSetLayoutBinding {
             binding: 0,
-            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
             descriptor_count: 1,
-            stage_flags: vk::ShaderStageFlags::VERTEX,
+            // the grid's fragment shader needs the camera too
+            stage_flags: vk::ShaderStageFlags::VERTEX
+                | vk::ShaderStageFlags::FRAGMENT
+                | vk::ShaderStageFlags::COMPUTE,
             ..Default::default()
         };
 
@@ -1206,81 +2488,314 @@ impl State {
         layout
     }
 
-    fn resize(&mut self, video: &platform::video::State) {
+    // Set 1, the storage buffer a material's compute prepass writes and its vertex shader reads
+    fn create_storage_layout(device: &ash::Device) -> vk::DescriptorSetLayout {
+        debug!("Creating storage descriptor set layout");
+
+        let storage_layout_binding = vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::COMPUTE,
+            ..Default::default()
+        };
+
+        let layout = unsafe {
+            vulkan_check!(device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo {
+                    p_bindings: ptr::addr_of!(storage_layout_binding),
+                    binding_count: 1,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        debug!("Created storage descriptor set layout {layout:#?}");
+
+        layout
+    }
+
+    // Set 2, the material's texture. It's a set of its own because set 0 is per frame and a
+    // material's texture is the same in every frame.
+    fn create_texture_layout(device: &ash::Device) -> vk::DescriptorSetLayout {
+        debug!("Creating texture descriptor set layout");
+
+        let texture_layout_binding = vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        };
+
+        let layout = unsafe {
+            vulkan_check!(device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo {
+                    p_bindings: ptr::addr_of!(texture_layout_binding),
+                    binding_count: 1,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        debug!("Created texture descriptor set layout {layout:#?}");
+
+        layout
+    }
+
+    fn set_layouts(&self) -> [vk::DescriptorSetLayout; 3] {
+        [
+            self.descriptor_layout,
+            self.storage_layout,
+            self.texture_layout,
+        ]
+    }
+
+    fn create_timestamp_pool(device: &ash::Device) -> vk::QueryPool {
+        debug!(
+            "Creating timestamp query pool with {} queries",
+            FRAME_COUNT * 2
+        );
+
+        let pool = unsafe {
+            vulkan_check!(device.create_query_pool(
+                &vk::QueryPoolCreateInfo {
+                    query_type: vk::QueryType::TIMESTAMP,
+                    query_count: (FRAME_COUNT * 2) as u32,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        debug!("Created timestamp query pool {pool:#?}");
+
+        pool
+    }
+
+    fn resize(&mut self, video: &dyn SurfaceProvider) {
+        self.recreate_swapchain(Self::window_extent(video));
+    }
+
+    // The only way the swapchain or anything sized or formatted after it gets changed, so it all
+    // stays consistent
+    fn recreate_swapchain(&mut self, extent: vk::Extent2D) {
         debug!("Recreating swap chain");
 
         debug!("Waiting for device idle");
         unsafe { vulkan_check!(self.device.device_wait_idle()) };
 
-        self.destroy_render_targets();
-        self.destroy_swapchain();
-        let (width, height) = video.get_size();
-        self.swapchain_extent = vk::Extent2D { width, height };
-        (self.swapchain, self.swapchain_images, self.swapchain_views) = Self::create_swapchain(
+        // the formats can change with the surface, like when the window goes to an HDR monitor
+        let gpu = &mut self.gpus[self.gpu];
+        match unsafe {
+            self.surface_loader
+                .get_physical_device_surface_formats(gpu.device, self.surface)
+        } {
+            Ok(formats) if !formats.is_empty() => gpu.surface_formats = formats,
+            Ok(_) => warn!("The surface has no formats anymore, keeping the old ones"),
+            Err(err) => warn!("Failed to get surface formats, keeping the old ones: {err}"),
+        }
+        let surface_format =
+            Self::choose_surface_format(&self.gpus[self.gpu], &self.config.surface_formats);
+        let old_format = self.swapchain.surface_format;
+        if (surface_format.format, surface_format.color_space)
+            != (old_format.format, old_format.color_space)
+        {
+            debug!(
+                "Surface format changed from {:?} {:?} to {:?} {:?}",
+                old_format.format,
+                old_format.color_space,
+                surface_format.format,
+                surface_format.color_space
+            );
+        }
+        self.swapchain
+            .destroy(&self.device, &self.swapchain_loader, &self.allocator);
+        self.swapchain = SwapchainResources::new(
+            &self.instance,
             &self.device,
+            &self.allocator,
             &self.gpus[self.gpu],
+            &self.surface_loader,
             &self.surface,
-            &self.present_mode,
-            &self.surface_format,
-            &self.swapchain_extent,
             &self.swapchain_loader,
+            surface_format,
+            self.present_mode,
+            self.config.swapchain_sharing,
+            self.config.swapchain_images,
+            Self::full_screen_exclusive_mode(&self.gpus[self.gpu], self.fullscreen_monitor),
+            extent,
+            self.render_scale,
         );
-        (self.depth_image) = Self::create_render_targets(
-            video,
-            &self.instance,
-            &self.gpus[self.gpu],
-            &self.device,
-            &self.allocator,
-        );
+
+        // exclusive mode belongs to the old swapchain
+        self.fullscreen_acquired = false;
+        if self.fullscreen_monitor.is_some() {
+            self.acquire_fullscreen_exclusive();
+        }
+    }
+
+    // What the swapchain gets for VK_EXT_full_screen_exclusive, None without it. Without a monitor
+    // it's disallowed, so the driver doesn't decide to go exclusive on its own.
+    fn full_screen_exclusive_mode(
+        gpu: &GpuInfo,
+        monitor: Option<vk::HMONITOR>,
+    ) -> Option<(vk::FullScreenExclusiveEXT, vk::HMONITOR)> {
+        if !gpu.full_screen_exclusive {
+            return None;
+        }
+        Some(match monitor {
+            Some(monitor) => (vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED, monitor),
+            None => (vk::FullScreenExclusiveEXT::DISALLOWED, ptr::null_mut()),
+        })
+    }
+
+    fn acquire_fullscreen_exclusive(&mut self) -> bool {
+        if self.swapchain.handle == vk::SwapchainKHR::null() {
+            return false;
+        }
+        match unsafe {
+            self.full_screen_exclusive_loader
+                .acquire_full_screen_exclusive_mode(self.swapchain.handle)
+        } {
+            Ok(()) => {
+                info!("Acquired exclusive fullscreen");
+                self.fullscreen_acquired = true;
+            }
+            // not focused, or something else has the monitor
+            Err(err) => debug!("Couldn't acquire exclusive fullscreen: {err}"),
+        }
+        self.fullscreen_acquired
+    }
+
+    // Exclusive fullscreen skips the compositor, for the lowest latency and sometimes a different
+    // refresh rate. Only Windows has it, with VK_EXT_full_screen_exclusive, on the monitor the
+    // window is on now. Without it the video backend makes the window borderless fullscreen
+    // instead, if it can, and turning exclusive fullscreen off puts it back. The window should
+    // already cover the monitor for exclusive mode to work.
+    //
+    // Windows takes it away when the window loses focus (alt-tab, a UAC prompt, another window
+    // going fullscreen), presenting keeps working but through the compositor. It's acquired again
+    // at the start of the next frame after that, so once the window has focus again. Returns
+    // whether it's acquired.
+    pub fn set_fullscreen_exclusive(
+        &mut self,
+        video: &mut dyn SurfaceProvider,
+        enabled: bool,
+    ) -> bool {
+        if !enabled {
+            if self.borderless_fallback {
+                self.borderless_fallback = false;
+                video.set_borderless_fullscreen(false);
+            }
+            if self.fullscreen_monitor.take().is_some() {
+                info!("Leaving exclusive fullscreen");
+                if self.fullscreen_acquired {
+                    unsafe {
+                        vulkan_check!(self
+                            .full_screen_exclusive_loader
+                            .release_full_screen_exclusive_mode(self.swapchain.handle))
+                    };
+                }
+                self.recreate_swapchain(self.swapchain.extent);
+            }
+            return false;
+        }
+
+        if !self.gpus[self.gpu].full_screen_exclusive {
+            warn!(
+                "Device {} doesn't support exclusive fullscreen, going borderless",
+                self.gpu
+            );
+            self.fall_back_to_borderless(video);
+            return false;
+        }
+        let Some(monitor) = video.monitor() else {
+            warn!("Can't tell which monitor the window is on, going borderless");
+            self.fall_back_to_borderless(video);
+            return false;
+        };
+        if self.fullscreen_monitor == Some(monitor) && self.fullscreen_acquired {
+            return true;
+        }
+
+        info!("Entering exclusive fullscreen");
+        self.fullscreen_monitor = Some(monitor);
+        // application controlled has to be set when the swapchain is made, this also acquires it
+        self.recreate_swapchain(self.swapchain.extent);
+        if !self.fullscreen_acquired {
+            warn!("Failed to acquire exclusive fullscreen, staying borderless until it can be");
+        }
+        self.fullscreen_acquired
+    }
+
+    fn fall_back_to_borderless(&mut self, video: &mut dyn SurfaceProvider) {
+        if self.borderless_fallback {
+            return;
+        }
+        self.borderless_fallback = video.set_borderless_fullscreen(true);
+        if !self.borderless_fallback {
+            warn!("The window can't go borderless fullscreen either, leaving it as it is");
+        }
+    }
+
+    pub fn fullscreen_exclusive(&self) -> bool {
+        self.fullscreen_acquired
+    }
+
+    pub fn fullscreen_exclusive_supported(&self) -> bool {
+        self.gpus[self.gpu].full_screen_exclusive
     }
 
     fn create_descriptor_pool(device: &ash::Device) -> vk::DescriptorPool {
-        debug!("Creating descriptor pool");
+        debug!("Creating descriptor pool block");
 
         const POOL_SIZES: [vk::DescriptorPoolSize; 11] = [
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::SAMPLER,
-                descriptor_count: 1000,
+                descriptor_count: DESCRIPTOR_BLOCK_SIZE,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                descriptor_count: 1000,
+                descriptor_count: DESCRIPTOR_BLOCK_SIZE,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::SAMPLED_IMAGE,
-                descriptor_count: 1000,
+                descriptor_count: DESCRIPTOR_BLOCK_SIZE,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::STORAGE_IMAGE,
-                descriptor_count: 1000,
+                descriptor_count: DESCRIPTOR_BLOCK_SIZE,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
-                descriptor_count: 1000,
+                descriptor_count: DESCRIPTOR_BLOCK_SIZE,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::STORAGE_TEXEL_BUFFER,
-                descriptor_count: 1000,
+                descriptor_count: DESCRIPTOR_BLOCK_SIZE,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::UNIFORM_BUFFER,
-                descriptor_count: 1000,
+                descriptor_count: DESCRIPTOR_BLOCK_SIZE,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::STORAGE_BUFFER,
-                descriptor_count: 1000,
+                descriptor_count: DESCRIPTOR_BLOCK_SIZE,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
-                descriptor_count: 1000,
+                descriptor_count: DESCRIPTOR_BLOCK_SIZE,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
-                descriptor_count: 1000,
+                descriptor_count: DESCRIPTOR_BLOCK_SIZE,
             },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::INPUT_ATTACHMENT,
-                descriptor_count: 1000,
+                descriptor_count: DESCRIPTOR_BLOCK_SIZE,
             },
         ];
 
@@ -1290,7 +2805,7 @@ impl State {
                     flags: vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
                     pool_size_count: POOL_SIZES.len() as u32,
                     p_pool_sizes: POOL_SIZES.as_ptr(),
-                    max_sets: 1000 * POOL_SIZES.len() as u32,
+                    max_sets: DESCRIPTOR_BLOCK_SIZE * POOL_SIZES.len() as u32,
                     ..Default::default()
                 },
                 Some(&State::get_allocation_callbacks())
@@ -1302,13 +2817,23 @@ impl State {
         pool
     }
 
-    fn allocate_uniform_buffers(allocator: &vk_mem::Allocator) -> Vec<HostBuffer> {
+    // Offsets into a dynamic uniform buffer have to be aligned
+    fn uniform_stride(gpu: &GpuInfo) -> u64 {
+        let alignment = gpu
+            .properties
+            .limits
+            .min_uniform_buffer_offset_alignment
+            .max(1);
+        (mem::size_of::<rendersystem::UniformData>() as u64).div_ceil(alignment) * alignment
+    }
+
+    fn allocate_uniform_buffers(allocator: &vk_mem::Allocator, gpu: &GpuInfo) -> Vec<HostBuffer> {
         debug!("Allocating {FRAME_COUNT} uniform buffers");
         let mut buffers = Vec::new();
         buffers.resize_with(3, || {
             vulkan_check!(HostBuffer::new(
                 allocator,
-                mem::size_of::<rendersystem::UniformData>() as u64,
+                Self::uniform_stride(gpu) * MAX_VIEWPORTS as u64,
                 vk::BufferUsageFlags::UNIFORM_BUFFER,
                 vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
             ))
@@ -1317,27 +2842,33 @@ impl State {
         buffers
     }
 
+    fn create_default_color_buffer(allocator: &vk_mem::Allocator) -> HostBuffer {
+        debug!("Creating default vertex colour buffer");
+        let buffer = vulkan_check!(HostBuffer::new(
+            allocator,
+            (mem::size_of::<f32>() * 4) as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        ));
+        let white: Vec<u8> = [1.0f32; 4]
+            .iter()
+            .flat_map(|value| value.to_ne_bytes())
+            .collect();
+        unsafe { buffer.read(&white, 0) };
+
+        buffer
+    }
+
     fn allocate_descriptor_sets(
         device: &ash::Device,
         layout: &vk::DescriptorSetLayout,
-        pool: &vk::DescriptorPool,
+        pools: &DescriptorPools,
         uniform_buffers: &[HostBuffer],
     ) -> Vec<vk::DescriptorSet> {
         debug!("Allocating {FRAME_COUNT} descriptor sets");
 
-        let mut layouts = Vec::new();
-        layouts.resize_with(3, || *layout);
-
-        let descriptor_sets = unsafe {
-            vulkan_check!(
-                device.allocate_descriptor_sets(&vk::DescriptorSetAllocateInfo {
-                    descriptor_pool: *pool,
-                    descriptor_set_count: FRAME_COUNT as u32,
-                    p_set_layouts: layouts.as_ptr(),
-                    ..Default::default()
-                })
-            )
-        };
+        // these live as long as the pools, so which one they're from doesn't matter
+        let (descriptor_sets, _) = vulkan_check!(pools.allocate(device, &[*layout; FRAME_COUNT]));
 
         let mut i = 0;
         let mut buffer_infos = Vec::new();
@@ -1357,7 +2888,7 @@ impl State {
             let write = vk::WriteDescriptorSet {
                 dst_binding: 0,
                 dst_array_element: 0,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
                 descriptor_count: 1,
                 p_buffer_info: ptr::addr_of!(buffer_infos[i]),
                 dst_set: descriptor_sets[i],
@@ -1372,205 +2903,617 @@ impl State {
         descriptor_sets
     }
 
-    pub fn init(video: &platform::video::State) -> Self {
+    pub fn init(video: &dyn SurfaceProvider, config: &rendersystem::RenderConfig) -> Self {
         debug!("Vulkan initialization started");
 
         debug!("Loading Vulkan library");
         let entry = unsafe { vulkan_check!(ash::Entry::load()) };
 
-        let instance = Self::create_instance(&entry);
-        let surface_loader = extensions::khr::Surface::new(&entry, &instance);
-        let surface = video.create_vulkan_surface(
+        let validation_errors = Arc::new(atomic::AtomicUsize::new(0));
+        let (instance, surface_capabilities2) = Self::create_instance(
             &entry,
-            &instance,
-            Some(&State::get_allocation_callbacks()),
+            video,
+            &config.app_name,
+            config.app_version,
+            config.validation,
+            &validation_errors,
         );
-        let gpus = Self::get_gpus(&instance, &surface_loader, &surface);
-        let gpu = 0;
-        let (device, graphics_queue, compute_queue) = Self::create_device(&instance, &gpus[gpu]);
+        let surface_loader = extensions::khr::Surface::new(&entry, &instance);
+        let surface =
+            video.create_surface(&entry, &instance, Some(&State::get_allocation_callbacks()));
+        let gpus = Self::get_gpus(&instance, &surface_loader, &surface, surface_capabilities2);
+        let gpu = match config.gpu {
+            Some(gpu) if gpu < gpus.len() => gpu,
+            Some(gpu) => {
+                warn!(
+                    "Preferred device {gpu} doesn't exist, there are only {} devices",
+                    gpus.len()
+                );
+                0
+            }
+            None => 0,
+        };
+        let (device, graphics_queue, compute_queue, present_queue) =
+            Self::create_device(&instance, &gpus[gpu]);
         let (command_pool, transfer_pool) = Self::create_command_pools(&device, &gpus[gpu]);
         let command_buffers = Self::allocate_command_buffers(&device, &command_pool);
-        let allocator = Self::create_allocator(&instance, &device, gpus[gpu].device);
+        let pass_pools = Self::create_pass_pools(&device, &gpus[gpu]);
+        let allocator =
+            Self::create_allocator(&instance, &device, gpus[gpu].device, &config.allocator);
         let fences = Self::create_fences(&device);
-        let (acquire_semaphores, render_complete_semaphores) = Self::create_semaphores(&device);
-        let surface_format = Self::choose_surface_format(&gpus[gpu]);
-        let present_mode = Self::choose_present_mode(&gpus[gpu]);
-        let video_size = video.get_size();
-        let swapchain_extent = vk::Extent2D {
-            width: video_size.0,
-            height: video_size.1,
-        };
+        let acquire_semaphores = Self::create_semaphores(&device, FRAME_COUNT);
+        let surface_format = Self::choose_surface_format(&gpus[gpu], &config.surface_formats);
+        let present_mode = Self::choose_present_mode(&gpus[gpu], &config.present_modes);
         let swapchain_loader = extensions::khr::Swapchain::new(&instance, &device);
-        let (swapchain, swapchain_images, swapchain_views) = Self::create_swapchain(
+        let render_scale = config.render_scale.clamp(0.25, 1.0);
+        let swapchain = SwapchainResources::new(
+            &instance,
             &device,
+            &allocator,
             &gpus[gpu],
+            &surface_loader,
             &surface,
-            &present_mode,
-            &surface_format,
-            &swapchain_extent,
             &swapchain_loader,
+            surface_format,
+            present_mode,
+            config.swapchain_sharing,
+            config.swapchain_images,
+            Self::full_screen_exclusive_mode(&gpus[gpu], None),
+            Self::window_extent(video),
+            render_scale,
         );
-        let depth_image =
-            Self::create_render_targets(video, &instance, &gpus[gpu], &device, &allocator);
         let descriptor_layout = Self::create_descriptor_layout(&device);
-        let descriptor_pool = Self::create_descriptor_pool(&device);
-        let uniform_buffers = Self::allocate_uniform_buffers(&allocator);
+        let storage_layout = Self::create_storage_layout(&device);
+        let texture_layout = Self::create_texture_layout(&device);
+        let descriptor_pools = DescriptorPools::new(&device);
+        let uniform_buffers = Self::allocate_uniform_buffers(&allocator, &gpus[gpu]);
+        let default_color_buffer = Some(Self::create_default_color_buffer(&allocator));
         let descriptor_sets = Self::allocate_descriptor_sets(
             &device,
             &descriptor_layout,
-            &descriptor_pool,
+            &descriptor_pools,
             &uniform_buffers,
         );
         let shader_object_loader = extensions::ext::ShaderObject::new(&instance, &device);
+        let present_wait_loader = extensions::khr::PresentWait::new(&instance, &device);
+        let full_screen_exclusive_loader =
+            extensions::ext::FullScreenExclusive::new(&instance, &device);
+        #[cfg(feature = "graphics_debug")]
+        let debug_utils_loader = extensions::ext::DebugUtils::new(&entry, &instance);
+        let timestamp_pool = Self::create_timestamp_pool(&device);
+        let timestamp_period = gpus[gpu].properties.limits.timestamp_period;
+        let texture_filtering =
+            Self::supported_texture_filtering(&gpus[gpu], config.texture_filtering);
+        let texture_sampler = Self::create_texture_sampler(&device, texture_filtering);
 
         debug!("Vulkan initialization succeeded");
 
         let mut self_ = Self {
+            config: config.clone(),
+
             entry,
             instance,
+            validation_errors,
             device,
             surface_loader,
             swapchain_loader,
             shader_object_loader,
+            present_wait_loader,
+            full_screen_exclusive_loader,
+            #[cfg(feature = "graphics_debug")]
+            debug_utils_loader,
             surface,
             gpu,
             gpus,
             graphics_queue,
             compute_queue,
+            present_queue,
             command_pool,
+            pass_pools,
+            pass_command_buffers: vec![Vec::new(); FRAME_COUNT],
+            pass_purposes: vec![Vec::new(); FRAME_COUNT],
             transfer_pool,
             command_buffers,
             fences,
+            last_in_flight: 0,
+            fence_pool: FencePool::new(),
             acquire_semaphores,
-            render_complete_semaphores,
-            allocator,
+            allocator: mem::ManuallyDrop::new(allocator),
             swapchain,
-            swapchain_images,
-            swapchain_views,
-            surface_format,
             present_mode,
-            swapchain_extent,
-            depth_image,
+            fullscreen_monitor: None,
+            fullscreen_acquired: false,
+            borderless_fallback: false,
+            clear_color: rendersystem::Color::BLACK,
+            grid: None,
+            grid_settings: rendersystem::GridSettings::default(),
+            lighting: None,
+            ambient_light: rendersystem::Color::from_srgb8(32, 32, 32),
+            logarithmic_depth: false,
+            sample_shading: None,
+            max_frame_latency: FRAME_COUNT,
+            present_wait: false,
+            render_scale,
+            pending_render_scale: None,
             descriptor_layout,
-            descriptor_pool,
+            storage_layout,
+            texture_layout,
+            descriptor_pools,
             descriptor_sets,
             uniform_buffers,
+            default_color_buffer,
+            timestamp_pool,
+            timestamp_period,
+            timestamps_written: vec![false; FRAME_COUNT],
+            gpu_time: 0.0,
+            gpu_time_total: 0.0,
+            stats: rendersystem::RenderStats::default(),
+            last_stats: rendersystem::RenderStats::default(),
+            texture_filtering,
+            texture_sampler,
+            default_texture: TextureData::default(),
 
             initialized: true,
             loaded: false,
 
             in_frame: false,
+            in_gui_pass: false,
             frame_index: 0,
+            #[cfg(feature = "graphics_debug")]
+            frame_number: 0,
             resized: false,
+            out_of_date: false,
+            suboptimal_count: 0,
+            device_lost: false,
+            events: Vec::new(),
             swapchain_index: 0,
 
             model_buffer: None,
+            retired_buffers: Vec::new(),
+            model_updates: Vec::new(),
+            model_staging: (0..FRAME_COUNT).map(|_| Vec::new()).collect(),
+            readback_request: None,
+            readbacks: Vec::new(),
+            next_readback: 0,
 
             last_shader: None,
             last_model: None,
+            scissors: Vec::new(),
+            viewport: None,
+            viewport_count: 0,
+            uniform_offset: 0,
+            window_uniforms: None,
+            depth_bias: (0.0, 0.0, 0.0),
+            stencil: None,
+            depth_write: true,
+            target_formats: None,
+            window_scissors: Vec::new(),
         };
         self_.set_gpu(self_.gpu);
+        self_.set_max_frame_latency(config.max_frame_latency);
+        self_.set_present_wait(config.present_wait);
+        if config.sample_shading.is_some() {
+            self_.set_sample_shading(config.sample_shading);
+        }
+        debug!("{:#?}", self_.queue_info());
 
         self_
     }
 
     pub fn load_resources(
         &mut self,
-        models: &mut HashMap<String, Arc<SyncUnsafeCell<rendersystem::Model>>>,
-    ) {
+        models: &mut HashMap<String, rendersystem::ThingHolder<rendersystem::Model>>,
+    ) -> Result<(), vk::Result> {
         if !models.is_empty() {
             debug!("Creating model buffer");
 
             let mut size = 0;
             models.iter_mut().for_each(|(_, model)| {
-                let model = unsafe { model.get().as_mut().unwrap() };
+                let model = &mut *model.get_mut();
                 model.handle.offset = size;
+                model.handle.capacity = model.size();
                 size += model.size();
             });
 
-            let transfer_buffer = vulkan_check!(HostBuffer::new(
+            let transfer_buffer = HostBuffer::new(
                 &self.allocator,
                 size,
                 vk::BufferUsageFlags::VERTEX_BUFFER
                     | vk::BufferUsageFlags::INDEX_BUFFER
                     | vk::BufferUsageFlags::TRANSFER_SRC,
                 vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
-            ));
+            )?;
 
             models.iter().for_each(|(_, model)| {
-                let model = unsafe { model.get().as_mut().unwrap() };
+                let model = model.get();
                 unsafe { transfer_buffer.read(model.data(), model.handle.offset) };
             });
 
-            self.model_buffer = Some(vulkan_check!(Buffer::new(
+            let model_buffer = Buffer::new(
                 &self.allocator,
                 size,
                 vk::BufferUsageFlags::VERTEX_BUFFER
                     | vk::BufferUsageFlags::INDEX_BUFFER
+                    | vk::BufferUsageFlags::TRANSFER_SRC
                     | vk::BufferUsageFlags::TRANSFER_DST,
-                vk::MemoryPropertyFlags::empty()
-            )));
+                vk::MemoryPropertyFlags::empty(),
+            );
+            self.model_buffer = match model_buffer {
+                Ok(model_buffer) => Some(model_buffer),
+                Err(err) => {
+                    transfer_buffer.destroy(&self.allocator);
+                    return Err(err);
+                }
+            };
 
-            transfer_buffer.buffer().copy(
+            let mut batch = TransferBatch::begin(&self.device, &self.transfer_pool);
+            models.iter().for_each(|(_, model)| {
+                let model = model.get();
+                batch.copy_region(
+                    &self.device,
+                    transfer_buffer.buffer(),
+                    self.model_buffer.as_ref().unwrap(),
+                    vk::BufferCopy {
+                        src_offset: model.handle.offset,
+                        dst_offset: model.handle.offset,
+                        size: model.size(),
+                    },
+                );
+            });
+            batch.submit(
                 &self.device,
-                &self.compute_queue,
+                &self.graphics_queue,
                 &self.transfer_pool,
-                self.model_buffer.as_ref().unwrap(),
+                &mut self.fence_pool,
             );
             transfer_buffer.destroy(&self.allocator);
         }
 
+        debug!("Creating default texture");
+        let mut default_texture = TextureData::default();
+        if let Err(err) = default_texture.upload(
+            self,
+            "default",
+            rendersystem::TextureColorSpace::Srgb,
+            0,
+            &[image::RgbaImage::from_pixel(
+                1,
+                1,
+                image::Rgba([255, 255, 255, 255]),
+            )],
+        ) {
+            if let Some(model_buffer) = self.model_buffer.take() {
+                model_buffer.destroy(&self.allocator);
+            }
+            return Err(err);
+        }
+        self.default_texture = default_texture;
+
         self.loaded = true;
+        Ok(())
     }
 
-    pub fn begin_cmds(&mut self, video: &platform::video::State) {
-        unsafe {
-            vulkan_check!(self.device.wait_for_fences(
-                &[self.fences[self.frame_index]],
-                true,
-                u64::MAX
-            ))
-        };
-
-        (self.swapchain_index, self.resized) = unsafe {
-            match self.swapchain_loader.acquire_next_image(
-                self.swapchain,
-                u64::MAX,
-                self.acquire_semaphores[self.frame_index],
-                vk::Fence::null(),
-            ) {
-                Ok(values) => (values.0 as usize, values.1),
-                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => (0, true),
-                Err(err) => {
-                    panic!("Failed to acquire next image: {err}");
-                }
+    // Re-uploads a model that's already in the model buffer. If it still fits in its old space it's
+    // overwritten at the start of the next frame, see record_model_updates. Otherwise the model
+    // buffer is copied into a bigger one with the model at the end and the old one is retired. The
+    // old space stays unused until the next load.
+    pub fn update_model(
+        &mut self,
+        name: &str,
+        handle: &mut ModelData,
+        data: &[u8],
+        vertices_size: usize,
+        indices_size: usize,
+    ) {
+        let size = data.len() as vk::DeviceSize;
+        let model_buffer = match self.model_buffer.as_ref() {
+            Some(model_buffer) => model_buffer,
+            None => {
+                handle.vertices_size = vertices_size as vk::DeviceSize;
+                handle.indices_size = indices_size as vk::DeviceSize;
+                return;
             }
         };
-        if self.resized {
-            self.resize(video);
-            return;
-        }
 
-        unsafe {
-            vulkan_check!(self.device.reset_fences(&[self.fences[self.frame_index]]));
-            vulkan_check!(self.device.reset_command_buffer(
-                self.command_buffers[self.frame_index],
-                vk::CommandBufferResetFlags::empty()
+        let staging = |allocator: &vk_mem::Allocator| {
+            let buffer = vulkan_check!(HostBuffer::new(
+                allocator,
+                size,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
             ));
-            vulkan_check!(self.device.begin_command_buffer(
-                self.command_buffers[self.frame_index],
-                &vk::CommandBufferBeginInfo {
-                    flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
-                    ..Default::default()
-                }
+            unsafe { buffer.read(data, 0) };
+            buffer
+        };
+
+        if size <= handle.capacity {
+            trace!("Queueing in-place update of model {name} ({size} bytes)");
+
+            // an older update to the same model that hasn't been copied yet would only be
+            // overwritten
+            let offset = handle.offset;
+            let (replaced, updates): (Vec<_>, Vec<_>) = self
+                .model_updates
+                .drain(..)
+                .partition(|(_, other)| *other == offset);
+            self.model_updates = updates;
+            for (buffer, _) in replaced {
+                buffer.destroy(&self.allocator);
+            }
+            // Vulkan doesn't allow empty buffers or copies
+            if size > 0 {
+                self.model_updates.push((staging(&self.allocator), offset));
+            }
+        } else {
+            let transfer_buffer = staging(&self.allocator);
+            let mut batch = TransferBatch::begin(&self.device, &self.transfer_pool);
+            let old_size = model_buffer.size();
+            debug!(
+                "Model {name} grew from {} to {size} bytes, reallocating model buffer",
+                handle.capacity
+            );
+
+            let new_buffer = vulkan_check!(Buffer::new(
+                &self.allocator,
+                old_size + size,
+                vk::BufferUsageFlags::VERTEX_BUFFER
+                    | vk::BufferUsageFlags::INDEX_BUFFER
+                    | vk::BufferUsageFlags::TRANSFER_SRC
+                    | vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::empty()
+            ));
+            batch.copy(&self.device, model_buffer, &new_buffer);
+            batch.copy_region(
+                &self.device,
+                transfer_buffer.buffer(),
+                &new_buffer,
+                vk::BufferCopy {
+                    src_offset: 0,
+                    dst_offset: old_size,
+                    size,
+                },
+            );
+            batch.submit(
+                &self.device,
+                &self.graphics_queue,
+                &self.transfer_pool,
+                &mut self.fence_pool,
+            );
+
+            let old_buffer = self.model_buffer.replace(new_buffer).unwrap();
+            self.retired_buffers.push((FRAME_COUNT, old_buffer));
+            handle.offset = old_size;
+            handle.capacity = size;
+            transfer_buffer.destroy(&self.allocator);
+        }
+
+        handle.vertices_size = vertices_size as vk::DeviceSize;
+        handle.indices_size = indices_size as vk::DeviceSize;
+        // the offsets or buffer changed, so the next draw has to rebind
+        self.last_model = None;
+    }
+
+    // Goes at the start of the frame's command buffer, before anything reads the model buffer.
+    // Earlier frames on the queue have to be done reading vertices before they're overwritten.
+    fn record_model_updates(&mut self) {
+        let Some(model_buffer) = self.model_buffer.as_ref() else {
+            return;
+        };
+        if self.model_updates.is_empty() {
+            return;
+        }
+
+        trace!("Recording {} model update(s)", self.model_updates.len());
+        let command_buffer = self.command_buffers[self.frame_index];
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[],
+            );
+            for (staging, offset) in &self.model_updates {
+                self.device.cmd_copy_buffer(
+                    command_buffer,
+                    *staging.buffer().handle(),
+                    *model_buffer.handle(),
+                    &[vk::BufferCopy {
+                        src_offset: 0,
+                        dst_offset: *offset,
+                        size: staging.buffer().size(),
+                    }],
+                );
+            }
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier {
+                    src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                    dst_access_mask: vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                        | vk::AccessFlags::INDEX_READ,
+                    ..Default::default()
+                }],
+                &[],
+                &[],
+            );
+        }
+        let staging = self.model_updates.drain(..).map(|(staging, _)| staging);
+        self.model_staging[self.frame_index].extend(staging);
+    }
+
+    // Called once the fence for this frame slot has been waited on, by then a buffer retired
+    // FRAME_COUNT frames ago can't be in use anymore
+    fn destroy_retired_buffers(&mut self, all: bool) {
+        for (frames_left, _) in &mut self.retired_buffers {
+            *frames_left = frames_left.saturating_sub(1);
+        }
+
+        let (expired, retired): (Vec<_>, Vec<_>) = self
+            .retired_buffers
+            .drain(..)
+            .partition(|(frames_left, _)| all || *frames_left == 0);
+        self.retired_buffers = retired;
+        for (_, buffer) in expired {
+            trace!("Destroying retired buffer {:#?}", buffer.handle());
+            buffer.destroy(&self.allocator);
+        }
+    }
+
+    // Waiting on the fence for this frame slot means the CPU is at most FRAME_COUNT frames ahead
+    // of the GPU no matter the present mode, so with an uncapped loop (IMMEDIATE/MAILBOX) input
+    // latency is bounded at roughly FRAME_COUNT frames instead of growing. A lower max frame
+    // latency also waits on the frame submitted that many frames ago.
+    pub fn begin_cmds(
+        &mut self,
+        video: &dyn SurfaceProvider,
+        prepasses: &[(&ComputeShaderData, &PrepassData)],
+    ) {
+        if self.device_lost {
+            return;
+        }
+        // minimized, nothing gets recorded or presented until it has a size again
+        let (width, height) = video.size();
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        if self.present_wait {
+            if !self.wait_for_fences(&[self.fences[self.frame_index]]) {
+                return;
+            }
+            self.wait_for_present();
+            if self.device_lost {
+                return;
+            }
+        } else {
+            let latency_index =
+                (self.frame_index + FRAME_COUNT - self.max_frame_latency) % FRAME_COUNT;
+            if !self.wait_for_fences(&[self.fences[self.frame_index], self.fences[latency_index]]) {
+                return;
+            }
+            debug_assert!(self.frames_in_flight() < self.max_frame_latency);
+        }
+        self.destroy_retired_buffers(false);
+        // whatever was recorded the last time this frame came around is done now
+        for staging in self.model_staging[self.frame_index].drain(..) {
+            staging.destroy(&self.allocator);
+        }
+        for readback in &mut self.readbacks {
+            readback.ready |= readback.slot == self.frame_index;
+        }
+        self.reset_pass_cmds();
+
+        self.read_timestamps(self.frame_index);
+
+        if self.fullscreen_monitor.is_some() && !self.fullscreen_acquired {
+            self.acquire_fullscreen_exclusive();
+        }
+
+        if let Some(scale) = self.pending_render_scale.take() {
+            self.render_scale = scale;
+            self.out_of_date = true;
+        }
+        // the swapchain doesn't always go out of date when a degenerate window gets its real size
+        if self.out_of_date
+            || (self.swapchain.degenerate && !Self::is_degenerate(Self::window_extent(video)))
+        {
+            self.out_of_date = false;
+            self.resize(video);
+        }
+        // the surface couldn't have one, so there's nothing to draw to
+        if self.swapchain.handle == vk::SwapchainKHR::null() {
+            return;
+        }
+
+        // suboptimal still presents fine, so it only gets recreated once it's actually out of date
+        // instead of every frame on drivers that always report it
+        let acquired = unsafe {
+            self.swapchain_loader.acquire_next_image(
+                self.swapchain.handle,
+                u64::MAX,
+                self.acquire_semaphores[self.frame_index],
+                vk::Fence::null(),
+            )
+        };
+        (self.swapchain_index, self.resized) = match acquired {
+            Ok((index, suboptimal)) => {
+                if suboptimal {
+                    self.note_suboptimal();
+                }
+                (index as usize, false)
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => (0, true),
+            Err(vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT) => {
+                self.lose_fullscreen_exclusive();
+                (0, true)
+            }
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                self.lose_device();
+                return;
+            }
+            Err(err) => {
+                panic!("Failed to acquire next image: {err}");
+            }
+        };
+        if self.resized {
+            self.resize(video);
+            return;
+        }
+
+        // the image can come back out of order (especially without vsync) while an older frame
+        // is still rendering to it
+        let image_fence = self.swapchain.images_in_flight[self.swapchain_index];
+        if image_fence != vk::Fence::null() && image_fence != self.fences[self.frame_index] {
+            if !self.wait_for_fences(&[image_fence]) {
+                return;
+            }
+        }
+        self.swapchain.images_in_flight[self.swapchain_index] = self.fences[self.frame_index];
+
+        unsafe {
+            vulkan_check!(self.device.reset_fences(&[self.fences[self.frame_index]]));
+            vulkan_check!(self.device.reset_command_buffer(
+                self.command_buffers[self.frame_index],
+                vk::CommandBufferResetFlags::empty()
+            ));
+            vulkan_check!(self.device.begin_command_buffer(
+                self.command_buffers[self.frame_index],
+                &vk::CommandBufferBeginInfo {
+                    flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                    ..Default::default()
+                }
             ));
+            self.device.cmd_reset_query_pool(
+                self.command_buffers[self.frame_index],
+                self.timestamp_pool,
+                (self.frame_index * 2) as u32,
+                2,
+            );
+            self.device.cmd_write_timestamp(
+                self.command_buffers[self.frame_index],
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.timestamp_pool,
+                (self.frame_index * 2) as u32,
+            );
         }
 
+        #[cfg(feature = "graphics_debug")]
+        self.label_frame();
+
+        self.record_model_updates();
+        self.run_prepasses(prepasses);
+
+        let (color_image, _) = self.color_target();
         let layout_barrier = vk::ImageMemoryBarrier {
             dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
             old_layout: vk::ImageLayout::UNDEFINED,
             new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            image: self.swapchain_images[self.swapchain_index],
+            image: color_image,
             subresource_range: vk::ImageSubresourceRange {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
                 base_mip_level: 0,
@@ -1580,35 +3523,67 @@ impl State {
             },
             ..Default::default()
         };
+        // shared by every frame, the last one could still be testing against it
+        let depth_barrier = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            image: *self.swapchain.depth_image.handle(),
+            subresource_range: DEPTH_SUBRESOURCE_RANGE,
+            ..Default::default()
+        };
 
         unsafe {
             self.device.cmd_pipeline_barrier(
                 self.command_buffers[self.frame_index],
-                vk::PipelineStageFlags::TOP_OF_PIPE,
-                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TOP_OF_PIPE | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
                 vk::DependencyFlags::empty(),
                 &[],
                 &[],
-                &[layout_barrier],
+                &[layout_barrier, depth_barrier],
             )
         };
 
+        self.begin_window_rendering(vk::AttachmentLoadOp::CLEAR);
+
+        self.scissors.clear();
+        self.viewport = None;
+        self.viewport_count = 0;
+        self.uniform_offset = 0;
+        self.write_window_uniforms();
+        self.depth_bias = (0.0, 0.0, 0.0);
+        self.stencil = None;
+        self.depth_write = true;
+        self.target_formats = None;
+        self.set_dynamic_state();
+
+        self.in_frame = true;
+        self.in_gui_pass = false;
+    }
+
+    // Clears at the start of the frame, loads when coming back from a render target
+    fn begin_window_rendering(&self, load_op: vk::AttachmentLoadOp) {
+        let (_, color_view) = self.color_target();
         let color_attachment = vk::RenderingAttachmentInfo {
-            image_view: self.swapchain_views[self.swapchain_index],
+            image_view: color_view,
             image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            load_op: vk::AttachmentLoadOp::CLEAR,
+            load_op,
             store_op: vk::AttachmentStoreOp::STORE,
             clear_value: vk::ClearValue {
                 color: vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.0, 1.0],
+                    float32: self.encode_color(self.clear_color),
                 },
             },
             ..Default::default()
         };
         let depth_attachment = vk::RenderingAttachmentInfo {
-            image_view: *self.depth_image.view(),
-            image_layout: vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
-            load_op: vk::AttachmentLoadOp::CLEAR,
+            image_view: *self.swapchain.depth_image.view(),
+            image_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            load_op,
             store_op: vk::AttachmentStoreOp::STORE,
             clear_value: vk::ClearValue {
                 depth_stencil: vk::ClearDepthStencilValue {
@@ -1622,9 +3597,10 @@ impl State {
             color_attachment_count: 1,
             p_color_attachments: ptr::addr_of!(color_attachment),
             p_depth_attachment: ptr::addr_of!(depth_attachment),
+            p_stencil_attachment: ptr::addr_of!(depth_attachment),
             layer_count: 1,
             render_area: vk::Rect2D {
-                extent: self.swapchain_extent,
+                extent: self.swapchain.render_extent,
                 ..Default::default()
             },
             ..Default::default()
@@ -1634,356 +3610,5441 @@ impl State {
             self.device
                 .cmd_begin_rendering(self.command_buffers[self.frame_index], &rendering_info)
         };
-
-        self.in_frame = true;
     }
 
-    pub fn render_model(&mut self, model: &rendersystem::Model) {
-        if self.last_model.is_none() || self.last_model.as_ref().unwrap() != &model.name {
-            unsafe {
-                self.device.cmd_bind_vertex_buffers(
-                    self.command_buffers[self.frame_index],
-                    0,
-                    &[*self.model_buffer.as_ref().unwrap().handle()],
-                    &[model.handle.offset],
-                );
-                self.device.cmd_bind_index_buffer(
-                    self.command_buffers[self.frame_index],
-                    *self.model_buffer.as_ref().unwrap().handle(),
-                    model.handle.offset + model.handle.vertices_size,
-                    vk::IndexType::UINT32,
-                );
-            }
-            self.last_model = Some(model.name.clone());
-        }
-
-        let shader = unsafe {
-            model
-                .material
-                .get()
-                .as_ref()
-                .unwrap()
-                .shader
-                .get()
-                .as_ref()
-                .unwrap()
-        };
+    // So frames in RenderDoc/Nsight captures can be matched up with the log
+    #[cfg(feature = "graphics_debug")]
+    fn label_frame(&self) {
+        let name = ffi::CString::new(format!("Frame {}", self.frame_number)).unwrap();
         unsafe {
-            self.shader_object_loader.cmd_bind_shaders(
-                self.command_buffers[self.frame_index],
-                &[vk::ShaderStageFlags::VERTEX, vk::ShaderStageFlags::FRAGMENT],
-                &[shader.handle.vertex_handle, shader.handle.fragment_handle],
-            );
-
-            self.device.cmd_draw_indexed(
+            self.debug_utils_loader.cmd_insert_debug_utils_label(
                 self.command_buffers[self.frame_index],
-                (model.handle.indices_size / mem::size_of::<u32>() as u64) as u32,
-                1,
-                0,
-                0,
-                0,
-            );
+                &vk::DebugUtilsLabelEXT {
+                    p_label_name: name.as_ptr(),
+                    ..Default::default()
+                },
+            )
         };
     }
 
-    pub fn present(&mut self) {
-        self.in_frame = false;
-
-        if self.resized {
-            self.resized = false;
+    // Dispatches have to happen outside of rendering, so every prepass runs before anything is drawn
+    fn run_prepasses(&self, prepasses: &[(&ComputeShaderData, &PrepassData)]) {
+        if prepasses.is_empty() {
             return;
         }
 
+        let command_buffer = self.command_buffers[self.frame_index];
         unsafe {
-            self.device
-                .cmd_end_rendering(self.command_buffers[self.frame_index])
-        };
-
-        let layout_barrier = vk::ImageMemoryBarrier {
-            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-            old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-            image: self.swapchain_images[self.swapchain_index],
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-            ..Default::default()
-        };
-
-        unsafe {
+            // the storage buffers are shared between frames, so the last frame's vertex shaders
+            // have to be done reading them
             self.device.cmd_pipeline_barrier(
-                self.command_buffers[self.frame_index],
-                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                command_buffer,
+                vk::PipelineStageFlags::VERTEX_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
                 vk::DependencyFlags::empty(),
                 &[],
                 &[],
-                &[layout_barrier],
+                &[],
             );
 
-            vulkan_check!(self
-                .device
-                .end_command_buffer(self.command_buffers[self.frame_index]));
-        };
-
-        let wait_stage = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
-        let submit_info = vk::SubmitInfo {
-            p_wait_dst_stage_mask: ptr::addr_of!(wait_stage),
-            wait_semaphore_count: 1,
-            p_wait_semaphores: ptr::addr_of!(self.acquire_semaphores[self.frame_index]),
-            signal_semaphore_count: 1,
-            p_signal_semaphores: ptr::addr_of!(self.render_complete_semaphores[self.frame_index]),
-            command_buffer_count: 1,
-            p_command_buffers: ptr::addr_of!(self.command_buffers[self.frame_index]),
-            ..Default::default()
-        };
+            for (shader, prepass) in prepasses {
+                self.device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    shader.pipeline,
+                );
+                self.device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    shader.pipeline_layout,
+                    0,
+                    &[
+                        self.descriptor_sets[self.frame_index],
+                        prepass.descriptor_set,
+                    ],
+                    &[0],
+                );
+                let (x, y, z) = prepass.group_count;
+                self.device.cmd_dispatch(command_buffer, x, y, z);
+            }
 
-        unsafe {
-            vulkan_check!(self.device.queue_submit(
-                self.compute_queue,
-                &[submit_info],
-                self.fences[self.frame_index]
-            ))
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::VERTEX_SHADER,
+                vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier {
+                    src_access_mask: vk::AccessFlags::SHADER_WRITE,
+                    dst_access_mask: vk::AccessFlags::SHADER_READ,
+                    ..Default::default()
+                }],
+                &[],
+                &[],
+            );
         }
+    }
 
-        let index = self.swapchain_index as u32;
-        let present_info = vk::PresentInfoKHR {
-            p_swapchains: ptr::addr_of!(self.swapchain),
-            swapchain_count: 1,
-            p_wait_semaphores: ptr::addr_of!(self.render_complete_semaphores[self.frame_index]),
-            wait_semaphore_count: 1,
-            p_image_indices: ptr::addr_of!(index),
-            ..Default::default()
-        };
+    // Binding 0 is the model's vertices, binding 1 is the default colour for models without one.
+    // Position only shaders with separate positions only read binding 0, which is just positions.
+    fn vertex_input(
+        format: rendersystem::VertexFormat,
+        position_only: bool,
+    ) -> (
+        Vec<vk::VertexInputBindingDescription>,
+        Vec<vk::VertexInputAttributeDescription>,
+    ) {
+        let float_size = mem::size_of::<f32>() as u32;
+        if position_only && format.layout == rendersystem::VertexLayout::SeparatePosition {
+            return (
+                vec![vk::VertexInputBindingDescription {
+                    binding: 0,
+                    stride: rendersystem::VertexFormat::POSITION_SIZE as u32,
+                    input_rate: vk::VertexInputRate::VERTEX,
+                }],
+                vec![vk::VertexInputAttributeDescription {
+                    location: 0,
+                    binding: 0,
+                    format: vk::Format::R32G32B32_SFLOAT,
+                    offset: 0,
+                }],
+            );
+        }
+        let (mut bindings, mut attributes) = Self::descriptor_vertex_input(&format.descriptor());
+        if !format.color {
+            // per instance, so every vertex reads the same white
+            bindings.push(vk::VertexInputBindingDescription {
+                binding: 1,
+                stride: float_size * 4,
+                input_rate: vk::VertexInputRate::INSTANCE,
+            });
+            attributes.push(vk::VertexInputAttributeDescription {
+                location: 4,
+                binding: 1,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: 0,
+            });
+        }
 
-        match unsafe {
-            self.swapchain_loader
-                .queue_present(self.compute_queue, &present_info)
-        } {
-            Ok(_) => {}
-            Err(err) if err == vk::Result::ERROR_OUT_OF_DATE_KHR => {}
-            Err(err) => {
-                panic!(
-                    "Failed to present frame {} (swapchain image {}): {err}",
-                    self.frame_index, self.swapchain_index
-                )
+        // the position gets binding 0 to itself, and the rest moves to 2 without it
+        if format.layout == rendersystem::VertexLayout::SeparatePosition {
+            let position_size = rendersystem::VertexFormat::POSITION_SIZE as u32;
+            bindings[0].stride = position_size;
+            bindings.push(vk::VertexInputBindingDescription {
+                binding: 2,
+                stride: format.stride() as u32 - position_size,
+                input_rate: vk::VertexInputRate::VERTEX,
+            });
+            for attribute in attributes
+                .iter_mut()
+                .filter(|attribute| attribute.binding == 0 && attribute.location != 0)
+            {
+                attribute.binding = 2;
+                attribute.offset -= position_size;
             }
         }
 
-        self.frame_index = (self.frame_index + 1) % FRAME_COUNT;
+        (bindings, attributes)
     }
 
-    pub fn unload_resources(&mut self) {
-        self.model_buffer.take().unwrap().destroy(&self.allocator);
+    // Everything in binding 0
+    fn descriptor_vertex_input(
+        descriptor: &rendersystem::VertexDescriptor,
+    ) -> (
+        Vec<vk::VertexInputBindingDescription>,
+        Vec<vk::VertexInputAttributeDescription>,
+    ) {
+        let bindings = vec![vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: descriptor.stride,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+        let attributes = descriptor
+            .attributes
+            .iter()
+            .map(|attribute| vk::VertexInputAttributeDescription {
+                location: attribute.location,
+                binding: 0,
+                format: Self::attribute_format(attribute.format),
+                offset: attribute.offset,
+            })
+            .collect();
+
+        (bindings, attributes)
     }
 
-    pub fn shutdown(mut self) {
-        debug!("Vulkan shutdown started");
+    fn model_vertex_input(
+        model: &rendersystem::Model,
+        position_only: bool,
+    ) -> (
+        Vec<vk::VertexInputBindingDescription>,
+        Vec<vk::VertexInputAttributeDescription>,
+    ) {
+        match &model.vertex_descriptor {
+            Some(descriptor) => Self::descriptor_vertex_input(descriptor),
+            None => Self::vertex_input(model.vertex_format, position_only),
+        }
+    }
 
-        self.loaded = false;
-        self.initialized = false;
+    fn attribute_format(format: rendersystem::AttributeFormat) -> vk::Format {
+        match format {
+            rendersystem::AttributeFormat::Float => vk::Format::R32_SFLOAT,
+            rendersystem::AttributeFormat::Float2 => vk::Format::R32G32_SFLOAT,
+            rendersystem::AttributeFormat::Float3 => vk::Format::R32G32B32_SFLOAT,
+            rendersystem::AttributeFormat::Float4 => vk::Format::R32G32B32A32_SFLOAT,
+            rendersystem::AttributeFormat::Int => vk::Format::R32_SINT,
+            rendersystem::AttributeFormat::Int2 => vk::Format::R32G32_SINT,
+            rendersystem::AttributeFormat::Int3 => vk::Format::R32G32B32_SINT,
+            rendersystem::AttributeFormat::Int4 => vk::Format::R32G32B32A32_SINT,
+            rendersystem::AttributeFormat::Uint => vk::Format::R32_UINT,
+            rendersystem::AttributeFormat::Uint2 => vk::Format::R32G32_UINT,
+            rendersystem::AttributeFormat::Uint3 => vk::Format::R32G32B32_UINT,
+            rendersystem::AttributeFormat::Uint4 => vk::Format::R32G32B32A32_UINT,
+            rendersystem::AttributeFormat::Unorm8x4 => vk::Format::R8G8B8A8_UNORM,
+            rendersystem::AttributeFormat::Snorm8x4 => vk::Format::R8G8B8A8_SNORM,
+            rendersystem::AttributeFormat::Unorm16x2 => vk::Format::R16G16_UNORM,
+            rendersystem::AttributeFormat::Snorm16x2 => vk::Format::R16G16_SNORM,
+        }
+    }
 
-        debug!("Waiting for device idle");
-        unsafe { vulkan_check!(self.device.device_wait_idle()) };
+    // Pipelines bake in everything but the viewport and scissor, shader objects need all of it set
+    fn set_dynamic_state(&self) {
+        let command_buffer = self.command_buffers[self.frame_index];
+        let viewport = self.viewport();
+        let scissor = self.scissor();
 
-        unsafe {
-            debug!("Freeing {FRAME_COUNT} uniform buffers");
-            for _ in 0..self.uniform_buffers.len() {
-                self.uniform_buffers.remove(0).destroy(&self.allocator)
+        if !self.uses_shader_objects() {
+            unsafe {
+                self.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+                self.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
             }
+            self.apply_depth_bias();
+            self.apply_stencil();
+            return;
+        }
 
-            debug!("Destroying descriptor pool {:#?}", self.descriptor_pool);
-            self.device.destroy_descriptor_pool(
-                self.descriptor_pool,
-                Some(&State::get_allocation_callbacks()),
+        let loader = &self.shader_object_loader;
+        unsafe {
+            loader.cmd_set_viewport_with_count(command_buffer, &[viewport]);
+            loader.cmd_set_scissor_with_count(command_buffer, &[scissor]);
+            loader.cmd_set_rasterizer_discard_enable(command_buffer, false);
+            loader.cmd_set_polygon_mode(command_buffer, vk::PolygonMode::FILL);
+            loader.cmd_set_cull_mode(command_buffer, vk::CullModeFlags::NONE);
+            loader.cmd_set_front_face(command_buffer, vk::FrontFace::COUNTER_CLOCKWISE);
+            loader.cmd_set_depth_test_enable(command_buffer, true);
+            loader.cmd_set_depth_compare_op(command_buffer, vk::CompareOp::LESS);
+            loader.cmd_set_depth_bounds_test_enable(command_buffer, false);
+            loader.cmd_set_depth_bias_enable(command_buffer, true);
+            loader.cmd_set_rasterization_samples(command_buffer, vk::SampleCountFlags::TYPE_1);
+            loader.cmd_set_sample_mask(command_buffer, vk::SampleCountFlags::TYPE_1, &[u32::MAX]);
+            loader.cmd_set_alpha_to_coverage_enable(command_buffer, false);
+            let attachment_count = self.target_formats.as_ref().map_or(1, Vec::len);
+            loader.cmd_set_color_blend_enable(
+                command_buffer,
+                0,
+                &vec![vk::FALSE; attachment_count],
+            );
+            loader.cmd_set_color_write_mask(
+                command_buffer,
+                0,
+                &vec![vk::ColorComponentFlags::RGBA; attachment_count],
             );
+        }
+        self.apply_depth_bias();
+        self.apply_stencil();
+    }
 
-            debug!(
-                "Destroying descriptor set layout {:#?}",
-                self.descriptor_layout
+    // Vertex input is per model, since models can have different vertex formats
+    fn set_vertex_input(&self, model: &rendersystem::Model, position_only: bool) {
+        let (bindings, attributes) = Self::model_vertex_input(model, position_only);
+        let bindings: Vec<vk::VertexInputBindingDescription2EXT> = bindings
+            .iter()
+            .map(|binding| vk::VertexInputBindingDescription2EXT {
+                binding: binding.binding,
+                stride: binding.stride,
+                input_rate: binding.input_rate,
+                divisor: 1,
+                ..Default::default()
+            })
+            .collect();
+        let attributes: Vec<vk::VertexInputAttributeDescription2EXT> = attributes
+            .iter()
+            .map(|attribute| vk::VertexInputAttributeDescription2EXT {
+                location: attribute.location,
+                binding: attribute.binding,
+                format: attribute.format,
+                offset: attribute.offset,
+                ..Default::default()
+            })
+            .collect();
+
+        unsafe {
+            self.shader_object_loader.cmd_set_vertex_input(
+                self.command_buffers[self.frame_index],
+                &bindings,
+                &attributes,
+            )
+        };
+    }
+
+    // The scaled down render target when rendering below native resolution, otherwise the
+    // swapchain image directly
+    fn color_target(&self) -> (vk::Image, vk::ImageView) {
+        if self.render_scale < 1.0 {
+            (
+                *self.swapchain.color_image.handle(),
+                *self.swapchain.color_image.view(),
+            )
+        } else {
+            (
+                self.swapchain.images[self.swapchain_index],
+                self.swapchain.views[self.swapchain_index],
+            )
+        }
+    }
+
+    // Leaves the swapchain image as a colour attachment, like it is when it's rendered to directly
+    fn blit_to_swapchain(&self) {
+        let command_buffer = self.command_buffers[self.frame_index];
+        let swapchain_image = self.swapchain.images[self.swapchain_index];
+
+        let transfer_barriers = [
+            vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image: *self.swapchain.color_image.handle(),
+                subresource_range: COLOR_SUBRESOURCE_RANGE,
+                ..Default::default()
+            },
+            vk::ImageMemoryBarrier {
+                dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                image: swapchain_image,
+                subresource_range: COLOR_SUBRESOURCE_RANGE,
+                ..Default::default()
+            },
+        ];
+        let attachment_barrier = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            image: swapchain_image,
+            subresource_range: COLOR_SUBRESOURCE_RANGE,
+            ..Default::default()
+        };
+
+        let layers = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let blit = vk::ImageBlit {
+            src_subresource: layers,
+            src_offsets: [
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: self.swapchain.render_extent.width as i32,
+                    y: self.swapchain.render_extent.height as i32,
+                    z: 1,
+                },
+            ],
+            dst_subresource: layers,
+            dst_offsets: [
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: self.swapchain.extent.width as i32,
+                    y: self.swapchain.extent.height as i32,
+                    z: 1,
+                },
+            ],
+        };
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &transfer_barriers,
             );
-            self.device.destroy_descriptor_set_layout(
-                self.descriptor_layout,
-                Some(&State::get_allocation_callbacks()),
+            self.device.cmd_blit_image(
+                command_buffer,
+                *self.swapchain.color_image.handle(),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                swapchain_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[attachment_barrier],
             );
+        }
+    }
 
-            self.destroy_render_targets();
-            self.destroy_swapchain();
-            debug!("Destroying {} semaphores", FRAME_COUNT * 2);
-            self.acquire_semaphores.iter().for_each(|semaphore| {
-                self.device
-                    .destroy_semaphore(*semaphore, Some(&State::get_allocation_callbacks()))
-            });
-            self.render_complete_semaphores
-                .iter()
-                .for_each(|semaphore| {
-                    self.device
-                        .destroy_semaphore(*semaphore, Some(&State::get_allocation_callbacks()))
-                });
+    // The GUI goes on the swapchain image after the scene is scaled up to it, so it's always at
+    // the window's resolution. There's no depth, it's on top of everything anyway.
+    #[cfg(feature = "egui")]
+    fn begin_gui_rendering(&mut self) {
+        if self.in_gui_pass {
+            return;
+        }
 
-            debug!("Destroying {FRAME_COUNT} fences");
-            self.fences.iter().for_each(|fence| {
-                self.device
-                    .destroy_fence(*fence, Some(&State::get_allocation_callbacks()))
-            });
-            self.acquire_semaphores.iter().for_each(|semaphore| {
-                self.device
-                    .destroy_semaphore(*semaphore, Some(&State::get_allocation_callbacks()))
-            });
-            debug!("Destroying transfer command pool {:#?}", self.transfer_pool);
-            self.device
-                .destroy_command_pool(self.transfer_pool, Some(&State::get_allocation_callbacks()));
-            debug!("Destroying command pool {:#?}", self.command_pool);
-            self.device
-                .destroy_command_pool(self.command_pool, Some(&State::get_allocation_callbacks()));
-            debug!("Destroying allocator");
-            ptr::drop_in_place(ptr::addr_of_mut!(self.allocator));
-            debug!("Destroying logical device {:#?}", self.device.handle());
-            self.device
-                .destroy_device(Some(&State::get_allocation_callbacks()));
-            debug!("Destroying surface {:#?}", self.surface);
-            self.surface_loader
-                .destroy_surface(self.surface, Some(&State::get_allocation_callbacks()));
-            debug!("Destroying instance {:#?}", self.instance.handle());
-            self.instance
-                .destroy_instance(Some(&State::get_allocation_callbacks()));
+        let command_buffer = self.command_buffers[self.frame_index];
+        unsafe { self.device.cmd_end_rendering(command_buffer) };
+        if self.render_scale < 1.0 {
+            self.blit_to_swapchain();
+        } else {
+            // the scene was drawn straight to it, the GUI loads that
+            let load_barrier = vk::MemoryBarrier {
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                ..Default::default()
+            };
+            unsafe {
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    vk::DependencyFlags::empty(),
+                    &[load_barrier],
+                    &[],
+                    &[],
+                )
+            };
         }
 
-        debug!("Vulkan shutdown succeeded");
+        let color_attachment = vk::RenderingAttachmentInfo {
+            image_view: self.swapchain.views[self.swapchain_index],
+            image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            load_op: vk::AttachmentLoadOp::LOAD,
+            store_op: vk::AttachmentStoreOp::STORE,
+            ..Default::default()
+        };
+        let rendering_info = vk::RenderingInfo {
+            color_attachment_count: 1,
+            p_color_attachments: ptr::addr_of!(color_attachment),
+            layer_count: 1,
+            render_area: vk::Rect2D {
+                extent: self.swapchain.extent,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        unsafe {
+            self.device
+                .cmd_begin_rendering(command_buffer, &rendering_info)
+        };
+        self.in_gui_pass = true;
     }
 
-    pub fn set_gpu(&mut self, gpu_idx: usize) -> usize {
-        let old_idx = self.gpu;
-        if gpu_idx < self.gpus.len() {
-            self.gpu = gpu_idx;
-            let gpu = &self.gpus[self.gpu];
+    pub fn set_clear_color(&mut self, color: rendersystem::Color) {
+        self.clear_color = color;
+    }
 
-            let name = unsafe {
-                ffi::CStr::from_ptr(gpu.properties.device_name.as_ptr())
-                    .to_str()
-                    .unwrap()
-            };
-            debug!(
-                "Selected {:#?} device {}, {} [{:04x}:{:04x}] with score {}",
-                gpu.properties.device_type,
-                gpu_idx,
-                name,
-                gpu.properties.vendor_id,
-                gpu.properties.device_id,
-                gpu.performance_score
-            );
+    pub fn set_logarithmic_depth(&mut self, enabled: bool) {
+        debug!(
+            "{} logarithmic depth",
+            if enabled { "Enabling" } else { "Disabling" }
+        );
+        self.logarithmic_depth = enabled;
+    }
+
+    pub fn logarithmic_depth(&self) -> bool {
+        self.logarithmic_depth
+    }
+
+    // The grid's pipeline, draw_grid does nothing until this is called
+    pub fn init_grid(
+        &mut self,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<(), rendersystem::ShaderError> {
+        let grid = GridData::new(self, vertex_binary, fragment_binary)?;
+        if let Some(old_grid) = self.grid.replace(grid) {
+            self.wait_idle();
+            old_grid.destroy(self);
         }
+        Ok(())
+    }
 
-        old_idx
+    pub fn is_grid_stale(&self) -> bool {
+        self.grid.as_ref().is_some_and(|grid| grid.is_stale(self))
     }
 
-    pub fn is_initialized(&self) -> bool {
-        self.initialized
+    pub fn recreate_grid_pipeline(
+        &mut self,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<(), rendersystem::ShaderError> {
+        let Some(mut grid) = self.grid.take() else {
+            return Ok(());
+        };
+        let result = grid.recreate_pipeline(self, vertex_binary, fragment_binary);
+        self.grid = Some(grid);
+        result
     }
 
-    pub fn is_loaded(&self) -> bool {
-        self.loaded
+    pub fn set_grid(&mut self, settings: rendersystem::GridSettings) {
+        self.grid_settings = settings;
     }
 
-    pub fn is_in_frame(&self) -> bool {
-        self.in_frame
+    pub fn grid(&self) -> rendersystem::GridSettings {
+        self.grid_settings
     }
-}
 
-pub type ShaderErrorType = vk::Result;
+    // The deferred lighting pass's pipeline, draw_lighting does nothing until this is called
+    pub fn init_lighting(
+        &mut self,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<(), rendersystem::ShaderError> {
+        let mut lighting = LightingData::new(self, vertex_binary, fragment_binary)?;
+        if let Some(old_lighting) = self.lighting.as_mut() {
+            lighting.lights = mem::take(&mut old_lighting.lights);
+        }
+        if let Some(old_lighting) = self.lighting.replace(lighting) {
+            self.wait_idle();
+            old_lighting.destroy(self);
+        }
+        Ok(())
+    }
 
-pub struct ShaderData {
-    vertex_handle: vk::ShaderEXT,
-    fragment_handle: vk::ShaderEXT,
-}
+    pub fn is_lighting_stale(&self) -> bool {
+        self.lighting
+            .as_ref()
+            .is_some_and(|lighting| lighting.is_stale(self))
+    }
 
-impl ShaderData {
-    pub fn new(
-        state: &State,
-        name: &str,
-        vertex_binary: Vec<u8>,
-        fragment_binary: Vec<u8>,
-    ) -> Result<Self, crate::engine::rendersystem::ShaderError> {
-        let vertex_info = vk::ShaderCreateInfoEXT {
-            flags: vk::ShaderCreateFlagsEXT::LINK_STAGE,
-            stage: vk::ShaderStageFlags::VERTEX,
-            next_stage: vk::ShaderStageFlags::FRAGMENT,
-            code_type: vk::ShaderCodeTypeEXT::SPIRV,
-            p_code: vertex_binary.as_ptr() as *const ffi::c_void,
-            code_size: vertex_binary.len(),
-            p_name: b"main\0".as_ptr() as *const i8,
-            p_set_layouts: ptr::addr_of!(state.descriptor_layout),
-            set_layout_count: 1,
-            ..Default::default()
+    pub fn recreate_lighting_pipeline(
+        &mut self,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<(), rendersystem::ShaderError> {
+        let Some(mut lighting) = self.lighting.take() else {
+            return Ok(());
         };
-        let fragment_info = vk::ShaderCreateInfoEXT {
-            flags: vk::ShaderCreateFlagsEXT::LINK_STAGE,
-            stage: vk::ShaderStageFlags::FRAGMENT,
-            code_type: vk::ShaderCodeTypeEXT::SPIRV,
-            p_code: fragment_binary.as_ptr() as *const ffi::c_void,
-            code_size: fragment_binary.len(),
-            p_name: b"main\0".as_ptr() as *const i8,
-            p_set_layouts: ptr::addr_of!(state.descriptor_layout),
-            set_layout_count: 1,
-            ..Default::default()
+        let result = lighting.recreate_pipeline(self, vertex_binary, fragment_binary);
+        self.lighting = Some(lighting);
+        result
+    }
+
+    // They live with the lighting pass, which copies them to the GPU at the start of each frame
+    pub fn set_lights(&mut self, lights: Vec<rendersystem::Light>) {
+        match self.lighting.as_mut() {
+            Some(lighting) => lighting.lights = lights,
+            None => warn!(
+                "Ignoring {} light(s), the lighting pass isn't set up",
+                lights.len()
+            ),
+        }
+    }
+
+    pub fn lights(&self) -> &[rendersystem::Light] {
+        self.lighting
+            .as_ref()
+            .map_or(&[], |lighting| lighting.lights.as_slice())
+    }
+
+    // Writes this frame's lights and points its set at the G-buffer, before anything in the frame
+    // can have bound it. The G-buffer has to be the one draw_lighting gets later.
+    pub fn prepare_lighting(&mut self, gbuffer: Option<&RenderTargetData>) {
+        if !self.in_frame || self.resized {
+            return;
+        }
+        let Some(mut lighting) = self.lighting.take() else {
+            return;
+        };
+        let lights = lighting.write_lights(self);
+        if let Some(gbuffer) = gbuffer {
+            lighting.write_descriptor_set(self, gbuffer, lights);
+        }
+        self.lighting = Some(lighting);
+    }
+
+    pub fn set_ambient_light(&mut self, color: rendersystem::Color) {
+        self.ambient_light = color;
+    }
+
+    pub fn ambient_light(&self) -> rendersystem::Color {
+        self.ambient_light
+    }
+
+    // Values written to UNORM targets are stored as is, so they have to be sRGB already
+    fn encode_color(&self, color: rendersystem::Color) -> [f32; 4] {
+        if self.srgb_target() {
+            color.to_linear()
+        } else {
+            color.to_srgb()
+        }
+    }
+
+    // Whether writes to the colour target get encoded to sRGB by the hardware
+    fn srgb_target(&self) -> bool {
+        matches!(
+            self.swapchain.surface_format.format,
+            vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::A8B8G8R8_SRGB_PACK32
+        )
+    }
+
+    // Shaders have to be recreated afterwards for it to take effect, the rest of the pipelines are
+    // made again in the next begin_cmds since they're stale then
+    pub fn set_sample_shading(&mut self, min_fraction: Option<f32>) {
+        let min_fraction = min_fraction.map(|fraction| fraction.clamp(0.0, 1.0));
+        if min_fraction.is_some() && !self.gpus[self.gpu].sample_rate_shading {
+            warn!("Device {} doesn't support sample rate shading", self.gpu);
+            self.sample_shading = None;
+            return;
+        }
+        if min_fraction.is_some() && self.gpus[self.gpu].shader_object {
+            debug!("Using pipelines instead of shader objects for sample shading");
+        }
+
+        debug!(
+            "Changing sample shading from {:?} to {min_fraction:?}",
+            self.sample_shading
+        );
+        // the old shaders are about to be destroyed
+        unsafe { vulkan_check!(self.device.device_wait_idle()) };
+        self.sample_shading = min_fraction;
+    }
+
+    pub fn sample_shading(&self) -> Option<f32> {
+        self.sample_shading
+    }
+
+    // Shader objects have no state for sample shading (only the shader can turn it on), so it
+    // needs pipelines even where they're supported
+    fn uses_shader_objects(&self) -> bool {
+        self.gpus[self.gpu].shader_object && self.sample_shading.is_none()
+    }
+
+    // Anisotropy needs the feature and gets clamped to the device's limit, anything that ends up
+    // at 1x or less is the same as trilinear
+    fn supported_texture_filtering(
+        gpu: &GpuInfo,
+        filtering: rendersystem::TextureFiltering,
+    ) -> rendersystem::TextureFiltering {
+        let rendersystem::TextureFiltering::Anisotropic(anisotropy) = filtering else {
+            return filtering;
         };
 
-        let (vertex_handle, fragment_handle) = match unsafe {
-            state.shader_object_loader.create_shaders(
-                &[vertex_info, fragment_info],
-                Some(&State::get_allocation_callbacks()),
-            )
-        } {
-            Ok(shaders) => (shaders[0], shaders[1]),
-            Err(err) => {
-                error!("Failed to create Vulkan shader {name}: {err}");
-                return Err(rendersystem::ShaderError::Backend(err));
+        if !gpu.sampler_anisotropy {
+            warn!("Device doesn't support anisotropic filtering, using trilinear");
+            return rendersystem::TextureFiltering::Trilinear;
+        }
+        let max = gpu.properties.limits.max_sampler_anisotropy as u8;
+        if anisotropy > max {
+            warn!("{anisotropy}x anisotropic filtering is more than the device's {max}x, using {max}x");
+        }
+        match anisotropy.min(max) {
+            0 | 1 => rendersystem::TextureFiltering::Trilinear,
+            anisotropy => rendersystem::TextureFiltering::Anisotropic(anisotropy),
+        }
+    }
+
+    fn create_texture_sampler(
+        device: &ash::Device,
+        filtering: rendersystem::TextureFiltering,
+    ) -> vk::Sampler {
+        debug!("Creating texture sampler with {filtering:?} filtering");
+
+        let (filter, mipmap_mode) = match filtering {
+            rendersystem::TextureFiltering::Nearest => {
+                (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST)
+            }
+            rendersystem::TextureFiltering::Bilinear => {
+                (vk::Filter::LINEAR, vk::SamplerMipmapMode::NEAREST)
             }
+            rendersystem::TextureFiltering::Trilinear
+            | rendersystem::TextureFiltering::Anisotropic(_) => {
+                (vk::Filter::LINEAR, vk::SamplerMipmapMode::LINEAR)
+            }
+        };
+        let anisotropy = match filtering {
+            rendersystem::TextureFiltering::Anisotropic(anisotropy) => Some(anisotropy as f32),
+            _ => None,
         };
 
-        Ok(Self {
-            vertex_handle,
-            fragment_handle,
-        })
+        unsafe {
+            vulkan_check!(device.create_sampler(
+                &vk::SamplerCreateInfo {
+                    mag_filter: filter,
+                    min_filter: filter,
+                    mipmap_mode,
+                    address_mode_u: vk::SamplerAddressMode::REPEAT,
+                    address_mode_v: vk::SamplerAddressMode::REPEAT,
+                    address_mode_w: vk::SamplerAddressMode::REPEAT,
+                    anisotropy_enable: anisotropy.is_some().into(),
+                    max_anisotropy: anisotropy.unwrap_or(1.0),
+                    max_lod: vk::LOD_CLAMP_NONE,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        }
     }
 
-    pub fn destroy(&self, backend: &State) {
+    // Replaces the sampler every material texture uses, returns what was actually used after
+    // falling back for the device
+    pub fn set_texture_filtering(
+        &mut self,
+        filtering: rendersystem::TextureFiltering,
+    ) -> rendersystem::TextureFiltering {
+        let filtering = Self::supported_texture_filtering(&self.gpus[self.gpu], filtering);
+        if filtering == self.texture_filtering {
+            return filtering;
+        }
+
+        debug!(
+            "Changing texture filtering from {:?} to {filtering:?}",
+            self.texture_filtering
+        );
+        // frames in flight could still be sampling with the old one
         unsafe {
-            backend
-                .shader_object_loader
-                .destroy_shader(self.vertex_handle, Some(&State::get_allocation_callbacks()));
-            backend.shader_object_loader.destroy_shader(
-                self.fragment_handle,
+            vulkan_check!(self.device.device_wait_idle());
+            self.device.destroy_sampler(
+                self.texture_sampler,
                 Some(&State::get_allocation_callbacks()),
             );
         }
-    }
+        self.texture_filtering = filtering;
+        self.texture_sampler = Self::create_texture_sampler(&self.device, filtering);
+        // the frontend does the same for every other texture
+        self.default_texture.write_descriptor(self);
 
-    pub fn vertex_extension() -> String {
-        String::from(".vert.spv")
+        filtering
     }
 
-    pub fn fragment_extension() -> String {
-        String::from(".frag.spv")
+    pub fn texture_filtering(&self) -> rendersystem::TextureFiltering {
+        self.texture_filtering
+    }
+
+    // 1 is the least input lag, but the CPU and GPU mostly take turns instead of working at the
+    // same time so the frame rate drops. FRAME_COUNT (the default) is the most throughput.
+    pub fn set_max_frame_latency(&mut self, frames: usize) {
+        let frames = frames.clamp(1, FRAME_COUNT);
+        debug!(
+            "Changing max frame latency from {} to {frames}",
+            self.max_frame_latency
+        );
+        self.max_frame_latency = frames;
+    }
+
+    pub fn max_frame_latency(&self) -> usize {
+        self.max_frame_latency
+    }
+
+    // A fence only says the GPU is done with a frame, it can still sit in the presentation
+    // engine's queue behind others for a while after that, so the input it was made with is older
+    // than it looks. Waiting for the present itself means a latency of 1 really is one frame on
+    // screen at a time, and the CPU starts the next one as late as it can. Only works if the
+    // device has VK_KHR_present_wait, otherwise it stays with fences.
+    pub fn set_present_wait(&mut self, enabled: bool) -> bool {
+        if enabled && !self.gpus[self.gpu].present_wait {
+            debug!(
+                "Device {} doesn't support present wait, using fences to limit latency",
+                self.gpu
+            );
+        }
+        self.present_wait = enabled && self.gpus[self.gpu].present_wait;
+        self.present_wait
+    }
+
+    pub fn present_wait(&self) -> bool {
+        self.present_wait
+    }
+
+    // Until the present max_frame_latency - 1 before the last one is on screen
+    fn wait_for_present(&mut self) {
+        let target = (self.swapchain.present_id + 1).saturating_sub(self.max_frame_latency as u64);
+        if target == 0 {
+            return;
+        }
+
+        match unsafe {
+            self.present_wait_loader.wait_for_present(
+                self.swapchain.handle,
+                target,
+                PRESENT_WAIT_TIMEOUT,
+            )
+        } {
+            // a minimized window might never show it, the fences still keep things in check
+            Ok(()) | Err(vk::Result::TIMEOUT) => {}
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.out_of_date = true,
+            Err(vk::Result::ERROR_DEVICE_LOST) => self.lose_device(),
+            Err(err) => warn!("Failed to wait for present {target}: {err}"),
+        }
+    }
+
+    fn frames_in_flight(&self) -> usize {
+        self.fences
+            .iter()
+            .filter(|fence| unsafe { self.device.get_fence_status(**fence) } == Ok(false))
+            .count()
+    }
+
+    // Renders at a fraction of the window size and scales up when presenting, clamped to
+    // 0.25..1.0. Takes effect in the next begin_cmds, which recreates the render targets once the
+    // GPU is idle.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        let scale = scale.clamp(0.25, 1.0);
+        if scale == self.render_scale() {
+            return;
+        }
+
+        debug!(
+            "Changing render scale from {} to {scale}",
+            self.render_scale()
+        );
+        self.pending_render_scale = (scale != self.render_scale).then_some(scale);
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.pending_render_scale.unwrap_or(self.render_scale)
+    }
+
+    // Can be more than FRAME_COUNT, and change when the swapchain is recreated
+    pub fn swapchain_image_count(&self) -> usize {
+        self.swapchain.images.len()
+    }
+
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+
+    // Only means anything between begin_cmds and present
+    pub fn image_index(&self) -> usize {
+        self.swapchain_index
+    }
+
+    // Not every driver says the swap chain is out of date when the window changes size, so the
+    // next begin_cmds recreates it at the new size either way
+    pub fn window_resized(&mut self) {
+        self.out_of_date = true;
+    }
+
+    // False until the window has a real size, see surface_extent
+    pub fn has_valid_size(&self) -> bool {
+        !self.swapchain.degenerate
+    }
+
+    pub fn render_size(&self) -> (u32, u32) {
+        (
+            self.swapchain.render_extent.width,
+            self.swapchain.render_extent.height,
+        )
+    }
+
+    fn intersect(a: vk::Rect2D, b: vk::Rect2D) -> vk::Rect2D {
+        let min_x = a.offset.x.max(b.offset.x);
+        let min_y = a.offset.y.max(b.offset.y);
+        let max_x = (a.offset.x as i64 + a.extent.width as i64)
+            .min(b.offset.x as i64 + b.extent.width as i64);
+        let max_y = (a.offset.y as i64 + a.extent.height as i64)
+            .min(b.offset.y as i64 + b.extent.height as i64);
+        vk::Rect2D {
+            offset: vk::Offset2D { x: min_x, y: min_y },
+            extent: vk::Extent2D {
+                width: (max_x - min_x as i64).max(0) as u32,
+                height: (max_y - min_y as i64).max(0) as u32,
+            },
+        }
+    }
+
+    fn viewport_area(&self) -> vk::Rect2D {
+        self.viewport.unwrap_or(vk::Rect2D {
+            extent: self.swapchain.render_extent,
+            ..Default::default()
+        })
+    }
+
+    fn viewport(&self) -> vk::Viewport {
+        let area = self.viewport_area();
+        vk::Viewport {
+            x: area.offset.x as f32,
+            y: area.offset.y as f32,
+            width: area.extent.width as f32,
+            height: area.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
+    }
+
+    fn scissor(&self) -> vk::Rect2D {
+        let area = self.viewport_area();
+        Self::intersect(self.scissors.last().copied().unwrap_or(area), area)
+    }
+
+    fn apply_scissor(&self) {
+        let command_buffer = self.command_buffers[self.frame_index];
+        let scissor = self.scissor();
+        unsafe {
+            if self.uses_shader_objects() {
+                self.shader_object_loader
+                    .cmd_set_scissor_with_count(command_buffer, &[scissor]);
+            } else {
+                self.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+            }
+        }
+    }
+
+    // In window pixels, gets scaled to the render size. Clips to whatever was pushed before it,
+    // and the stack starts out empty every frame.
+    pub fn push_scissor(&mut self, x: i32, y: i32, width: u32, height: u32) {
+        if !self.in_frame {
+            return;
+        }
+
+        let scale = self.render_scale as f64;
+        let current = self.scissor();
+        let min_x = ((x as f64 * scale) as i64).max(current.offset.x as i64);
+        let min_y = ((y as f64 * scale) as i64).max(current.offset.y as i64);
+        let max_x = (((x as i64 + width as i64) as f64 * scale) as i64)
+            .min(current.offset.x as i64 + current.extent.width as i64);
+        let max_y = (((y as i64 + height as i64) as f64 * scale) as i64)
+            .min(current.offset.y as i64 + current.extent.height as i64);
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D {
+                x: min_x as i32,
+                y: min_y as i32,
+            },
+            extent: vk::Extent2D {
+                width: (max_x - min_x).max(0) as u32,
+                height: (max_y - min_y).max(0) as u32,
+            },
+        };
+        trace!("Pushing scissor {scissor:?}");
+
+        self.scissors.push(scissor);
+        self.apply_scissor();
+    }
+
+    // Goes in the frame's first uniform slot, which viewports and targets don't use. Draws read
+    // it when the GPU runs them, so changing it in the middle of a frame changes it for the
+    // draws before too.
+    pub fn update_uniforms(&mut self, data: rendersystem::UniformData) {
+        self.window_uniforms = Some(data);
+        if self.in_frame {
+            self.write_window_uniforms();
+        }
+    }
+
+    fn write_window_uniforms(&self) {
+        if let Some(data) = &self.window_uniforms {
+            unsafe { self.uniform_buffers[self.frame_index].read(data.bytes(), 0) };
+        }
+    }
+
+    // Draws after this go to part of the render target, seen from the camera. Each one gets its
+    // own slot in the frame's uniform buffer, so there can only be MAX_VIEWPORTS - 1 per frame.
+    pub fn begin_viewport(
+        &mut self,
+        camera: &rendersystem::Camera,
+        rect: rendersystem::ViewportRect,
+    ) {
+        if !self.in_frame {
+            return;
+        }
+        if self.viewport_count + 1 >= MAX_VIEWPORTS {
+            warn!(
+                "Only {} viewports can be drawn per frame",
+                MAX_VIEWPORTS - 1
+            );
+            return;
+        }
+        self.viewport_count += 1;
+
+        let width = self.swapchain.render_extent.width as f32;
+        let height = self.swapchain.render_extent.height as f32;
+        let min_x = (rect.x.clamp(0.0, 1.0) * width).round();
+        let min_y = (rect.y.clamp(0.0, 1.0) * height).round();
+        let max_x = ((rect.x + rect.width).clamp(0.0, 1.0) * width).round();
+        let max_y = ((rect.y + rect.height).clamp(0.0, 1.0) * height).round();
+        let area = vk::Rect2D {
+            offset: vk::Offset2D {
+                x: min_x as i32,
+                y: min_y as i32,
+            },
+            extent: vk::Extent2D {
+                width: (max_x - min_x).max(1.0) as u32,
+                height: (max_y - min_y).max(1.0) as u32,
+            },
+        };
+        trace!("Beginning viewport {} at {area:?}", self.viewport_count);
+
+        let mut data = camera.uniform_data(area.extent.width as f32 / area.extent.height as f32);
+        if self.logarithmic_depth {
+            data.set_logarithmic_depth(camera.far);
+        }
+        let offset = self.viewport_count as u64 * Self::uniform_stride(&self.gpus[self.gpu]);
+        unsafe { self.uniform_buffers[self.frame_index].read(data.bytes(), offset) };
+        self.uniform_offset = offset as u32;
+        self.viewport = Some(area);
+        self.apply_viewport();
+    }
+
+    pub fn end_viewport(&mut self) {
+        if !self.in_frame {
+            return;
+        }
+
+        self.viewport = None;
+        self.uniform_offset = 0;
+        self.apply_viewport();
+    }
+
+    // Draws after this go to the render target instead of the window, seen from the camera. The
+    // window's rendering is ended and picks up where it left off in end_target, so it can't be
+    // done inside a viewport. Takes a viewport's uniform slot.
+    pub fn begin_target(&mut self, target: &RenderTargetData, camera: &rendersystem::Camera) {
+        if !self.in_frame {
+            return;
+        }
+        if self.target_formats.is_some() || self.viewport.is_some() {
+            warn!("Can't draw to a render target inside a viewport or another render target");
+            return;
+        }
+        if target.is_stale(self) {
+            warn!("Render target is the wrong size for this frame, not drawing to it");
+            return;
+        }
+        if self.viewport_count + 1 >= MAX_VIEWPORTS {
+            warn!(
+                "Only {} viewports and render targets can be drawn per frame",
+                MAX_VIEWPORTS - 1
+            );
+            return;
+        }
+        self.viewport_count += 1;
+        trace!(
+            "Beginning render target with {} attachment(s)",
+            target.formats.len()
+        );
+
+        let command_buffer = self.command_buffers[self.frame_index];
+        let mut barriers: Vec<vk::ImageMemoryBarrier> = target
+            .color_images
+            .iter()
+            .map(|image| vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                image: *image.handle(),
+                subresource_range: COLOR_SUBRESOURCE_RANGE,
+                ..Default::default()
+            })
+            .collect();
+        barriers.push(vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            image: *target.depth_image.handle(),
+            subresource_range: DEPTH_SUBRESOURCE_RANGE,
+            ..Default::default()
+        });
+        let color_attachments: Vec<vk::RenderingAttachmentInfo> = target
+            .color_images
+            .iter()
+            .map(|image| vk::RenderingAttachmentInfo {
+                image_view: *image.view(),
+                image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::STORE,
+                ..Default::default()
+            })
+            .collect();
+        let depth_attachment = vk::RenderingAttachmentInfo {
+            image_view: *target.depth_image.view(),
+            image_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+            ..Default::default()
+        };
+        let rendering_info = vk::RenderingInfo {
+            color_attachment_count: color_attachments.len() as u32,
+            p_color_attachments: color_attachments.as_ptr(),
+            p_depth_attachment: ptr::addr_of!(depth_attachment),
+            p_stencil_attachment: ptr::addr_of!(depth_attachment),
+            layer_count: 1,
+            render_area: vk::Rect2D {
+                extent: target.extent,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        unsafe {
+            self.device.cmd_end_rendering(command_buffer);
+            // the last frame could still be drawing to or reading from them
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+            self.device
+                .cmd_begin_rendering(command_buffer, &rendering_info);
+        }
+
+        let mut data =
+            camera.uniform_data(target.extent.width as f32 / target.extent.height as f32);
+        if self.logarithmic_depth {
+            data.set_logarithmic_depth(camera.far);
+        }
+        let offset = self.viewport_count as u64 * Self::uniform_stride(&self.gpus[self.gpu]);
+        unsafe { self.uniform_buffers[self.frame_index].read(data.bytes(), offset) };
+        self.uniform_offset = offset as u32;
+        self.viewport = Some(vk::Rect2D {
+            extent: target.extent,
+            ..Default::default()
+        });
+        self.window_scissors = mem::take(&mut self.scissors);
+        self.target_formats = Some(target.formats.clone());
+        self.last_shader = None;
+        self.set_dynamic_state();
+    }
+
+    // Leaves the target's colour images ready to be sampled and goes back to the window
+    pub fn end_target(&mut self, target: &RenderTargetData) {
+        if !self.in_frame || self.target_formats.is_none() {
+            return;
+        }
+
+        let command_buffer = self.command_buffers[self.frame_index];
+        let barriers: Vec<vk::ImageMemoryBarrier> = target
+            .color_images
+            .iter()
+            .map(|image| vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                image: *image.handle(),
+                subresource_range: COLOR_SUBRESOURCE_RANGE,
+                ..Default::default()
+            })
+            .collect();
+        // what was drawn to the window before has to be written before it's loaded again
+        let window_barrier = vk::MemoryBarrier {
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ..Default::default()
+        };
+        unsafe {
+            self.device.cmd_end_rendering(command_buffer);
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[window_barrier],
+                &[],
+                &barriers,
+            );
+        }
+        self.begin_window_rendering(vk::AttachmentLoadOp::LOAD);
+        trace!("Ended render target");
+
+        self.viewport = None;
+        self.uniform_offset = 0;
+        self.scissors = mem::take(&mut self.window_scissors);
+        self.target_formats = None;
+        self.last_shader = None;
+        self.set_dynamic_state();
+    }
+
+    fn apply_viewport(&self) {
+        let command_buffer = self.command_buffers[self.frame_index];
+        let viewport = self.viewport();
+        unsafe {
+            if self.uses_shader_objects() {
+                self.shader_object_loader
+                    .cmd_set_viewport_with_count(command_buffer, &[viewport]);
+            } else {
+                self.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            }
+        }
+        self.apply_scissor();
+    }
+
+    // For everything drawn after it until it's changed again or the frame ends, so shadow casters
+    // and decals can be pushed back or forward without their own pipelines
+    pub fn set_depth_bias(&mut self, constant: f32, slope: f32, clamp: f32) {
+        if !self.in_frame {
+            return;
+        }
+
+        let clamp = if clamp != 0.0 && !self.gpus[self.gpu].depth_bias_clamp {
+            warn!("Device {} doesn't support depth bias clamping", self.gpu);
+            0.0
+        } else {
+            clamp
+        };
+        self.depth_bias = (constant, slope, clamp);
+        self.apply_depth_bias();
+    }
+
+    // Like the depth bias, for everything drawn after it. None turns the stencil test off.
+    pub fn set_stencil(&mut self, stencil: Option<rendersystem::StencilState>, depth_write: bool) {
+        if !self.in_frame {
+            return;
+        }
+
+        self.stencil = stencil;
+        self.depth_write = depth_write;
+        self.apply_stencil();
+    }
+
+    // Only where the scissor lets it, so other viewports keep theirs
+    pub fn clear_stencil(&mut self) {
+        if !self.in_frame || self.resized {
+            return;
+        }
+
+        unsafe {
+            self.device.cmd_clear_attachments(
+                self.command_buffers[self.frame_index],
+                &[vk::ClearAttachment {
+                    aspect_mask: vk::ImageAspectFlags::STENCIL,
+                    clear_value: vk::ClearValue {
+                        depth_stencil: vk::ClearDepthStencilValue {
+                            depth: 1.0,
+                            stencil: 0,
+                        },
+                    },
+                    ..Default::default()
+                }],
+                &[vk::ClearRect {
+                    rect: self.scissor(),
+                    base_array_layer: 0,
+                    layer_count: 1,
+                }],
+            )
+        };
+    }
+
+    fn apply_stencil(&self) {
+        let command_buffer = self.command_buffers[self.frame_index];
+        let stencil = self.stencil.unwrap_or_default();
+        unsafe {
+            self.device
+                .cmd_set_depth_write_enable(command_buffer, self.depth_write);
+            self.device
+                .cmd_set_stencil_test_enable(command_buffer, self.stencil.is_some());
+            self.device.cmd_set_stencil_op(
+                command_buffer,
+                vk::StencilFaceFlags::FRONT_AND_BACK,
+                Self::stencil_op(stencil.fail),
+                Self::stencil_op(stencil.pass),
+                Self::stencil_op(stencil.depth_fail),
+                Self::compare_op(stencil.compare),
+            );
+            self.device.cmd_set_stencil_compare_mask(
+                command_buffer,
+                vk::StencilFaceFlags::FRONT_AND_BACK,
+                stencil.compare_mask,
+            );
+            self.device.cmd_set_stencil_write_mask(
+                command_buffer,
+                vk::StencilFaceFlags::FRONT_AND_BACK,
+                stencil.write_mask,
+            );
+            self.device.cmd_set_stencil_reference(
+                command_buffer,
+                vk::StencilFaceFlags::FRONT_AND_BACK,
+                stencil.reference,
+            );
+        }
+    }
+
+    fn compare_op(compare: rendersystem::CompareOp) -> vk::CompareOp {
+        match compare {
+            rendersystem::CompareOp::Never => vk::CompareOp::NEVER,
+            rendersystem::CompareOp::Less => vk::CompareOp::LESS,
+            rendersystem::CompareOp::Equal => vk::CompareOp::EQUAL,
+            rendersystem::CompareOp::LessOrEqual => vk::CompareOp::LESS_OR_EQUAL,
+            rendersystem::CompareOp::Greater => vk::CompareOp::GREATER,
+            rendersystem::CompareOp::NotEqual => vk::CompareOp::NOT_EQUAL,
+            rendersystem::CompareOp::GreaterOrEqual => vk::CompareOp::GREATER_OR_EQUAL,
+            rendersystem::CompareOp::Always => vk::CompareOp::ALWAYS,
+        }
+    }
+
+    fn stencil_op(op: rendersystem::StencilOp) -> vk::StencilOp {
+        match op {
+            rendersystem::StencilOp::Keep => vk::StencilOp::KEEP,
+            rendersystem::StencilOp::Zero => vk::StencilOp::ZERO,
+            rendersystem::StencilOp::Replace => vk::StencilOp::REPLACE,
+            rendersystem::StencilOp::IncrementAndClamp => vk::StencilOp::INCREMENT_AND_CLAMP,
+            rendersystem::StencilOp::DecrementAndClamp => vk::StencilOp::DECREMENT_AND_CLAMP,
+            rendersystem::StencilOp::Invert => vk::StencilOp::INVERT,
+            rendersystem::StencilOp::IncrementAndWrap => vk::StencilOp::INCREMENT_AND_WRAP,
+            rendersystem::StencilOp::DecrementAndWrap => vk::StencilOp::DECREMENT_AND_WRAP,
+        }
+    }
+
+    fn apply_depth_bias(&self) {
+        let (constant, slope, clamp) = self.depth_bias;
+        unsafe {
+            self.device.cmd_set_depth_bias(
+                self.command_buffers[self.frame_index],
+                constant,
+                clamp,
+                slope,
+            )
+        };
+    }
+
+    pub fn pop_scissor(&mut self) {
+        if !self.in_frame {
+            return;
+        }
+
+        if self.scissors.pop().is_none() {
+            warn!("Popped a scissor with none pushed");
+            return;
+        }
+        self.apply_scissor();
+    }
+
+    pub fn render_model(&mut self, model: &rendersystem::Model) {
+        let material = model.material.get();
+        let shader = material.shader.get();
+        if model.vertex_descriptor != shader.vertex_descriptor {
+            warn!(
+                "Model {} and shader {} have different vertex descriptors, not drawing it",
+                model.name, shader.name
+            );
+            return;
+        }
+        if shader.color_formats != self.target_formats {
+            warn!(
+                "Shader {} isn't made for what's being drawn to, not drawing model {}",
+                shader.name, model.name
+            );
+            return;
+        }
+
+        if self.last_model.is_none() || self.last_model.as_ref().unwrap() != &model.name {
+            unsafe {
+                self.device.cmd_bind_vertex_buffers(
+                    self.command_buffers[self.frame_index],
+                    0,
+                    &[*self.model_buffer.as_ref().unwrap().handle()],
+                    &[model.handle.offset],
+                );
+                // raw vertices have everything in binding 0
+                let builtin_format = model.vertex_descriptor.is_none();
+                if builtin_format && !model.vertex_format.color {
+                    self.device.cmd_bind_vertex_buffers(
+                        self.command_buffers[self.frame_index],
+                        1,
+                        &[*self
+                            .default_color_buffer
+                            .as_ref()
+                            .unwrap()
+                            .buffer()
+                            .handle()],
+                        &[0],
+                    );
+                    self.stats.buffer_binds += 1;
+                }
+                if builtin_format
+                    && model.vertex_format.layout == rendersystem::VertexLayout::SeparatePosition
+                {
+                    let vertex_count =
+                        model.handle.vertices_size as usize / model.vertex_format.stride();
+                    self.device.cmd_bind_vertex_buffers(
+                        self.command_buffers[self.frame_index],
+                        2,
+                        &[*self.model_buffer.as_ref().unwrap().handle()],
+                        &[model.handle.offset
+                            + model.vertex_format.attributes_offset(vertex_count) as u64],
+                    );
+                    self.stats.buffer_binds += 1;
+                }
+                self.device.cmd_bind_index_buffer(
+                    self.command_buffers[self.frame_index],
+                    *self.model_buffer.as_ref().unwrap().handle(),
+                    model.handle.offset + model.handle.vertices_size,
+                    vk::IndexType::UINT32,
+                );
+            }
+            self.stats.buffer_binds += 2;
+            self.last_model = Some(model.name.clone());
+        }
+
+        let command_buffer = self.command_buffers[self.frame_index];
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                shader.handle.pipeline_layout,
+                0,
+                &[self.descriptor_sets[self.frame_index]],
+                &[self.uniform_offset],
+            );
+            self.stats.descriptor_binds += 1;
+            if let Some(prepass) = material
+                .prepass
+                .as_ref()
+                .and_then(|prepass| prepass.handle.as_ref())
+            {
+                self.device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    shader.handle.pipeline_layout,
+                    1,
+                    &[prepass.descriptor_set],
+                    &[],
+                );
+                self.stats.descriptor_binds += 1;
+            }
+            // an atlas region takes the place of the texture
+            let texture = material.texture.as_ref().map(|texture| texture.get());
+            let atlas = material
+                .atlas_region
+                .as_ref()
+                .map(|(atlas, region)| (atlas.get(), region.page()));
+            let texture_set = match &atlas {
+                Some((atlas, page)) => atlas.page_handle(*page),
+                None => texture.as_ref().map(|texture| &texture.handle),
+            }
+            .filter(|handle| handle.is_uploaded())
+            .map_or(self.default_texture.descriptor_set, |handle| {
+                handle.descriptor_set
+            });
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                shader.handle.pipeline_layout,
+                2,
+                &[texture_set],
+                &[],
+            );
+            self.stats.descriptor_binds += 1;
+
+            self.stats.pipeline_binds += 1;
+            if self.uses_shader_objects() {
+                let mut stages = vec![vk::ShaderStageFlags::VERTEX, vk::ShaderStageFlags::FRAGMENT];
+                let mut handles = vec![shader.handle.vertex_handle, shader.handle.fragment_handle];
+                // with the feature enabled these have to be bound too, even if it's to nothing
+                if self.gpus[self.gpu].tessellation_shader {
+                    stages.extend([
+                        vk::ShaderStageFlags::TESSELLATION_CONTROL,
+                        vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+                    ]);
+                    handles.extend([
+                        shader.handle.tessellation_control_handle,
+                        shader.handle.tessellation_evaluation_handle,
+                    ]);
+                }
+                if self.gpus[self.gpu].geometry_shader {
+                    stages.push(vk::ShaderStageFlags::GEOMETRY);
+                    handles.push(shader.handle.geometry_handle);
+                }
+                self.shader_object_loader
+                    .cmd_bind_shaders(command_buffer, &stages, &handles);
+
+                let topology = match (shader.handle.patch_control_points, model.topology) {
+                    (Some(patch_control_points), _) => {
+                        self.shader_object_loader
+                            .cmd_set_patch_control_points(command_buffer, patch_control_points);
+                        self.shader_object_loader
+                            .cmd_set_tessellation_domain_origin(
+                                command_buffer,
+                                vk::TessellationDomainOrigin::UPPER_LEFT,
+                            );
+                        vk::PrimitiveTopology::PATCH_LIST
+                    }
+                    (None, rendersystem::Topology::TriangleList) => {
+                        vk::PrimitiveTopology::TRIANGLE_LIST
+                    }
+                    (None, rendersystem::Topology::TriangleStrip) => {
+                        vk::PrimitiveTopology::TRIANGLE_STRIP
+                    }
+                    (None, rendersystem::Topology::LineList) => vk::PrimitiveTopology::LINE_LIST,
+                    (None, rendersystem::Topology::PointList) => vk::PrimitiveTopology::POINT_LIST,
+                };
+                self.shader_object_loader
+                    .cmd_set_primitive_topology(command_buffer, topology);
+                self.set_vertex_input(model, shader.handle.position_only);
+                // restart isn't allowed for list topologies without another extension
+                self.shader_object_loader.cmd_set_primitive_restart_enable(
+                    command_buffer,
+                    topology == vk::PrimitiveTopology::TRIANGLE_STRIP,
+                );
+            } else {
+                self.device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    shader.handle.pipelines[ShaderData::pipeline_index(
+                        model.topology,
+                        model
+                            .vertex_descriptor
+                            .is_none()
+                            .then_some(model.vertex_format),
+                    )],
+                );
+            }
+
+            self.device.cmd_draw_indexed(
+                self.command_buffers[self.frame_index],
+                model.handle.index_count() as u32,
+                1,
+                0,
+                0,
+                0,
+            );
+        };
+        self.stats.draw_calls += 1;
+        self.stats.triangles_drawn += model.triangle_count();
+    }
+
+    // Gets the command buffer for a pass this frame, already begun. Asking for the same purpose
+    // again gives the same one. They're submitted in the order they were first asked for, before
+    // the main command buffer.
+    pub fn pass_cmds(&mut self, purpose: &str) -> Option<vk::CommandBuffer> {
+        if !self.in_frame {
+            return None;
+        }
+
+        let purposes = &mut self.pass_purposes[self.frame_index];
+        let command_buffers = &mut self.pass_command_buffers[self.frame_index];
+        if let Some(index) = purposes.iter().position(|used| used == purpose) {
+            return Some(command_buffers[index]);
+        }
+
+        let index = purposes.len();
+        if index == command_buffers.len() {
+            trace!(
+                "Allocating pass command buffer {index} for frame {}",
+                self.frame_index
+            );
+            command_buffers.push(unsafe {
+                vulkan_check!(self.device.allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo {
+                        level: vk::CommandBufferLevel::PRIMARY,
+                        command_pool: self.pass_pools[self.frame_index],
+                        command_buffer_count: 1,
+                        ..Default::default()
+                    }
+                ))[0]
+            });
+        }
+        purposes.push(String::from(purpose));
+
+        let command_buffer = command_buffers[index];
+        unsafe {
+            vulkan_check!(self.device.begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo {
+                    flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                    ..Default::default()
+                }
+            ))
+        };
+
+        Some(command_buffer)
+    }
+
+    // The frame's fence has been waited on, so nothing from its pool is still executing
+    fn reset_pass_cmds(&mut self) {
+        if self.pass_command_buffers[self.frame_index].is_empty() {
+            return;
+        }
+
+        unsafe {
+            vulkan_check!(self.device.reset_command_pool(
+                self.pass_pools[self.frame_index],
+                vk::CommandPoolResetFlags::empty()
+            ))
+        };
+        self.pass_purposes[self.frame_index].clear();
+    }
+
+    // Draws the grid in the main view, after the models so they've filled in the depth it's
+    // tested against
+    pub fn draw_grid(&mut self) {
+        if !self.in_frame || self.resized || !self.grid_settings.enabled {
+            return;
+        }
+        let Some(grid) = self.grid.as_ref() else {
+            return;
+        };
+
+        let command_buffer = self.command_buffers[self.frame_index];
+        let settings = self.grid_settings;
+        let mut push_constants = Vec::with_capacity(GridData::PUSH_CONSTANT_SIZE as usize);
+        push_constants.extend(
+            self.encode_color(settings.color)
+                .iter()
+                .flat_map(|value| value.to_ne_bytes()),
+        );
+        push_constants.extend(settings.spacing.to_ne_bytes());
+        push_constants.extend(settings.fade_distance.to_ne_bytes());
+        push_constants.resize(GridData::PUSH_CONSTANT_SIZE as usize, 0);
+
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                grid.pipeline,
+            );
+            self.device
+                .cmd_set_viewport(command_buffer, 0, &[self.viewport()]);
+            self.device
+                .cmd_set_scissor(command_buffer, 0, &[self.scissor()]);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                grid.pipeline_layout,
+                0,
+                &[self.descriptor_sets[self.frame_index]],
+                &[self.uniform_offset],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                grid.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &push_constants,
+            );
+            self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+
+        // whatever was bound before isn't anymore, and shader objects need their state back
+        self.last_shader = None;
+        self.set_dynamic_state();
+    }
+
+    // Draws the frame's text over the main view. The indices are split up by the page their
+    // glyphs are on, each page is a draw of its own.
+    pub fn draw_text(
+        &mut self,
+        text: &mut TextData,
+        vertices: &[rendersystem::text::TextVertex],
+        indices: &[Vec<u32>],
+    ) {
+        if !self.in_frame || self.resized {
+            return;
+        }
+        let index_count: usize = indices.iter().map(Vec::len).sum();
+        if index_count == 0 {
+            return;
+        }
+
+        let vertex_size = mem::size_of::<rendersystem::text::TextVertex>();
+        let index_size = mem::size_of::<u32>();
+        text.reserve_buffers(
+            self,
+            (vertices.len() * vertex_size) as vk::DeviceSize,
+            (index_count * index_size) as vk::DeviceSize,
+        );
+        let vertex_buffer = text.vertex_buffers[self.frame_index].as_ref().unwrap();
+        let index_buffer = text.index_buffers[self.frame_index].as_ref().unwrap();
+        unsafe {
+            vertex_buffer.read(
+                std::slice::from_raw_parts(
+                    vertices.as_ptr() as *const u8,
+                    vertices.len() * vertex_size,
+                ),
+                0,
+            );
+        }
+        let mut index_offset = 0;
+        for page_indices in indices {
+            unsafe {
+                index_buffer.read(
+                    std::slice::from_raw_parts(
+                        page_indices.as_ptr() as *const u8,
+                        page_indices.len() * index_size,
+                    ),
+                    (index_offset * index_size) as vk::DeviceSize,
+                );
+            }
+            index_offset += page_indices.len();
+        }
+
+        let command_buffer = self.command_buffers[self.frame_index];
+        let viewport = self.viewport();
+        let mut push_constants = Vec::with_capacity(TextData::PUSH_CONSTANT_SIZE as usize);
+        push_constants.extend(viewport.width.to_ne_bytes());
+        push_constants.extend(viewport.height.to_ne_bytes());
+        push_constants.extend((self.srgb_target() as u32).to_ne_bytes());
+        push_constants.resize(TextData::PUSH_CONSTANT_SIZE as usize, 0);
+
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                text.pipeline,
+            );
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[*vertex_buffer.buffer().handle()],
+                &[0],
+            );
+            self.device.cmd_bind_index_buffer(
+                command_buffer,
+                *index_buffer.buffer().handle(),
+                0,
+                vk::IndexType::UINT32,
+            );
+            self.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            self.device
+                .cmd_set_scissor(command_buffer, 0, &[self.scissor()]);
+            self.device.cmd_push_constants(
+                command_buffer,
+                text.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &push_constants,
+            );
+        }
+
+        let mut first_index = 0;
+        for (page, page_indices) in text.pages.iter().zip(indices) {
+            if !page_indices.is_empty() {
+                unsafe {
+                    self.device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        text.pipeline_layout,
+                        0,
+                        &[page.descriptor_set],
+                        &[],
+                    );
+                    self.device.cmd_draw_indexed(
+                        command_buffer,
+                        page_indices.len() as u32,
+                        1,
+                        first_index as u32,
+                        0,
+                        0,
+                    );
+                }
+            }
+            first_index += page_indices.len();
+        }
+
+        // whatever was bound before isn't anymore, and shader objects need their state back
+        self.last_shader = None;
+        self.last_model = None;
+        self.set_dynamic_state();
+    }
+
+    // Lights the G-buffer into the current viewport, which has to be drawn from the same camera
+    // it was filled from. The target has to be done (end_target) first.
+    pub fn draw_lighting(&mut self, gbuffer: &RenderTargetData) {
+        if !self.in_frame || self.resized || self.target_formats.is_some() {
+            return;
+        }
+        let Some(mut lighting) = self.lighting.take() else {
+            return;
+        };
+
+        if !lighting.is_prepared(self, gbuffer) {
+            warn!("Not lighting a G-buffer that prepare_lighting wasn't given this frame");
+            self.lighting = Some(lighting);
+            return;
+        }
+
+        let command_buffer = self.command_buffers[self.frame_index];
+        let mut push_constants = Vec::with_capacity(LightingData::PUSH_CONSTANT_SIZE as usize);
+        push_constants.extend(
+            self.ambient_light
+                .to_linear()
+                .iter()
+                .flat_map(|value| value.to_ne_bytes()),
+        );
+        push_constants.extend((lighting.lights.len() as u32).to_ne_bytes());
+        push_constants.extend((self.srgb_target() as u32).to_ne_bytes());
+        push_constants.resize(LightingData::PUSH_CONSTANT_SIZE as usize, 0);
+
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                lighting.pipeline,
+            );
+            self.device
+                .cmd_set_viewport(command_buffer, 0, &[self.viewport()]);
+            self.device
+                .cmd_set_scissor(command_buffer, 0, &[self.scissor()]);
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                lighting.pipeline_layout,
+                0,
+                &[
+                    self.descriptor_sets[self.frame_index],
+                    lighting.descriptor_sets[self.frame_index],
+                ],
+                &[self.uniform_offset],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                lighting.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &push_constants,
+            );
+            self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+        self.lighting = Some(lighting);
+
+        // same as the grid
+        self.last_shader = None;
+        self.set_dynamic_state();
+    }
+
+    // Draws egui's output over everything else, has to be the last thing drawn in the frame since
+    // it ends the scene's rendering, see begin_gui_rendering
+    #[cfg(feature = "egui")]
+    pub fn draw_gui(
+        &mut self,
+        gui: &mut GuiData,
+        primitives: &[egui::ClippedPrimitive],
+        pixels_per_point: f32,
+    ) {
+        if !self.in_frame || self.resized {
+            return;
+        }
+
+        let meshes: Vec<(egui::Rect, &egui::Mesh)> = primitives
+            .iter()
+            .filter_map(|primitive| match &primitive.primitive {
+                egui::epaint::Primitive::Mesh(mesh) => Some((primitive.clip_rect, mesh)),
+                // there's no way to call back into the renderer from egui
+                egui::epaint::Primitive::Callback(_) => None,
+            })
+            .collect();
+        let vertex_count: usize = meshes.iter().map(|(_, mesh)| mesh.vertices.len()).sum();
+        let index_count: usize = meshes.iter().map(|(_, mesh)| mesh.indices.len()).sum();
+        if index_count == 0 {
+            return;
+        }
+
+        let vertex_size = mem::size_of::<egui::epaint::Vertex>();
+        let index_size = mem::size_of::<u32>();
+        gui.reserve_buffers(
+            self,
+            (vertex_count * vertex_size) as vk::DeviceSize,
+            (index_count * index_size) as vk::DeviceSize,
+        );
+        self.begin_gui_rendering();
+        let vertex_buffer = gui.vertex_buffers[self.frame_index].as_ref().unwrap();
+        let index_buffer = gui.index_buffers[self.frame_index].as_ref().unwrap();
+
+        let (mut vertex_offset, mut index_offset) = (0, 0);
+        for (_, mesh) in &meshes {
+            unsafe {
+                vertex_buffer.read(
+                    std::slice::from_raw_parts(
+                        mesh.vertices.as_ptr() as *const u8,
+                        mesh.vertices.len() * vertex_size,
+                    ),
+                    (vertex_offset * vertex_size) as vk::DeviceSize,
+                );
+                index_buffer.read(
+                    std::slice::from_raw_parts(
+                        mesh.indices.as_ptr() as *const u8,
+                        mesh.indices.len() * index_size,
+                    ),
+                    (index_offset * index_size) as vk::DeviceSize,
+                );
+            }
+            vertex_offset += mesh.vertices.len();
+            index_offset += mesh.indices.len();
+        }
+
+        let command_buffer = self.command_buffers[self.frame_index];
+        let (width, height) = (
+            self.swapchain.extent.width as f32,
+            self.swapchain.extent.height as f32,
+        );
+        let mut push_constants = Vec::with_capacity(GuiData::PUSH_CONSTANT_SIZE as usize);
+        push_constants.extend((width / pixels_per_point).to_ne_bytes());
+        push_constants.extend((height / pixels_per_point).to_ne_bytes());
+        push_constants.extend((self.srgb_target() as u32).to_ne_bytes());
+        push_constants.resize(GuiData::PUSH_CONSTANT_SIZE as usize, 0);
+
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                gui.pipeline,
+            );
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[*vertex_buffer.buffer().handle()],
+                &[0],
+            );
+            self.device.cmd_bind_index_buffer(
+                command_buffer,
+                *index_buffer.buffer().handle(),
+                0,
+                vk::IndexType::UINT32,
+            );
+            self.device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width,
+                    height,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            self.device.cmd_push_constants(
+                command_buffer,
+                gui.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &push_constants,
+            );
+        }
+
+        let (mut vertex_offset, mut index_offset) = (0, 0);
+        for (clip_rect, mesh) in &meshes {
+            let first_vertex = vertex_offset;
+            let first_index = index_offset;
+            vertex_offset += mesh.vertices.len();
+            index_offset += mesh.indices.len();
+
+            let texture = match gui.textures.get(&mesh.texture_id) {
+                Some(texture) => texture,
+                None => continue,
+            };
+
+            // clip rectangles are in points and can go off the screen
+            let min_x = (clip_rect.min.x * pixels_per_point)
+                .round()
+                .clamp(0.0, width);
+            let min_y = (clip_rect.min.y * pixels_per_point)
+                .round()
+                .clamp(0.0, height);
+            let max_x = (clip_rect.max.x * pixels_per_point)
+                .round()
+                .clamp(min_x, width);
+            let max_y = (clip_rect.max.y * pixels_per_point)
+                .round()
+                .clamp(min_y, height);
+            if max_x == min_x || max_y == min_y {
+                continue;
+            }
+
+            unsafe {
+                self.device.cmd_set_scissor(
+                    command_buffer,
+                    0,
+                    &[vk::Rect2D {
+                        offset: vk::Offset2D {
+                            x: min_x as i32,
+                            y: min_y as i32,
+                        },
+                        extent: vk::Extent2D {
+                            width: (max_x - min_x) as u32,
+                            height: (max_y - min_y) as u32,
+                        },
+                    }],
+                );
+                self.device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    gui.pipeline_layout,
+                    0,
+                    &[texture.descriptor_set],
+                    &[],
+                );
+                self.device.cmd_draw_indexed(
+                    command_buffer,
+                    mesh.indices.len() as u32,
+                    1,
+                    first_index as u32,
+                    first_vertex as i32,
+                    0,
+                );
+            }
+        }
+
+        // whatever was bound before isn't anymore
+        self.last_shader = None;
+        self.last_model = None;
+    }
+
+    // Nothing was recorded, so the frame index stays put. The stats are for an empty frame.
+    pub fn skip_frame(&mut self) {
+        if self.in_frame {
+            warn!("Skipping a frame that's already begun, it'll be presented with the next one");
+            return;
+        }
+
+        trace!("Skipping frame");
+        self.last_stats = mem::take(&mut self.stats);
+    }
+
+    pub fn present(&mut self) {
+        if !self.in_frame {
+            return;
+        }
+        self.in_frame = false;
+
+        if self.resized {
+            self.resized = false;
+            return;
+        }
+
+        unsafe {
+            self.device
+                .cmd_end_rendering(self.command_buffers[self.frame_index])
+        };
+
+        if self.render_scale < 1.0 && !self.in_gui_pass {
+            self.blit_to_swapchain();
+        }
+        // the swapchain image has the whole frame in it now, either way
+        if self.readback_request.is_some() {
+            let image = self.swapchain.images[self.swapchain_index];
+            let transfer_barrier = vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                subresource_range: COLOR_SUBRESOURCE_RANGE,
+                ..Default::default()
+            };
+            let present_barrier = vk::ImageMemoryBarrier {
+                old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                image,
+                subresource_range: COLOR_SUBRESOURCE_RANGE,
+                ..Default::default()
+            };
+
+            unsafe {
+                self.device.cmd_pipeline_barrier(
+                    self.command_buffers[self.frame_index],
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[transfer_barrier],
+                )
+            };
+            self.record_readback(image);
+            unsafe {
+                self.device.cmd_pipeline_barrier(
+                    self.command_buffers[self.frame_index],
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[present_barrier],
+                )
+            };
+        } else {
+            let layout_barrier = vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                image: self.swapchain.images[self.swapchain_index],
+                subresource_range: COLOR_SUBRESOURCE_RANGE,
+                ..Default::default()
+            };
+
+            unsafe {
+                self.device.cmd_pipeline_barrier(
+                    self.command_buffers[self.frame_index],
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[layout_barrier],
+                )
+            };
+        }
+
+        if self.swapchain.present_transfer.is_some() {
+            let release_barrier = State::present_transfer_barrier(
+                &self.gpus[self.gpu],
+                self.swapchain.images[self.swapchain_index],
+            );
+            unsafe {
+                self.device.cmd_pipeline_barrier(
+                    self.command_buffers[self.frame_index],
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[release_barrier],
+                )
+            };
+        }
+
+        unsafe {
+            self.device.cmd_write_timestamp(
+                self.command_buffers[self.frame_index],
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.timestamp_pool,
+                (self.frame_index * 2 + 1) as u32,
+            );
+            self.timestamps_written[self.frame_index] = true;
+
+            vulkan_check!(self
+                .device
+                .end_command_buffer(self.command_buffers[self.frame_index]));
+        };
+
+        let pass_count = self.pass_purposes[self.frame_index].len();
+        let mut command_buffers =
+            Vec::from(&self.pass_command_buffers[self.frame_index][..pass_count]);
+        for &command_buffer in &command_buffers {
+            unsafe { vulkan_check!(self.device.end_command_buffer(command_buffer)) };
+        }
+        command_buffers.push(self.command_buffers[self.frame_index]);
+
+        let wait_stage = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        let submit_info = vk::SubmitInfo {
+            p_wait_dst_stage_mask: ptr::addr_of!(wait_stage),
+            wait_semaphore_count: 1,
+            p_wait_semaphores: ptr::addr_of!(self.acquire_semaphores[self.frame_index]),
+            signal_semaphore_count: 1,
+            p_signal_semaphores: ptr::addr_of!(
+                self.swapchain.render_complete_semaphores[self.swapchain_index]
+            ),
+            command_buffer_count: command_buffers.len() as u32,
+            p_command_buffers: command_buffers.as_ptr(),
+            ..Default::default()
+        };
+
+        match unsafe {
+            self.device.queue_submit(
+                self.graphics_queue,
+                &[submit_info],
+                self.fences[self.frame_index],
+            )
+        } {
+            Ok(()) => {}
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                self.lose_device();
+                return;
+            }
+            Err(err) => panic!("Failed to submit frame {}: {err}", self.frame_index),
+        }
+        // fences only get signalled from here on, so this is never less than the real number
+        self.last_in_flight = self.frames_in_flight();
+
+        let mut present_wait_semaphore =
+            self.swapchain.render_complete_semaphores[self.swapchain_index];
+        if let Some(present_transfer) = &self.swapchain.present_transfer {
+            let acquire_wait_stage = vk::PipelineStageFlags::ALL_COMMANDS;
+            let acquire_info = vk::SubmitInfo {
+                p_wait_dst_stage_mask: ptr::addr_of!(acquire_wait_stage),
+                wait_semaphore_count: 1,
+                p_wait_semaphores: ptr::addr_of!(present_wait_semaphore),
+                signal_semaphore_count: 1,
+                p_signal_semaphores: ptr::addr_of!(
+                    present_transfer.semaphores[self.swapchain_index]
+                ),
+                command_buffer_count: 1,
+                p_command_buffers: ptr::addr_of!(
+                    present_transfer.command_buffers[self.swapchain_index]
+                ),
+                ..Default::default()
+            };
+
+            match unsafe {
+                self.device
+                    .queue_submit(self.present_queue, &[acquire_info], vk::Fence::null())
+            } {
+                Ok(()) => {}
+                Err(vk::Result::ERROR_DEVICE_LOST) => {
+                    self.lose_device();
+                    return;
+                }
+                Err(err) => panic!(
+                    "Failed to hand swapchain image {} to the present queue: {err}",
+                    self.swapchain_index
+                ),
+            }
+            present_wait_semaphore = present_transfer.semaphores[self.swapchain_index];
+        }
+
+        let index = self.swapchain_index as u32;
+        self.swapchain.present_id += 1;
+        let present_id = vk::PresentIdKHR {
+            swapchain_count: 1,
+            p_present_ids: ptr::addr_of!(self.swapchain.present_id),
+            ..Default::default()
+        };
+        let present_info = vk::PresentInfoKHR {
+            p_next: if self.present_wait {
+                ptr::addr_of!(present_id) as *const ffi::c_void
+            } else {
+                ptr::null()
+            },
+            p_swapchains: ptr::addr_of!(self.swapchain.handle),
+            swapchain_count: 1,
+            p_wait_semaphores: ptr::addr_of!(present_wait_semaphore),
+            wait_semaphore_count: 1,
+            p_image_indices: ptr::addr_of!(index),
+            ..Default::default()
+        };
+
+        match unsafe {
+            self.swapchain_loader
+                .queue_present(self.present_queue, &present_info)
+        } {
+            Ok(suboptimal) => {
+                if suboptimal {
+                    self.note_suboptimal();
+                }
+            }
+            Err(err) if err == vk::Result::ERROR_OUT_OF_DATE_KHR => self.out_of_date = true,
+            Err(vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT) => {
+                self.lose_fullscreen_exclusive()
+            }
+            Err(vk::Result::ERROR_DEVICE_LOST) => self.lose_device(),
+            Err(err) => {
+                panic!(
+                    "Failed to present frame {} (swapchain image {}): {err}",
+                    self.frame_index, self.swapchain_index
+                )
+            }
+        }
+
+        self.last_stats = mem::take(&mut self.stats);
+        self.frame_index = (self.frame_index + 1) % FRAME_COUNT;
+        #[cfg(feature = "graphics_debug")]
+        {
+            self.frame_number += 1;
+        }
+    }
+
+    // Screenshots without stalling. The copy happens at the end of the next frame that's presented
+    // and can be taken with take_readback once the GPU is done with that frame, which is usually
+    // max_frame_latency frames later. By then the game has moved on, so it's an earlier frame
+    // than what's on screen. It's at the render scale, without the upscale.
+    pub fn request_readback(&mut self) -> u64 {
+        if let Some(id) = self.readback_request {
+            return id;
+        }
+
+        let id = self.next_readback;
+        self.next_readback += 1;
+        debug!("Requesting readback {id}");
+        self.readback_request = Some(id);
+        id
+    }
+
+    // None until it's done. Ones that failed or got lost with the device stay None.
+    pub fn take_readback(&mut self, id: u64) -> Option<image::RgbaImage> {
+        let index = self
+            .readbacks
+            .iter()
+            .position(|readback| readback.id == id)?;
+        let readback = &self.readbacks[index];
+        if !readback.ready
+            && unsafe { self.device.get_fence_status(self.fences[readback.slot]) } != Ok(true)
+        {
+            return None;
+        }
+
+        let readback = self.readbacks.remove(index);
+        let size = readback.width as usize * readback.height as usize * 4;
+        let data =
+            unsafe { std::slice::from_raw_parts(readback.buffer.address() as *const u8, size) };
+        let image = Self::readback_to_rgba(readback.format, readback.width, readback.height, data);
+        readback.buffer.destroy(&self.allocator);
+        debug!("Took readback {id}");
+
+        image
+    }
+
+    // The image has to be in TRANSFER_SRC_OPTIMAL already
+    fn record_readback(&mut self, image: vk::Image) {
+        let Some(id) = self.readback_request.take() else {
+            return;
+        };
+
+        let extent = self.swapchain.extent;
+        let buffer = match HostBuffer::new(
+            &self.allocator,
+            extent.width as vk::DeviceSize * extent.height as vk::DeviceSize * 4,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        ) {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                error!("Failed to create buffer for readback {id}: {err}");
+                return;
+            }
+        };
+
+        let command_buffer = self.command_buffers[self.frame_index];
+        let region = vk::BufferImageCopy {
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            ..Default::default()
+        };
+        let host_barrier = vk::BufferMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: *buffer.buffer().handle(),
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
+        };
+        unsafe {
+            self.device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                *buffer.buffer().handle(),
+                &[region],
+            );
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[host_barrier],
+                &[],
+            );
+        }
+
+        self.readbacks.push(Readback {
+            id,
+            buffer,
+            slot: self.frame_index,
+            ready: false,
+            width: extent.width,
+            height: extent.height,
+            format: self.swapchain.surface_format.format,
+        });
+    }
+
+    fn readback_to_rgba(
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Option<image::RgbaImage> {
+        let pixels = data
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                let packed = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                // 10 bits to 8, and 2 bits of alpha to 8
+                let channel = |shift: u32| ((packed >> shift) & 0x3ff) as u8 >> 2;
+                let alpha = (packed >> 30) as u8 * 85;
+                match format {
+                    vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => {
+                        [pixel[2], pixel[1], pixel[0], pixel[3]]
+                    }
+                    vk::Format::A2B10G10R10_UNORM_PACK32 => {
+                        [channel(0), channel(10), channel(20), alpha]
+                    }
+                    vk::Format::A2R10G10B10_UNORM_PACK32 => {
+                        [channel(20), channel(10), channel(0), alpha]
+                    }
+                    _ => [pixel[0], pixel[1], pixel[2], pixel[3]],
+                }
+            })
+            .collect();
+        image::RgbaImage::from_raw(width, height, pixels)
+    }
+
+    fn note_suboptimal(&mut self) {
+        self.suboptimal_count += 1;
+        // only on powers of two so it doesn't flood the log
+        if self.suboptimal_count.is_power_of_two() {
+            debug!(
+                "Swap chain has been suboptimal {} time(s), keeping it until it's out of date",
+                self.suboptimal_count
+            );
+        }
+    }
+
+    // Frames are submitted on the compute queue and presented on the present queue, transfers go
+    // through the graphics queue
+    pub fn queue_info(&self) -> QueueInfo {
+        let gpu = &self.gpus[self.gpu];
+        // picked for it in get_gpus, but the surface could have changed since
+        let present_supported = unsafe {
+            self.surface_loader.get_physical_device_surface_support(
+                gpu.device,
+                gpu.present_family_index,
+                self.surface,
+            )
+        }
+        .unwrap_or(false);
+        if !present_supported {
+            warn!(
+                "Queue family {} is used for presenting but doesn't support the surface",
+                gpu.present_family_index
+            );
+        }
+
+        QueueInfo {
+            graphics_family: gpu.graphics_family_index,
+            compute_family: gpu.compute_family_index,
+            present_family: gpu.present_family_index,
+            transfer_family: gpu.graphics_family_index,
+            distinct: gpu.graphics_family_index != gpu.present_family_index,
+            present_supported,
+        }
+    }
+
+    // Textures are decoded to RGBA8, the colour space picks whether sampling converts from sRGB
+    fn texture_format(color_space: rendersystem::TextureColorSpace) -> vk::Format {
+        match color_space {
+            rendersystem::TextureColorSpace::Srgb => vk::Format::R8G8B8A8_SRGB,
+            rendersystem::TextureColorSpace::Linear => vk::Format::R8G8B8A8_UNORM,
+        }
+    }
+
+    // All of these have to support being colour attachments, so there's nothing to check
+    pub fn target_format(format: rendersystem::TargetFormat) -> vk::Format {
+        match format {
+            rendersystem::TargetFormat::Rgba8 => vk::Format::R8G8B8A8_UNORM,
+            rendersystem::TargetFormat::Rgba8Srgb => vk::Format::R8G8B8A8_SRGB,
+            rendersystem::TargetFormat::Rgb10A2 => vk::Format::A2B10G10R10_UNORM_PACK32,
+            rendersystem::TargetFormat::Rg16Float => vk::Format::R16G16_SFLOAT,
+            rendersystem::TargetFormat::Rgba16Float => vk::Format::R16G16B16A16_SFLOAT,
+            rendersystem::TargetFormat::R32Float => vk::Format::R32_SFLOAT,
+            rendersystem::TargetFormat::Rgba32Float => vk::Format::R32G32B32A32_SFLOAT,
+        }
+    }
+
+    pub fn max_color_attachments(&self) -> u32 {
+        self.gpus[self.gpu].properties.limits.max_color_attachments
+    }
+
+    pub fn present_mode(&self) -> rendersystem::PresentMode {
+        match self.present_mode {
+            vk::PresentModeKHR::IMMEDIATE => rendersystem::PresentMode::Immediate,
+            vk::PresentModeKHR::MAILBOX => rendersystem::PresentMode::Mailbox,
+            vk::PresentModeKHR::FIFO_RELAXED => rendersystem::PresentMode::FifoRelaxed,
+            _ => rendersystem::PresentMode::Fifo,
+        }
+    }
+
+    pub fn wait_idle(&self) {
+        unsafe { vulkan_check!(self.device.device_wait_idle()) };
+    }
+
+    pub fn suboptimal_count(&self) -> u64 {
+        self.suboptimal_count
+    }
+
+    // Windows took it away, see set_fullscreen_exclusive
+    fn lose_fullscreen_exclusive(&mut self) {
+        if self.fullscreen_acquired {
+            info!("Lost exclusive fullscreen, it'll be acquired again when possible");
+        }
+        self.fullscreen_acquired = false;
+    }
+
+    // Stops rendering until the device is recreated
+    fn lose_device(&mut self) {
+        error!("Lost Vulkan device {}", self.gpu);
+        self.device_lost = true;
+        self.events.push(rendersystem::RenderEvent::DeviceLost);
+    }
+
+    // False if the device was lost while waiting
+    fn wait_for_fences(&mut self, fences: &[vk::Fence]) -> bool {
+        match unsafe { self.device.wait_for_fences(fences, true, u64::MAX) } {
+            Ok(()) => true,
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                self.lose_device();
+                false
+            }
+            Err(err) => panic!("Failed to wait for fences: {err}"),
+        }
+    }
+
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, rendersystem::RenderEvent> {
+        self.events.drain(..)
+    }
+
+    pub fn fence_pool_stats(&self) -> FencePoolStats {
+        self.fence_pool.stats()
+    }
+
+    pub fn frame_resource_stats(&self) -> FrameResourceStats {
+        FrameResourceStats {
+            in_flight: self.last_in_flight,
+            pass_command_buffers: self.pass_command_buffers.iter().map(Vec::len).sum(),
+            staging_buffers: self.model_staging.iter().map(Vec::len).sum(),
+            retired_buffers: self.retired_buffers.len(),
+            descriptor_pools: self.descriptor_pools.pools.borrow().len(),
+            readbacks: self.readbacks.len(),
+        }
+    }
+
+    pub fn validation_errors(&self) -> usize {
+        self.validation_errors.load(atomic::Ordering::Relaxed)
+    }
+
+    pub fn unload_resources(&mut self) {
+        let mut default_texture = mem::take(&mut self.default_texture);
+        default_texture.destroy(self);
+        if let Some(model_buffer) = self.model_buffer.take() {
+            model_buffer.destroy(&self.allocator);
+        }
+        self.destroy_retired_buffers(true);
+        // the models get uploaded with their new geometry when they're loaded again
+        let staging = self
+            .model_staging
+            .iter_mut()
+            .flat_map(|staging| staging.drain(..));
+        for staging in self
+            .model_updates
+            .drain(..)
+            .map(|(staging, _)| staging)
+            .chain(staging)
+        {
+            staging.destroy(&self.allocator);
+        }
+        self.loaded = false;
+    }
+
+    unsafe fn destroy_device_objects(&mut self) {
+        if let Some(grid) = self.grid.take() {
+            grid.destroy(self);
+        }
+        if let Some(lighting) = self.lighting.take() {
+            lighting.destroy(self);
+        }
+
+        debug!("Freeing {} readback(s)", self.readbacks.len());
+        for readback in self.readbacks.drain(..) {
+            readback.buffer.destroy(&self.allocator);
+        }
+        self.readback_request = None;
+
+        debug!("Destroying texture sampler {:#?}", self.texture_sampler);
+        self.device.destroy_sampler(
+            self.texture_sampler,
+            Some(&State::get_allocation_callbacks()),
+        );
+
+        debug!("Destroying timestamp query pool {:#?}", self.timestamp_pool);
+        self.device.destroy_query_pool(
+            self.timestamp_pool,
+            Some(&State::get_allocation_callbacks()),
+        );
+
+        debug!("Freeing {FRAME_COUNT} uniform buffers");
+        for _ in 0..self.uniform_buffers.len() {
+            self.uniform_buffers.remove(0).destroy(&self.allocator)
+        }
+
+        debug!("Freeing default vertex colour buffer");
+        if let Some(default_color_buffer) = self.default_color_buffer.take() {
+            default_color_buffer.destroy(&self.allocator);
+        }
+
+        self.descriptor_pools.destroy(&self.device);
+
+        debug!(
+            "Destroying descriptor set layout {:#?}",
+            self.descriptor_layout
+        );
+        self.device.destroy_descriptor_set_layout(
+            self.descriptor_layout,
+            Some(&State::get_allocation_callbacks()),
+        );
+        debug!(
+            "Destroying storage descriptor set layout {:#?}",
+            self.storage_layout
+        );
+        self.device.destroy_descriptor_set_layout(
+            self.storage_layout,
+            Some(&State::get_allocation_callbacks()),
+        );
+        debug!(
+            "Destroying texture descriptor set layout {:#?}",
+            self.texture_layout
+        );
+        self.device.destroy_descriptor_set_layout(
+            self.texture_layout,
+            Some(&State::get_allocation_callbacks()),
+        );
+
+        self.swapchain
+            .destroy(&self.device, &self.swapchain_loader, &self.allocator);
+        Self::destroy_semaphores(&self.device, &mut self.acquire_semaphores);
+
+        debug!("Destroying {FRAME_COUNT} fences");
+        self.fences.iter().for_each(|fence| {
+            self.device
+                .destroy_fence(*fence, Some(&State::get_allocation_callbacks()))
+        });
+        debug!(
+            "Destroying fence pool with {} fence(s)",
+            self.fence_pool.stats().created
+        );
+        self.fence_pool.destroy(&self.device);
+        debug!("Destroying transfer command pool {:#?}", self.transfer_pool);
+        self.device
+            .destroy_command_pool(self.transfer_pool, Some(&State::get_allocation_callbacks()));
+        debug!("Destroying {FRAME_COUNT} pass command pools");
+        for pool in self.pass_pools.drain(..) {
+            self.device
+                .destroy_command_pool(pool, Some(&State::get_allocation_callbacks()));
+        }
+        debug!("Destroying command pool {:#?}", self.command_pool);
+        self.device
+            .destroy_command_pool(self.command_pool, Some(&State::get_allocation_callbacks()));
+        debug!("Destroying allocator");
+        mem::ManuallyDrop::drop(&mut self.allocator);
+        debug!("Destroying logical device {:#?}", self.device.handle());
+        self.device
+            .destroy_device(Some(&State::get_allocation_callbacks()));
+    }
+
+    // Tears down everything owned by the logical device and rebuilds it on another GPU. Resources
+    // have to be unloaded first and uploaded again afterwards by the caller.
+    pub fn recreate_device(&mut self, video: &dyn SurfaceProvider, gpu_idx: usize) {
+        debug!("Recreating device objects on device {gpu_idx}");
+
+        debug!("Waiting for device idle");
+        unsafe {
+            vulkan_check!(self.device.device_wait_idle());
+            self.destroy_device_objects();
+        }
+
+        self.set_gpu(gpu_idx);
+        (
+            self.device,
+            self.graphics_queue,
+            self.compute_queue,
+            self.present_queue,
+        ) = Self::create_device(&self.instance, &self.gpus[self.gpu]);
+        (self.command_pool, self.transfer_pool) =
+            Self::create_command_pools(&self.device, &self.gpus[self.gpu]);
+        self.command_buffers = Self::allocate_command_buffers(&self.device, &self.command_pool);
+        self.pass_pools = Self::create_pass_pools(&self.device, &self.gpus[self.gpu]);
+        self.pass_command_buffers = vec![Vec::new(); FRAME_COUNT];
+        self.pass_purposes = vec![Vec::new(); FRAME_COUNT];
+        self.allocator = mem::ManuallyDrop::new(Self::create_allocator(
+            &self.instance,
+            &self.device,
+            self.gpus[self.gpu].device,
+            &self.config.allocator,
+        ));
+        self.fences = Self::create_fences(&self.device);
+        self.acquire_semaphores = Self::create_semaphores(&self.device, FRAME_COUNT);
+        let surface_format =
+            Self::choose_surface_format(&self.gpus[self.gpu], &self.config.surface_formats);
+        self.present_mode =
+            Self::choose_present_mode(&self.gpus[self.gpu], &self.config.present_modes);
+        self.swapchain_loader = extensions::khr::Swapchain::new(&self.instance, &self.device);
+        // it's per monitor and device, so it has to be asked for again
+        self.fullscreen_monitor = None;
+        self.fullscreen_acquired = false;
+        self.swapchain = SwapchainResources::new(
+            &self.instance,
+            &self.device,
+            &self.allocator,
+            &self.gpus[self.gpu],
+            &self.surface_loader,
+            &self.surface,
+            &self.swapchain_loader,
+            surface_format,
+            self.present_mode,
+            self.config.swapchain_sharing,
+            self.config.swapchain_images,
+            Self::full_screen_exclusive_mode(&self.gpus[self.gpu], None),
+            Self::window_extent(video),
+            self.render_scale,
+        );
+        self.descriptor_layout = Self::create_descriptor_layout(&self.device);
+        self.storage_layout = Self::create_storage_layout(&self.device);
+        self.texture_layout = Self::create_texture_layout(&self.device);
+        self.descriptor_pools = DescriptorPools::new(&self.device);
+        self.uniform_buffers =
+            Self::allocate_uniform_buffers(&self.allocator, &self.gpus[self.gpu]);
+        self.default_color_buffer = Some(Self::create_default_color_buffer(&self.allocator));
+        self.descriptor_sets = Self::allocate_descriptor_sets(
+            &self.device,
+            &self.descriptor_layout,
+            &self.descriptor_pools,
+            &self.uniform_buffers,
+        );
+        self.shader_object_loader =
+            extensions::ext::ShaderObject::new(&self.instance, &self.device);
+        self.present_wait_loader = extensions::khr::PresentWait::new(&self.instance, &self.device);
+        self.full_screen_exclusive_loader =
+            extensions::ext::FullScreenExclusive::new(&self.instance, &self.device);
+        self.present_wait = self.present_wait && self.gpus[self.gpu].present_wait;
+        self.timestamp_pool = Self::create_timestamp_pool(&self.device);
+        self.timestamp_period = self.gpus[self.gpu].properties.limits.timestamp_period;
+        self.timestamps_written = vec![false; FRAME_COUNT];
+        self.texture_filtering =
+            Self::supported_texture_filtering(&self.gpus[self.gpu], self.texture_filtering);
+        self.texture_sampler = Self::create_texture_sampler(&self.device, self.texture_filtering);
+
+        self.frame_index = 0;
+        self.swapchain_index = 0;
+        self.last_shader = None;
+        self.last_model = None;
+        self.device_lost = false;
+        debug!("{:#?}", self.queue_info());
+
+        debug!("Recreated device objects successfully");
+    }
+
+    pub fn shutdown(mut self) {
+        debug!("Vulkan shutdown started");
+
+        self.loaded = false;
+        self.initialized = false;
+
+        debug!("Waiting for device idle");
+        unsafe { vulkan_check!(self.device.device_wait_idle()) };
+
+        unsafe {
+            self.destroy_device_objects();
+            debug!("Destroying surface {:#?}", self.surface);
+            self.surface_loader
+                .destroy_surface(self.surface, Some(&State::get_allocation_callbacks()));
+            debug!("Destroying instance {:#?}", self.instance.handle());
+            self.instance
+                .destroy_instance(Some(&State::get_allocation_callbacks()));
+        }
+
+        debug!("Vulkan shutdown succeeded");
+    }
+
+    pub fn set_gpu(&mut self, gpu_idx: usize) -> usize {
+        let old_idx = self.gpu;
+        if gpu_idx < self.gpus.len() {
+            self.gpu = gpu_idx;
+            let gpu = &self.gpus[self.gpu];
+
+            let name = unsafe {
+                ffi::CStr::from_ptr(gpu.properties.device_name.as_ptr())
+                    .to_str()
+                    .unwrap()
+            };
+            debug!(
+                "Selected {:#?} device {}, {} [{:04x}:{:04x}] with score {}",
+                gpu.properties.device_type,
+                gpu_idx,
+                name,
+                gpu.properties.vendor_id,
+                gpu.properties.device_id,
+                gpu.performance_score
+            );
+        }
+
+        old_idx
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    pub fn is_in_frame(&self) -> bool {
+        self.in_frame
+    }
+
+    pub fn available_gpus(&self) -> Vec<GpuDescription> {
+        self.gpus
+            .iter()
+            .enumerate()
+            .map(|(index, gpu)| GpuDescription {
+                index,
+                name: unsafe {
+                    ffi::CStr::from_ptr(gpu.properties.device_name.as_ptr())
+                        .to_string_lossy()
+                        .into_owned()
+                },
+                gpu_type: match gpu.properties.device_type {
+                    vk::PhysicalDeviceType::DISCRETE_GPU => rendersystem::GpuType::Discrete,
+                    vk::PhysicalDeviceType::INTEGRATED_GPU => rendersystem::GpuType::Integrated,
+                    vk::PhysicalDeviceType::VIRTUAL_GPU => rendersystem::GpuType::Virtual,
+                    vk::PhysicalDeviceType::CPU => rendersystem::GpuType::Cpu,
+                    _ => rendersystem::GpuType::Other,
+                },
+                vendor_id: gpu.properties.vendor_id,
+                device_id: gpu.properties.device_id,
+                performance_score: gpu.performance_score,
+                selected: index == self.gpu,
+            })
+            .collect()
+    }
+
+    // PCI vendor and device ID of the GPU in use
+    pub fn gpu_ids(&self) -> (u32, u32) {
+        let properties = &self.gpus[self.gpu].properties;
+        (properties.vendor_id, properties.device_id)
+    }
+
+    // The frame's fence has to have been waited on. WAIT doesn't actually wait then, but without
+    // it the results can come back as not ready and the frame's time would be skipped.
+    fn read_timestamps(&mut self, frame: usize) {
+        let valid_bits = self.gpus[self.gpu].timestamp_valid_bits;
+        if !self.timestamps_written[frame] || valid_bits == 0 {
+            self.timestamps_written[frame] = false;
+            return;
+        }
+
+        let mut timestamps = [0u64; 2];
+        if unsafe {
+            self.device.get_query_pool_results(
+                self.timestamp_pool,
+                (frame * 2) as u32,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )
+        }
+        .is_ok()
+        {
+            let mask = u64::MAX >> (u64::BITS - valid_bits.min(u64::BITS));
+            let ticks = (timestamps[1] & mask).wrapping_sub(timestamps[0] & mask) & mask;
+            self.gpu_time = ticks as f64 * self.timestamp_period as f64 / 1_000_000.0;
+            self.gpu_time_total += self.gpu_time;
+        }
+        self.timestamps_written[frame] = false;
+    }
+
+    // In milliseconds, lags a few frames behind
+    pub fn gpu_time(&self) -> f64 {
+        self.gpu_time
+    }
+
+    pub fn reset_gpu_time_total(&mut self) {
+        self.gpu_time_total = 0.0;
+    }
+
+    // Waits for the frames still in flight so their times are included
+    pub fn gpu_time_total(&mut self) -> f64 {
+        self.wait_idle();
+        for frame in 0..FRAME_COUNT {
+            self.read_timestamps(frame);
+        }
+        self.gpu_time_total
+    }
+
+    // Without the submitted triangles, the frontend counts those
+    pub fn render_stats(&self) -> rendersystem::RenderStats {
+        self.last_stats
+    }
+}
+
+pub type ShaderErrorType = vk::Result;
+pub type PassCommandBuffer = vk::CommandBuffer;
+
+pub struct ShaderData {
+    // Only used with shader objects, null for stages the shader doesn't have
+    vertex_handle: vk::ShaderEXT,
+    tessellation_control_handle: vk::ShaderEXT,
+    tessellation_evaluation_handle: vk::ShaderEXT,
+    geometry_handle: vk::ShaderEXT,
+    fragment_handle: vk::ShaderEXT,
+    // Set when the tessellation stages are used, the topology is a patch list then
+    patch_control_points: Option<u32>,
+    pipeline_layout: vk::PipelineLayout,
+    // One per rendersystem::Topology, only used without shader objects
+    pipelines: Vec<vk::Pipeline>,
+    // What the pipelines were made for
+    formats: (vk::Format, vk::Format),
+    sample_shading: Option<f32>,
+    // The vertex shader reads nothing but the position, like a depth prepass or shadow shader
+    position_only: bool,
+}
+
+impl ShaderData {
+    pub fn new(
+        state: &State,
+        name: &str,
+        vertex_binary: Vec<u8>,
+        fragment_binary: Vec<u8>,
+        tessellation: Option<&rendersystem::Tessellation>,
+        geometry_binary: Option<&[u8]>,
+        vertex_descriptor: Option<&rendersystem::VertexDescriptor>,
+        color_formats: Option<&[rendersystem::TargetFormat]>,
+    ) -> Result<Self, crate::engine::rendersystem::ShaderError> {
+        let gpu = &state.gpus[state.gpu];
+        // unlike tessellation, there's nothing sensible to draw without it
+        if geometry_binary.is_some() && !gpu.geometry_shader {
+            error!(
+                "Shader {name} has a geometry stage, but the GPU doesn't support geometry shaders"
+            );
+            return Err(rendersystem::ShaderError::Unsupported("geometry shaders"));
+        }
+        let tessellation = tessellation.filter(|_| {
+            if !gpu.tessellation_shader {
+                warn!("GPU doesn't support tessellation, shader {name} is drawn without it");
+            }
+            gpu.tessellation_shader
+        });
+        let patch_control_points = tessellation.map(|tessellation| {
+            let max = gpu.properties.limits.max_tessellation_patch_size;
+            if !(1..=max).contains(&tessellation.patch_control_points) {
+                warn!(
+                    "Shader {name} has {} control points per patch, clamping to 1-{max}",
+                    tessellation.patch_control_points
+                );
+            }
+            tessellation.patch_control_points.clamp(1, max)
+        });
+
+        let position_only = vertex_descriptor.is_none()
+            && rendersystem::spirv::vertex_inputs(&vertex_binary)
+                .is_ok_and(|inputs| inputs.iter().all(|input| input.location == 0));
+        if position_only {
+            debug!("Shader {name} only reads positions");
+        }
+
+        // in the order they run
+        let mut stages = vec![(vk::ShaderStageFlags::VERTEX, vertex_binary.as_slice())];
+        if let Some(tessellation) = tessellation {
+            stages.push((
+                vk::ShaderStageFlags::TESSELLATION_CONTROL,
+                &tessellation.control_binary,
+            ));
+            stages.push((
+                vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+                &tessellation.evaluation_binary,
+            ));
+        }
+        if let Some(geometry_binary) = geometry_binary {
+            stages.push((vk::ShaderStageFlags::GEOMETRY, geometry_binary));
+        }
+        stages.push((vk::ShaderStageFlags::FRAGMENT, fragment_binary.as_slice()));
+
+        let set_layouts = state.set_layouts();
+        let pipeline_layout = match unsafe {
+            state.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo {
+                    p_set_layouts: set_layouts.as_ptr(),
+                    set_layout_count: set_layouts.len() as u32,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks()),
+            )
+        } {
+            Ok(layout) => layout,
+            Err(err) => {
+                error!("Failed to create pipeline layout for shader {name}: {err}");
+                return Err(rendersystem::ShaderError::Backend(err));
+            }
+        };
+
+        let result = if state.uses_shader_objects() {
+            Self::create_shader_objects(state, &stages)
+                .map(|handles| (handles, Vec::new()))
+                .map_err(rendersystem::ShaderError::Backend)
+        } else {
+            Self::create_pipelines(
+                state,
+                pipeline_layout,
+                &stages,
+                patch_control_points,
+                vertex_descriptor,
+                position_only,
+                color_formats,
+            )
+            .map(|pipelines| (Vec::new(), pipelines))
+        };
+        let (handles, pipelines) = match result {
+            Ok(handles) => handles,
+            Err(err) => {
+                error!("Failed to create Vulkan shader {name}: {err:?}");
+                unsafe {
+                    state.device.destroy_pipeline_layout(
+                        pipeline_layout,
+                        Some(&State::get_allocation_callbacks()),
+                    )
+                };
+                return Err(err);
+            }
+        };
+
+        let handle = |stage| {
+            stages
+                .iter()
+                .zip(&handles)
+                .find(|((other, _), _)| *other == stage)
+                .map_or(vk::ShaderEXT::null(), |(_, handle)| *handle)
+        };
+        Ok(Self {
+            vertex_handle: handle(vk::ShaderStageFlags::VERTEX),
+            tessellation_control_handle: handle(vk::ShaderStageFlags::TESSELLATION_CONTROL),
+            tessellation_evaluation_handle: handle(vk::ShaderStageFlags::TESSELLATION_EVALUATION),
+            geometry_handle: handle(vk::ShaderStageFlags::GEOMETRY),
+            fragment_handle: handle(vk::ShaderStageFlags::FRAGMENT),
+            patch_control_points,
+            pipeline_layout,
+            pipelines,
+            formats: state.swapchain.formats(),
+            sample_shading: state.sample_shading,
+            position_only,
+        })
+    }
+
+    // All linked together, each one's next stage is the one after it
+    fn create_shader_objects(
+        state: &State,
+        stages: &[(vk::ShaderStageFlags, &[u8])],
+    ) -> Result<Vec<vk::ShaderEXT>, vk::Result> {
+        let set_layouts = state.set_layouts();
+        let create_infos: Vec<vk::ShaderCreateInfoEXT> = stages
+            .iter()
+            .enumerate()
+            .map(|(i, (stage, binary))| vk::ShaderCreateInfoEXT {
+                flags: vk::ShaderCreateFlagsEXT::LINK_STAGE,
+                stage: *stage,
+                next_stage: stages
+                    .get(i + 1)
+                    .map_or(vk::ShaderStageFlags::empty(), |(next, _)| *next),
+                code_type: vk::ShaderCodeTypeEXT::SPIRV,
+                p_code: binary.as_ptr() as *const ffi::c_void,
+                code_size: binary.len(),
+                p_name: b"main\0".as_ptr() as *const i8,
+                p_set_layouts: set_layouts.as_ptr(),
+                set_layout_count: set_layouts.len() as u32,
+                ..Default::default()
+            })
+            .collect();
+
+        unsafe {
+            state
+                .shader_object_loader
+                .create_shaders(&create_infos, Some(&State::get_allocation_callbacks()))
+        }
+    }
+
+    fn create_shader_module(
+        state: &State,
+        binary: &[u8],
+    ) -> Result<vk::ShaderModule, rendersystem::ShaderError> {
+        // copies into u32s, the byte vector might not be aligned
+        let code = ash::util::read_spv(&mut io::Cursor::new(binary))
+            .map_err(rendersystem::ShaderError::InvalidSpirv)?;
+        unsafe {
+            state.device.create_shader_module(
+                &vk::ShaderModuleCreateInfo {
+                    p_code: code.as_ptr(),
+                    code_size: code.len() * mem::size_of::<u32>(),
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks()),
+            )
+        }
+        .map_err(rendersystem::ShaderError::Backend)
+    }
+
+    // Pipelines are grouped by vertex format, then by topology in the order of the enum. Shaders
+    // with a vertex descriptor only have pipelines for that (None).
+    fn pipeline_index(
+        topology: rendersystem::Topology,
+        vertex_format: Option<rendersystem::VertexFormat>,
+    ) -> usize {
+        const TOPOLOGY_COUNT: usize = 4;
+        vertex_format.map_or(0, |format| format.index()) * TOPOLOGY_COUNT + topology as usize
+    }
+
+    fn destroy_shader_modules(state: &State, modules: &[vk::ShaderModule]) {
+        for module in modules {
+            unsafe {
+                state
+                    .device
+                    .destroy_shader_module(*module, Some(&State::get_allocation_callbacks()))
+            };
+        }
+    }
+
+    fn create_pipelines(
+        state: &State,
+        pipeline_layout: vk::PipelineLayout,
+        stages: &[(vk::ShaderStageFlags, &[u8])],
+        patch_control_points: Option<u32>,
+        vertex_descriptor: Option<&rendersystem::VertexDescriptor>,
+        position_only: bool,
+        color_formats: Option<&[rendersystem::TargetFormat]>,
+    ) -> Result<Vec<vk::Pipeline>, rendersystem::ShaderError> {
+        let mut modules = Vec::new();
+        for (_, binary) in stages {
+            match Self::create_shader_module(state, binary) {
+                Ok(module) => modules.push(module),
+                Err(err) => {
+                    Self::destroy_shader_modules(state, &modules);
+                    return Err(err);
+                }
+            }
+        }
+
+        let stages: Vec<vk::PipelineShaderStageCreateInfo> = stages
+            .iter()
+            .zip(&modules)
+            .map(|((stage, _), module)| vk::PipelineShaderStageCreateInfo {
+                stage: *stage,
+                module: *module,
+                p_name: b"main\0".as_ptr() as *const i8,
+                ..Default::default()
+            })
+            .collect();
+
+        let vertex_inputs: Vec<_> = match vertex_descriptor {
+            Some(descriptor) => vec![State::descriptor_vertex_input(descriptor)],
+            None => rendersystem::VertexFormat::ALL
+                .iter()
+                .map(|&format| State::vertex_input(format, position_only))
+                .collect(),
+        };
+        let vertex_input_states: Vec<vk::PipelineVertexInputStateCreateInfo> = vertex_inputs
+            .iter()
+            .map(
+                |(bindings, attributes)| vk::PipelineVertexInputStateCreateInfo {
+                    vertex_binding_description_count: bindings.len() as u32,
+                    p_vertex_binding_descriptions: bindings.as_ptr(),
+                    vertex_attribute_description_count: attributes.len() as u32,
+                    p_vertex_attribute_descriptions: attributes.as_ptr(),
+                    ..Default::default()
+                },
+            )
+            .collect();
+
+        // without shader objects the topology class and vertex input can't change dynamically,
+        // so there's a pipeline for each combination of them, see pipeline_index. With
+        // tessellation everything is patches, the index layout stays the same anyway.
+        let input_assembly_states: Vec<vk::PipelineInputAssemblyStateCreateInfo> = [
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            vk::PrimitiveTopology::TRIANGLE_STRIP,
+            vk::PrimitiveTopology::LINE_LIST,
+            vk::PrimitiveTopology::POINT_LIST,
+        ]
+        .iter()
+        .map(|&topology| {
+            let topology = if patch_control_points.is_some() {
+                vk::PrimitiveTopology::PATCH_LIST
+            } else {
+                topology
+            };
+            vk::PipelineInputAssemblyStateCreateInfo {
+                topology,
+                primitive_restart_enable: (topology == vk::PrimitiveTopology::TRIANGLE_STRIP)
+                    .into(),
+                ..Default::default()
+            }
+        })
+        .collect();
+        let tessellation_state = vk::PipelineTessellationStateCreateInfo {
+            patch_control_points: patch_control_points.unwrap_or(0),
+            ..Default::default()
+        };
+
+        let viewport_state = vk::PipelineViewportStateCreateInfo {
+            viewport_count: 1,
+            scissor_count: 1,
+            ..Default::default()
+        };
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo {
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_bias_enable: vk::TRUE,
+            line_width: 1.0,
+            ..Default::default()
+        };
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            sample_shading_enable: state.sample_shading.is_some().into(),
+            min_sample_shading: state.sample_shading.unwrap_or(0.0),
+            ..Default::default()
+        };
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo {
+            depth_test_enable: vk::TRUE,
+            depth_write_enable: vk::TRUE,
+            depth_compare_op: vk::CompareOp::LESS,
+            ..Default::default()
+        };
+        // the window, or every attachment of the render targets the shader draws to
+        let color_formats: Vec<vk::Format> = match color_formats {
+            Some(formats) => formats
+                .iter()
+                .map(|&format| State::target_format(format))
+                .collect(),
+            None => vec![state.swapchain.surface_format.format],
+        };
+        let color_blend_attachments = vec![
+            vk::PipelineColorBlendAttachmentState {
+                color_write_mask: vk::ColorComponentFlags::RGBA,
+                ..Default::default()
+            };
+            color_formats.len()
+        ];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+            attachment_count: color_blend_attachments.len() as u32,
+            p_attachments: color_blend_attachments.as_ptr(),
+            ..Default::default()
+        };
+        let dynamic_states = [
+            vk::DynamicState::VIEWPORT,
+            vk::DynamicState::SCISSOR,
+            vk::DynamicState::DEPTH_BIAS,
+            vk::DynamicState::DEPTH_WRITE_ENABLE,
+            vk::DynamicState::STENCIL_TEST_ENABLE,
+            vk::DynamicState::STENCIL_OP,
+            vk::DynamicState::STENCIL_COMPARE_MASK,
+            vk::DynamicState::STENCIL_WRITE_MASK,
+            vk::DynamicState::STENCIL_REFERENCE,
+        ];
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo {
+            dynamic_state_count: dynamic_states.len() as u32,
+            p_dynamic_states: dynamic_states.as_ptr(),
+            ..Default::default()
+        };
+        let rendering_info = vk::PipelineRenderingCreateInfo {
+            color_attachment_count: color_formats.len() as u32,
+            p_color_attachment_formats: color_formats.as_ptr(),
+            depth_attachment_format: state.swapchain.depth_image.format(),
+            stencil_attachment_format: state.swapchain.depth_image.format(),
+            ..Default::default()
+        };
+
+        let create_infos: Vec<vk::GraphicsPipelineCreateInfo> = vertex_input_states
+            .iter()
+            .flat_map(|vertex_input_state| {
+                input_assembly_states
+                    .iter()
+                    .map(move |input_assembly_state| (vertex_input_state, input_assembly_state))
+            })
+            .map(
+                |(vertex_input_state, input_assembly_state)| vk::GraphicsPipelineCreateInfo {
+                    p_next: ptr::addr_of!(rendering_info) as *const ffi::c_void,
+                    stage_count: stages.len() as u32,
+                    p_stages: stages.as_ptr(),
+                    p_vertex_input_state: vertex_input_state,
+                    p_input_assembly_state: input_assembly_state,
+                    p_tessellation_state: if patch_control_points.is_some() {
+                        ptr::addr_of!(tessellation_state)
+                    } else {
+                        ptr::null()
+                    },
+                    p_viewport_state: ptr::addr_of!(viewport_state),
+                    p_rasterization_state: ptr::addr_of!(rasterization_state),
+                    p_multisample_state: ptr::addr_of!(multisample_state),
+                    p_depth_stencil_state: ptr::addr_of!(depth_stencil_state),
+                    p_color_blend_state: ptr::addr_of!(color_blend_state),
+                    p_dynamic_state: ptr::addr_of!(dynamic_state),
+                    layout: pipeline_layout,
+                    ..Default::default()
+                },
+            )
+            .collect();
+
+        let result = unsafe {
+            state.device.create_graphics_pipelines(
+                vk::PipelineCache::null(),
+                &create_infos,
+                Some(&State::get_allocation_callbacks()),
+            )
+        };
+
+        Self::destroy_shader_modules(state, &modules);
+
+        // the ones that did get made come back with the error, the rest are null
+        result.map_err(|(pipelines, err)| {
+            for pipeline in pipelines {
+                if pipeline != vk::Pipeline::null() {
+                    unsafe {
+                        state
+                            .device
+                            .destroy_pipeline(pipeline, Some(&State::get_allocation_callbacks()))
+                    };
+                }
+            }
+            rendersystem::ShaderError::Backend(err)
+        })
+    }
+
+    // Shader objects get their formats when they're drawn, pipelines have to be made again.
+    // Turning sample shading on or off switches between the two, see uses_shader_objects.
+    pub fn is_stale(&self, state: &State) -> bool {
+        (!self.pipelines.is_empty() && self.formats != state.swapchain.formats())
+            || self.sample_shading != state.sample_shading
+    }
+
+    pub fn destroy(&self, backend: &State) {
+        unsafe {
+            for handle in [
+                self.vertex_handle,
+                self.tessellation_control_handle,
+                self.tessellation_evaluation_handle,
+                self.geometry_handle,
+                self.fragment_handle,
+            ] {
+                if handle != vk::ShaderEXT::null() {
+                    backend
+                        .shader_object_loader
+                        .destroy_shader(handle, Some(&State::get_allocation_callbacks()));
+                }
+            }
+            for pipeline in &self.pipelines {
+                backend
+                    .device
+                    .destroy_pipeline(*pipeline, Some(&State::get_allocation_callbacks()));
+            }
+            backend.device.destroy_pipeline_layout(
+                self.pipeline_layout,
+                Some(&State::get_allocation_callbacks()),
+            );
+        }
+    }
+
+    pub fn vertex_extension() -> String {
+        String::from(".vert.spv")
+    }
+
+    pub fn fragment_extension() -> String {
+        String::from(".frag.spv")
+    }
+
+    pub fn tessellation_control_extension() -> String {
+        String::from(".tesc.spv")
+    }
+
+    pub fn tessellation_evaluation_extension() -> String {
+        String::from(".tese.spv")
+    }
+
+    pub fn geometry_extension() -> String {
+        String::from(".geom.spv")
+    }
+}
+
+pub struct ComputeShaderData {
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl ComputeShaderData {
+    pub fn new(
+        state: &State,
+        name: &str,
+        binary: Vec<u8>,
+    ) -> Result<Self, crate::engine::rendersystem::ShaderError> {
+        let set_layouts = state.set_layouts();
+        let pipeline_layout = match unsafe {
+            state.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo {
+                    p_set_layouts: set_layouts.as_ptr(),
+                    set_layout_count: set_layouts.len() as u32,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks()),
+            )
+        } {
+            Ok(layout) => layout,
+            Err(err) => {
+                error!("Failed to create pipeline layout for compute shader {name}: {err}");
+                return Err(rendersystem::ShaderError::Backend(err));
+            }
+        };
+
+        let result = ShaderData::create_shader_module(state, &binary).and_then(|module| {
+            let result = unsafe {
+                state.device.create_compute_pipelines(
+                    vk::PipelineCache::null(),
+                    &[vk::ComputePipelineCreateInfo {
+                        stage: vk::PipelineShaderStageCreateInfo {
+                            stage: vk::ShaderStageFlags::COMPUTE,
+                            module,
+                            p_name: b"main\0".as_ptr() as *const i8,
+                            ..Default::default()
+                        },
+                        layout: pipeline_layout,
+                        ..Default::default()
+                    }],
+                    Some(&State::get_allocation_callbacks()),
+                )
+            };
+            unsafe {
+                state
+                    .device
+                    .destroy_shader_module(module, Some(&State::get_allocation_callbacks()))
+            };
+            result
+                .map(|pipelines| pipelines[0])
+                .map_err(|(_, err)| rendersystem::ShaderError::Backend(err))
+        });
+
+        match result {
+            Ok(pipeline) => Ok(Self {
+                pipeline_layout,
+                pipeline,
+            }),
+            Err(err) => {
+                error!("Failed to create Vulkan compute shader {name}: {err:?}");
+                unsafe {
+                    state.device.destroy_pipeline_layout(
+                        pipeline_layout,
+                        Some(&State::get_allocation_callbacks()),
+                    )
+                };
+                Err(err)
+            }
+        }
+    }
+
+    pub fn destroy(&self, backend: &State) {
+        unsafe {
+            backend
+                .device
+                .destroy_pipeline(self.pipeline, Some(&State::get_allocation_callbacks()));
+            backend.device.destroy_pipeline_layout(
+                self.pipeline_layout,
+                Some(&State::get_allocation_callbacks()),
+            );
+        }
+    }
+
+    pub fn extension() -> String {
+        String::from(".comp.spv")
+    }
+}
+
+pub struct PrepassData {
+    buffer: Buffer,
+    descriptor_set: vk::DescriptorSet,
+    descriptor_pool: vk::DescriptorPool,
+    group_count: (u32, u32, u32),
+}
+
+impl PrepassData {
+    pub fn new(state: &State, name: &str, buffer_size: u64, group_count: (u32, u32, u32)) -> Self {
+        debug!("Creating {buffer_size} byte prepass storage buffer for material {name}");
+
+        let buffer = vulkan_check!(Buffer::new(
+            &state.allocator,
+            buffer_size,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ));
+
+        let (descriptor_sets, descriptor_pool) = vulkan_check!(state
+            .descriptor_pools
+            .allocate(&state.device, &[state.storage_layout]));
+        let descriptor_set = descriptor_sets[0];
+
+        let buffer_info = vk::DescriptorBufferInfo {
+            buffer: buffer.handle,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        };
+        unsafe {
+            state.device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet {
+                    dst_set: descriptor_set,
+                    dst_binding: 0,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 1,
+                    p_buffer_info: ptr::addr_of!(buffer_info),
+                    ..Default::default()
+                }],
+                &[],
+            )
+        };
+
+        Self {
+            buffer,
+            descriptor_set,
+            descriptor_pool,
+            group_count,
+        }
+    }
+
+    pub fn destroy(self, backend: &State) {
+        unsafe {
+            vulkan_check!(backend
+                .device
+                .free_descriptor_sets(self.descriptor_pool, &[self.descriptor_set]))
+        };
+        self.buffer.destroy(&backend.allocator);
+    }
+}
+
+// A material texture's resident mips. Streaming replaces the whole image whenever they change,
+// the descriptor set stays and just gets rewritten.
+#[derive(Default)]
+pub struct TextureData {
+    image: Option<Image>,
+    // The mip of the full texture that's level 0 here
+    base_mip: u32,
+    descriptor_set: vk::DescriptorSet,
+    descriptor_pool: vk::DescriptorPool,
+}
+
+impl TextureData {
+    pub fn upload(
+        &mut self,
+        state: &mut State,
+        name: &str,
+        color_space: rendersystem::TextureColorSpace,
+        base_mip: u32,
+        mips: &[image::RgbaImage],
+    ) -> Result<(), vk::Result> {
+        let (width, height) = mips[0].dimensions();
+        debug!(
+            "Uploading {width}x{height} texture {name} from mip {base_mip} with {} level(s)",
+            mips.len()
+        );
+
+        let format = State::texture_format(color_space);
+        let size: usize = mips.iter().map(|mip| mip.as_raw().len()).sum();
+        let transfer_buffer = HostBuffer::new(
+            &state.allocator,
+            size as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let mut regions = Vec::new();
+        let mut offset = 0;
+        for (level, mip) in mips.iter().enumerate() {
+            unsafe { transfer_buffer.read(mip.as_raw(), offset) };
+            regions.push(vk::BufferImageCopy {
+                buffer_offset: offset,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: level as u32,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                image_extent: vk::Extent3D {
+                    width: mip.width(),
+                    height: mip.height(),
+                    depth: 1,
+                },
+                ..Default::default()
+            });
+            offset += mip.as_raw().len() as vk::DeviceSize;
+        }
+
+        let level_count = mips.len() as u32;
+        let image = match Image::new(
+            &state.device,
+            &state.allocator,
+            format,
+            &mut vk::ImageCreateInfo {
+                extent: vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                mip_levels: level_count,
+                array_layers: 1,
+                samples: vk::SampleCountFlags::TYPE_1,
+                usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+                image_type: vk::ImageType::TYPE_2D,
+                ..Default::default()
+            },
+            &mut vk::ImageViewCreateInfo {
+                view_type: vk::ImageViewType::TYPE_2D,
+                subresource_range: vk::ImageSubresourceRange {
+                    level_count,
+                    ..COLOR_SUBRESOURCE_RANGE
+                },
+                ..Default::default()
+            },
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::AutoPreferDevice,
+                ..Default::default()
+            },
+        ) {
+            Ok(image) => image,
+            Err(err) => {
+                transfer_buffer.destroy(&state.allocator);
+                return Err(err);
+            }
+        };
+
+        let mut batch = TransferBatch::begin(&state.device, &state.transfer_pool);
+        batch.copy_regions_to_image(
+            &state.device,
+            transfer_buffer.buffer(),
+            *image.handle(),
+            vk::ImageLayout::UNDEFINED,
+            level_count,
+            &regions,
+        );
+        batch.submit(
+            &state.device,
+            &state.graphics_queue,
+            &state.transfer_pool,
+            &mut state.fence_pool,
+        );
+        transfer_buffer.destroy(&state.allocator);
+
+        match self.image.take() {
+            // frames in flight could still be sampling the old one through the same set
+            Some(mut old_image) => {
+                state.wait_idle();
+                old_image.destroy(&state.device, &state.allocator);
+            }
+            None => {
+                let (descriptor_sets, descriptor_pool) = match state
+                    .descriptor_pools
+                    .allocate(&state.device, &[state.texture_layout])
+                {
+                    Ok(allocation) => allocation,
+                    Err(err) => {
+                        let mut image = image;
+                        image.destroy(&state.device, &state.allocator);
+                        return Err(err);
+                    }
+                };
+                self.descriptor_set = descriptor_sets[0];
+                self.descriptor_pool = descriptor_pool;
+            }
+        }
+        self.image = Some(image);
+        self.base_mip = base_mip;
+        self.write_descriptor(state);
+
+        Ok(())
+    }
+
+    // Needed again whenever the texture sampler is replaced
+    pub fn write_descriptor(&self, state: &State) {
+        let Some(image) = &self.image else {
+            return;
+        };
+
+        let image_info = vk::DescriptorImageInfo {
+            sampler: state.texture_sampler,
+            image_view: *image.view(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        unsafe {
+            state.device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet {
+                    dst_set: self.descriptor_set,
+                    dst_binding: 0,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    p_image_info: ptr::addr_of!(image_info),
+                    ..Default::default()
+                }],
+                &[],
+            )
+        };
+    }
+
+    pub fn is_uploaded(&self) -> bool {
+        self.image.is_some()
+    }
+
+    pub fn base_mip(&self) -> Option<u32> {
+        self.image.as_ref().map(|_| self.base_mip)
+    }
+
+    // Nothing can be using it anymore
+    pub fn destroy(&mut self, state: &State) {
+        if let Some(mut image) = self.image.take() {
+            unsafe {
+                vulkan_check!(state
+                    .device
+                    .free_descriptor_sets(self.descriptor_pool, &[self.descriptor_set]))
+            };
+            image.destroy(&state.device, &state.allocator);
+        }
+        self.descriptor_set = vk::DescriptorSet::null();
+        self.descriptor_pool = vk::DescriptorPool::null();
+    }
+}
+
+pub struct ModelData {
+    offset: vk::DeviceSize,
+    // How much space the model has in the model buffer, can be more than it's using after updates
+    capacity: vk::DeviceSize,
+    vertices_size: vk::DeviceSize,
+    indices_size: vk::DeviceSize,
+}
+
+impl ModelData {
+    pub fn new(state: &State, name: &str, vertices_size: usize, indices_size: usize) -> Self {
+        Self {
+            offset: 0,
+            capacity: 0,
+            vertices_size: vertices_size as vk::DeviceSize,
+            indices_size: indices_size as vk::DeviceSize,
+        }
+    }
+
+    pub fn index_count(&self) -> u64 {
+        self.indices_size / mem::size_of::<u32>() as u64
+    }
+}
+
+// An image with its own descriptor set for sampling it, for the GUI's textures and the glyph
+// cache's pages
+struct SampledImage {
+    image: Image,
+    descriptor_set: vk::DescriptorSet,
+    descriptor_pool: vk::DescriptorPool,
+}
+
+impl SampledImage {
+    fn new(
+        state: &State,
+        descriptor_layout: vk::DescriptorSetLayout,
+        sampler: vk::Sampler,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let image = vulkan_check!(Image::new(
+            &state.device,
+            &state.allocator,
+            format,
+            &mut vk::ImageCreateInfo {
+                extent: vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1
+                },
+                mip_levels: 1,
+                array_layers: 1,
+                samples: vk::SampleCountFlags::TYPE_1,
+                usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+                image_type: vk::ImageType::TYPE_2D,
+                ..Default::default()
+            },
+            &mut vk::ImageViewCreateInfo {
+                view_type: vk::ImageViewType::TYPE_2D,
+                subresource_range: COLOR_SUBRESOURCE_RANGE,
+                ..Default::default()
+            },
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::AutoPreferDevice,
+                ..Default::default()
+            }
+        ));
+
+        let (descriptor_sets, descriptor_pool) = vulkan_check!(state
+            .descriptor_pools
+            .allocate(&state.device, &[descriptor_layout]));
+        let descriptor_set = descriptor_sets[0];
+        let image_info = vk::DescriptorImageInfo {
+            sampler,
+            image_view: *image.view(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        unsafe {
+            state.device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet {
+                    dst_set: descriptor_set,
+                    dst_binding: 0,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    p_image_info: ptr::addr_of!(image_info),
+                    ..Default::default()
+                }],
+                &[],
+            )
+        };
+
+        Self {
+            image,
+            descriptor_set,
+            descriptor_pool,
+        }
+    }
+
+    // Copies tightly packed pixels into part of the image and waits for it. old_layout is
+    // UNDEFINED the first time, the rest of the image is garbage until it's written.
+    fn write(
+        &self,
+        state: &mut State,
+        pixels: &[u8],
+        old_layout: vk::ImageLayout,
+        offset: vk::Offset3D,
+        extent: vk::Extent3D,
+    ) {
+        let transfer_buffer = vulkan_check!(HostBuffer::new(
+            &state.allocator,
+            pixels.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        ));
+        unsafe { transfer_buffer.read(pixels, 0) };
+
+        let mut batch = TransferBatch::begin(&state.device, &state.transfer_pool);
+        batch.copy_to_image(
+            &state.device,
+            transfer_buffer.buffer(),
+            *self.image.handle(),
+            old_layout,
+            offset,
+            extent,
+        );
+        batch.submit(
+            &state.device,
+            &state.graphics_queue,
+            &state.transfer_pool,
+            &mut state.fence_pool,
+        );
+        transfer_buffer.destroy(&state.allocator);
+    }
+
+    fn destroy(mut self, state: &State) {
+        unsafe {
+            vulkan_check!(state
+                .device
+                .free_descriptor_sets(self.descriptor_pool, &[self.descriptor_set]))
+        };
+        self.image.destroy(&state.device, &state.allocator);
+    }
+}
+
+// Makes sure a buffer for the current frame has room for size bytes. They grow to the next power
+// of two and never shrink, and aren't in use once the frame's fence has been waited on.
+fn reserve_host_buffer(
+    state: &State,
+    buffer: &mut Option<HostBuffer>,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+) {
+    if buffer
+        .as_ref()
+        .map_or(false, |buffer| buffer.buffer().size() >= size)
+    {
+        return;
+    }
+    if let Some(buffer) = buffer.take() {
+        buffer.destroy(&state.allocator);
+    }
+    let size = size.next_power_of_two();
+    trace!(
+        "Allocating {size} byte {usage:?} buffer for frame {}",
+        state.frame_index
+    );
+    *buffer = Some(vulkan_check!(HostBuffer::new(
+        &state.allocator,
+        size,
+        usage,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )));
+}
+
+// The UI pipeline and egui's textures, plus vertex and index buffers for each frame that get
+// rewritten every frame and grow when they're too small
+#[cfg(feature = "egui")]
+pub struct GuiData {
+    descriptor_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    formats: (vk::Format, vk::Format),
+    sample_shading: Option<f32>,
+    sampler: vk::Sampler,
+    textures: HashMap<egui::TextureId, SampledImage>,
+    vertex_buffers: Vec<Option<HostBuffer>>,
+    index_buffers: Vec<Option<HostBuffer>>,
+}
+
+#[cfg(feature = "egui")]
+impl GuiData {
+    // Screen size in points and whether the target is sRGB
+    const PUSH_CONSTANT_SIZE: u32 = 16;
+
+    pub fn new(
+        state: &State,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<Self, rendersystem::ShaderError> {
+        debug!("Creating GUI pipeline");
+
+        let sampler_binding = vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        };
+        let descriptor_layout = unsafe {
+            vulkan_check!(state.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo {
+                    binding_count: 1,
+                    p_bindings: ptr::addr_of!(sampler_binding),
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: Self::PUSH_CONSTANT_SIZE,
+        };
+        let pipeline_layout = unsafe {
+            vulkan_check!(state.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo {
+                    set_layout_count: 1,
+                    p_set_layouts: ptr::addr_of!(descriptor_layout),
+                    push_constant_range_count: 1,
+                    p_push_constant_ranges: ptr::addr_of!(push_constant_range),
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        let pipeline =
+            match Self::create_pipeline(state, pipeline_layout, vertex_binary, fragment_binary) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    error!("Failed to create GUI pipeline: {err:?}");
+                    unsafe {
+                        state.device.destroy_pipeline_layout(
+                            pipeline_layout,
+                            Some(&State::get_allocation_callbacks()),
+                        );
+                        state.device.destroy_descriptor_set_layout(
+                            descriptor_layout,
+                            Some(&State::get_allocation_callbacks()),
+                        );
+                    }
+                    return Err(err);
+                }
+            };
+
+        let sampler = unsafe {
+            vulkan_check!(state.device.create_sampler(
+                &vk::SamplerCreateInfo {
+                    mag_filter: vk::Filter::LINEAR,
+                    min_filter: vk::Filter::LINEAR,
+                    address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        Ok(Self {
+            descriptor_layout,
+            pipeline_layout,
+            pipeline,
+            formats: state.swapchain.formats(),
+            sample_shading: state.sample_shading,
+            sampler,
+            textures: HashMap::new(),
+            vertex_buffers: (0..FRAME_COUNT).map(|_| None).collect(),
+            index_buffers: (0..FRAME_COUNT).map(|_| None).collect(),
+        })
+    }
+
+    pub fn is_stale(&self, state: &State) -> bool {
+        self.formats != state.swapchain.formats() || self.sample_shading != state.sample_shading
+    }
+
+    // Only the pipeline depends on the swapchain, the textures and buffers stay
+    pub fn recreate_pipeline(
+        &mut self,
+        state: &State,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<(), rendersystem::ShaderError> {
+        debug!("Recreating GUI pipeline");
+        let pipeline =
+            Self::create_pipeline(state, self.pipeline_layout, vertex_binary, fragment_binary)?;
+        unsafe {
+            state
+                .device
+                .destroy_pipeline(self.pipeline, Some(&State::get_allocation_callbacks()))
+        };
+        self.pipeline = pipeline;
+        self.formats = state.swapchain.formats();
+        self.sample_shading = state.sample_shading;
+        Ok(())
+    }
+
+    fn create_pipeline(
+        state: &State,
+        pipeline_layout: vk::PipelineLayout,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<vk::Pipeline, rendersystem::ShaderError> {
+        let vertex_module = ShaderData::create_shader_module(state, vertex_binary)?;
+        let fragment_module = match ShaderData::create_shader_module(state, fragment_binary) {
+            Ok(module) => module,
+            Err(err) => {
+                unsafe {
+                    state.device.destroy_shader_module(
+                        vertex_module,
+                        Some(&State::get_allocation_callbacks()),
+                    )
+                };
+                return Err(err);
+            }
+        };
+
+        let stages = [
+            vk::PipelineShaderStageCreateInfo {
+                stage: vk::ShaderStageFlags::VERTEX,
+                module: vertex_module,
+                p_name: b"main\0".as_ptr() as *const i8,
+                ..Default::default()
+            },
+            vk::PipelineShaderStageCreateInfo {
+                stage: vk::ShaderStageFlags::FRAGMENT,
+                module: fragment_module,
+                p_name: b"main\0".as_ptr() as *const i8,
+                ..Default::default()
+            },
+        ];
+
+        // egui's vertices are a position and texture coordinate in points and an sRGB colour
+        let binding = vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: mem::size_of::<egui::epaint::Vertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        };
+        let attributes = [
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: 0,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: 8,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 2,
+                binding: 0,
+                format: vk::Format::R8G8B8A8_UNORM,
+                offset: 16,
+            },
+        ];
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo {
+            vertex_binding_description_count: 1,
+            p_vertex_binding_descriptions: ptr::addr_of!(binding),
+            vertex_attribute_description_count: attributes.len() as u32,
+            p_vertex_attribute_descriptions: attributes.as_ptr(),
+            ..Default::default()
+        };
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            ..Default::default()
+        };
+        let viewport_state = vk::PipelineViewportStateCreateInfo {
+            viewport_count: 1,
+            scissor_count: 1,
+            ..Default::default()
+        };
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo {
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width: 1.0,
+            ..Default::default()
+        };
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            sample_shading_enable: state.sample_shading.is_some().into(),
+            min_sample_shading: state.sample_shading.unwrap_or(0.0),
+            ..Default::default()
+        };
+        // on top of everything, so no depth
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default();
+        // premultiplied alpha
+        let color_blend_attachment = vk::PipelineColorBlendAttachmentState {
+            blend_enable: vk::TRUE,
+            src_color_blend_factor: vk::BlendFactor::ONE,
+            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_DST_ALPHA,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        };
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+            attachment_count: 1,
+            p_attachments: ptr::addr_of!(color_blend_attachment),
+            ..Default::default()
+        };
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo {
+            dynamic_state_count: dynamic_states.len() as u32,
+            p_dynamic_states: dynamic_states.as_ptr(),
+            ..Default::default()
+        };
+        let color_format = state.swapchain.surface_format.format;
+        let rendering_info = vk::PipelineRenderingCreateInfo {
+            color_attachment_count: 1,
+            p_color_attachment_formats: ptr::addr_of!(color_format),
+            ..Default::default()
+        };
+
+        let result = unsafe {
+            state.device.create_graphics_pipelines(
+                vk::PipelineCache::null(),
+                &[vk::GraphicsPipelineCreateInfo {
+                    p_next: ptr::addr_of!(rendering_info) as *const ffi::c_void,
+                    stage_count: stages.len() as u32,
+                    p_stages: stages.as_ptr(),
+                    p_vertex_input_state: ptr::addr_of!(vertex_input_state),
+                    p_input_assembly_state: ptr::addr_of!(input_assembly_state),
+                    p_viewport_state: ptr::addr_of!(viewport_state),
+                    p_rasterization_state: ptr::addr_of!(rasterization_state),
+                    p_multisample_state: ptr::addr_of!(multisample_state),
+                    p_depth_stencil_state: ptr::addr_of!(depth_stencil_state),
+                    p_color_blend_state: ptr::addr_of!(color_blend_state),
+                    p_dynamic_state: ptr::addr_of!(dynamic_state),
+                    layout: pipeline_layout,
+                    ..Default::default()
+                }],
+                Some(&State::get_allocation_callbacks()),
+            )
+        };
+
+        unsafe {
+            state
+                .device
+                .destroy_shader_module(vertex_module, Some(&State::get_allocation_callbacks()));
+            state
+                .device
+                .destroy_shader_module(fragment_module, Some(&State::get_allocation_callbacks()));
+        }
+
+        result
+            .map(|pipelines| pipelines[0])
+            .map_err(|(_, err)| rendersystem::ShaderError::Backend(err))
+    }
+
+    // Textures can be updated while earlier frames are still drawing with them, so this waits for
+    // the GPU. egui only does it when the font atlas changes, which is rare enough.
+    pub fn update_textures(
+        &mut self,
+        state: &mut State,
+        deltas: &[(egui::TextureId, egui::epaint::ImageDelta)],
+    ) {
+        if deltas.is_empty() {
+            return;
+        }
+        state.wait_idle();
+
+        for (id, delta) in deltas {
+            let [width, height] = delta.image.size();
+            trace!("Updating {width}x{height} GUI texture {id:?}");
+
+            let pixels: Vec<u8> = match &delta.image {
+                egui::ImageData::Color(image) => image
+                    .pixels
+                    .iter()
+                    .flat_map(|pixel| pixel.to_array())
+                    .collect(),
+                egui::ImageData::Font(image) => image
+                    .srgba_pixels(None)
+                    .flat_map(|pixel| pixel.to_array())
+                    .collect(),
+            };
+
+            let (offset, old_layout) = match delta.pos {
+                Some([x, y]) => (
+                    vk::Offset3D {
+                        x: x as i32,
+                        y: y as i32,
+                        z: 0,
+                    },
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ),
+                None => {
+                    if let Some(texture) = self.textures.remove(id) {
+                        texture.destroy(state);
+                    }
+                    let texture = SampledImage::new(
+                        state,
+                        self.descriptor_layout,
+                        self.sampler,
+                        vk::Format::R8G8B8A8_UNORM,
+                        width as u32,
+                        height as u32,
+                    );
+                    self.textures.insert(*id, texture);
+                    (vk::Offset3D::default(), vk::ImageLayout::UNDEFINED)
+                }
+            };
+            let texture = match self.textures.get(id) {
+                Some(texture) => texture,
+                None => {
+                    warn!("GUI texture {id:?} was partially updated before being created");
+                    continue;
+                }
+            };
+            texture.write(
+                state,
+                &pixels,
+                old_layout,
+                offset,
+                vk::Extent3D {
+                    width: width as u32,
+                    height: height as u32,
+                    depth: 1,
+                },
+            );
+        }
+    }
+
+    pub fn free_textures(&mut self, state: &State, ids: &[egui::TextureId]) {
+        if ids.is_empty() {
+            return;
+        }
+        state.wait_idle();
+
+        for id in ids {
+            if let Some(texture) = self.textures.remove(id) {
+                trace!("Freeing GUI texture {id:?}");
+                texture.destroy(state);
+            }
+        }
+    }
+
+    fn reserve_buffers(
+        &mut self,
+        state: &State,
+        vertices_size: vk::DeviceSize,
+        indices_size: vk::DeviceSize,
+    ) {
+        reserve_host_buffer(
+            state,
+            &mut self.vertex_buffers[state.frame_index],
+            vertices_size,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+        reserve_host_buffer(
+            state,
+            &mut self.index_buffers[state.frame_index],
+            indices_size,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        );
+    }
+
+    pub fn destroy(mut self, state: &State) {
+        debug!("Destroying GUI");
+        for (_, texture) in self.textures.drain() {
+            texture.destroy(state);
+        }
+        for buffer in self
+            .vertex_buffers
+            .drain(..)
+            .chain(self.index_buffers.drain(..))
+            .flatten()
+        {
+            buffer.destroy(&state.allocator);
+        }
+        unsafe {
+            state
+                .device
+                .destroy_sampler(self.sampler, Some(&State::get_allocation_callbacks()));
+            state
+                .device
+                .destroy_pipeline(self.pipeline, Some(&State::get_allocation_callbacks()));
+            state.device.destroy_pipeline_layout(
+                self.pipeline_layout,
+                Some(&State::get_allocation_callbacks()),
+            );
+            state.device.destroy_descriptor_set_layout(
+                self.descriptor_layout,
+                Some(&State::get_allocation_callbacks()),
+            );
+        }
+    }
+}
+
+// A colour image for each format plus a depth image of its own, all at the render size so the
+// same viewport and scissor math works. The depth format is the window's, so shaders made for
+// the target can share their pipelines' depth state with everything else.
+pub struct RenderTargetData {
+    color_images: Vec<Image>,
+    depth_image: Image,
+    formats: Vec<rendersystem::TargetFormat>,
+    extent: vk::Extent2D,
+}
+
+impl RenderTargetData {
+    pub fn new(
+        state: &State,
+        name: &str,
+        formats: &[rendersystem::TargetFormat],
+    ) -> Result<Self, vk::Result> {
+        let extent = state.swapchain.render_extent;
+        debug!(
+            "Creating {}x{} render target {name} with formats {formats:?}",
+            extent.width, extent.height
+        );
+
+        let create_image = |format, usage, aspect_mask| {
+            Image::new(
+                &state.device,
+                &state.allocator,
+                format,
+                &mut vk::ImageCreateInfo {
+                    extent: vk::Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    },
+                    mip_levels: 1,
+                    array_layers: 1,
+                    samples: vk::SampleCountFlags::TYPE_1,
+                    usage,
+                    image_type: vk::ImageType::TYPE_2D,
+                    ..Default::default()
+                },
+                &mut vk::ImageViewCreateInfo {
+                    view_type: vk::ImageViewType::TYPE_2D,
+                    subresource_range: vk::ImageSubresourceRange {
+                        aspect_mask,
+                        ..COLOR_SUBRESOURCE_RANGE
+                    },
+                    ..Default::default()
+                },
+                &vk_mem::AllocationCreateInfo {
+                    usage: vk_mem::MemoryUsage::AutoPreferDevice,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let mut depth_image = create_image(
+            state.swapchain.depth_image.format(),
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            DEPTH_SUBRESOURCE_RANGE.aspect_mask,
+        )?;
+        let mut color_images: Vec<Image> = Vec::new();
+        for &format in formats {
+            match create_image(
+                State::target_format(format),
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+                vk::ImageAspectFlags::COLOR,
+            ) {
+                Ok(image) => color_images.push(image),
+                Err(err) => {
+                    for mut image in color_images {
+                        image.destroy(&state.device, &state.allocator);
+                    }
+                    depth_image.destroy(&state.device, &state.allocator);
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(Self {
+            color_images,
+            depth_image,
+            formats: formats.to_vec(),
+            extent,
+        })
+    }
+
+    // Has to be made again when the render size changes
+    pub fn is_stale(&self, state: &State) -> bool {
+        self.extent != state.swapchain.render_extent
+    }
+
+    pub fn destroy(&mut self, state: &State) {
+        for image in &mut self.color_images {
+            image.destroy(&state.device, &state.allocator);
+        }
+        self.depth_image.destroy(&state.device, &state.allocator);
+    }
+}
+
+// The glyph cache's pages as textures and the text pipeline, plus vertex and index buffers for
+// each frame like the GUI's. Text goes on top of the main view after the grid, without depth.
+pub struct TextData {
+    descriptor_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    formats: (vk::Format, vk::Format),
+    sample_shading: Option<f32>,
+    sampler: vk::Sampler,
+    pages: Vec<SampledImage>,
+    vertex_buffers: Vec<Option<HostBuffer>>,
+    index_buffers: Vec<Option<HostBuffer>>,
+}
+
+impl TextData {
+    // Screen size in pixels and whether the target is sRGB
+    const PUSH_CONSTANT_SIZE: u32 = 16;
+
+    pub fn new(
+        state: &State,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<Self, rendersystem::ShaderError> {
+        debug!("Creating text pipeline");
+
+        // same as a material's, one sampled image
+        let descriptor_layout = State::create_texture_layout(&state.device);
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: Self::PUSH_CONSTANT_SIZE,
+        };
+        let pipeline_layout = unsafe {
+            vulkan_check!(state.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo {
+                    set_layout_count: 1,
+                    p_set_layouts: ptr::addr_of!(descriptor_layout),
+                    push_constant_range_count: 1,
+                    p_push_constant_ranges: ptr::addr_of!(push_constant_range),
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        let pipeline =
+            match Self::create_pipeline(state, pipeline_layout, vertex_binary, fragment_binary) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    error!("Failed to create text pipeline: {err:?}");
+                    unsafe {
+                        state.device.destroy_pipeline_layout(
+                            pipeline_layout,
+                            Some(&State::get_allocation_callbacks()),
+                        );
+                        state.device.destroy_descriptor_set_layout(
+                            descriptor_layout,
+                            Some(&State::get_allocation_callbacks()),
+                        );
+                    }
+                    return Err(err);
+                }
+            };
+
+        // linear, since the render scale can stretch the glyphs
+        let sampler = unsafe {
+            vulkan_check!(state.device.create_sampler(
+                &vk::SamplerCreateInfo {
+                    mag_filter: vk::Filter::LINEAR,
+                    min_filter: vk::Filter::LINEAR,
+                    address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        Ok(Self {
+            descriptor_layout,
+            pipeline_layout,
+            pipeline,
+            formats: state.swapchain.formats(),
+            sample_shading: state.sample_shading,
+            sampler,
+            pages: Vec::new(),
+            vertex_buffers: (0..FRAME_COUNT).map(|_| None).collect(),
+            index_buffers: (0..FRAME_COUNT).map(|_| None).collect(),
+        })
+    }
+
+    pub fn is_stale(&self, state: &State) -> bool {
+        self.formats != state.swapchain.formats() || self.sample_shading != state.sample_shading
+    }
+
+    // Only the pipeline depends on the swapchain, the pages and buffers stay
+    pub fn recreate_pipeline(
+        &mut self,
+        state: &State,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<(), rendersystem::ShaderError> {
+        debug!("Recreating text pipeline");
+        let pipeline =
+            Self::create_pipeline(state, self.pipeline_layout, vertex_binary, fragment_binary)?;
+        unsafe {
+            state
+                .device
+                .destroy_pipeline(self.pipeline, Some(&State::get_allocation_callbacks()))
+        };
+        self.pipeline = pipeline;
+        self.formats = state.swapchain.formats();
+        self.sample_shading = state.sample_shading;
+        Ok(())
+    }
+
+    fn create_pipeline(
+        state: &State,
+        pipeline_layout: vk::PipelineLayout,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<vk::Pipeline, rendersystem::ShaderError> {
+        // a position in pixels, a texture coordinate in the page and a linear colour
+        let binding = vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: mem::size_of::<rendersystem::text::TextVertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        };
+        let attributes = [
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: 0,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: 8,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 2,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: 16,
+            },
+        ];
+
+        create_screen_pipeline(
+            state,
+            pipeline_layout,
+            vertex_binary,
+            fragment_binary,
+            &vk::PipelineVertexInputStateCreateInfo {
+                vertex_binding_description_count: 1,
+                p_vertex_binding_descriptions: ptr::addr_of!(binding),
+                vertex_attribute_description_count: attributes.len() as u32,
+                p_vertex_attribute_descriptions: attributes.as_ptr(),
+                ..Default::default()
+            },
+            // on top of the models
+            &vk::PipelineDepthStencilStateCreateInfo::default(),
+            &vk::PipelineColorBlendAttachmentState {
+                blend_enable: vk::TRUE,
+                src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+                dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                color_blend_op: vk::BlendOp::ADD,
+                src_alpha_blend_factor: vk::BlendFactor::ZERO,
+                dst_alpha_blend_factor: vk::BlendFactor::ONE,
+                alpha_blend_op: vk::BlendOp::ADD,
+                color_write_mask: vk::ColorComponentFlags::RGBA,
+            },
+        )
+    }
+
+    // Makes images for pages the cache added since the last call and copies the regions it
+    // changed in the others. New pages get copied whole, which is also how everything gets back
+    // after the device is recreated. Like the GUI's textures, this waits for the GPU first.
+    pub fn update_pages(
+        &mut self,
+        state: &mut State,
+        pages: &[image::GrayImage],
+        dirty: &[rendersystem::DirtyRegion],
+    ) {
+        let existing = self.pages.len();
+        let dirty: Vec<_> = dirty
+            .iter()
+            .filter(|region| region.page < existing)
+            .collect();
+        if pages.len() <= existing && dirty.is_empty() {
+            return;
+        }
+        state.wait_idle();
+
+        for page in &pages[existing.min(pages.len())..] {
+            let (width, height) = page.dimensions();
+            trace!(
+                "Creating {width}x{height} glyph cache page {}",
+                self.pages.len()
+            );
+            let image = SampledImage::new(
+                state,
+                self.descriptor_layout,
+                self.sampler,
+                vk::Format::R8_UNORM,
+                width,
+                height,
+            );
+            image.write(
+                state,
+                page.as_raw(),
+                vk::ImageLayout::UNDEFINED,
+                vk::Offset3D::default(),
+                vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                },
+            );
+            self.pages.push(image);
+        }
+
+        for region in dirty {
+            trace!("Updating glyph cache region {region:?}");
+            let pixels = image::imageops::crop_imm(
+                &pages[region.page],
+                region.x,
+                region.y,
+                region.width,
+                region.height,
+            )
+            .to_image();
+            self.pages[region.page].write(
+                state,
+                pixels.as_raw(),
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::Offset3D {
+                    x: region.x as i32,
+                    y: region.y as i32,
+                    z: 0,
+                },
+                vk::Extent3D {
+                    width: region.width,
+                    height: region.height,
+                    depth: 1,
+                },
+            );
+        }
+    }
+
+    fn reserve_buffers(
+        &mut self,
+        state: &State,
+        vertices_size: vk::DeviceSize,
+        indices_size: vk::DeviceSize,
+    ) {
+        reserve_host_buffer(
+            state,
+            &mut self.vertex_buffers[state.frame_index],
+            vertices_size,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+        reserve_host_buffer(
+            state,
+            &mut self.index_buffers[state.frame_index],
+            indices_size,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        );
+    }
+
+    pub fn destroy(mut self, state: &State) {
+        debug!("Destroying text");
+        for page in self.pages.drain(..) {
+            page.destroy(state);
+        }
+        for buffer in self
+            .vertex_buffers
+            .drain(..)
+            .chain(self.index_buffers.drain(..))
+            .flatten()
+        {
+            buffer.destroy(&state.allocator);
+        }
+        unsafe {
+            state
+                .device
+                .destroy_sampler(self.sampler, Some(&State::get_allocation_callbacks()));
+            state
+                .device
+                .destroy_pipeline(self.pipeline, Some(&State::get_allocation_callbacks()));
+            state.device.destroy_pipeline_layout(
+                self.pipeline_layout,
+                Some(&State::get_allocation_callbacks()),
+            );
+            state.device.destroy_descriptor_set_layout(
+                self.descriptor_layout,
+                Some(&State::get_allocation_callbacks()),
+            );
+        }
     }
 }
 
-pub struct ModelData {
-    offset: vk::DeviceSize,
-    vertices_size: vk::DeviceSize,
-    indices_size: vk::DeviceSize,
+// The ground grid is one fullscreen triangle, the fragment shader finds where each pixel's ray
+// hits the ground and draws the lines there. Its depth is the ground's, so it gets tested
+// against the models like anything else but doesn't write it, since it's mostly transparent.
+pub struct GridData {
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    formats: (vk::Format, vk::Format),
+    sample_shading: Option<f32>,
 }
 
-impl ModelData {
-    pub fn new(state: &State, name: &str, vertices_size: usize, indices_size: usize) -> Self {
-        Self {
+impl GridData {
+    // Colour, spacing and fade distance
+    const PUSH_CONSTANT_SIZE: u32 = 32;
+
+    pub fn new(
+        state: &State,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<Self, rendersystem::ShaderError> {
+        debug!("Creating grid pipeline");
+
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
             offset: 0,
-            vertices_size: vertices_size as vk::DeviceSize,
-            indices_size: indices_size as vk::DeviceSize,
+            size: Self::PUSH_CONSTANT_SIZE,
+        };
+        let pipeline_layout = unsafe {
+            vulkan_check!(state.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo {
+                    set_layout_count: 1,
+                    p_set_layouts: ptr::addr_of!(state.descriptor_layout),
+                    push_constant_range_count: 1,
+                    p_push_constant_ranges: ptr::addr_of!(push_constant_range),
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        let pipeline =
+            match Self::create_pipeline(state, pipeline_layout, vertex_binary, fragment_binary) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    error!("Failed to create grid pipeline: {err:?}");
+                    unsafe {
+                        state.device.destroy_pipeline_layout(
+                            pipeline_layout,
+                            Some(&State::get_allocation_callbacks()),
+                        )
+                    };
+                    return Err(err);
+                }
+            };
+
+        Ok(Self {
+            pipeline_layout,
+            pipeline,
+            formats: state.swapchain.formats(),
+            sample_shading: state.sample_shading,
+        })
+    }
+
+    pub fn is_stale(&self, state: &State) -> bool {
+        self.formats != state.swapchain.formats() || self.sample_shading != state.sample_shading
+    }
+
+    pub fn recreate_pipeline(
+        &mut self,
+        state: &State,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<(), rendersystem::ShaderError> {
+        debug!("Recreating grid pipeline");
+        let pipeline =
+            Self::create_pipeline(state, self.pipeline_layout, vertex_binary, fragment_binary)?;
+        unsafe {
+            state
+                .device
+                .destroy_pipeline(self.pipeline, Some(&State::get_allocation_callbacks()))
+        };
+        self.pipeline = pipeline;
+        self.formats = state.swapchain.formats();
+        self.sample_shading = state.sample_shading;
+        Ok(())
+    }
+
+    // Depth tested but not written, since it's mostly transparent
+    fn create_pipeline(
+        state: &State,
+        pipeline_layout: vk::PipelineLayout,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<vk::Pipeline, rendersystem::ShaderError> {
+        create_fullscreen_pipeline(
+            state,
+            pipeline_layout,
+            vertex_binary,
+            fragment_binary,
+            &vk::PipelineDepthStencilStateCreateInfo {
+                depth_test_enable: vk::TRUE,
+                depth_write_enable: vk::FALSE,
+                depth_compare_op: vk::CompareOp::LESS,
+                ..Default::default()
+            },
+            &vk::PipelineColorBlendAttachmentState {
+                blend_enable: vk::TRUE,
+                src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+                dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                color_blend_op: vk::BlendOp::ADD,
+                src_alpha_blend_factor: vk::BlendFactor::ZERO,
+                dst_alpha_blend_factor: vk::BlendFactor::ONE,
+                alpha_blend_op: vk::BlendOp::ADD,
+                color_write_mask: vk::ColorComponentFlags::RGBA,
+            },
+        )
+    }
+
+    pub fn destroy(self, state: &State) {
+        debug!("Destroying grid pipeline");
+        unsafe {
+            state
+                .device
+                .destroy_pipeline(self.pipeline, Some(&State::get_allocation_callbacks()));
+            state.device.destroy_pipeline_layout(
+                self.pipeline_layout,
+                Some(&State::get_allocation_callbacks()),
+            );
+        }
+    }
+}
+
+// One triangle covering the screen with no vertex input (it comes from the vertex index), for
+// the grid and the lighting pass. It draws to the window.
+fn create_fullscreen_pipeline(
+    state: &State,
+    pipeline_layout: vk::PipelineLayout,
+    vertex_binary: &[u8],
+    fragment_binary: &[u8],
+    depth_stencil_state: &vk::PipelineDepthStencilStateCreateInfo,
+    color_blend_attachment: &vk::PipelineColorBlendAttachmentState,
+) -> Result<vk::Pipeline, rendersystem::ShaderError> {
+    create_screen_pipeline(
+        state,
+        pipeline_layout,
+        vertex_binary,
+        fragment_binary,
+        &vk::PipelineVertexInputStateCreateInfo::default(),
+        depth_stencil_state,
+        color_blend_attachment,
+    )
+}
+
+// A triangle list pipeline for the window's formats with dynamic viewport and scissor, for
+// things drawn in the main view that aren't models
+fn create_screen_pipeline(
+    state: &State,
+    pipeline_layout: vk::PipelineLayout,
+    vertex_binary: &[u8],
+    fragment_binary: &[u8],
+    vertex_input_state: &vk::PipelineVertexInputStateCreateInfo,
+    depth_stencil_state: &vk::PipelineDepthStencilStateCreateInfo,
+    color_blend_attachment: &vk::PipelineColorBlendAttachmentState,
+) -> Result<vk::Pipeline, rendersystem::ShaderError> {
+    let vertex_module = ShaderData::create_shader_module(state, vertex_binary)?;
+    let fragment_module = match ShaderData::create_shader_module(state, fragment_binary) {
+        Ok(module) => module,
+        Err(err) => {
+            unsafe {
+                state
+                    .device
+                    .destroy_shader_module(vertex_module, Some(&State::get_allocation_callbacks()))
+            };
+            return Err(err);
+        }
+    };
+
+    let stages = [
+        vk::PipelineShaderStageCreateInfo {
+            stage: vk::ShaderStageFlags::VERTEX,
+            module: vertex_module,
+            p_name: b"main\0".as_ptr() as *const i8,
+            ..Default::default()
+        },
+        vk::PipelineShaderStageCreateInfo {
+            stage: vk::ShaderStageFlags::FRAGMENT,
+            module: fragment_module,
+            p_name: b"main\0".as_ptr() as *const i8,
+            ..Default::default()
+        },
+    ];
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo {
+        topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        ..Default::default()
+    };
+    let viewport_state = vk::PipelineViewportStateCreateInfo {
+        viewport_count: 1,
+        scissor_count: 1,
+        ..Default::default()
+    };
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo {
+        polygon_mode: vk::PolygonMode::FILL,
+        cull_mode: vk::CullModeFlags::NONE,
+        front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+        line_width: 1.0,
+        ..Default::default()
+    };
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo {
+        rasterization_samples: vk::SampleCountFlags::TYPE_1,
+        sample_shading_enable: state.sample_shading.is_some().into(),
+        min_sample_shading: state.sample_shading.unwrap_or(0.0),
+        ..Default::default()
+    };
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+        attachment_count: 1,
+        p_attachments: color_blend_attachment,
+        ..Default::default()
+    };
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo {
+        dynamic_state_count: dynamic_states.len() as u32,
+        p_dynamic_states: dynamic_states.as_ptr(),
+        ..Default::default()
+    };
+    let color_format = state.swapchain.surface_format.format;
+    let rendering_info = vk::PipelineRenderingCreateInfo {
+        color_attachment_count: 1,
+        p_color_attachment_formats: ptr::addr_of!(color_format),
+        depth_attachment_format: state.swapchain.depth_image.format(),
+        stencil_attachment_format: state.swapchain.depth_image.format(),
+        ..Default::default()
+    };
+
+    let result = unsafe {
+        state.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::GraphicsPipelineCreateInfo {
+                p_next: ptr::addr_of!(rendering_info) as *const ffi::c_void,
+                stage_count: stages.len() as u32,
+                p_stages: stages.as_ptr(),
+                p_vertex_input_state: vertex_input_state,
+                p_input_assembly_state: ptr::addr_of!(input_assembly_state),
+                p_viewport_state: ptr::addr_of!(viewport_state),
+                p_rasterization_state: ptr::addr_of!(rasterization_state),
+                p_multisample_state: ptr::addr_of!(multisample_state),
+                p_depth_stencil_state: depth_stencil_state,
+                p_color_blend_state: ptr::addr_of!(color_blend_state),
+                p_dynamic_state: ptr::addr_of!(dynamic_state),
+                layout: pipeline_layout,
+                ..Default::default()
+            }],
+            Some(&State::get_allocation_callbacks()),
+        )
+    };
+
+    unsafe {
+        state
+            .device
+            .destroy_shader_module(vertex_module, Some(&State::get_allocation_callbacks()));
+        state
+            .device
+            .destroy_shader_module(fragment_module, Some(&State::get_allocation_callbacks()));
+    }
+
+    result
+        .map(|pipelines| pipelines[0])
+        .map_err(|(_, err)| rendersystem::ShaderError::Backend(err))
+}
+
+// The deferred lighting pass, a fullscreen triangle that reads the G-buffer and adds up every
+// light for each pixel. It writes the geometry's depth too, so transparent things drawn forward
+// afterwards get hidden behind it.
+pub struct LightingData {
+    descriptor_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    formats: (vk::Format, vk::Format),
+    sample_shading: Option<f32>,
+    sampler: vk::Sampler,
+    lights: Vec<rendersystem::Light>,
+    // Per frame, grown like the GUI's buffers
+    light_buffers: Vec<Option<HostBuffer>>,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    descriptor_pool: vk::DescriptorPool,
+    // What each frame's set points at, it can't be written again once it's bound in the frame
+    written: Vec<Option<(Vec<vk::ImageView>, vk::Buffer)>>,
+}
+
+impl LightingData {
+    // Ambient colour, light count and whether the target is sRGB
+    const PUSH_CONSTANT_SIZE: u32 = 32;
+    // Position and range, colour and intensity
+    const LIGHT_SIZE: vk::DeviceSize = 32;
+    const GBUFFER_BINDINGS: u32 = 4;
+
+    pub fn new(
+        state: &State,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<Self, rendersystem::ShaderError> {
+        debug!("Creating lighting pipeline");
+
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = (0..=Self::GBUFFER_BINDINGS)
+            .map(|binding| vk::DescriptorSetLayoutBinding {
+                binding,
+                descriptor_type: if binding < Self::GBUFFER_BINDINGS {
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+                } else {
+                    vk::DescriptorType::STORAGE_BUFFER
+                },
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            })
+            .collect();
+        let descriptor_layout = unsafe {
+            vulkan_check!(state.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo {
+                    binding_count: bindings.len() as u32,
+                    p_bindings: bindings.as_ptr(),
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        let set_layouts = [state.descriptor_layout, descriptor_layout];
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: Self::PUSH_CONSTANT_SIZE,
+        };
+        let pipeline_layout = unsafe {
+            vulkan_check!(state.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo {
+                    set_layout_count: set_layouts.len() as u32,
+                    p_set_layouts: set_layouts.as_ptr(),
+                    push_constant_range_count: 1,
+                    p_push_constant_ranges: ptr::addr_of!(push_constant_range),
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        let pipeline =
+            match Self::create_pipeline(state, pipeline_layout, vertex_binary, fragment_binary) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    error!("Failed to create lighting pipeline: {err:?}");
+                    unsafe {
+                        state.device.destroy_pipeline_layout(
+                            pipeline_layout,
+                            Some(&State::get_allocation_callbacks()),
+                        );
+                        state.device.destroy_descriptor_set_layout(
+                            descriptor_layout,
+                            Some(&State::get_allocation_callbacks()),
+                        );
+                    }
+                    return Err(err);
+                }
+            };
+
+        // every pixel reads its own texel, so there's nothing to filter
+        let sampler = unsafe {
+            vulkan_check!(state.device.create_sampler(
+                &vk::SamplerCreateInfo {
+                    mag_filter: vk::Filter::NEAREST,
+                    min_filter: vk::Filter::NEAREST,
+                    address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                    ..Default::default()
+                },
+                Some(&State::get_allocation_callbacks())
+            ))
+        };
+
+        let (descriptor_sets, descriptor_pool) = vulkan_check!(state
+            .descriptor_pools
+            .allocate(&state.device, &[descriptor_layout; FRAME_COUNT]));
+
+        Ok(Self {
+            descriptor_layout,
+            pipeline_layout,
+            pipeline,
+            formats: state.swapchain.formats(),
+            sample_shading: state.sample_shading,
+            sampler,
+            lights: Vec::new(),
+            light_buffers: (0..FRAME_COUNT).map(|_| None).collect(),
+            descriptor_sets,
+            descriptor_pool,
+            written: vec![None; FRAME_COUNT],
+        })
+    }
+
+    pub fn is_stale(&self, state: &State) -> bool {
+        self.formats != state.swapchain.formats() || self.sample_shading != state.sample_shading
+    }
+
+    pub fn recreate_pipeline(
+        &mut self,
+        state: &State,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<(), rendersystem::ShaderError> {
+        debug!("Recreating lighting pipeline");
+        let pipeline =
+            Self::create_pipeline(state, self.pipeline_layout, vertex_binary, fragment_binary)?;
+        unsafe {
+            state
+                .device
+                .destroy_pipeline(self.pipeline, Some(&State::get_allocation_callbacks()))
+        };
+        self.pipeline = pipeline;
+        self.formats = state.swapchain.formats();
+        self.sample_shading = state.sample_shading;
+        Ok(())
+    }
+
+    // Depth is always written, the G-buffer's empty pixels are discarded instead. Lights are
+    // added up in the shader, so there's no blending.
+    fn create_pipeline(
+        state: &State,
+        pipeline_layout: vk::PipelineLayout,
+        vertex_binary: &[u8],
+        fragment_binary: &[u8],
+    ) -> Result<vk::Pipeline, rendersystem::ShaderError> {
+        create_fullscreen_pipeline(
+            state,
+            pipeline_layout,
+            vertex_binary,
+            fragment_binary,
+            &vk::PipelineDepthStencilStateCreateInfo {
+                depth_test_enable: vk::TRUE,
+                depth_write_enable: vk::TRUE,
+                depth_compare_op: vk::CompareOp::ALWAYS,
+                ..Default::default()
+            },
+            &vk::PipelineColorBlendAttachmentState {
+                blend_enable: vk::FALSE,
+                color_write_mask: vk::ColorComponentFlags::RGBA,
+                ..Default::default()
+            },
+        )
+    }
+
+    // The lights for this frame, the buffer isn't in use once the frame's fence has been waited on
+    fn write_lights(&mut self, state: &State) -> vk::Buffer {
+        let size = cmp::max(self.lights.len() as vk::DeviceSize, 1) * Self::LIGHT_SIZE;
+        let buffer = &mut self.light_buffers[state.frame_index];
+        if !buffer
+            .as_ref()
+            .is_some_and(|buffer| buffer.buffer().size() >= size)
+        {
+            if let Some(buffer) = buffer.take() {
+                buffer.destroy(&state.allocator);
+            }
+            let size = size.next_power_of_two();
+            trace!(
+                "Allocating {size} byte light buffer for frame {}",
+                state.frame_index
+            );
+            *buffer = Some(vulkan_check!(HostBuffer::new(
+                &state.allocator,
+                size,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )));
+        }
+        let buffer = buffer.as_ref().unwrap();
+
+        let data: Vec<u8> = self
+            .lights
+            .iter()
+            .flat_map(|light| {
+                let color = light.color.to_linear();
+                [
+                    light.position.x,
+                    light.position.y,
+                    light.position.z,
+                    light.range,
+                    color[0],
+                    color[1],
+                    color[2],
+                    light.intensity,
+                ]
+            })
+            .flat_map(|value| value.to_ne_bytes())
+            .collect();
+        unsafe { buffer.read(&data, 0) };
+
+        *buffer.buffer().handle()
+    }
+
+    fn gbuffer_views(gbuffer: &RenderTargetData) -> Vec<vk::ImageView> {
+        gbuffer
+            .color_images
+            .iter()
+            .map(|image| *image.view())
+            .collect()
+    }
+
+    fn is_prepared(&self, state: &State, gbuffer: &RenderTargetData) -> bool {
+        self.written[state.frame_index]
+            .as_ref()
+            .is_some_and(|(views, _)| *views == Self::gbuffer_views(gbuffer))
+    }
+
+    // Points this frame's set at the G-buffer and light buffer if it doesn't already
+    fn write_descriptor_set(
+        &mut self,
+        state: &State,
+        gbuffer: &RenderTargetData,
+        lights: vk::Buffer,
+    ) {
+        let views = Self::gbuffer_views(gbuffer);
+        let written = &mut self.written[state.frame_index];
+        if written
+            .as_ref()
+            .is_some_and(|(written_views, written_lights)| {
+                *written_views == views && *written_lights == lights
+            })
+        {
+            return;
+        }
+
+        let descriptor_set = self.descriptor_sets[state.frame_index];
+        let image_infos: Vec<vk::DescriptorImageInfo> = views
+            .iter()
+            .map(|&image_view| vk::DescriptorImageInfo {
+                sampler: self.sampler,
+                image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            })
+            .collect();
+        let buffer_info = vk::DescriptorBufferInfo {
+            buffer: lights,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        };
+        let mut writes: Vec<vk::WriteDescriptorSet> = image_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, image_info)| vk::WriteDescriptorSet {
+                dst_set: descriptor_set,
+                dst_binding: binding as u32,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                p_image_info: image_info,
+                ..Default::default()
+            })
+            .collect();
+        writes.push(vk::WriteDescriptorSet {
+            dst_set: descriptor_set,
+            dst_binding: Self::GBUFFER_BINDINGS,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            p_buffer_info: ptr::addr_of!(buffer_info),
+            ..Default::default()
+        });
+        unsafe { state.device.update_descriptor_sets(&writes, &[]) };
+
+        *written = Some((views, lights));
+    }
+
+    pub fn destroy(mut self, state: &State) {
+        debug!("Destroying lighting pipeline");
+        for buffer in self.light_buffers.drain(..).flatten() {
+            buffer.destroy(&state.allocator);
+        }
+        unsafe {
+            vulkan_check!(state
+                .device
+                .free_descriptor_sets(self.descriptor_pool, &self.descriptor_sets));
+            state
+                .device
+                .destroy_sampler(self.sampler, Some(&State::get_allocation_callbacks()));
+            state
+                .device
+                .destroy_pipeline(self.pipeline, Some(&State::get_allocation_callbacks()));
+            state.device.destroy_pipeline_layout(
+                self.pipeline_layout,
+                Some(&State::get_allocation_callbacks()),
+            );
+            state.device.destroy_descriptor_set_layout(
+                self.descriptor_layout,
+                Some(&State::get_allocation_callbacks()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::rendersystem::test_util::*;
+
+    #[test]
+    fn transfer_batch_round_trip() {
+        let Some((_video, mut render)) = headless_state(64, 64) else {
+            return;
+        };
+        let mut backend = render.backend.get();
+        let state = &mut *backend;
+
+        let usage = vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST;
+        let host = vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE;
+        let data: Vec<Vec<u8>> = [4, 256, 4096, 12]
+            .iter()
+            .enumerate()
+            .map(|(i, size)| (0..*size).map(|j| (i * 31 + j) as u8).collect())
+            .collect();
+        let buffers: Vec<_> = data
+            .iter()
+            .map(|data| {
+                let size = data.len() as vk::DeviceSize;
+                let source = HostBuffer::new(&state.allocator, size, usage, host).unwrap();
+                unsafe { source.read(data, 0) };
+                let device = Buffer::new(
+                    &state.allocator,
+                    size,
+                    usage,
+                    vk::MemoryPropertyFlags::empty(),
+                )
+                .unwrap();
+                let destination = HostBuffer::new(&state.allocator, size, usage, host).unwrap();
+                (source, device, destination)
+            })
+            .collect();
+
+        // there and back again, with an empty copy that has to be skipped in each batch
+        let mut batch = TransferBatch::begin(&state.device, &state.transfer_pool);
+        for (source, device, _) in &buffers {
+            batch.copy(&state.device, source.buffer(), device);
+        }
+        batch.copy_region(
+            &state.device,
+            buffers[0].0.buffer(),
+            &buffers[0].1,
+            vk::BufferCopy::default(),
+        );
+        batch.submit(
+            &state.device,
+            &state.graphics_queue,
+            &state.transfer_pool,
+            &mut state.fence_pool,
+        );
+        let mut batch = TransferBatch::begin(&state.device, &state.transfer_pool);
+        for (_, device, destination) in &buffers {
+            batch.copy(&state.device, device, destination.buffer());
+        }
+        batch.copy_region(
+            &state.device,
+            &buffers[0].1,
+            buffers[0].2.buffer(),
+            vk::BufferCopy::default(),
+        );
+        batch.submit(
+            &state.device,
+            &state.graphics_queue,
+            &state.transfer_pool,
+            &mut state.fence_pool,
+        );
+
+        for (data, (source, device, destination)) in data.iter().zip(buffers) {
+            let copied = unsafe {
+                std::slice::from_raw_parts(destination.address() as *const u8, data.len())
+            };
+            assert_eq!(copied, data.as_slice());
+            source.destroy(&state.allocator);
+            device.destroy(&state.allocator);
+            destination.destroy(&state.allocator);
+        }
+
+        drop(backend);
+        render.shutdown();
+    }
+
+    #[test]
+    fn allocation_callbacks_keep_layout() {
+        let scope = vk::SystemAllocationScope::OBJECT;
+        let user_data = ptr::null_mut();
+        for alignment in [1, 8, 64, 256] {
+            let mut p_memory = vulkan_alloc(user_data, 24, alignment, scope);
+            assert!(!p_memory.is_null());
+            assert_eq!(p_memory as usize % alignment, 0);
+            unsafe { ptr::write_bytes(p_memory as *mut u8, 0xAB, 24) };
+
+            // growing and shrinking have to keep the contents and the alignment
+            let mut size = 24;
+            for new_size in [100, 4096, 16, 1000] {
+                p_memory = vulkan_realloc(user_data, p_memory, new_size, alignment, scope);
+                assert!(!p_memory.is_null());
+                assert_eq!(p_memory as usize % alignment, 0);
+                let kept = size.min(new_size);
+                let contents = unsafe { std::slice::from_raw_parts(p_memory as *const u8, kept) };
+                assert!(contents.iter().all(|&byte| byte == 0xAB));
+                unsafe { ptr::write_bytes(p_memory as *mut u8, 0xAB, new_size) };
+                size = new_size;
+            }
+
+            vulkan_dealloc(user_data, p_memory);
         }
+
+        // null and zero sizes are allocating and freeing
+        let p_memory = vulkan_realloc(user_data, ptr::null_mut(), 32, 16, scope);
+        assert!(!p_memory.is_null());
+        assert!(vulkan_realloc(user_data, p_memory, 0, 16, scope).is_null());
+        vulkan_dealloc(user_data, ptr::null_mut());
     }
 }
@@ -0,0 +1,128 @@
+use log::{debug, info, warn};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+// Search paths for game files. Everything is looked up by a path relative to the mounts (like
+// shaders/basic.vert.spv), and the highest priority mount that has the file wins, with later
+// mounts winning ties. That way a mods directory or an in-memory overlay can replace files
+// without the loaders knowing. Clones share the same mounts.
+
+enum MountSource {
+    Directory(String),
+    Memory(HashMap<String, Vec<u8>>),
+}
+
+struct Mount {
+    name: String,
+    source: MountSource,
+    priority: i32,
+}
+
+#[derive(Clone, Default)]
+pub struct Vfs {
+    mounts: Arc<RwLock<Vec<Mount>>>,
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert(&self, mount: Mount) {
+        let mut mounts = self.mounts.write().unwrap();
+        // before anything with the same priority, so the newest one is searched first
+        let index = mounts
+            .iter()
+            .position(|other| other.priority <= mount.priority)
+            .unwrap_or(mounts.len());
+        mounts.insert(index, mount);
+    }
+
+    pub fn mount(&self, path: &str, priority: i32) {
+        let path = path.trim_end_matches(['/', '\\']).replace('\\', "/");
+        if !Path::new(&path).is_dir() {
+            warn!("Mounting {path}, which isn't a directory (yet)");
+        }
+        info!("Mounting {path} with priority {priority}");
+        self.insert(Mount {
+            name: path.clone(),
+            source: MountSource::Directory(path),
+            priority,
+        });
+    }
+
+    // Files by their path relative to the mounts, like shaders/basic.vert.spv
+    pub fn mount_memory(&self, name: &str, files: HashMap<String, Vec<u8>>, priority: i32) {
+        info!(
+            "Mounting {} file(s) in memory as {name} with priority {priority}",
+            files.len()
+        );
+        self.insert(Mount {
+            name: String::from(name),
+            source: MountSource::Memory(files),
+            priority,
+        });
+    }
+
+    pub fn unmount(&self, name: &str) -> bool {
+        let mut mounts = self.mounts.write().unwrap();
+        let Some(index) = mounts.iter().position(|mount| mount.name == name) else {
+            warn!("Can't unmount {name}, it isn't mounted");
+            return false;
+        };
+        info!("Unmounting {name}");
+        mounts.remove(index);
+        true
+    }
+
+    pub fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let mounts = self.mounts.read().unwrap();
+        for mount in mounts.iter() {
+            match &mount.source {
+                MountSource::Directory(directory) => {
+                    let full_path = format!("{directory}/{path}");
+                    match fs::read(&full_path) {
+                        Ok(data) => {
+                            debug!("Loaded {path} from {}", mount.name);
+                            return Ok(data);
+                        }
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                MountSource::Memory(files) => {
+                    if let Some(data) = files.get(path) {
+                        debug!("Loaded {path} from {}", mount.name);
+                        return Ok(data.clone());
+                    }
+                }
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{path} isn't in any of the {} mount(s)", mounts.len()),
+        ))
+    }
+
+    // For loaders that need a real path, so only directories are searched
+    pub fn resolve(&self, path: &str) -> Option<String> {
+        let mounts = self.mounts.read().unwrap();
+        mounts.iter().find_map(|mount| match &mount.source {
+            MountSource::Directory(directory) => {
+                let full_path = format!("{directory}/{path}");
+                if Path::new(&full_path).is_file() {
+                    debug!("Found {path} in {}", mount.name);
+                    Some(full_path)
+                } else {
+                    None
+                }
+            }
+            MountSource::Memory(_) => None,
+        })
+    }
+}
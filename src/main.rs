@@ -5,8 +5,6 @@
     ),
     windows_subsystem = "windows"
 )]
-#![feature(sync_unsafe_cell)]
-#![feature(vec_into_raw_parts)]
 
 mod engine;
 mod game;
@@ -26,14 +24,22 @@ pub struct Args {
     game: String,
     #[arg(short, long, default_value_t = false)]
     wait_for_debugger: bool,
+    #[arg(short, long)]
+    benchmark: Option<u32>,
 }
 
 fn main() {
     platform::init();
-    let mut engine_state = engine::State::init(Args::parse());
-
-    while engine_state.video().update() {
-        engine_state.update();
+    let args = Args::parse();
+    let benchmark = args.benchmark;
+    let mut engine_state = engine::State::init(args);
+
+    if let Some(frame_count) = benchmark {
+        engine_state.run_benchmark(frame_count);
+    } else {
+        while engine_state.video().update() {
+            engine_state.update();
+        }
     }
 
     engine_state.shutdown();
@@ -1,9 +1,13 @@
+#[cfg(feature = "gpu_telemetry")]
+pub mod telemetry;
 pub mod video;
 
 #[cfg(unix)]
 mod unix;
 #[cfg(any(windows, xbox))]
 mod win32;
+#[cfg(feature = "winit")]
+pub mod winit;
 
 mod platform_impl {
     #[cfg(unix)]
@@ -23,3 +27,14 @@ pub fn shutdown() {
 pub fn have_debugger() -> bool {
     unsafe { platform_impl::have_debugger() }
 }
+
+// For optional libraries like NVML, null if they aren't there
+#[cfg(feature = "gpu_telemetry")]
+pub fn load_library(name: &str) -> *mut std::ffi::c_void {
+    unsafe { platform_impl::load_library(name) }
+}
+
+#[cfg(feature = "gpu_telemetry")]
+pub fn get_symbol(library: *mut std::ffi::c_void, name: &str) -> *mut std::ffi::c_void {
+    unsafe { platform_impl::get_symbol(library, name) }
+}
@@ -0,0 +1,173 @@
+use crate::engine::rendersystem::GpuTelemetry;
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use std::{ffi, mem};
+
+// GPU utilization, temperature and VRAM use from wherever the platform has them. NVML comes with
+// NVIDIA's driver on both Linux and Windows, everything else only has hwmon on Linux (amdgpu fills
+// it in, i915 mostly doesn't).
+pub fn query(vendor_id: u32, device_id: u32) -> Option<GpuTelemetry> {
+    if vendor_id == NVIDIA_VENDOR_ID {
+        if let Some(telemetry) = NVML
+            .as_ref()
+            .and_then(|nvml| nvml.query(vendor_id, device_id))
+        {
+            return Some(telemetry);
+        }
+    }
+
+    #[cfg(unix)]
+    return crate::platform::platform_impl::telemetry::query(vendor_id, device_id);
+    #[cfg(not(unix))]
+    None
+}
+
+const NVIDIA_VENDOR_ID: u32 = 0x10de;
+
+#[cfg(unix)]
+const NVML_LIBRARY: &str = "libnvidia-ml.so.1";
+#[cfg(not(unix))]
+const NVML_LIBRARY: &str = "nvml.dll";
+
+// Loaded the first time it's needed and kept until the process exits
+static NVML: Lazy<Option<Nvml>> = Lazy::new(|| unsafe { Nvml::load() });
+
+type NvmlDevice = *mut ffi::c_void;
+
+#[repr(C)]
+#[derive(Default)]
+struct NvmlPciInfo {
+    bus_id_legacy: [ffi::c_char; 16],
+    domain: u32,
+    bus: u32,
+    device: u32,
+    // Device ID in the top half, vendor in the bottom
+    pci_device_id: u32,
+    pci_sub_system_id: u32,
+    bus_id: [ffi::c_char; 32],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct NvmlUtilization {
+    gpu: u32,
+    memory: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct NvmlMemory {
+    total: u64,
+    free: u64,
+    used: u64,
+}
+
+// Only the bits that get used, everything returns 0 on success
+struct Nvml {
+    device_get_count: unsafe extern "C" fn(*mut u32) -> ffi::c_int,
+    device_get_handle_by_index: unsafe extern "C" fn(u32, *mut NvmlDevice) -> ffi::c_int,
+    device_get_pci_info: unsafe extern "C" fn(NvmlDevice, *mut NvmlPciInfo) -> ffi::c_int,
+    device_get_utilization_rates:
+        unsafe extern "C" fn(NvmlDevice, *mut NvmlUtilization) -> ffi::c_int,
+    device_get_temperature: unsafe extern "C" fn(NvmlDevice, u32, *mut u32) -> ffi::c_int,
+    device_get_memory_info: unsafe extern "C" fn(NvmlDevice, *mut NvmlMemory) -> ffi::c_int,
+}
+
+// NVML's handles are only used while NVML is loaded, which is forever
+unsafe impl Send for Nvml {}
+unsafe impl Sync for Nvml {}
+
+impl Nvml {
+    const TEMPERATURE_GPU: u32 = 0;
+
+    unsafe fn load() -> Option<Self> {
+        let library = crate::platform::load_library(NVML_LIBRARY);
+        if library.is_null() {
+            debug!("{NVML_LIBRARY} isn't there, no NVML telemetry");
+            return None;
+        }
+
+        let symbol = |name: &str| {
+            let symbol = crate::platform::get_symbol(library, name);
+            if symbol.is_null() {
+                warn!("{NVML_LIBRARY} doesn't have {name}");
+            }
+            symbol
+        };
+        let init = symbol("nvmlInit_v2");
+        let symbols = [
+            symbol("nvmlDeviceGetCount_v2"),
+            symbol("nvmlDeviceGetHandleByIndex_v2"),
+            symbol("nvmlDeviceGetPciInfo_v3"),
+            symbol("nvmlDeviceGetUtilizationRates"),
+            symbol("nvmlDeviceGetTemperature"),
+            symbol("nvmlDeviceGetMemoryInfo"),
+        ];
+        if init.is_null() || symbols.iter().any(|symbol| symbol.is_null()) {
+            return None;
+        }
+
+        let init: unsafe extern "C" fn() -> ffi::c_int = mem::transmute(init);
+        let result = init();
+        if result != 0 {
+            warn!("Failed to initialize NVML: {result}");
+            return None;
+        }
+
+        debug!("Loaded NVML for GPU telemetry");
+        Some(Self {
+            device_get_count: mem::transmute(symbols[0]),
+            device_get_handle_by_index: mem::transmute(symbols[1]),
+            device_get_pci_info: mem::transmute(symbols[2]),
+            device_get_utilization_rates: mem::transmute(symbols[3]),
+            device_get_temperature: mem::transmute(symbols[4]),
+            device_get_memory_info: mem::transmute(symbols[5]),
+        })
+    }
+
+    // The first device with the same IDs, it can't tell identical GPUs apart
+    fn find_device(&self, vendor_id: u32, device_id: u32) -> Option<NvmlDevice> {
+        let mut count = 0;
+        if unsafe { (self.device_get_count)(&mut count) } != 0 {
+            return None;
+        }
+
+        (0..count).find_map(|index| {
+            let mut device = std::ptr::null_mut();
+            let mut pci_info = NvmlPciInfo::default();
+            unsafe {
+                if (self.device_get_handle_by_index)(index, &mut device) != 0
+                    || (self.device_get_pci_info)(device, &mut pci_info) != 0
+                {
+                    return None;
+                }
+            }
+            (pci_info.pci_device_id == (device_id << 16 | vendor_id)).then_some(device)
+        })
+    }
+
+    fn query(&self, vendor_id: u32, device_id: u32) -> Option<GpuTelemetry> {
+        let device = self.find_device(vendor_id, device_id)?;
+
+        let mut utilization = NvmlUtilization::default();
+        let mut temperature = 0;
+        let mut memory = NvmlMemory::default();
+        let (utilization, temperature, memory) = unsafe {
+            (
+                ((self.device_get_utilization_rates)(device, &mut utilization) == 0)
+                    .then_some(utilization),
+                ((self.device_get_temperature)(device, Self::TEMPERATURE_GPU, &mut temperature)
+                    == 0)
+                    .then_some(temperature),
+                ((self.device_get_memory_info)(device, &mut memory) == 0).then_some(memory),
+            )
+        };
+
+        Some(GpuTelemetry {
+            utilization: utilization.map(|utilization| utilization.gpu as f32),
+            temperature: temperature.map(|temperature| temperature as f32),
+            vram_used: memory.as_ref().map(|memory| memory.used),
+            vram_total: memory.as_ref().map(|memory| memory.total),
+        })
+    }
+}
@@ -1,5 +1,10 @@
+#[cfg(feature = "gpu_telemetry")]
+pub mod telemetry;
 pub mod video;
 
+#[cfg(feature = "gpu_telemetry")]
+use std::ffi;
+
 pub unsafe fn init() {}
 
 pub unsafe fn shutdown() {}
@@ -7,3 +12,25 @@ pub unsafe fn shutdown() {}
 pub unsafe fn have_debugger() -> bool {
     false
 }
+
+#[cfg(feature = "gpu_telemetry")]
+#[link(name = "dl")]
+extern "C" {
+    fn dlopen(filename: *const ffi::c_char, flags: ffi::c_int) -> *mut ffi::c_void;
+    fn dlsym(handle: *mut ffi::c_void, symbol: *const ffi::c_char) -> *mut ffi::c_void;
+}
+
+#[cfg(feature = "gpu_telemetry")]
+const RTLD_NOW: ffi::c_int = 2;
+
+#[cfg(feature = "gpu_telemetry")]
+pub unsafe fn load_library(name: &str) -> *mut ffi::c_void {
+    let name = ffi::CString::new(name).unwrap();
+    dlopen(name.as_ptr(), RTLD_NOW)
+}
+
+#[cfg(feature = "gpu_telemetry")]
+pub unsafe fn get_symbol(library: *mut ffi::c_void, name: &str) -> *mut ffi::c_void {
+    let name = ffi::CString::new(name).unwrap();
+    dlsym(library, name.as_ptr())
+}
@@ -0,0 +1,53 @@
+use crate::engine::rendersystem::GpuTelemetry;
+use std::{fs, path::Path};
+
+// The kernel driver's files in sysfs. amdgpu has all of them, other drivers have some or none.
+pub fn query(vendor_id: u32, device_id: u32) -> Option<GpuTelemetry> {
+    let device = find_device(vendor_id, device_id)?;
+
+    let telemetry = GpuTelemetry {
+        utilization: read_number(&device.join("gpu_busy_percent")).map(|busy| busy as f32),
+        temperature: temperature(&device),
+        vram_used: read_number(&device.join("mem_info_vram_used")),
+        vram_total: read_number(&device.join("mem_info_vram_total")),
+    };
+    (telemetry != GpuTelemetry::default()).then_some(telemetry)
+}
+
+// /sys/class/drm/cardN/device for the first card with the same PCI IDs. The connectors
+// (cardN-DP-1 and so on) are in there too.
+fn find_device(vendor_id: u32, device_id: u32) -> Option<std::path::PathBuf> {
+    fs::read_dir("/sys/class/drm")
+        .ok()?
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("card"))
+                .is_some_and(|index| index.chars().all(|c| c.is_ascii_digit()))
+        })
+        .map(|entry| entry.path().join("device"))
+        .find(|device| {
+            read_hex(&device.join("vendor")) == Some(vendor_id)
+                && read_hex(&device.join("device")) == Some(device_id)
+        })
+}
+
+// The first hwmon temperature is the edge temperature, in millidegrees
+fn temperature(device: &Path) -> Option<f32> {
+    fs::read_dir(device.join("hwmon"))
+        .ok()?
+        .flatten()
+        .find_map(|hwmon| read_number(&hwmon.path().join("temp1_input")))
+        .map(|millidegrees| millidegrees as f32 / 1000.0)
+}
+
+fn read_number(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn read_hex(path: &Path) -> Option<u32> {
+    let value = fs::read_to_string(path).ok()?;
+    u32::from_str_radix(value.trim().trim_start_matches("0x"), 16).ok()
+}
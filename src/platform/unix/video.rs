@@ -1,18 +1,59 @@
+use crate::engine::rendersystem::RenderEvent;
 use ash::{extensions, vk};
-use log::{debug, info};
-use std::{ffi, mem};
+use log::{debug, info, warn};
+use std::{ffi, mem, time};
+use xcb::randr;
 use xcb::x;
 use xcb::Xid;
 
+#[derive(Clone, Debug)]
+pub struct Monitor {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub primary: bool,
+}
+
+// Where the window starts out
+#[derive(Clone, Debug, Default)]
+pub struct VideoConfig {
+    // A name from list_monitors (like DP-1), None or one that isn't connected is the primary one
+    pub monitor: Option<String>,
+}
+
 pub struct State {
     connection: xcb::Connection,
+    root: x::Window,
     window: x::Window,
     title: String,
+    x: i32,
+    y: i32,
     width: u32,
     height: u32,
+    // What set_geometry asked for, to tell if the window manager changed it
+    requested_geometry: Option<(i32, i32, u32, u32)>,
+    // Geometry, decorated and always on top from before borderless fullscreen changed them
+    saved_window: Option<((i32, i32, u32, u32), bool, bool)>,
+    borderless_fullscreen: bool,
+    double_click_time: time::Duration,
+    double_click_distance: f32,
+    // button, when and where
+    last_click: Option<(u32, time::Instant, f32, f32)>,
+    monitors: Vec<Monitor>,
+    monitor: usize,
+    decorated: bool,
+    always_on_top: bool,
     resized: bool,
     focused: bool,
+    // Unmapped, which is what minimizing does
+    minimized: bool,
+    // Some window managers send a 0x0 or 1x1 ConfigureNotify before the real one, the size is
+    // only trusted once a bigger one arrives or the window's shown
+    configured: bool,
     closed: bool,
+    events: Vec<RenderEvent>,
 }
 
 fn get_xcb_atom(connection: &xcb::Connection, name: &str) -> x::Atom {
@@ -23,18 +64,164 @@ fn get_xcb_atom(connection: &xcb::Connection, name: &str) -> x::Atom {
     connection.wait_for_reply(reply).unwrap().atom()
 }
 
+// Atoms in _NET_SUPPORTED are the EWMH features the window manager says it handles
+fn wm_supports(connection: &xcb::Connection, root: x::Window, atom: x::Atom) -> bool {
+    let supported = get_xcb_atom(connection, "_NET_SUPPORTED");
+    if supported == x::ATOM_NONE {
+        return false;
+    }
+    let cookie = connection.send_request(&x::GetProperty {
+        delete: false,
+        window: root,
+        property: supported,
+        r#type: x::ATOM_ATOM,
+        long_offset: 0,
+        long_length: u32::MAX,
+    });
+    match connection.wait_for_reply(cookie) {
+        Ok(reply) => reply.value::<x::Atom>().contains(&atom),
+        Err(_) => false,
+    }
+}
+
+fn have_randr(connection: &xcb::Connection) -> bool {
+    connection
+        .active_extensions()
+        .any(|ext| ext == xcb::Extension::RandR)
+}
+
+// Used when the screen says it's 0x0, which some virtual servers do
+const FALLBACK_SCREEN_SIZE: (u32, u32) = (1280, 720);
+
+fn get_monitors(connection: &xcb::Connection, screen: &x::Screen) -> Vec<Monitor> {
+    let (width, height) = if screen.width_in_pixels() == 0 || screen.height_in_pixels() == 0 {
+        warn!(
+            "Screen reports a size of {}x{}, assuming {}x{}",
+            screen.width_in_pixels(),
+            screen.height_in_pixels(),
+            FALLBACK_SCREEN_SIZE.0,
+            FALLBACK_SCREEN_SIZE.1
+        );
+        FALLBACK_SCREEN_SIZE
+    } else {
+        (
+            screen.width_in_pixels() as u32,
+            screen.height_in_pixels() as u32,
+        )
+    };
+    let fallback = vec![Monitor {
+        name: String::from("default"),
+        x: 0,
+        y: 0,
+        width,
+        height,
+        primary: true,
+    }];
+
+    if !have_randr(connection) {
+        warn!("RandR is not available, assuming a single monitor");
+        return fallback;
+    }
+
+    let cookie = connection.send_request(&randr::GetMonitors {
+        window: screen.root(),
+        get_active: true,
+    });
+    let reply = match connection.wait_for_reply(cookie) {
+        Ok(reply) => reply,
+        Err(err) => {
+            warn!("Failed to get monitors, assuming a single monitor: {err}");
+            return fallback;
+        }
+    };
+
+    let monitors: Vec<Monitor> = reply
+        .monitors()
+        .map(|info| {
+            let cookie = connection.send_request(&x::GetAtomName { atom: info.name() });
+            let name = match connection.wait_for_reply(cookie) {
+                Ok(reply) => reply.name().to_utf8().into_owned(),
+                Err(_) => String::from("unknown"),
+            };
+
+            Monitor {
+                name,
+                x: info.x() as i32,
+                y: info.y() as i32,
+                width: info.width() as u32,
+                height: info.height() as u32,
+                primary: info.primary(),
+            }
+        })
+        .filter(|monitor| monitor.width > 0 && monitor.height > 0)
+        .collect();
+
+    if monitors.is_empty() {
+        fallback
+    } else {
+        monitors
+    }
+}
+
 impl State {
     pub fn init() -> Self {
+        Self::init_with(VideoConfig::default())
+    }
+
+    pub fn init_with(config: VideoConfig) -> Self {
         info!("XCB video initialization started");
+        debug!("{config:#?}");
 
-        let (connection, screen_num) = xcb::Connection::connect(None).unwrap();
-        let screen = connection
-            .get_setup()
-            .roots()
-            .nth(screen_num as usize)
-            .unwrap();
-        let height = (screen.height_in_pixels() as f32 / 1.5) as u32;
-        let width = height * 16 / 9;
+        let (connection, screen_num) =
+            xcb::Connection::connect_with_extensions(None, &[], &[xcb::Extension::RandR]).unwrap();
+        let setup = connection.get_setup();
+        let screen = match setup.roots().nth(screen_num as usize) {
+            Some(screen) => screen,
+            None => {
+                warn!("Screen {screen_num} doesn't exist, using the first one");
+                setup.roots().next().expect("X server has no screens")
+            }
+        };
+        let root = screen.root();
+
+        let monitors = get_monitors(&connection, screen);
+        for (i, monitor) in monitors.iter().enumerate() {
+            debug!(
+                "Monitor {i}: {} {}x{} at ({}, {}){}",
+                monitor.name,
+                monitor.width,
+                monitor.height,
+                monitor.x,
+                monitor.y,
+                if monitor.primary { " (primary)" } else { "" }
+            );
+        }
+        let primary = monitors
+            .iter()
+            .position(|monitor| monitor.primary)
+            .unwrap_or(0);
+        let monitor = match &config.monitor {
+            Some(name) => match monitors.iter().position(|monitor| &monitor.name == name) {
+                Some(monitor) => monitor,
+                None => {
+                    warn!("Monitor {name} isn't connected, using the primary one");
+                    primary
+                }
+            },
+            None => primary,
+        };
+        let (x, y, width, height) = {
+            let monitor = &monitors[monitor];
+            // same aspect ratio as the monitor, so it fits on ultrawide and portrait monitors too
+            let width = (monitor.width as f32 / 1.5) as u32;
+            let height = (monitor.height as f32 / 1.5) as u32;
+            (
+                monitor.x + (monitor.width - width) as i32 / 2,
+                monitor.y + (monitor.height - height) as i32 / 2,
+                width,
+                height,
+            )
+        };
         let window = connection.generate_id();
         let title = format!(
             "{} v{}.{}.{} by {}",
@@ -48,9 +235,9 @@ impl State {
         let cookie = connection.send_request_checked(&x::CreateWindow {
             depth: x::COPY_FROM_PARENT as u8,
             wid: window,
-            parent: screen.root(),
-            x: 0,
-            y: 0,
+            parent: root,
+            x: x as i16,
+            y: y as i16,
             width: width as u16,
             height: height as u16,
             border_width: 0,
@@ -58,7 +245,15 @@ impl State {
             visual: screen.root_visual(),
             value_list: &[
                 x::Cw::BackPixel(screen.black_pixel()),
-                x::Cw::EventMask(x::EventMask::FOCUS_CHANGE | x::EventMask::STRUCTURE_NOTIFY),
+                x::Cw::EventMask(
+                    x::EventMask::FOCUS_CHANGE
+                        | x::EventMask::STRUCTURE_NOTIFY
+                        | x::EventMask::KEY_PRESS
+                        | x::EventMask::KEY_RELEASE
+                        | x::EventMask::POINTER_MOTION
+                        | x::EventMask::BUTTON_PRESS
+                        | x::EventMask::BUTTON_RELEASE,
+                ),
             ],
         });
         if connection.check_request(cookie).is_err() {
@@ -87,7 +282,21 @@ impl State {
             data: &[delete_data],
         });
 
+        if have_randr(&connection) {
+            connection.send_request(&randr::SelectInput {
+                window: root,
+                enable: randr::NotifyMask::SCREEN_CHANGE
+                    | randr::NotifyMask::CRTC_CHANGE
+                    | randr::NotifyMask::OUTPUT_CHANGE,
+            });
+        }
+
         connection.send_request(&x::MapWindow { window });
+        // window managers tend to ignore the position given when the window is created
+        connection.send_request(&x::ConfigureWindow {
+            window,
+            value_list: &[x::ConfigWindow::X(x), x::ConfigWindow::Y(y)],
+        });
 
         if connection.flush().is_err() {
             panic!("Failed to flush XCB connection");
@@ -97,22 +306,62 @@ impl State {
 
         State {
             connection,
+            root,
             window,
             title,
+            x,
+            y,
             width,
             height,
+            requested_geometry: None,
+            saved_window: None,
+            borderless_fullscreen: false,
+            double_click_time: time::Duration::from_millis(500),
+            double_click_distance: 4.0,
+            last_click: None,
+            monitors,
+            monitor,
+            decorated: true,
+            always_on_top: false,
             resized: false,
             focused: false,
+            minimized: false,
+            configured: false,
             closed: false,
+            events: Vec::new(),
         }
     }
 
     pub fn update(&mut self) -> bool {
-        if let Ok(Some(xcb::Event::X(event))) = self.connection.poll_for_event() {
-            match event {
+        match self.connection.poll_for_event() {
+            Ok(Some(xcb::Event::X(event))) => match event {
                 x::Event::ConfigureNotify(ev) => {
                     let new_width = ev.width() as u32;
                     let new_height = ev.height() as u32;
+                    if !self.configured && new_width > 1 && new_height > 1 {
+                        debug!("Window configured at {new_width}x{new_height}");
+                        self.configured = true;
+                    }
+                    // the event is relative to the window manager's frame if there is one
+                    let cookie = self.connection.send_request(&x::TranslateCoordinates {
+                        src_window: self.window,
+                        dst_window: self.root,
+                        src_x: 0,
+                        src_y: 0,
+                    });
+                    if let Ok(reply) = self.connection.wait_for_reply(cookie) {
+                        self.x = reply.dst_x() as i32;
+                        self.y = reply.dst_y() as i32;
+                    }
+                    if let Some(requested) = self.requested_geometry.take() {
+                        let actual = (self.x, self.y, new_width, new_height);
+                        if actual != requested {
+                            debug!(
+                                "Window manager changed the requested geometry {requested:?} \
+                                 to {actual:?}"
+                            );
+                        }
+                    }
 
                     if new_width != self.width || new_height != self.height {
                         self.resized = true;
@@ -122,30 +371,370 @@ impl State {
                         );
                         self.width = new_width;
                         self.height = new_height;
+                        self.events
+                            .push(RenderEvent::Resized(new_width, new_height));
                     }
                 }
+                x::Event::MapNotify(_) => {
+                    info!("Window shown");
+                    self.minimized = false;
+                    // without a window manager nothing configures it, it keeps the size it was
+                    // made with
+                    self.configured = true;
+                }
+                x::Event::UnmapNotify(_) => {
+                    info!("Window hidden");
+                    self.minimized = true;
+                }
                 x::Event::FocusIn(_) => {
                     info!("Window focused");
                     self.focused = true;
+                    self.events.push(RenderEvent::Focused(true));
                 }
                 x::Event::FocusOut(_) => {
                     info!("Window unfocused");
                     self.focused = false;
+                    self.events.push(RenderEvent::Focused(false));
+                }
+                x::Event::KeyPress(ev) => self.events.push(RenderEvent::Key {
+                    code: ev.detail() as u32,
+                    pressed: true,
+                }),
+                x::Event::KeyRelease(ev) => self.events.push(RenderEvent::Key {
+                    code: ev.detail() as u32,
+                    pressed: false,
+                }),
+                x::Event::MotionNotify(ev) => self.events.push(RenderEvent::MouseMoved {
+                    x: ev.event_x() as f32,
+                    y: ev.event_y() as f32,
+                }),
+                x::Event::ButtonPress(ev) => {
+                    self.push_button(ev.detail(), true, ev.event_x(), ev.event_y())
+                }
+                x::Event::ButtonRelease(ev) => {
+                    self.push_button(ev.detail(), false, ev.event_x(), ev.event_y())
                 }
                 x::Event::ClientMessage(ev) => {
                     if let x::ClientMessageData::Data32(atom) = ev.data() {
                         let delete_atom = get_xcb_atom(&self.connection, "WM_DELETE_WINDOW");
                         let atom = unsafe { mem::transmute::<u32, x::Atom>(atom[0]) };
                         self.closed = atom == delete_atom;
+                        if self.closed {
+                            self.events.push(RenderEvent::Closed);
+                        }
                     }
                 }
                 _ => {}
-            }
+            },
+            Ok(Some(xcb::Event::RandR(randr::Event::ScreenChangeNotify(_))))
+            | Ok(Some(xcb::Event::RandR(randr::Event::Notify(_)))) => self.refresh_monitors(),
+            _ => {}
         }
 
         !self.closed
     }
 
+    // X numbers them left, middle, right, then the scroll wheel as buttons 4 and 5 (and 6 and 7
+    // for sideways), which only send a press per notch
+    fn push_button(&mut self, button: u8, pressed: bool, x: i16, y: i16) {
+        let scroll = match button {
+            4 => Some((0.0, 1.0)),
+            5 => Some((0.0, -1.0)),
+            6 => Some((-1.0, 0.0)),
+            7 => Some((1.0, 0.0)),
+            _ => None,
+        };
+        if let Some((x, y)) = scroll {
+            if pressed {
+                self.events.push(RenderEvent::Scroll { x, y });
+            }
+            return;
+        }
+
+        let button = match button {
+            1 => 0,
+            2 => 2,
+            3 => 1,
+            _ => return,
+        };
+        self.events
+            .push(RenderEvent::MouseButton { button, pressed });
+        if pressed {
+            self.detect_double_click(button, x as f32, y as f32);
+        }
+    }
+
+    fn detect_double_click(&mut self, button: u32, x: f32, y: f32) {
+        let now = time::Instant::now();
+        if let Some((last_button, last_time, last_x, last_y)) = self.last_click {
+            if last_button == button
+                && now - last_time <= self.double_click_time
+                && (x - last_x).hypot(y - last_y) <= self.double_click_distance
+            {
+                self.events.push(RenderEvent::DoubleClick { button });
+                // a third click starts over instead of being another double click
+                self.last_click = None;
+                return;
+            }
+        }
+        self.last_click = Some((button, now, x, y));
+    }
+
+    // How far apart two presses can be and still count as a double click, in time and pixels
+    pub fn set_double_click(&mut self, time: time::Duration, distance: f32) {
+        self.double_click_time = time;
+        self.double_click_distance = distance;
+    }
+
+    fn refresh_monitors(&mut self) {
+        let screen = self
+            .connection
+            .get_setup()
+            .roots()
+            .find(|screen| screen.root() == self.root)
+            .unwrap();
+        self.monitors = get_monitors(&self.connection, screen);
+        info!(
+            "Monitor configuration changed, {} monitor(s) now connected",
+            self.monitors.len()
+        );
+
+        if self.monitor >= self.monitors.len() {
+            info!("Current monitor was disconnected, moving window to the primary monitor");
+            let primary = self
+                .monitors
+                .iter()
+                .position(|monitor| monitor.primary)
+                .unwrap_or(0);
+            self.move_to_monitor(primary);
+        }
+    }
+
+    pub fn list_monitors(&self) -> &Vec<Monitor> {
+        &self.monitors
+    }
+
+    pub fn move_to_monitor(&mut self, index: usize) -> bool {
+        let Some(monitor) = self.monitors.get(index) else {
+            warn!(
+                "Can't move window to monitor {index}, only {} are connected",
+                self.monitors.len()
+            );
+            return false;
+        };
+
+        let width = self.width.min(monitor.width);
+        let height = self.height.min(monitor.height);
+        let x = monitor.x + (monitor.width - width) as i32 / 2;
+        let y = monitor.y + (monitor.height - height) as i32 / 2;
+        info!(
+            "Moving window to monitor {index} ({}) at ({x}, {y})",
+            monitor.name
+        );
+
+        self.connection.send_request(&x::ConfigureWindow {
+            window: self.window,
+            value_list: &[
+                x::ConfigWindow::X(x),
+                x::ConfigWindow::Y(y),
+                x::ConfigWindow::Width(width),
+                x::ConfigWindow::Height(height),
+            ],
+        });
+        if self.connection.flush().is_err() {
+            warn!("Failed to flush XCB connection");
+            return false;
+        }
+
+        self.monitor = index;
+        true
+    }
+
+    // _MOTIF_WM_HINTS is ancient but it's what every window manager that can turn decorations off
+    // looks at. If nothing has created the atom yet, nothing is going to read it either.
+    pub fn set_decorated(&mut self, decorated: bool) -> bool {
+        let hints = get_xcb_atom(&self.connection, "_MOTIF_WM_HINTS");
+        if hints == x::ATOM_NONE {
+            warn!("Window manager doesn't support _MOTIF_WM_HINTS, can't change decorations");
+            return false;
+        }
+
+        info!(
+            "{} window decorations",
+            if decorated { "Enabling" } else { "Disabling" }
+        );
+        // flags (only decorations are set), functions, decorations, input mode, status
+        let data: [u32; 5] = [1 << 1, 0, decorated as u32, 0, 0];
+        let cookie = self.connection.send_request_checked(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window: self.window,
+            property: hints,
+            r#type: hints,
+            data: &data,
+        });
+        if self.connection.check_request(cookie).is_err() {
+            warn!("Failed to set _MOTIF_WM_HINTS");
+            return false;
+        }
+
+        self.decorated = decorated;
+        true
+    }
+
+    pub fn decorated(&self) -> bool {
+        self.decorated
+    }
+
+    // The window is already mapped, so this has to ask the window manager through a message to the
+    // root window instead of setting _NET_WM_STATE directly
+    pub fn set_always_on_top(&mut self, always_on_top: bool) -> bool {
+        let state = get_xcb_atom(&self.connection, "_NET_WM_STATE");
+        let above = get_xcb_atom(&self.connection, "_NET_WM_STATE_ABOVE");
+        if state == x::ATOM_NONE
+            || above == x::ATOM_NONE
+            || !wm_supports(&self.connection, self.root, above)
+        {
+            warn!("Window manager doesn't support _NET_WM_STATE_ABOVE, can't change always on top");
+            return false;
+        }
+
+        info!(
+            "{} always on top",
+            if always_on_top {
+                "Enabling"
+            } else {
+                "Disabling"
+            }
+        );
+        // action (0 remove, 1 add), first property, second property, source (1 is an application)
+        let data = [always_on_top as u32, above.resource_id(), 0, 1, 0];
+        self.connection.send_request(&x::SendEvent {
+            propagate: false,
+            destination: x::SendEventDest::Window(self.root),
+            event_mask: x::EventMask::SUBSTRUCTURE_NOTIFY | x::EventMask::SUBSTRUCTURE_REDIRECT,
+            event: &x::ClientMessageEvent::new(
+                self.window,
+                state,
+                x::ClientMessageData::Data32(data),
+            ),
+        });
+        if self.connection.flush().is_err() {
+            warn!("Failed to flush XCB connection");
+            return false;
+        }
+
+        self.always_on_top = always_on_top;
+        true
+    }
+
+    pub fn always_on_top(&self) -> bool {
+        self.always_on_top
+    }
+
+    // Moves and resizes in one request, so the compositor never sees the in between state.
+    // Clamped to the monitor the middle of it ends up on (or the current one).
+    pub fn set_geometry(&mut self, x: i32, y: i32, width: u32, height: u32) -> bool {
+        let center = (x + width as i32 / 2, y + height as i32 / 2);
+        let index = self
+            .monitors
+            .iter()
+            .position(|monitor| {
+                (monitor.x..monitor.x + monitor.width as i32).contains(&center.0)
+                    && (monitor.y..monitor.y + monitor.height as i32).contains(&center.1)
+            })
+            .unwrap_or(self.monitor.min(self.monitors.len() - 1));
+        let monitor = &self.monitors[index];
+
+        let width = width.clamp(1, monitor.width);
+        let height = height.clamp(1, monitor.height);
+        let x = x.clamp(monitor.x, monitor.x + (monitor.width - width) as i32);
+        let y = y.clamp(monitor.y, monitor.y + (monitor.height - height) as i32);
+        info!(
+            "Setting window geometry to {width}x{height} at ({x}, {y}) on monitor {index} ({})",
+            monitor.name
+        );
+
+        self.connection.send_request(&x::ConfigureWindow {
+            window: self.window,
+            value_list: &[
+                x::ConfigWindow::X(x),
+                x::ConfigWindow::Y(y),
+                x::ConfigWindow::Width(width),
+                x::ConfigWindow::Height(height),
+            ],
+        });
+        if self.connection.flush().is_err() {
+            warn!("Failed to flush XCB connection");
+            return false;
+        }
+
+        self.monitor = index;
+        self.requested_geometry = Some((x, y, width, height));
+        true
+    }
+
+    pub fn geometry(&self) -> (i32, i32, u32, u32) {
+        (self.x, self.y, self.width, self.height)
+    }
+
+    // For going back to the same place and look after fullscreen
+    pub fn save_window_state(&mut self) {
+        debug!(
+            "Saving window geometry {:?}, decorated {} and always on top {}",
+            self.geometry(),
+            self.decorated,
+            self.always_on_top
+        );
+        self.saved_window = Some((self.geometry(), self.decorated, self.always_on_top));
+    }
+
+    pub fn restore_window_state(&mut self) -> bool {
+        let Some(((x, y, width, height), decorated, always_on_top)) = self.saved_window.take()
+        else {
+            warn!("No saved window state to restore");
+            return false;
+        };
+
+        // the window manager might ignore these, the geometry still gets restored
+        if decorated != self.decorated {
+            self.set_decorated(decorated);
+        }
+        if always_on_top != self.always_on_top {
+            self.set_always_on_top(always_on_top);
+        }
+        self.set_geometry(x, y, width, height)
+    }
+
+    // Covers the monitor the window is on without decorations, which is as close as X gets to
+    // fullscreen without changing video modes. Turning it off puts the window back how it was.
+    pub fn set_borderless_fullscreen(&mut self, enabled: bool) -> bool {
+        if enabled == self.borderless_fullscreen {
+            return true;
+        }
+
+        info!(
+            "{} borderless fullscreen",
+            if enabled { "Entering" } else { "Leaving" }
+        );
+        let changed = if enabled {
+            self.save_window_state();
+            self.set_decorated(false);
+            let monitor = &self.monitors[self.monitor];
+            let (x, y, width, height) = (monitor.x, monitor.y, monitor.width, monitor.height);
+            self.set_geometry(x, y, width, height)
+        } else {
+            self.restore_window_state()
+        };
+        if changed {
+            self.borderless_fullscreen = enabled;
+        }
+        changed
+    }
+
+    pub fn borderless_fullscreen(&self) -> bool {
+        self.borderless_fullscreen
+    }
+
     pub fn shutdown(&mut self) {
         info!("XCB video shutdown started");
 
@@ -171,6 +760,14 @@ impl State {
         self.focused
     }
 
+    pub fn is_minimized(&self) -> bool {
+        self.minimized
+    }
+
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, RenderEvent> {
+        self.events.drain(..)
+    }
+
     pub fn create_vulkan_surface(
         &self,
         entry: &ash::Entry,
@@ -191,3 +788,30 @@ impl State {
         }
     }
 }
+
+impl crate::engine::rendersystem::SurfaceProvider for State {
+    fn create_surface(
+        &self,
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        alloc_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> vk::SurfaceKHR {
+        self.create_vulkan_surface(entry, instance, alloc_callbacks)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.get_size()
+    }
+
+    fn instance_extensions(&self) -> Vec<String> {
+        vec![String::from("VK_KHR_xcb_surface")]
+    }
+
+    fn set_borderless_fullscreen(&mut self, enabled: bool) -> bool {
+        State::set_borderless_fullscreen(self, enabled)
+    }
+
+    fn configured(&self) -> bool {
+        self.configured
+    }
+}
@@ -1,6 +1,10 @@
 pub mod video;
 
+#[cfg(feature = "gpu_telemetry")]
+use std::ffi;
 use windows_sys::Win32::System::Diagnostics::Debug::*;
+#[cfg(feature = "gpu_telemetry")]
+use windows_sys::Win32::System::LibraryLoader::*;
 
 pub unsafe fn init() {}
 
@@ -9,3 +13,18 @@ pub unsafe fn shutdown() {}
 pub unsafe fn have_debugger() -> bool {
     IsDebuggerPresent() != 0
 }
+
+#[cfg(feature = "gpu_telemetry")]
+pub unsafe fn load_library(name: &str) -> *mut ffi::c_void {
+    let name = ffi::CString::new(name).unwrap();
+    LoadLibraryA(name.as_ptr() as *const u8) as *mut ffi::c_void
+}
+
+#[cfg(feature = "gpu_telemetry")]
+pub unsafe fn get_symbol(library: *mut ffi::c_void, name: &str) -> *mut ffi::c_void {
+    let name = ffi::CString::new(name).unwrap();
+    match GetProcAddress(library as isize, name.as_ptr() as *const u8) {
+        Some(symbol) => symbol as *mut ffi::c_void,
+        None => std::ptr::null_mut(),
+    }
+}
@@ -1,7 +1,9 @@
+use crate::engine::rendersystem::RenderEvent;
 use ash::{extensions, vk};
 use log::{debug, info};
 use std::{ffi, mem, ptr};
 use windows_sys::Win32::Foundation::*;
+use windows_sys::Win32::Graphics::Gdi::*;
 use windows_sys::Win32::System::LibraryLoader::*;
 use windows_sys::Win32::UI::WindowsAndMessaging::*;
 
@@ -20,7 +22,9 @@ pub struct State {
     height: u32,
     resized: bool,
     focused: bool,
+    minimized: bool,
     closed: bool,
+    events: Vec<RenderEvent>,
 }
 
 impl State {
@@ -53,6 +57,9 @@ impl State {
 
                     if new_width != self_.width || new_height != self_.height {
                         self_.resized = true;
+                        self_
+                            .events
+                            .push(RenderEvent::Resized(new_width, new_height));
                         info!(
                             "Window resized from {}x{} to {}x{}",
                             self_.width, self_.height, new_width, new_height
@@ -61,10 +68,12 @@ impl State {
 
                     self_.width = new_width;
                     self_.height = new_height;
+                    self_.minimized = wparam == SIZE_MINIMIZED as usize;
                     0
                 }
                 WM_ACTIVATEAPP => {
                     self_.focused = wparam != 0;
+                    self_.events.push(RenderEvent::Focused(self_.focused));
                     info!(
                         "Window {}",
                         if self_.focused {
@@ -75,9 +84,40 @@ impl State {
                     );
                     0
                 }
+                WM_KEYDOWN | WM_KEYUP => {
+                    self_.events.push(RenderEvent::Key {
+                        code: wparam as u32,
+                        pressed: message == WM_KEYDOWN,
+                    });
+                    0
+                }
+                WM_MOUSEMOVE => {
+                    // signed, they can be negative on multiple monitors
+                    self_.events.push(RenderEvent::MouseMoved {
+                        x: (lparam & 0xffff) as i16 as f32,
+                        y: ((lparam >> 16) & 0xffff) as i16 as f32,
+                    });
+                    0
+                }
+                WM_LBUTTONDOWN | WM_LBUTTONUP | WM_RBUTTONDOWN | WM_RBUTTONUP | WM_MBUTTONDOWN
+                | WM_MBUTTONUP => {
+                    self_.events.push(RenderEvent::MouseButton {
+                        button: match message {
+                            WM_LBUTTONDOWN | WM_LBUTTONUP => 0,
+                            WM_RBUTTONDOWN | WM_RBUTTONUP => 1,
+                            _ => 2,
+                        },
+                        pressed: matches!(
+                            message,
+                            WM_LBUTTONDOWN | WM_RBUTTONDOWN | WM_MBUTTONDOWN
+                        ),
+                    });
+                    0
+                }
                 WM_CLOSE => {
                     info!("Window closed");
                     self_.closed = true;
+                    self_.events.push(RenderEvent::Closed);
                     0
                 }
                 _ => DefWindowProcA(message_window, message, wparam, lparam),
@@ -189,7 +229,9 @@ impl State {
             height,
             resized: false,
             focused: false,
+            minimized: false,
             closed: false,
+            events: Vec::new(),
         }
     }
 
@@ -243,6 +285,14 @@ impl State {
         self.focused
     }
 
+    pub fn is_minimized(&self) -> bool {
+        self.minimized
+    }
+
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, RenderEvent> {
+        self.events.drain(..)
+    }
+
     #[cfg(not(xbox))]
     pub fn create_vulkan_surface(
         &self,
@@ -264,3 +314,28 @@ impl State {
         }
     }
 }
+
+#[cfg(not(xbox))]
+impl crate::engine::rendersystem::SurfaceProvider for State {
+    fn create_surface(
+        &self,
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        alloc_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> vk::SurfaceKHR {
+        self.create_vulkan_surface(entry, instance, alloc_callbacks)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.get_size()
+    }
+
+    fn instance_extensions(&self) -> Vec<String> {
+        vec![String::from("VK_KHR_win32_surface")]
+    }
+
+    fn monitor(&self) -> Option<vk::HMONITOR> {
+        let monitor = unsafe { MonitorFromWindow(self.window, MONITOR_DEFAULTTONEAREST) };
+        (monitor != 0).then_some(monitor as vk::HMONITOR)
+    }
+}
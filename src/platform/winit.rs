@@ -0,0 +1,243 @@
+use crate::engine::rendersystem::RenderEvent;
+use ash::{extensions, vk};
+use log::{debug, info};
+use raw_window_handle::{
+    HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle, RawWindowHandle,
+};
+use winit::dpi::PhysicalSize;
+use winit::event::{ElementState, Event, MouseButton, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::platform::run_return::EventLoopExtRunReturn;
+use winit::window::{Window, WindowBuilder};
+
+// Window and surface provider backed by winit instead of the native XCB/Win32 code. It has the
+// same interface as platform::video::State, engine::Video is this instead with the winit feature.
+pub struct WinitSurfaceProvider {
+    event_loop: EventLoop<()>,
+    window: Window,
+    width: u32,
+    height: u32,
+    resized: bool,
+    focused: bool,
+    closed: bool,
+    events: Vec<RenderEvent>,
+}
+
+impl WinitSurfaceProvider {
+    pub fn init() -> Self {
+        info!("winit video initialization started");
+
+        let event_loop = EventLoop::new();
+        let title = format!(
+            "{} v{}.{}.{} by {}",
+            crate::GAME_NAME,
+            crate::GAME_VERSION_MAJOR,
+            crate::GAME_VERSION_MINOR,
+            crate::GAME_VERSION_PATCH,
+            crate::GAME_ORGANIZATION_NAME
+        );
+
+        let mut builder = WindowBuilder::new().with_title(&title);
+        if let Some(monitor) = event_loop.primary_monitor() {
+            let size = monitor.size();
+            builder = builder.with_inner_size(PhysicalSize::new(
+                (size.width as f32 / 1.5) as u32,
+                (size.height as f32 / 1.5) as u32,
+            ));
+        }
+        let window = builder
+            .build(&event_loop)
+            .unwrap_or_else(|err| panic!("Failed to create window: {err}"));
+
+        let size = window.inner_size();
+        debug!(
+            "Created {}x{} window titled {title}",
+            size.width, size.height
+        );
+
+        info!("winit video initialization succeeded");
+
+        Self {
+            event_loop,
+            window,
+            width: size.width,
+            height: size.height,
+            resized: false,
+            focused: false,
+            closed: false,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self) -> bool {
+        self.event_loop.run_return(|event, _, control_flow| {
+            *control_flow = ControlFlow::Poll;
+
+            match event {
+                Event::WindowEvent { event, .. } => match event {
+                    WindowEvent::Resized(size) => {
+                        if size.width != self.width || size.height != self.height {
+                            self.resized = true;
+                            info!(
+                                "Window resized from {}x{} to {}x{}",
+                                self.width, self.height, size.width, size.height
+                            );
+                            self.width = size.width;
+                            self.height = size.height;
+                            self.events
+                                .push(RenderEvent::Resized(size.width, size.height));
+                        }
+                    }
+                    WindowEvent::Focused(focused) => {
+                        self.focused = focused;
+                        info!("Window {}", if focused { "focused" } else { "unfocused" });
+                        self.events.push(RenderEvent::Focused(focused));
+                    }
+                    WindowEvent::KeyboardInput { input, .. } => {
+                        self.events.push(RenderEvent::Key {
+                            code: input.scancode,
+                            pressed: input.state == ElementState::Pressed,
+                        })
+                    }
+                    WindowEvent::CursorMoved { position, .. } => {
+                        self.events.push(RenderEvent::MouseMoved {
+                            x: position.x as f32,
+                            y: position.y as f32,
+                        })
+                    }
+                    WindowEvent::MouseInput { state, button, .. } => {
+                        let button = match button {
+                            MouseButton::Left => 0,
+                            MouseButton::Right => 1,
+                            MouseButton::Middle => 2,
+                            MouseButton::Other(_) => return,
+                        };
+                        self.events.push(RenderEvent::MouseButton {
+                            button,
+                            pressed: state == ElementState::Pressed,
+                        })
+                    }
+                    WindowEvent::CloseRequested => {
+                        info!("Window closed");
+                        self.closed = true;
+                        self.events.push(RenderEvent::Closed);
+                    }
+                    _ => {}
+                },
+                Event::MainEventsCleared => *control_flow = ControlFlow::Exit,
+                _ => {}
+            }
+        });
+
+        !self.closed
+    }
+
+    pub fn shutdown(&mut self) {
+        info!("winit video shutdown started");
+        self.window.set_visible(false);
+        info!("winit video shutdown succeeded");
+    }
+
+    pub fn get_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn resized(&mut self) -> bool {
+        let ret = self.resized;
+        self.resized = false;
+        ret
+    }
+
+    pub fn focused(&self) -> bool {
+        self.focused
+    }
+
+    pub fn is_minimized(&self) -> bool {
+        self.window.is_minimized().unwrap_or(false)
+    }
+
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, RenderEvent> {
+        self.events.drain(..)
+    }
+
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+}
+
+impl crate::engine::rendersystem::SurfaceProvider for WinitSurfaceProvider {
+    fn create_surface(
+        &self,
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        alloc_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> vk::SurfaceKHR {
+        // done here instead of with ash-window, which is on a newer raw-window-handle than winit
+        let surface = unsafe {
+            match (
+                self.window.raw_display_handle(),
+                self.window.raw_window_handle(),
+            ) {
+                (RawDisplayHandle::Xlib(display), RawWindowHandle::Xlib(window)) => {
+                    extensions::khr::XlibSurface::new(entry, instance).create_xlib_surface(
+                        &vk::XlibSurfaceCreateInfoKHR {
+                            dpy: display.display as _,
+                            window: window.window,
+                            ..Default::default()
+                        },
+                        alloc_callbacks,
+                    )
+                }
+                (RawDisplayHandle::Xcb(display), RawWindowHandle::Xcb(window)) => {
+                    extensions::khr::XcbSurface::new(entry, instance).create_xcb_surface(
+                        &vk::XcbSurfaceCreateInfoKHR {
+                            connection: display.connection as _,
+                            window: window.window,
+                            ..Default::default()
+                        },
+                        alloc_callbacks,
+                    )
+                }
+                (RawDisplayHandle::Wayland(display), RawWindowHandle::Wayland(window)) => {
+                    extensions::khr::WaylandSurface::new(entry, instance).create_wayland_surface(
+                        &vk::WaylandSurfaceCreateInfoKHR {
+                            display: display.display as _,
+                            surface: window.surface as _,
+                            ..Default::default()
+                        },
+                        alloc_callbacks,
+                    )
+                }
+                (RawDisplayHandle::Windows(_), RawWindowHandle::Win32(window)) => {
+                    extensions::khr::Win32Surface::new(entry, instance).create_win32_surface(
+                        &vk::Win32SurfaceCreateInfoKHR {
+                            hinstance: window.hinstance as _,
+                            hwnd: window.hwnd as _,
+                            ..Default::default()
+                        },
+                        alloc_callbacks,
+                    )
+                }
+                (display, window) => {
+                    panic!("Unsupported winit window {window:?} on display {display:?}")
+                }
+            }
+        };
+        surface.unwrap_or_else(|err| panic!("Failed to create winit surface: {}", err))
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.get_size()
+    }
+
+    fn instance_extensions(&self) -> Vec<String> {
+        let extension = match self.window.raw_display_handle() {
+            RawDisplayHandle::Xlib(_) => "VK_KHR_xlib_surface",
+            RawDisplayHandle::Xcb(_) => "VK_KHR_xcb_surface",
+            RawDisplayHandle::Wayland(_) => "VK_KHR_wayland_surface",
+            RawDisplayHandle::Windows(_) => "VK_KHR_win32_surface",
+            display => panic!("Unsupported winit display {display:?}"),
+        };
+        vec![String::from(extension)]
+    }
+}